@@ -0,0 +1,297 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use criterion::{black_box, criterion_group, BatchSize, BenchmarkId, Criterion, Throughput};
+use matching_engine::data_types::{Order, Side};
+use std::sync::Arc;
+use std::thread;
+use matching_engine::clock::{MonotonicClock, Sequencer};
+use matching_engine::order_book::{LadderBook, OrderBook};
+use matching_engine::price_levels::PriceLevels;
+use matching_engine::spsc::{self, WaitStrategy};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rand_distr::{Bernoulli, Distribution, Normal, Uniform};
+
+/// CountingAllocator counts the allocations so that the benches can report them per operation
+struct CountingAllocator;
+
+static ALLOCATIONS : AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator
+{
+    unsafe fn alloc(&self, layout : Layout) -> *mut u8
+    {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr : *mut u8, layout : Layout)
+    {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr : *mut u8, layout : Layout, new_size : usize) -> *mut u8
+    {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL : CountingAllocator = CountingAllocator;
+
+const SYMBOL : &str = "TSLA";
+
+/// Price band of the ladder books, wide enough for the levels of all the benches
+const BAND : (f32, f32, f32) = (90.0, 111.0, 0.005);
+
+fn ladder_book() -> LadderBook
+{
+    LadderBook::with_ladder(SYMBOL, BAND, Arc::new(MonotonicClock::new()), Arc::new(Sequencer::new())).unwrap()
+}
+
+/// fill_levels adds one order per price level on each side of the book,
+/// the bids below 100 and the asks above 101
+fn fill_levels<Bid : PriceLevels, Ask : PriceLevels>(mut book : OrderBook<Bid, Ask>, levels : u32) -> OrderBook<Bid, Ask>
+{
+    for i in 0..levels
+    {
+        book.insert_order_at_level(&mut Order::new(2 * i + 1, Side::Buy, 100.0 - i as f32 * 0.01, 100)).unwrap();
+        book.insert_order_at_level(&mut Order::new(2 * i + 2, Side::Sell, 101.0 + i as f32 * 0.01, 100)).unwrap();
+    }
+    book.drain_events();
+    book
+}
+
+fn book_with_levels(levels : u32) -> OrderBook
+{
+    fill_levels(OrderBook::new(SYMBOL), levels)
+}
+
+/// book_with_queue creates a book with a single bid level holding the given number of orders
+fn book_with_queue(orders : u32) -> OrderBook
+{
+    let mut book = OrderBook::new(SYMBOL);
+    for id in 1..=orders
+    {
+        book.insert_order_at_level(&mut Order::new(id, Side::Buy, 100.0, 10)).unwrap();
+    }
+    book.drain_events();
+    book
+}
+
+/// Command is a step of the mixed flow
+#[derive(Copy, Clone)]
+enum Command
+{
+    Insert(Order),
+    Cancel(Order),
+}
+
+/// mixed_flow generates orders the way test_client does, a normal random walk of the
+/// price with a bernoulli side and a uniform quantity, and cancels one in ten of them
+fn mixed_flow(count : u32) -> Vec<Command>
+{
+    let mut rng = StdRng::seed_from_u64(42);
+    let normal = Normal::new(0.05, 0.22).unwrap();
+    let qty_distr = Uniform::from(1..500);
+    let bernoulli = Bernoulli::new(0.55).unwrap();
+    let mut evolving_price = 1021.2f32;
+
+    let mut commands = Vec::with_capacity(count as usize);
+    for id in 1..=count
+    {
+        let side = if bernoulli.sample(&mut rng) { Side::Sell } else { Side::Buy };
+        evolving_price += normal.sample(&mut rng);
+        let order = Order::new(id, side, (evolving_price * 100.0).round() / 100.0, qty_distr.sample(&mut rng));
+        commands.push(Command::Insert(order));
+        if id % 10 == 0
+        {
+            if let Command::Insert(order) = commands[commands.len() - 5]
+            {
+                commands.push(Command::Cancel(order));
+            }
+        }
+    }
+    commands
+}
+
+fn run_flow(book : &mut OrderBook, commands : &[Command])
+{
+    for command in commands
+    {
+        match *command
+        {
+            Command::Insert(mut order) => book.insert_order_at_level(&mut order).unwrap(),
+            // The order may have been filled already
+            Command::Cancel(order) => { let _ = book.cancel_order(&order); },
+        }
+    }
+    book.drain_events();
+}
+
+fn insert_without_match(c : &mut Criterion)
+{
+    let mut group = c.benchmark_group("insert_without_match");
+    for levels in [10u32, 1000]
+    {
+        group.bench_with_input(BenchmarkId::from_parameter(levels), &levels, |b, &levels|
+        {
+            b.iter_batched_ref(|| book_with_levels(levels), |book|
+            {
+                book.insert_order_at_level(black_box(&mut Order::new(u32::MAX, Side::Buy, 99.995, 100))).unwrap();
+            }, BatchSize::SmallInput);
+        });
+        group.bench_with_input(BenchmarkId::new("ladder", levels), &levels, |b, &levels|
+        {
+            b.iter_batched_ref(|| fill_levels(ladder_book(), levels), |book|
+            {
+                book.insert_order_at_level(black_box(&mut Order::new(u32::MAX, Side::Buy, 99.995, 100))).unwrap();
+            }, BatchSize::SmallInput);
+        });
+    }
+    group.finish();
+}
+
+fn sweep_levels(c : &mut Criterion)
+{
+    let mut group = c.benchmark_group("sweep_levels");
+    for levels in [1u32, 10, 100]
+    {
+        group.throughput(Throughput::Elements(levels as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(levels), &levels, |b, &levels|
+        {
+            b.iter_batched_ref(|| book_with_levels(levels), |book|
+            {
+                book.insert_order_at_level(black_box(&mut Order::new(u32::MAX, Side::Buy, 110.0, 100 * levels))).unwrap();
+            }, BatchSize::SmallInput);
+        });
+        group.bench_with_input(BenchmarkId::new("ladder", levels), &levels, |b, &levels|
+        {
+            b.iter_batched_ref(|| fill_levels(ladder_book(), levels), |book|
+            {
+                book.insert_order_at_level(black_box(&mut Order::new(u32::MAX, Side::Buy, 110.0, 100 * levels))).unwrap();
+            }, BatchSize::SmallInput);
+        });
+    }
+    group.finish();
+}
+
+fn cancel_from_deep_queue(c : &mut Criterion)
+{
+    let mut group = c.benchmark_group("cancel_from_deep_queue");
+    // The orders are found through the index of the slab, the position in the queue
+    // and the length of the queue should not matter
+    for (name, id, orders) in [("front", 1u32, 1000), ("middle", 500, 1000), ("back", 1000, 1000), ("back_of_10000", 10000, 10000)]
+    {
+        let order = Order::new(id, Side::Buy, 100.0, 10);
+        group.bench_function(name, |b|
+        {
+            b.iter_batched_ref(|| book_with_queue(orders), |book|
+            {
+                book.cancel_order(black_box(&order)).unwrap();
+            }, BatchSize::SmallInput);
+        });
+    }
+    group.finish();
+}
+
+fn mixed(c : &mut Criterion)
+{
+    let commands = mixed_flow(10_000);
+    let mut group = c.benchmark_group("mixed_flow");
+    group.throughput(Throughput::Elements(commands.len() as u64));
+    group.sample_size(20);
+    group.bench_function("10000_orders", |b|
+    {
+        b.iter_batched_ref(|| OrderBook::new(SYMBOL), |book| run_flow(book, black_box(&commands)), BatchSize::LargeInput);
+    });
+    group.finish();
+}
+
+/// hand_off passes batches of orders to a thread and back through two rings, as between
+/// the connections and the matching thread of the server, for both wait strategies
+fn hand_off(c : &mut Criterion)
+{
+    const BATCH : u32 = 1000;
+    let mut group = c.benchmark_group("hand_off");
+    group.throughput(Throughput::Elements(BATCH as u64));
+    for strategy in [WaitStrategy::BusySpin, WaitStrategy::Park]
+    {
+        let (mut requests, mut inbound) = spsc::channel::<Option<Order>>(1024, strategy);
+        let (mut outbound, mut responses) = spsc::channel::<Option<Order>>(1024, strategy);
+        let echo = thread::spawn(move ||
+        {
+            while let Some(order) = inbound.pop()
+            {
+                if outbound.push(order).is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        group.bench_function(BenchmarkId::new("round_trip", format!("{:?}", strategy)), |b|
+        {
+            b.iter(||
+            {
+                // The batch fits the rings, the echo thread answers while the orders are pushed
+                for id in 0..BATCH
+                {
+                    requests.push(Some(Order::new(id, Side::Buy, 100.0, 10))).unwrap();
+                }
+                for _ in 0..BATCH
+                {
+                    black_box(responses.pop());
+                }
+            });
+        });
+        drop(requests);
+        echo.join().unwrap();
+    }
+    group.finish();
+}
+
+/// allocations_per_op runs the operation on fresh books and returns its mean number of allocations
+fn allocations_per_op(runs : u64, setup : impl Fn() -> OrderBook, op : impl Fn(&mut OrderBook)) -> f64
+{
+    let mut total = 0;
+    for _ in 0..runs
+    {
+        let mut book = setup();
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        op(&mut book);
+        total += ALLOCATIONS.load(Ordering::Relaxed) - before;
+    }
+    total as f64 / runs as f64
+}
+
+fn report_allocations()
+{
+    let commands = mixed_flow(10_000);
+    let reports = [
+        ("insert_without_match/1000", allocations_per_op(100, || book_with_levels(1000),
+            |book| book.insert_order_at_level(&mut Order::new(u32::MAX, Side::Buy, 99.995, 100)).unwrap())),
+        ("sweep_levels/100", allocations_per_op(100, || book_with_levels(100),
+            |book| book.insert_order_at_level(&mut Order::new(u32::MAX, Side::Buy, 110.0, 10_000)).unwrap())),
+        ("cancel_from_deep_queue/back", allocations_per_op(100, || book_with_queue(1000),
+            |book| { book.cancel_order(&Order::new(1000, Side::Buy, 100.0, 10)).unwrap(); })),
+        ("mixed_flow/10000_orders", allocations_per_op(5, || OrderBook::new(SYMBOL),
+            |book| run_flow(book, &commands)) / commands.len() as f64),
+    ];
+
+    println!("\nAllocations per operation");
+    for (name, allocations) in reports
+    {
+        println!("{:<32} {:>10.2}", name, allocations);
+    }
+}
+
+criterion_group!(benches, insert_without_match, sweep_levels, cancel_from_deep_queue, mixed, hand_off);
+
+fn main()
+{
+    benches();
+    report_allocations();
+    Criterion::default().configure_from_args().final_summary();
+}
@@ -0,0 +1,341 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use serde::Serialize;
+
+/// AdminCommand is an operator request changing what can be traded, it is sent
+/// as a single text line, e.g. `HALT TSLA` or `HALT_ACCOUNT 7`
+#[derive(Clone, PartialEq, Debug)]
+pub enum AdminCommand
+{
+    /// Stops the new orders on the symbol, the resting orders can still be cancelled
+    HaltSymbol(String),
+    ResumeSymbol(String),
+    /// Stops the new orders of the account on every symbol
+    HaltAccount(u32),
+    ResumeAccount(u32),
+    /// Only the cancels are accepted, on every symbol
+    DisableNewOrders,
+    EnableNewOrders,
+    /// Cancels every resting order and disables the new orders
+    KillSwitch,
+    /// Ends the trading session, the DAY orders expire and the new orders are rejected
+    /// until they are enabled again
+    CloseSession,
+}
+
+impl AdminCommand
+{
+    pub fn parse(line : &str) -> Result<AdminCommand, &'static str>
+    {
+        let mut words = line.split_whitespace();
+        let command = words.next().ok_or("empty command")?;
+        let mut argument = || words.next().ok_or("missing argument");
+        let command = match command.to_ascii_uppercase().as_str()
+        {
+            "HALT" => AdminCommand::HaltSymbol(argument()?.to_string()),
+            "RESUME" => AdminCommand::ResumeSymbol(argument()?.to_string()),
+            "HALT_ACCOUNT" => AdminCommand::HaltAccount(argument()?.parse().map_err(|_| "invalid account")?),
+            "RESUME_ACCOUNT" => AdminCommand::ResumeAccount(argument()?.parse().map_err(|_| "invalid account")?),
+            "CANCEL_ONLY" => AdminCommand::DisableNewOrders,
+            "ENABLE" => AdminCommand::EnableNewOrders,
+            "KILL" => AdminCommand::KillSwitch,
+            "CLOSE" => AdminCommand::CloseSession,
+            _ => return Err("unknown command"),
+        };
+        if words.next().is_some()
+        {
+            return Err("too many arguments");
+        }
+        Ok(command)
+    }
+}
+
+impl fmt::Display for AdminCommand
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            AdminCommand::HaltSymbol(symbol) => write!(f, "HALT {}", symbol),
+            AdminCommand::ResumeSymbol(symbol) => write!(f, "RESUME {}", symbol),
+            AdminCommand::HaltAccount(account) => write!(f, "HALT_ACCOUNT {}", account),
+            AdminCommand::ResumeAccount(account) => write!(f, "RESUME_ACCOUNT {}", account),
+            AdminCommand::DisableNewOrders => write!(f, "CANCEL_ONLY"),
+            AdminCommand::EnableNewOrders => write!(f, "ENABLE"),
+            AdminCommand::KillSwitch => write!(f, "KILL"),
+            AdminCommand::CloseSession => write!(f, "CLOSE"),
+        }
+    }
+}
+
+/// Phase is the state of the trading session on every symbol
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase
+{
+    /// The new orders are accepted, unless their symbol or account is halted
+    Open,
+    /// Only the cancels are accepted, after `CANCEL_ONLY` or the kill switch
+    CancelOnly,
+    /// The session was closed, the new orders are rejected until it is enabled again
+    Closed,
+}
+
+/// TradingControls holds the halts decided by the operators
+#[derive(Clone, Debug, Default)]
+pub struct TradingControls
+{
+    _halted_symbols : HashSet<String>,
+    _halted_accounts : HashSet<u32>,
+    _new_orders_disabled : bool,
+    _session_closed : bool,
+}
+
+impl TradingControls
+{
+    pub fn phase(&self) -> Phase
+    {
+        if self._session_closed
+        {
+            Phase::Closed
+        }
+        else if self._new_orders_disabled
+        {
+            Phase::CancelOnly
+        }
+        else
+        {
+            Phase::Open
+        }
+    }
+
+    /// halted_symbols returns the halted symbols in alphabetical order
+    pub fn halted_symbols(&self) -> Vec<&str>
+    {
+        let mut symbols : Vec<&str> = self._halted_symbols.iter().map(|symbol| symbol.as_str()).collect();
+        symbols.sort_unstable();
+        symbols
+    }
+
+    /// halted_accounts returns the halted accounts in increasing order
+    pub fn halted_accounts(&self) -> Vec<u32>
+    {
+        let mut accounts : Vec<u32> = self._halted_accounts.iter().copied().collect();
+        accounts.sort_unstable();
+        accounts
+    }
+
+    pub fn is_symbol_halted(&self, symbol : &str) -> bool
+    {
+        self._halted_symbols.contains(symbol)
+    }
+
+    pub fn is_account_halted(&self, account : u32) -> bool
+    {
+        self._halted_accounts.contains(&account)
+    }
+
+    pub fn new_orders_enabled(&self) -> bool
+    {
+        self.phase() == Phase::Open
+    }
+
+    /// check_new_order tells whether an order entering or amending a position is allowed,
+    /// the cancels are always allowed
+    pub fn check_new_order(&self, symbol : &str, account : u32) -> Result<(), &'static str>
+    {
+        if self._session_closed
+        {
+            return Err("Trading session is closed");
+        }
+        if self._new_orders_disabled
+        {
+            return Err("New orders are disabled");
+        }
+        if self.is_symbol_halted(symbol)
+        {
+            return Err("Symbol is halted");
+        }
+        if self.is_account_halted(account)
+        {
+            return Err("Account is halted");
+        }
+        Ok(())
+    }
+
+    /// apply updates the halts, the orders cancelled by the kill switch or expired
+    /// by the session close are left to the caller
+    pub fn apply(&mut self, command : &AdminCommand)
+    {
+        match command
+        {
+            AdminCommand::HaltSymbol(symbol) => { self._halted_symbols.insert(symbol.clone()); },
+            AdminCommand::ResumeSymbol(symbol) => { self._halted_symbols.remove(symbol); },
+            AdminCommand::HaltAccount(account) => { self._halted_accounts.insert(*account); },
+            AdminCommand::ResumeAccount(account) => { self._halted_accounts.remove(account); },
+            AdminCommand::DisableNewOrders | AdminCommand::KillSwitch => self._new_orders_disabled = true,
+            AdminCommand::EnableNewOrders =>
+            {
+                self._new_orders_disabled = false;
+                self._session_closed = false;
+            },
+            AdminCommand::CloseSession => self._session_closed = true,
+        }
+    }
+}
+
+/// Journal records the admin commands, one line per command prefixed by its timestamp
+pub struct Journal
+{
+    _writer : Box<dyn Write + Send>,
+}
+
+impl Journal
+{
+    pub fn new(writer : Box<dyn Write + Send>) -> Journal
+    {
+        Journal { _writer : writer }
+    }
+
+    /// open appends the commands to the file, created if missing
+    pub fn open(path : impl AsRef<Path>) -> io::Result<Journal>
+    {
+        let file : File = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Journal::new(Box::new(file)))
+    }
+
+    /// record writes the command and flushes it before it is acknowledged
+    pub fn record(&mut self, timestamp : u64, command : &AdminCommand) -> io::Result<()>
+    {
+        writeln!(self._writer, "{} {}", timestamp, command)?;
+        self._writer.flush()
+    }
+
+    /// read parses the commands of a journal, in the order they were recorded
+    pub fn read(reader : impl BufRead) -> Result<Vec<(u64, AdminCommand)>, &'static str>
+    {
+        let mut commands = vec![];
+        for line in reader.lines()
+        {
+            let line = line.map_err(|_| "cannot read the journal")?;
+            if line.trim().is_empty()
+            {
+                continue;
+            }
+            let (timestamp, command) = line.split_once(' ').ok_or("missing timestamp")?;
+            commands.push((timestamp.parse().map_err(|_| "invalid timestamp")?, AdminCommand::parse(command)?));
+        }
+        Ok(commands)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+    use super::*;
+
+    /// SharedBuffer lets the test read what the journal wrote
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer
+    {
+        fn write(&mut self, buf : &[u8]) -> io::Result<usize>
+        {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()>
+        {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn parses_and_formats_commands()
+    {
+        let commands = vec![
+            AdminCommand::HaltSymbol("TSLA".to_string()),
+            AdminCommand::ResumeSymbol("TSLA".to_string()),
+            AdminCommand::HaltAccount(7),
+            AdminCommand::ResumeAccount(7),
+            AdminCommand::DisableNewOrders,
+            AdminCommand::EnableNewOrders,
+            AdminCommand::KillSwitch,
+            AdminCommand::CloseSession,
+        ];
+        for command in commands
+        {
+            assert_eq!(AdminCommand::parse(&command.to_string()), Ok(command));
+        }
+        assert_eq!(AdminCommand::parse("halt AAPL\r\n"), Ok(AdminCommand::HaltSymbol("AAPL".to_string())));
+        assert_eq!(AdminCommand::parse("HALT"), Err("missing argument"));
+        assert_eq!(AdminCommand::parse("HALT_ACCOUNT x"), Err("invalid account"));
+        assert_eq!(AdminCommand::parse("KILL now"), Err("too many arguments"));
+        assert_eq!(AdminCommand::parse("STOP"), Err("unknown command"));
+    }
+
+    #[test]
+    fn controls_block_new_orders()
+    {
+        let mut controls = TradingControls::default();
+        assert_eq!(controls.check_new_order("TSLA", 7), Ok(()));
+
+        controls.apply(&AdminCommand::HaltSymbol("TSLA".to_string()));
+        controls.apply(&AdminCommand::HaltAccount(7));
+        assert_eq!(controls.check_new_order("TSLA", 8), Err("Symbol is halted"));
+        assert_eq!(controls.check_new_order("AAPL", 7), Err("Account is halted"));
+        assert_eq!(controls.check_new_order("AAPL", 8), Ok(()));
+
+        controls.apply(&AdminCommand::KillSwitch);
+        assert_eq!(controls.check_new_order("AAPL", 8), Err("New orders are disabled"));
+        controls.apply(&AdminCommand::EnableNewOrders);
+        controls.apply(&AdminCommand::ResumeSymbol("TSLA".to_string()));
+        controls.apply(&AdminCommand::ResumeAccount(7));
+        assert_eq!(controls.check_new_order("TSLA", 7), Ok(()));
+    }
+
+    #[test]
+    fn controls_report_the_session_phase()
+    {
+        let mut controls = TradingControls::default();
+        assert_eq!(controls.phase(), Phase::Open);
+        controls.apply(&AdminCommand::HaltAccount(8));
+        controls.apply(&AdminCommand::HaltAccount(7));
+        controls.apply(&AdminCommand::HaltSymbol("TSLA".to_string()));
+        controls.apply(&AdminCommand::HaltSymbol("AAPL".to_string()));
+        assert_eq!(controls.halted_symbols(), vec!["AAPL", "TSLA"]);
+        assert_eq!(controls.halted_accounts(), vec![7, 8]);
+
+        controls.apply(&AdminCommand::DisableNewOrders);
+        assert_eq!(controls.phase(), Phase::CancelOnly);
+        assert!(!controls.new_orders_enabled());
+        controls.apply(&AdminCommand::CloseSession);
+        assert_eq!(controls.phase(), Phase::Closed);
+        assert_eq!(controls.check_new_order("MSFT", 9), Err("Trading session is closed"));
+        controls.apply(&AdminCommand::EnableNewOrders);
+        assert_eq!(controls.phase(), Phase::Open);
+        assert!(controls.new_orders_enabled());
+    }
+
+    #[test]
+    fn journal_can_be_read_back()
+    {
+        let buffer = SharedBuffer::default();
+        let mut journal = Journal::new(Box::new(buffer.clone()));
+        journal.record(10, &AdminCommand::HaltSymbol("TSLA".to_string())).unwrap();
+        journal.record(20, &AdminCommand::KillSwitch).unwrap();
+
+        let written = buffer.0.lock().unwrap().clone();
+        assert_eq!(String::from_utf8(written.clone()).unwrap(), "10 HALT TSLA\n20 KILL\n");
+        assert_eq!(Journal::read(Cursor::new(written)), Ok(vec![
+            (10, AdminCommand::HaltSymbol("TSLA".to_string())),
+            (20, AdminCommand::KillSwitch),
+        ]));
+    }
+}
@@ -0,0 +1,1231 @@
+use matching_engine::clock::{Clock, MonotonicClock};
+use matching_engine::engine::Engine;
+use matching_engine::events::Event;
+use matching_engine::data_types::{Order, OrderType, Side, TimeInForce};
+use matching_engine::fees::FeeSchedule;
+use matching_engine::fix::*;
+use matching_engine::logging;
+use matching_engine::risk::{RiskChecker, RiskLimits};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tracing::{info, info_span, warn};
+use tokio::time::{interval, Duration, Instant};
+use bytes::BytesMut;
+
+const ENGINE_COMP_ID : &str = "ENGINE";
+const DEFAULT_ADDRESS : &str = "127.0.0.1:9878";
+const DEFAULT_HEART_BT_INT : u64 = 30;
+/// Longest heartbeat interval accepted at logon, in seconds
+const MAX_HEART_BT_INT : u64 = 3600;
+/// Time of the day the trading session closes and the DAY orders expire, 21:00 UTC
+const SESSION_CLOSE : u64 = 21 * 3600 * 1_000_000_000;
+const NANOS_PER_DAY : u64 = 86_400_000_000_000;
+/// Number of messages kept for the resends of a session, the older ones are gap filled
+const MAX_SENT_MESSAGES : usize = 10_000;
+
+/// OrderState tracks a live order entered through the gateway
+#[derive(Debug)]
+struct OrderState
+{
+    /// SenderCompID of the session which entered the order
+    owner : String,
+    cl_ord_id : String,
+    symbol : String,
+    /// The order as it rests in the book, qty is the leaves quantity
+    order : Order,
+    order_qty : u32,
+    cum_qty : u32,
+    notional : f64,
+    /// ClOrdID of a pending cancel or replace request
+    pending_cl_ord_id : Option<String>,
+}
+
+/// Gateway maps the FIX order entry messages onto the engine and routes
+/// the resulting execution reports to the owner sessions
+#[derive(Default)]
+struct Gateway
+{
+    _engine : Engine,
+    _risk : RiskChecker,
+    _orders : HashMap<u32, OrderState>,
+    _cl_ord_ids : HashMap<(String, String), u32>,
+    _sessions : HashMap<String, mpsc::UnboundedSender<FixMessage>>,
+    _next_order_id : u32,
+    _next_exec_id : u64,
+    /// Clock time of the last expiry check
+    _last_timer : u64,
+}
+
+fn decode_side(side : Option<&str>) -> Option<Side>
+{
+    match side
+    {
+        Some("1") => Some(Side::Buy),
+        Some("2") => Some(Side::Sell),
+        _ => None,
+    }
+}
+
+fn encode_side(side : Side) -> &'static str
+{
+    match side
+    {
+        Side::Buy => "1",
+        Side::Sell => "2",
+    }
+}
+
+impl Gateway
+{
+    fn new(symbols : &[&str], limits : RiskLimits, fees : FeeSchedule, clock : Arc<dyn Clock>) -> Gateway
+    {
+        let mut gateway = Gateway {
+            _last_timer : clock.now(),
+            _engine : Engine::with_clock(clock),
+            _risk : RiskChecker::new(limits),
+            _next_order_id : 1,
+            _next_exec_id : 1,
+            ..Default::default() };
+        for symbol in symbols
+        {
+            gateway._engine.add_symbol(symbol);
+            gateway._engine.book_mut(symbol).unwrap().fees_mut().set_default_schedule(fees.clone());
+        }
+        gateway
+    }
+
+    fn send(&self, owner : &str, message : FixMessage)
+    {
+        if let Some(session) = self._sessions.get(owner)
+        {
+            let _ = session.send(message);
+        }
+    }
+
+    fn next_exec_id(&mut self) -> u64
+    {
+        let exec_id = self._next_exec_id;
+        self._next_exec_id += 1;
+        exec_id
+    }
+
+    /// execution_report builds an ExecutionReport with the current state of the order
+    fn execution_report(&mut self, order_id : u32, exec_type : &str) -> FixMessage
+    {
+        let exec_id = self.next_exec_id();
+        let state = &self._orders[&order_id];
+        let ord_status = match exec_type
+        {
+            "4" | "8" | "C" => exec_type,
+            _ if state.order.qty == 0 => "2",
+            _ if state.cum_qty > 0 => "1",
+            _ => "0",
+        };
+        let avg_px = if state.cum_qty > 0 { state.notional / state.cum_qty as f64 } else { 0.0 };
+
+        let mut report = FixMessage::new(EXECUTION_REPORT)
+            .with(ORDER_ID, order_id)
+            .with(CL_ORD_ID, &state.cl_ord_id)
+            .with(EXEC_ID, exec_id)
+            .with(EXEC_TYPE, exec_type)
+            .with(ORD_STATUS, ord_status)
+            .with(ACCOUNT, state.order.account)
+            .with(SYMBOL, &state.symbol)
+            .with(SIDE, encode_side(state.order.side))
+            .with(ORDER_QTY, state.order_qty);
+        if state.order.order_type == OrderType::Limit
+        {
+            report.push(PRICE, state.order.price);
+        }
+        report.push(LEAVES_QTY, if matches!(exec_type, "4" | "C") { 0 } else { state.order.qty });
+        report.push(CUM_QTY, state.cum_qty);
+        report.push(AVG_PX, avg_px);
+        report
+    }
+
+    fn reject_order(&mut self, owner : &str, request : &FixMessage, reason : &str)
+    {
+        info!(reason, "rejected order");
+        let exec_id = self.next_exec_id();
+        let report = FixMessage::new(EXECUTION_REPORT)
+            .with(ORDER_ID, "NONE")
+            .with(CL_ORD_ID, request.get(CL_ORD_ID).unwrap_or(""))
+            .with(EXEC_ID, exec_id)
+            .with(EXEC_TYPE, "8")
+            .with(ORD_STATUS, "8")
+            .with(SYMBOL, request.get(SYMBOL).unwrap_or(""))
+            .with(SIDE, request.get(SIDE).unwrap_or(""))
+            .with(LEAVES_QTY, 0)
+            .with(CUM_QTY, 0)
+            .with(AVG_PX, 0)
+            .with(TEXT, reason);
+        self.send(owner, report);
+    }
+
+    fn reject_cancel(&self, owner : &str, request : &FixMessage, reason : &str)
+    {
+        let response_to = if request.msg_type() == ORDER_CANCEL_REQUEST { "1" } else { "2" };
+        let reject = FixMessage::new(ORDER_CANCEL_REJECT)
+            .with(ORDER_ID, "NONE")
+            .with(CL_ORD_ID, request.get(CL_ORD_ID).unwrap_or(""))
+            .with(ORIG_CL_ORD_ID, request.get(ORIG_CL_ORD_ID).unwrap_or(""))
+            .with(ORD_STATUS, "8")
+            .with(CXL_REJ_RESPONSE_TO, response_to)
+            .with(TEXT, reason);
+        self.send(owner, reject);
+    }
+
+    fn remove_order(&mut self, order_id : u32)
+    {
+        if let Some(state) = self._orders.remove(&order_id)
+        {
+            self._cl_ord_ids.remove(&(state.owner, state.cl_ord_id));
+        }
+    }
+
+    /// on_timer expires the GTD orders reached by the engine clock, and the DAY
+    /// orders once the clock went past the session close
+    fn on_timer(&mut self)
+    {
+        let now = self._engine.clock().now();
+        let session_close = now / NANOS_PER_DAY * NANOS_PER_DAY + SESSION_CLOSE;
+        if self._last_timer < session_close && session_close <= now
+        {
+            self._engine.close_session();
+        }
+        self._last_timer = now;
+        self._engine.expire_orders();
+        self.dispatch_events();
+    }
+
+    /// new_order handles a NewOrderSingle: the order is acknowledged, matched
+    /// and the resulting fills are reported to both sides
+    fn new_order(&mut self, owner : &str, request : &FixMessage)
+    {
+        let cl_ord_id = match request.get(CL_ORD_ID)
+        {
+            Some(cl_ord_id) => cl_ord_id.to_string(),
+            None => return self.reject_order(owner, request, "ClOrdID is missing"),
+        };
+        if self._cl_ord_ids.contains_key(&(owner.to_string(), cl_ord_id.clone()))
+        {
+            return self.reject_order(owner, request, "Duplicate ClOrdID");
+        }
+
+        let symbol = request.get(SYMBOL).unwrap_or("").to_string();
+        if self._engine.book(&symbol).is_none()
+        {
+            return self.reject_order(owner, request, "Unknown symbol");
+        }
+
+        let side = match decode_side(request.get(SIDE))
+        {
+            Some(side) => side,
+            None => return self.reject_order(owner, request, "Unsupported side"),
+        };
+
+        let order_qty : u32 = request.get_as(ORDER_QTY).unwrap_or(0);
+        if order_qty == 0
+        {
+            return self.reject_order(owner, request, "OrderQty must be positive");
+        }
+
+        let (order_type, price) = match (request.get(ORD_TYPE), request.get_as::<f32>(PRICE))
+        {
+            (Some("1"), _) => (OrderType::Market, 0.0f32),
+            (Some("2"), Some(price)) if price > 0.0 => (OrderType::Limit, price),
+            (Some("2"), _) => return self.reject_order(owner, request, "Limit order without a valid price"),
+            _ => return self.reject_order(owner, request, "Unsupported OrdType"),
+        };
+
+        let time_in_force = match (request.get(TIME_IN_FORCE), request.get(EXPIRE_TIME))
+        {
+            (None, _) | (Some("1"), _) => TimeInForce::GoodTillCancel,
+            (Some("0"), _) => TimeInForce::Day,
+            (Some("6"), Some(expire_time)) => match parse_utc_timestamp(expire_time)
+            {
+                Some(time) => TimeInForce::GoodTillDate(time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64),
+                None => return self.reject_order(owner, request, "Invalid ExpireTime"),
+            },
+            (Some("6"), None) => return self.reject_order(owner, request, "GTD order without an ExpireTime"),
+            _ => return self.reject_order(owner, request, "Unsupported TimeInForce"),
+        };
+
+        let order_id = self._next_order_id;
+        self._next_order_id += 1;
+
+        let mut order = Order::new(order_id, side, price, order_qty);
+        order.account = request.get_as(ACCOUNT).unwrap_or(0);
+        order.order_type = order_type;
+        order.time_in_force = time_in_force;
+
+        if let Err(reason) = self._engine.controls().check_new_order(&symbol, order.account)
+        {
+            return self.reject_order(owner, request, reason);
+        }
+
+        let timestamp = self._engine.clock().now();
+        if let Err(reason) = self._risk.check(&symbol, &order, timestamp)
+        {
+            return self.reject_order(owner, request, reason.as_str());
+        }
+
+        // The order is known to the client only once the engine accepted it, its fills
+        // are reported after the ack from the events of the insert
+        let entered = order;
+        if let Err(reason) = self._engine.insert_order(&symbol, &mut order)
+        {
+            return self.reject_order(owner, request, reason);
+        }
+
+        self._cl_ord_ids.insert((owner.to_string(), cl_ord_id.clone()), order_id);
+        self._orders.insert(order_id, OrderState {
+            owner : owner.to_string(),
+            cl_ord_id,
+            symbol : symbol.clone(),
+            order : entered,
+            order_qty,
+            cum_qty : 0,
+            notional : 0.0,
+            pending_cl_ord_id : None });
+        let ack = self.execution_report(order_id, "0");
+        self.send(owner, ack);
+        self.dispatch_events();
+
+        // The unfilled quantity of a market order is not rested in the book
+        if order_type == OrderType::Market && self._orders.contains_key(&order_id)
+        {
+            let report = self.execution_report(order_id, "4");
+            self.send(owner, report);
+            self.remove_order(order_id);
+        }
+    }
+
+    fn find_order(&self, owner : &str, request : &FixMessage) -> Option<u32>
+    {
+        let orig_cl_ord_id = request.get(ORIG_CL_ORD_ID)?;
+        self._cl_ord_ids.get(&(owner.to_string(), orig_cl_ord_id.to_string())).copied()
+    }
+
+    /// check_order_identity checks that the Side and the Symbol of a cancel or a replace,
+    /// when given, are those of the original order
+    fn check_order_identity(&self, order_id : u32, request : &FixMessage) -> Result<(), &'static str>
+    {
+        let state = &self._orders[&order_id];
+        if request.get(SYMBOL).is_some_and(|symbol| symbol != state.symbol)
+        {
+            return Err("Symbol does not match the order");
+        }
+        if request.get(SIDE).is_some_and(|side| side != encode_side(state.order.side))
+        {
+            return Err("Side does not match the order");
+        }
+        Ok(())
+    }
+
+    /// cancel_order handles an OrderCancelRequest
+    fn cancel_order(&mut self, owner : &str, request : &FixMessage)
+    {
+        let order_id = match self.find_order(owner, request)
+        {
+            Some(order_id) => order_id,
+            None => return self.reject_cancel(owner, request, "Unknown order"),
+        };
+        if let Err(reason) = self.check_order_identity(order_id, request)
+        {
+            return self.reject_cancel(owner, request, reason);
+        }
+
+        let state = self._orders.get_mut(&order_id).unwrap();
+        state.pending_cl_ord_id = request.get(CL_ORD_ID).map(|cl_ord_id| cl_ord_id.to_string());
+        let (symbol, order) = (state.symbol.clone(), state.order);
+        if let Err(reason) = self._engine.cancel_order(&symbol, &order)
+        {
+            self._orders.get_mut(&order_id).unwrap().pending_cl_ord_id = None;
+            return self.reject_cancel(owner, request, reason);
+        }
+        self.dispatch_events();
+    }
+
+    /// replace_order handles an OrderCancelReplaceRequest, the new OrderQty
+    /// includes the quantity already executed
+    fn replace_order(&mut self, owner : &str, request : &FixMessage)
+    {
+        let order_id = match self.find_order(owner, request)
+        {
+            Some(order_id) => order_id,
+            None => return self.reject_cancel(owner, request, "Unknown order"),
+        };
+        if let Err(reason) = self.check_order_identity(order_id, request)
+        {
+            return self.reject_cancel(owner, request, reason);
+        }
+
+        let state = &self._orders[&order_id];
+        let order_qty : u32 = request.get_as(ORDER_QTY).unwrap_or(state.order_qty);
+        let price = match request.get(PRICE)
+        {
+            None => state.order.price,
+            Some(price) => match price.parse::<f32>()
+            {
+                Ok(price) if price.is_finite() && price > 0.0 => price,
+                _ => return self.reject_cancel(owner, request, "Invalid price"),
+            },
+        };
+        if order_qty <= state.cum_qty
+        {
+            return self.reject_cancel(owner, request, "OrderQty is not above the executed quantity");
+        }
+
+        let (symbol, order, leaves_qty) = (state.symbol.clone(), state.order, order_qty - state.cum_qty);
+        let replacement = Order { price, qty : leaves_qty, ..order };
+        if let Err(reason) = self._risk.check_replace(&symbol, &replacement, self._engine.clock().now())
+        {
+            return self.reject_cancel(owner, request, reason.as_str());
+        }
+        let state = self._orders.get_mut(&order_id).unwrap();
+        state.pending_cl_ord_id = request.get(CL_ORD_ID).map(|cl_ord_id| cl_ord_id.to_string());
+        if let Err(reason) = self._engine.amend_order(&symbol, &order, price, leaves_qty)
+        {
+            self._orders.get_mut(&order_id).unwrap().pending_cl_ord_id = None;
+            return self.reject_cancel(owner, request, reason);
+        }
+        self.dispatch_events();
+    }
+
+    /// dispatch_events turns the engine events into execution reports
+    /// for the sessions owning the orders
+    fn dispatch_events(&mut self)
+    {
+        let events = self._engine.drain_events();
+        let mut quoted : Option<&str> = None;
+        for (symbol, _) in events.iter()
+        {
+            if quoted != Some(symbol.as_str())
+            {
+                let (best_bid, best_ask) = self._engine.quotes(symbol);
+                self._risk.update_quotes(symbol, best_bid, best_ask);
+                quoted = Some(symbol);
+            }
+        }
+        for (symbol, event) in events
+        {
+            self._risk.on_event(&symbol, &event);
+            match event
+            {
+                Event::Traded(trade) =>
+                {
+                    for order_id in [trade.aggressive_id, trade.passive_id]
+                    {
+                        let state = match self._orders.get_mut(&order_id)
+                        {
+                            Some(state) => state,
+                            None => continue,
+                        };
+                        state.order.qty -= trade.qty;
+                        state.cum_qty += trade.qty;
+                        state.notional += trade.price as f64 * trade.qty as f64;
+
+                        let mut report = self.execution_report(order_id, "F");
+                        report.push(LAST_PX, trade.price);
+                        report.push(LAST_QTY, trade.qty);
+                        // The fee of the fill as an absolute amount, negative for a rebate
+                        report.push(COMMISSION, if order_id == trade.aggressive_id { trade.taker_fee } else { trade.maker_fee });
+                        report.push(COMM_TYPE, "3");
+                        report.push(TRD_MATCH_ID, trade.match_id);
+                        report.push(TRANSACT_TIME, utc_timestamp(UNIX_EPOCH + Duration::from_nanos(trade.timestamp)));
+                        let state = &self._orders[&order_id];
+                        self.send(&state.owner.clone(), report);
+                        if state.order.qty == 0
+                        {
+                            self.remove_order(order_id);
+                        }
+                    }
+                },
+                Event::Replaced { old: _, new } =>
+                {
+                    let state = match self._orders.get_mut(&new.id)
+                    {
+                        Some(state) => state,
+                        None => continue,
+                    };
+                    let orig_cl_ord_id = state.cl_ord_id.clone();
+                    if let Some(cl_ord_id) = state.pending_cl_ord_id.take()
+                    {
+                        state.cl_ord_id = cl_ord_id;
+                    }
+                    state.order = new;
+                    state.order_qty = state.cum_qty + new.qty;
+
+                    let key = (state.owner.clone(), state.cl_ord_id.clone());
+                    self._cl_ord_ids.remove(&(state.owner.clone(), orig_cl_ord_id.clone()));
+                    self._cl_ord_ids.insert(key, new.id);
+
+                    let mut report = self.execution_report(new.id, "5");
+                    report.push(ORIG_CL_ORD_ID, orig_cl_ord_id);
+                    self.send(&self._orders[&new.id].owner, report);
+                },
+                Event::Cancelled(order) =>
+                {
+                    let state = match self._orders.get_mut(&order.id)
+                    {
+                        Some(state) => state,
+                        None => continue,
+                    };
+                    let orig_cl_ord_id = state.cl_ord_id.clone();
+                    if let Some(cl_ord_id) = state.pending_cl_ord_id.take()
+                    {
+                        state.cl_ord_id = cl_ord_id;
+                    }
+
+                    let mut report = self.execution_report(order.id, "4");
+                    report.push(ORIG_CL_ORD_ID, orig_cl_ord_id.clone());
+                    let owner = self._orders[&order.id].owner.clone();
+                    self.send(&owner, report);
+                    self._cl_ord_ids.remove(&(owner, orig_cl_ord_id));
+                    self.remove_order(order.id);
+                },
+                Event::Expired(order) =>
+                {
+                    let owner = match self._orders.get(&order.id)
+                    {
+                        Some(state) => state.owner.clone(),
+                        None => continue,
+                    };
+                    let report = self.execution_report(order.id, "C");
+                    self.send(&owner, report);
+                    self.remove_order(order.id);
+                },
+                Event::Added(_) | Event::MassCancelled { .. } => {},
+            }
+        }
+    }
+}
+
+/// Session holds the state of a FIX session with a single counterparty
+struct Session
+{
+    /// Clock of the engine, giving the sending times
+    clock : Arc<dyn Clock>,
+    /// SenderCompID of the counterparty, set at logon
+    counterparty : Option<String>,
+    next_out_seq : u64,
+    next_in_seq : u64,
+    /// The last messages sent on the session as (sequence number, sending time, message),
+    /// kept for resends up to MAX_SENT_MESSAGES
+    sent : VecDeque<(u64, String, FixMessage)>,
+    heart_bt_int : u64,
+    last_received : Instant,
+    last_sent : Instant,
+    test_request_sent : bool,
+    resend_requested : bool,
+}
+
+impl Session
+{
+    fn new(clock : Arc<dyn Clock>) -> Session
+    {
+        Session {
+            clock,
+            counterparty : None,
+            next_out_seq : 1,
+            next_in_seq : 1,
+            sent : VecDeque::new(),
+            heart_bt_int : DEFAULT_HEART_BT_INT,
+            last_received : Instant::now(),
+            last_sent : Instant::now(),
+            test_request_sent : false,
+            resend_requested : false }
+    }
+
+    async fn send(&mut self, socket : &mut TcpStream, message : FixMessage) -> std::io::Result<()>
+    {
+        let sending_time = utc_timestamp(self.clock.system_time());
+        let target = self.counterparty.clone().unwrap_or_default();
+        let bytes = message.encode(ENGINE_COMP_ID, &target, self.next_out_seq, &sending_time);
+        if self.sent.len() == MAX_SENT_MESSAGES
+        {
+            self.sent.pop_front();
+        }
+        self.sent.push_back((self.next_out_seq, sending_time, message));
+        self.next_out_seq += 1;
+        self.last_sent = Instant::now();
+        socket.write_all(&bytes).await
+    }
+
+    /// resend writes again the messages in the requested range, application messages
+    /// are flagged as possible duplicates and session messages are replaced by gap fills,
+    /// as are the messages which are not kept anymore
+    async fn resend(&mut self, socket : &mut TcpStream, begin : u64, end : u64) -> std::io::Result<()>
+    {
+        let end = if end == 0 { self.next_out_seq - 1 } else { end.min(self.next_out_seq - 1) };
+        let target = self.counterparty.clone().unwrap_or_default();
+        let sending_time = utc_timestamp(self.clock.system_time());
+        let first_kept = self.sent.front().map(|(seq, _, _)| *seq).unwrap_or(self.next_out_seq);
+        let mut gap_start : Option<u64> = (begin < first_kept).then_some(begin);
+        let mut bytes = vec![];
+
+        for (seq, orig_sending_time, message) in self.sent.iter().filter(|(seq, _, _)| *seq >= begin && *seq <= end)
+        {
+            if is_admin(message.msg_type())
+            {
+                gap_start.get_or_insert(*seq);
+                continue;
+            }
+
+            if let Some(gap_seq) = gap_start.take()
+            {
+                let gap_fill = FixMessage::new(SEQUENCE_RESET).with(POSS_DUP_FLAG, "Y").with(GAP_FILL_FLAG, "Y").with(NEW_SEQ_NO, seq);
+                bytes.extend(gap_fill.encode(ENGINE_COMP_ID, &target, gap_seq, &sending_time));
+            }
+
+            let resent = message.clone().with(POSS_DUP_FLAG, "Y").with(ORIG_SENDING_TIME, orig_sending_time);
+            bytes.extend(resent.encode(ENGINE_COMP_ID, &target, *seq, &sending_time));
+        }
+
+        if let Some(gap_seq) = gap_start
+        {
+            let gap_fill = FixMessage::new(SEQUENCE_RESET).with(POSS_DUP_FLAG, "Y").with(GAP_FILL_FLAG, "Y").with(NEW_SEQ_NO, end + 1);
+            bytes.extend(gap_fill.encode(ENGINE_COMP_ID, &target, gap_seq, &sending_time));
+        }
+
+        self.last_sent = Instant::now();
+        socket.write_all(&bytes).await
+    }
+
+    /// on_message applies the session level rules to a received message and
+    /// forwards the application messages to the gateway
+    ///
+    /// # Return
+    ///
+    /// false when the session must be closed
+    async fn on_message(&mut self, socket : &mut TcpStream, message : FixMessage, gateway : &Arc<Mutex<Gateway>>,
+                        outgoing : &mpsc::UnboundedSender<FixMessage>) -> std::io::Result<bool>
+    {
+        self.last_received = Instant::now();
+        self.test_request_sent = false;
+        let msg_type = message.msg_type().to_string();
+        let seq = message.seq_num();
+
+        if self.counterparty.is_none()
+        {
+            if msg_type != LOGON || message.get(TARGET_COMP_ID) != Some(ENGINE_COMP_ID)
+            {
+                warn!(?message, "first message is not a valid Logon");
+                return Ok(false);
+            }
+
+            let counterparty = message.get(SENDER_COMP_ID).unwrap_or("").to_string();
+            let logged_on =
+            {
+                let mut gateway = gateway.lock().unwrap();
+                let logged_on = gateway._sessions.contains_key(&counterparty);
+                if !logged_on
+                {
+                    gateway._sessions.insert(counterparty.clone(), outgoing.clone());
+                }
+                logged_on
+            };
+            if logged_on
+            {
+                // The live session is left untouched, the counterparty of this one is never set
+                warn!(%counterparty, "rejected logon, the session is already logged on");
+                let logout = FixMessage::new(LOGOUT).with(TEXT, "Session is already logged on");
+                let bytes = logout.encode(ENGINE_COMP_ID, &counterparty, self.next_out_seq, &utc_timestamp(self.clock.system_time()));
+                socket.write_all(&bytes).await?;
+                return Ok(false);
+            }
+            self.heart_bt_int = message.get_as(HEART_BT_INT).unwrap_or(DEFAULT_HEART_BT_INT).clamp(1, MAX_HEART_BT_INT);
+            self.counterparty = Some(counterparty);
+            let logon = FixMessage::new(LOGON).with(ENCRYPT_METHOD, 0).with(HEART_BT_INT, self.heart_bt_int);
+            self.send(socket, logon).await?;
+        }
+
+        if msg_type == SEQUENCE_RESET
+        {
+            // The sequence is never moved back, in both the reset and the gap fill modes,
+            // so that the messages already processed are not accepted again
+            let new_seq : u64 = message.get_as(NEW_SEQ_NO).unwrap_or(self.next_in_seq);
+            if new_seq < self.next_in_seq
+            {
+                let reject = FixMessage::new(REJECT).with(REF_SEQ_NUM, seq)
+                    .with(TEXT, format!("NewSeqNo too low, expecting at least {}", self.next_in_seq));
+                self.send(socket, reject).await?;
+                return Ok(true);
+            }
+            self.next_in_seq = new_seq;
+            self.resend_requested = false;
+            return Ok(true);
+        }
+
+        if seq < self.next_in_seq
+        {
+            if message.get(POSS_DUP_FLAG) == Some("Y")
+            {
+                return Ok(true);
+            }
+            let logout = FixMessage::new(LOGOUT).with(TEXT, format!("MsgSeqNum too low, expecting {}", self.next_in_seq));
+            self.send(socket, logout).await?;
+            return Ok(false);
+        }
+
+        if seq > self.next_in_seq
+        {
+            if !self.resend_requested
+            {
+                let resend_request = FixMessage::new(RESEND_REQUEST).with(BEGIN_SEQ_NO, self.next_in_seq).with(END_SEQ_NO, 0);
+                self.send(socket, resend_request).await?;
+                self.resend_requested = true;
+            }
+
+            // Only the messages which do not depend on the sequence are processed out of order
+            if msg_type != LOGON && msg_type != RESEND_REQUEST && msg_type != LOGOUT
+            {
+                return Ok(true);
+            }
+        }
+        else
+        {
+            self.next_in_seq += 1;
+        }
+
+        let counterparty = self.counterparty.clone().unwrap_or_default();
+        match msg_type.as_str()
+        {
+            LOGON | HEARTBEAT => {},
+            TEST_REQUEST =>
+            {
+                let heartbeat = FixMessage::new(HEARTBEAT).with(TEST_REQ_ID, message.get(TEST_REQ_ID).unwrap_or(""));
+                self.send(socket, heartbeat).await?;
+            },
+            RESEND_REQUEST =>
+            {
+                let begin = message.get_as(BEGIN_SEQ_NO).unwrap_or(1);
+                let end = message.get_as(END_SEQ_NO).unwrap_or(0);
+                self.resend(socket, begin, end).await?;
+            },
+            LOGOUT =>
+            {
+                self.send(socket, FixMessage::new(LOGOUT)).await?;
+                return Ok(false);
+            },
+            NEW_ORDER_SINGLE | ORDER_CANCEL_REQUEST | ORDER_CANCEL_REPLACE_REQUEST =>
+            {
+                let span = info_span!("order_entry", session = %counterparty, msg_type = msg_type.as_str(), cl_ord_id = message.get(CL_ORD_ID),
+                                      symbol = message.get(SYMBOL));
+                let _entered = span.enter();
+                let mut gateway = gateway.lock().unwrap();
+                match msg_type.as_str()
+                {
+                    NEW_ORDER_SINGLE => gateway.new_order(&counterparty, &message),
+                    ORDER_CANCEL_REQUEST => gateway.cancel_order(&counterparty, &message),
+                    _ => gateway.replace_order(&counterparty, &message),
+                }
+            },
+            _ =>
+            {
+                let reject = FixMessage::new(REJECT).with(REF_SEQ_NUM, seq).with(TEXT, "Unsupported MsgType");
+                self.send(socket, reject).await?;
+            },
+        }
+        Ok(true)
+    }
+
+    /// on_timer sends the heartbeats and checks that the counterparty is alive
+    ///
+    /// # Return
+    ///
+    /// false when the counterparty has not been heard for too long
+    async fn on_timer(&mut self, socket : &mut TcpStream) -> std::io::Result<bool>
+    {
+        if self.counterparty.is_none()
+        {
+            return Ok(true);
+        }
+
+        let heart_bt_int = Duration::from_secs(self.heart_bt_int);
+        if self.last_received.elapsed() > heart_bt_int.saturating_mul(2)
+        {
+            return Ok(false);
+        }
+
+        if self.last_received.elapsed() > heart_bt_int.saturating_add(heart_bt_int / 5) && !self.test_request_sent
+        {
+            let test_request = FixMessage::new(TEST_REQUEST).with(TEST_REQ_ID, self.next_out_seq);
+            self.send(socket, test_request).await?;
+            self.test_request_sent = true;
+        }
+        else if self.last_sent.elapsed() >= heart_bt_int
+        {
+            self.send(socket, FixMessage::new(HEARTBEAT)).await?;
+        }
+        Ok(true)
+    }
+}
+
+async fn run_session(mut socket : TcpStream, gateway : Arc<Mutex<Gateway>>)
+{
+    let (outgoing, mut execution_reports) = mpsc::unbounded_channel();
+    let clock = gateway.lock().unwrap()._engine.clock().clone();
+    let mut session = Session::new(clock);
+    let mut buf = BytesMut::with_capacity(4096);
+    let mut timer = interval(Duration::from_secs(1));
+
+    loop
+    {
+        let alive = tokio::select!
+        {
+            read = socket.read_buf(&mut buf) =>
+            {
+                match read
+                {
+                    Ok(0) | Err(_) => false,
+                    Ok(_) =>
+                    {
+                        let mut alive = true;
+                        while alive
+                        {
+                            match FixMessage::decode(&mut buf)
+                            {
+                                Ok(Some(message)) =>
+                                {
+                                    alive = session.on_message(&mut socket, message, &gateway, &outgoing).await.unwrap_or(false);
+                                },
+                                Ok(None) => break,
+                                Err(reason) =>
+                                {
+                                    warn!(reason, "closing session, cannot decode message");
+                                    alive = false;
+                                },
+                            }
+                        }
+                        alive
+                    },
+                }
+            },
+            Some(report) = execution_reports.recv() => session.send(&mut socket, report).await.is_ok(),
+            _ = timer.tick() => session.on_timer(&mut socket).await.unwrap_or(false),
+        };
+
+        if !alive
+        {
+            break;
+        }
+    }
+
+    if let Some(counterparty) = session.counterparty
+    {
+        gateway.lock().unwrap()._sessions.remove(&counterparty);
+    }
+}
+
+/// expire_orders runs the expiry checks of the gateway every 100 milliseconds
+async fn expire_orders(gateway : Arc<Mutex<Gateway>>)
+{
+    let mut timer = interval(Duration::from_millis(100));
+    loop
+    {
+        timer.tick().await;
+        gateway.lock().unwrap().on_timer();
+    }
+}
+
+async fn serve(listener : TcpListener, gateway : Arc<Mutex<Gateway>>)
+{
+    tokio::spawn(expire_orders(gateway.clone()));
+    loop
+    {
+        let (socket, address) = match listener.accept().await
+        {
+            Ok(connection) => connection,
+            Err(e) =>
+            {
+                warn!(error = ?e, "failed to accept connection");
+                continue;
+            },
+        };
+        info!(%address, "accepted FIX connection");
+        tokio::spawn(run_session(socket, gateway.clone()));
+    }
+}
+
+/// default_fee_schedule pays a rebate to the makers and charges the takers
+fn default_fee_schedule() -> FeeSchedule
+{
+    FeeSchedule::flat(-0.0002, 0.0003, 0.01)
+}
+
+/// fix_gateway [address] [symbol...]
+#[tokio::main]
+async fn main()
+{
+    logging::init();
+    let args : Vec<String> = std::env::args().skip(1).collect();
+    let address = args.first().map(|address| address.as_str()).unwrap_or(DEFAULT_ADDRESS);
+    let mut symbols : Vec<&str> = args.iter().skip(1).map(|symbol| symbol.as_str()).collect();
+    if symbols.is_empty()
+    {
+        symbols.push("TSLA");
+    }
+
+    let listener = TcpListener::bind(address).await.unwrap();
+    info!(address, ?symbols, "FIX gateway listening");
+    serve(listener, Arc::new(Mutex::new(Gateway::new(&symbols, RiskLimits::exchange_defaults(), default_fee_schedule(), Arc::new(MonotonicClock::new()))))).await;
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use matching_engine::clock::ManualClock;
+    use tokio::time::timeout;
+
+    /// Time of the clock of the test gateway, 2024-01-02 10:11:12.123 UTC
+    const NOW : u64 = 1_704_190_272_123_000_000;
+
+    /// TestClient is a minimal FIX initiator used to drive the gateway
+    struct TestClient
+    {
+        socket : TcpStream,
+        buf : BytesMut,
+        comp_id : String,
+        next_seq : u64,
+    }
+
+    impl TestClient
+    {
+        async fn connect(address : std::net::SocketAddr, comp_id : &str) -> TestClient
+        {
+            let socket = TcpStream::connect(address).await.unwrap();
+            let mut client = TestClient { socket, buf : BytesMut::new(), comp_id : comp_id.to_string(), next_seq : 1 };
+            client.send(FixMessage::new(LOGON).with(ENCRYPT_METHOD, 0).with(HEART_BT_INT, 30)).await;
+            assert_eq!(client.recv().await.msg_type(), LOGON);
+            client
+        }
+
+        async fn send_with_seq(&mut self, message : FixMessage, seq : u64)
+        {
+            let bytes = message.encode(&self.comp_id, ENGINE_COMP_ID, seq, "20240102-10:11:12.123");
+            self.socket.write_all(&bytes).await.unwrap();
+        }
+
+        async fn send(&mut self, message : FixMessage)
+        {
+            let seq = self.next_seq;
+            self.next_seq += 1;
+            self.send_with_seq(message, seq).await;
+        }
+
+        async fn recv(&mut self) -> FixMessage
+        {
+            loop
+            {
+                if let Some(message) = FixMessage::decode(&mut self.buf).unwrap()
+                {
+                    return message;
+                }
+                let read = timeout(Duration::from_secs(5), self.socket.read_buf(&mut self.buf)).await.unwrap().unwrap();
+                assert!(read > 0, "connection closed");
+            }
+        }
+    }
+
+    async fn start_gateway() -> std::net::SocketAddr
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Arc::new(Mutex::new(Gateway::new(&["TSLA"], RiskLimits { max_order_qty : Some(1000), ..Default::default() },
+                                                      FeeSchedule::flat(-0.0001, 0.0002, 0.0), Arc::new(ManualClock::new(NOW)))))));
+        address
+    }
+
+    fn new_order_single(cl_ord_id : &str, side : &str, qty : u32, price : f32) -> FixMessage
+    {
+        FixMessage::new(NEW_ORDER_SINGLE)
+            .with(CL_ORD_ID, cl_ord_id)
+            .with(ACCOUNT, 7)
+            .with(SYMBOL, "TSLA")
+            .with(SIDE, side)
+            .with(ORDER_QTY, qty)
+            .with(ORD_TYPE, "2")
+            .with(PRICE, price)
+    }
+
+    #[tokio::test]
+    async fn new_orders_are_acknowledged_and_filled()
+    {
+        let address = start_gateway().await;
+        let mut buyer = TestClient::connect(address, "BUYER").await;
+        let mut seller = TestClient::connect(address, "SELLER").await;
+
+        buyer.send(new_order_single("b-1", "1", 100, 122.5f32)).await;
+        let ack = buyer.recv().await;
+        assert_eq!(ack.msg_type(), EXECUTION_REPORT);
+        assert_eq!(ack.get(EXEC_TYPE), Some("0"));
+        assert_eq!(ack.get(CL_ORD_ID), Some("b-1"));
+        assert_eq!(ack.get(LEAVES_QTY), Some("100"));
+
+        seller.send(new_order_single("s-1", "2", 30, 122.0f32)).await;
+        assert_eq!(seller.recv().await.get(EXEC_TYPE), Some("0"));
+        let seller_fill = seller.recv().await;
+        assert_eq!(seller_fill.get(EXEC_TYPE), Some("F"));
+        assert_eq!(seller_fill.get(ORD_STATUS), Some("2"));
+        assert_eq!(seller_fill.get_as::<f32>(LAST_PX), Some(122.5f32));
+        assert_eq!(seller_fill.get(LAST_QTY), Some("30"));
+        assert_eq!(seller_fill.get(COMM_TYPE), Some("3"));
+        assert!((seller_fill.get_as::<f64>(COMMISSION).unwrap() - 0.735).abs() < 1e-9);
+
+        let buyer_fill = buyer.recv().await;
+        assert_eq!(buyer_fill.get(CL_ORD_ID), Some("b-1"));
+        assert_eq!(buyer_fill.get(ORD_STATUS), Some("1"));
+        assert_eq!(buyer_fill.get(CUM_QTY), Some("30"));
+        assert_eq!(buyer_fill.get(LEAVES_QTY), Some("70"));
+        assert!((buyer_fill.get_as::<f64>(COMMISSION).unwrap() + 0.3675).abs() < 1e-9);
+        // Both sides of the fill share the match id
+        assert_eq!(buyer_fill.get(TRD_MATCH_ID), seller_fill.get(TRD_MATCH_ID));
+        assert_eq!(buyer_fill.get(TRANSACT_TIME), Some("20240102-10:11:12.123"));
+        assert_eq!(buyer_fill.get(SENDING_TIME), Some("20240102-10:11:12.123"));
+    }
+
+    #[tokio::test]
+    async fn orders_can_be_replaced_and_cancelled()
+    {
+        let address = start_gateway().await;
+        let mut client = TestClient::connect(address, "CLIENT").await;
+
+        client.send(new_order_single("c-1", "1", 100, 122.5f32)).await;
+        assert_eq!(client.recv().await.get(EXEC_TYPE), Some("0"));
+
+        client.send(FixMessage::new(ORDER_CANCEL_REPLACE_REQUEST)
+            .with(ORIG_CL_ORD_ID, "c-1").with(CL_ORD_ID, "c-2").with(SYMBOL, "TSLA")
+            .with(SIDE, "1").with(ORDER_QTY, 50).with(ORD_TYPE, "2").with(PRICE, 122.4f32)).await;
+        let replaced = client.recv().await;
+        assert_eq!(replaced.get(EXEC_TYPE), Some("5"));
+        assert_eq!(replaced.get(CL_ORD_ID), Some("c-2"));
+        assert_eq!(replaced.get(ORIG_CL_ORD_ID), Some("c-1"));
+        assert_eq!(replaced.get(LEAVES_QTY), Some("50"));
+        assert_eq!(replaced.get_as::<f32>(PRICE), Some(122.4f32));
+
+        client.send(FixMessage::new(ORDER_CANCEL_REQUEST)
+            .with(ORIG_CL_ORD_ID, "c-1").with(CL_ORD_ID, "c-3").with(SYMBOL, "TSLA").with(SIDE, "1")).await;
+        let reject = client.recv().await;
+        assert_eq!(reject.msg_type(), ORDER_CANCEL_REJECT);
+        assert_eq!(reject.get(CXL_REJ_RESPONSE_TO), Some("1"));
+
+        client.send(FixMessage::new(ORDER_CANCEL_REQUEST)
+            .with(ORIG_CL_ORD_ID, "c-2").with(CL_ORD_ID, "c-4").with(SYMBOL, "TSLA").with(SIDE, "1")).await;
+        let cancelled = client.recv().await;
+        assert_eq!(cancelled.get(EXEC_TYPE), Some("4"));
+        assert_eq!(cancelled.get(ORD_STATUS), Some("4"));
+        assert_eq!(cancelled.get(CL_ORD_ID), Some("c-4"));
+        assert_eq!(cancelled.get(ORIG_CL_ORD_ID), Some("c-2"));
+    }
+
+    #[tokio::test]
+    async fn replaces_must_match_the_order()
+    {
+        let address = start_gateway().await;
+        let mut client = TestClient::connect(address, "CLIENT").await;
+        client.send(new_order_single("c-1", "1", 100, 122.5f32)).await;
+        assert_eq!(client.recv().await.get(EXEC_TYPE), Some("0"));
+
+        let replace = |cl_ord_id : &str, symbol : &str, side : &str, price : &str| FixMessage::new(ORDER_CANCEL_REPLACE_REQUEST)
+            .with(ORIG_CL_ORD_ID, "c-1").with(CL_ORD_ID, cl_ord_id).with(SYMBOL, symbol)
+            .with(SIDE, side).with(ORDER_QTY, 50).with(ORD_TYPE, "2").with(PRICE, price);
+        for (request, reason) in [(replace("c-2", "TSLA", "1", "NaN"), "Invalid price"),
+                                  (replace("c-3", "TSLA", "1", "-1"), "Invalid price"),
+                                  (replace("c-4", "AAPL", "1", "122.4"), "Symbol does not match the order"),
+                                  (replace("c-5", "TSLA", "2", "122.4"), "Side does not match the order")]
+        {
+            client.send(request).await;
+            let reject = client.recv().await;
+            assert_eq!(reject.msg_type(), ORDER_CANCEL_REJECT);
+            assert_eq!(reject.get(TEXT), Some(reason));
+        }
+
+        // The replacement goes through the pre-trade checks
+        client.send(FixMessage::new(ORDER_CANCEL_REPLACE_REQUEST)
+            .with(ORIG_CL_ORD_ID, "c-1").with(CL_ORD_ID, "c-8").with(SYMBOL, "TSLA")
+            .with(SIDE, "1").with(ORDER_QTY, 1001).with(ORD_TYPE, "2").with(PRICE, 122.4f32)).await;
+        let reject = client.recv().await;
+        assert_eq!(reject.msg_type(), ORDER_CANCEL_REJECT);
+        assert_eq!(reject.get(TEXT), Some("Order quantity exceeds the limit"));
+
+        client.send(FixMessage::new(ORDER_CANCEL_REQUEST)
+            .with(ORIG_CL_ORD_ID, "c-1").with(CL_ORD_ID, "c-6").with(SYMBOL, "TSLA").with(SIDE, "2")).await;
+        assert_eq!(client.recv().await.get(TEXT), Some("Side does not match the order"));
+        client.send(replace("c-7", "TSLA", "1", "122.4")).await;
+        assert_eq!(client.recv().await.get(EXEC_TYPE), Some("5"));
+    }
+
+    #[tokio::test]
+    async fn second_logon_of_a_live_session_is_rejected()
+    {
+        let address = start_gateway().await;
+        let mut client = TestClient::connect(address, "CLIENT").await;
+
+        let mut intruder = TestClient { socket : TcpStream::connect(address).await.unwrap(), buf : BytesMut::new(), comp_id : "CLIENT".to_string(), next_seq : 1 };
+        intruder.send(FixMessage::new(LOGON).with(ENCRYPT_METHOD, 0).with(HEART_BT_INT, 30)).await;
+        let logout = intruder.recv().await;
+        assert_eq!(logout.msg_type(), LOGOUT);
+        assert_eq!(logout.get(TEXT), Some("Session is already logged on"));
+
+        // The reports still reach the live session
+        client.send(new_order_single("c-1", "1", 100, 122.5f32)).await;
+        assert_eq!(client.recv().await.get(EXEC_TYPE), Some("0"));
+    }
+
+    #[tokio::test]
+    async fn heartbeat_interval_is_bounded_at_logon()
+    {
+        let address = start_gateway().await;
+        let socket = TcpStream::connect(address).await.unwrap();
+        let mut client = TestClient { socket, buf : BytesMut::new(), comp_id : "CLIENT".to_string(), next_seq : 1 };
+        client.send(FixMessage::new(LOGON).with(ENCRYPT_METHOD, 0).with(HEART_BT_INT, u64::MAX)).await;
+        let logon = client.recv().await;
+        assert_eq!(logon.msg_type(), LOGON);
+        assert_eq!(logon.get(HEART_BT_INT), Some("3600"));
+
+        // The session outlives the next ticks of its timer
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        client.send(FixMessage::new(TEST_REQUEST).with(TEST_REQ_ID, "ping")).await;
+        assert_eq!(client.recv().await.msg_type(), HEARTBEAT);
+    }
+
+    #[tokio::test]
+    async fn invalid_orders_are_rejected()
+    {
+        let address = start_gateway().await;
+        let mut client = TestClient::connect(address, "CLIENT").await;
+
+        client.send(FixMessage::new(NEW_ORDER_SINGLE).with(CL_ORD_ID, "c-1").with(SYMBOL, "AAPL")
+            .with(SIDE, "1").with(ORDER_QTY, 10).with(ORD_TYPE, "2").with(PRICE, 122.5f32)).await;
+        let reject = client.recv().await;
+        assert_eq!(reject.get(EXEC_TYPE), Some("8"));
+        assert_eq!(reject.get(TEXT), Some("Unknown symbol"));
+
+        client.send(FixMessage::new(NEW_ORDER_SINGLE).with(CL_ORD_ID, "c-2").with(SYMBOL, "TSLA")
+            .with(SIDE, "1").with(ORDER_QTY, 10).with(ORD_TYPE, "2")).await;
+        assert_eq!(client.recv().await.get(TEXT), Some("Limit order without a valid price"));
+
+        // The pre-trade checks reject the order before it reaches the book
+        client.send(new_order_single("c-3", "1", 1001, 122.5f32)).await;
+        let reject = client.recv().await;
+        assert_eq!(reject.get(EXEC_TYPE), Some("8"));
+        assert_eq!(reject.get(TEXT), Some("Order quantity exceeds the limit"));
+    }
+
+    #[tokio::test]
+    async fn orders_rejected_by_the_engine_are_not_acknowledged()
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut gateway = Gateway::new(&[], RiskLimits::default(), FeeSchedule::default(), Arc::new(ManualClock::new(NOW)));
+        gateway._engine.add_ladder_symbol("TSLA", (100.0, 150.0, 0.1)).unwrap();
+        tokio::spawn(serve(listener, Arc::new(Mutex::new(gateway))));
+        let mut client = TestClient::connect(address, "CLIENT").await;
+
+        // The price is off the ticks of the book, the order is never known to the client
+        client.send(new_order_single("c-1", "1", 10, 122.25f32)).await;
+        let reject = client.recv().await;
+        assert_eq!((reject.get(EXEC_TYPE), reject.get(ORD_STATUS)), (Some("8"), Some("8")));
+        assert_eq!(reject.get(TEXT), Some("Price is outside of the price band of the OrderBook"));
+
+        client.send(FixMessage::new(ORDER_CANCEL_REQUEST).with(CL_ORD_ID, "c-2").with(ORIG_CL_ORD_ID, "c-1")).await;
+        assert_eq!(client.recv().await.get(TEXT), Some("Unknown order"));
+        client.send(new_order_single("c-1", "1", 10, 122.2f32)).await;
+        assert_eq!(client.recv().await.get(EXEC_TYPE), Some("0"));
+    }
+
+    #[tokio::test]
+    async fn gtd_orders_are_expired()
+    {
+        let address = start_gateway().await;
+        let mut client = TestClient::connect(address, "CLIENT").await;
+
+        client.send(new_order_single("c-1", "1", 10, 122.5f32).with(TIME_IN_FORCE, "6")).await;
+        assert_eq!(client.recv().await.get(TEXT), Some("GTD order without an ExpireTime"));
+
+        // The expiry time is already reached by the clock of the gateway
+        client.send(new_order_single("c-2", "1", 10, 122.5f32).with(TIME_IN_FORCE, "6").with(EXPIRE_TIME, "20240102-10:11:12.000")).await;
+        assert_eq!(client.recv().await.get(EXEC_TYPE), Some("0"));
+        let expired = client.recv().await;
+        assert_eq!(expired.get(CL_ORD_ID), Some("c-2"));
+        assert_eq!(expired.get(EXEC_TYPE), Some("C"));
+        assert_eq!(expired.get(ORD_STATUS), Some("C"));
+        assert_eq!(expired.get(LEAVES_QTY), Some("0"));
+    }
+
+    #[tokio::test]
+    async fn session_answers_test_and_resend_requests()
+    {
+        let address = start_gateway().await;
+        let mut client = TestClient::connect(address, "CLIENT").await;
+
+        client.send(FixMessage::new(TEST_REQUEST).with(TEST_REQ_ID, "ping")).await;
+        let heartbeat = client.recv().await;
+        assert_eq!(heartbeat.msg_type(), HEARTBEAT);
+        assert_eq!(heartbeat.get(TEST_REQ_ID), Some("ping"));
+        assert_eq!(heartbeat.seq_num(), 2);
+
+        client.send(new_order_single("c-1", "1", 100, 122.5f32)).await;
+        assert_eq!(client.recv().await.seq_num(), 3);
+
+        client.send(FixMessage::new(RESEND_REQUEST).with(BEGIN_SEQ_NO, 1).with(END_SEQ_NO, 0)).await;
+        let gap_fill = client.recv().await;
+        assert_eq!(gap_fill.msg_type(), SEQUENCE_RESET);
+        assert_eq!(gap_fill.seq_num(), 1);
+        assert_eq!(gap_fill.get(GAP_FILL_FLAG), Some("Y"));
+        assert_eq!(gap_fill.get(NEW_SEQ_NO), Some("3"));
+        let resent = client.recv().await;
+        assert_eq!(resent.msg_type(), EXECUTION_REPORT);
+        assert_eq!(resent.seq_num(), 3);
+        assert_eq!(resent.get(POSS_DUP_FLAG), Some("Y"));
+        assert_eq!(resent.get(CL_ORD_ID), Some("c-1"));
+    }
+
+    #[tokio::test]
+    async fn sequence_gap_triggers_resend_request()
+    {
+        let address = start_gateway().await;
+        let mut client = TestClient::connect(address, "CLIENT").await;
+
+        // Skip sequence number 2, the order must not be processed until the gap is filled
+        client.send_with_seq(new_order_single("c-1", "1", 100, 122.5f32), 3).await;
+        let resend_request = client.recv().await;
+        assert_eq!(resend_request.msg_type(), RESEND_REQUEST);
+        assert_eq!(resend_request.get(BEGIN_SEQ_NO), Some("2"));
+
+        client.send_with_seq(FixMessage::new(SEQUENCE_RESET).with(GAP_FILL_FLAG, "Y").with(NEW_SEQ_NO, 3), 2).await;
+        client.send_with_seq(new_order_single("c-1", "1", 100, 122.5f32).with(POSS_DUP_FLAG, "Y"), 3).await;
+        let ack = client.recv().await;
+        assert_eq!(ack.get(EXEC_TYPE), Some("0"));
+        assert_eq!(ack.get(CL_ORD_ID), Some("c-1"));
+    }
+
+    #[tokio::test]
+    async fn sequence_reset_cannot_lower_the_sequence()
+    {
+        let address = start_gateway().await;
+        let mut client = TestClient::connect(address, "CLIENT").await;
+        client.send(new_order_single("c-1", "1", 100, 122.5f32)).await;
+        assert_eq!(client.recv().await.get(EXEC_TYPE), Some("0"));
+
+        // Neither mode moves the expected sequence number back to the processed messages
+        for gap_fill in ["N", "Y"]
+        {
+            client.send(FixMessage::new(SEQUENCE_RESET).with(GAP_FILL_FLAG, gap_fill).with(NEW_SEQ_NO, 2)).await;
+            let reject = client.recv().await;
+            assert_eq!(reject.msg_type(), REJECT);
+            assert_eq!(reject.get(TEXT), Some("NewSeqNo too low, expecting at least 3"));
+        }
+        client.send_with_seq(new_order_single("c-1", "1", 100, 122.5f32), 2).await;
+        let logout = client.recv().await;
+        assert_eq!(logout.msg_type(), LOGOUT);
+        assert_eq!(logout.get(TEXT), Some("MsgSeqNum too low, expecting 3"));
+    }
+}
@@ -1,70 +1,733 @@
-use matching_engine::order_book::OrderBook;
-use matching_engine::data_types::{Order, Side};
-use tokio::{net::{TcpListener, TcpStream}, io::AsyncReadExt};
-use tokio::io::{self, AsyncWriteExt};
-use bytes::{BytesMut, Buf};
-use bytes::BufMut;
-use std::io::Cursor;
-use byteorder::{NetworkEndian};
+use matching_engine::admin::{AdminCommand, Journal};
+use matching_engine::clock::{Clock, MonotonicClock};
+use matching_engine::engine::Engine;
+use matching_engine::data_types::{Order, Side, Symbol};
+use matching_engine::itch::{self, ItchPublisher};
+use matching_engine::market_data::{self, FeedPublisher, RecoveryStore};
+use matching_engine::logging;
+use matching_engine::metrics::{self, BookGauges, Metrics, Stage};
+use matching_engine::order_entry::FrameBuffer;
+use matching_engine::rest::{self, Reply, RestRequest, Route};
+use matching_engine::positions::MarkMethod;
+use matching_engine::risk::{RiskChecker, RiskLimits};
+use matching_engine::sbe::{self, Message};
+use matching_engine::spsc::{self, Consumer, Producer, WaitStrategy};
+use tokio::{net::{TcpListener, TcpStream}, io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader}};
+use tokio::runtime::Handle;
+use tokio::sync::{broadcast, oneshot, mpsc::{unbounded_channel, UnboundedSender}};
+use bytes::{Buf, Bytes, BytesMut};
+use std::fs::File;
+use std::io::ErrorKind;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, info_span, trace, warn};
 
 
+const SYMBOL : &str = "TSLA";
+const ADMIN_JOURNAL : &str = "admin.journal";
+/// Number of slots of the rings between the order connections, the matching thread and the publisher
+const RING_CAPACITY : usize = 4096;
+/// Size of the receive buffer of an order connection
+const RECEIVE_BUFFER : usize = 64 * 1024;
+/// Longest wait of the matching thread for orders before it serves the admin commands
+const IDLE_WAIT : Duration = Duration::from_millis(10);
+/// Period of the check of the GTD orders against the engine clock
+const EXPIRY_INTERVAL : Duration = Duration::from_millis(100);
+/// Number of times a connection yields to the other tasks while the ring is full before it sleeps
+const FULL_RING_YIELDS : u32 = 64;
+/// Sleep of a connection between two attempts once the ring stayed full
+const FULL_RING_BACKOFF : Duration = Duration::from_micros(100);
+
+/// AdminRequest is an operator command sent to the matching thread with the channel of its response
+type AdminRequest = (AdminCommand, oneshot::Sender<Result<usize, &'static str>>);
+
+/// Request is an order entry message decoded by a connection for the matching thread
+#[derive(Debug)]
+enum Request
+{
+    New { symbol : Symbol, order : Order },
+    Cancel { symbol : Symbol, order_id : u32 },
+    Replace { symbol : Symbol, order_id : u32, price : f32, qty : u32 },
+    /// The start of an SBE connection, the execution reports of its orders are sent to the channel
+    Session(UnboundedSender<Bytes>),
+}
+
+/// SbeSession is the SBE connection currently entering orders
+struct SbeSession
+{
+    orders : sbe::Session,
+    reports : UnboundedSender<Bytes>,
+}
+
+/// Publication is a slot of the ring to the publisher: the ITCH messages and the SBE
+/// market data resulting from a command
+#[derive(Default)]
+struct Publication
+{
+    itch : Vec<itch::SequencedMessage>,
+    sbe : BytesMut,
+}
+
+/// Exchange is the state of the matching thread. The requests come from the connections
+/// through a ring, where None marks the end of a connection, and the resulting market
+/// data leaves through another ring to the publisher, so that the feeds stay in
+/// sequence. Every timestamp is read from the clock of the engine
+struct Exchange
+{
+    engine : Engine,
+    risk : RiskChecker,
+    publisher : ItchPublisher,
+    journal : Journal,
+    messages : Producer<Publication>,
+    metrics : Arc<Metrics>,
+    /// Depth gauges of the traded symbol
+    book_gauges : Arc<BookGauges>,
+    /// The connection entering orders when it speaks SBE, the binary connections get no reports
+    session : Option<SbeSession>,
+    /// Execution reports of the current request
+    reports : BytesMut,
+}
+
+impl Exchange
+{
+    fn clock(&self) -> &Arc<dyn Clock>
+    {
+        self.engine.clock()
+    }
+
+    /// itch_timestamp returns the current time as an ITCH timestamp
+    fn itch_timestamp(&self) -> u64
+    {
+        itch::nanos_since_midnight(self.clock().system_time())
+    }
+
+    /// publish hands the messages over to the publisher, the buffers of the slot are reused
+    fn publish(&mut self, mut messages : Vec<itch::SequencedMessage>, market_data : &[u8])
+    {
+        if !messages.is_empty() || !market_data.is_empty()
+        {
+            self.messages.publish(|slot|
+            {
+                slot.itch.clear();
+                slot.itch.append(&mut messages);
+                slot.sbe.clear();
+                slot.sbe.extend_from_slice(market_data);
+            });
+        }
+    }
+
+    /// publish_events publishes the events of the last command, reports them to the SBE
+    /// session and keeps the risk state up to date
+    fn publish_events(&mut self)
+    {
+        let events = self.engine.drain_events();
+        let now = self.clock().now();
+        let mut quoted : Option<&str> = None;
+        for (symbol, event) in events.iter()
+        {
+            if quoted != Some(symbol.as_str())
+            {
+                let (best_bid, best_ask) = self.engine.quotes(symbol);
+                self.risk.update_quotes(symbol, best_bid, best_ask);
+                quoted = Some(symbol);
+            }
+            self.risk.on_event(symbol, event);
+            self.metrics.on_event(event);
+            if symbol == SYMBOL
+            {
+                self.book_gauges.on_event(event);
+            }
+            if let Some(session) = self.session.as_mut()
+            {
+                session.orders.on_event(event, now, &mut self.reports);
+            }
+        }
+        let (bid_levels, ask_levels) = self.engine.book(SYMBOL).unwrap().level_counts();
+        self.book_gauges.set_levels(bid_levels, ask_levels);
+        let timestamp = self.itch_timestamp();
+        let messages = self.publisher.publish(timestamp, &events);
+        let updates = sbe::book_updates(events.iter().filter(|(symbol, _)| symbol == SYMBOL).map(|(_, event)| event));
+        let mut market_data = BytesMut::new();
+        if !updates.is_empty()
+        {
+            sbe::MarketDataIncrementalRefresh::encode(now, SYMBOL, &updates, &mut market_data);
+        }
+        self.publish(messages, &market_data);
+    }
+
+    /// reject logs the reject of a request and reports it to the SBE session
+    fn reject(&mut self, order_id : u32, symbol : Symbol, side : Side, reason : &str)
+    {
+        self.metrics.rejects().inc();
+        info!(order_id, %symbol, reason, "rejected order");
+        let now = self.clock().now();
+        if let Some(session) = self.session.as_mut()
+        {
+            session.orders.reject(order_id, symbol, side, reason, now, &mut self.reports);
+        }
+    }
+
+    fn submit(&mut self, symbol : Symbol, mut order : Order)
+    {
+        self.metrics.orders().inc();
+        if symbol.as_str() != SYMBOL
+        {
+            return self.reject(order.id, symbol, order.side, "Unknown symbol");
+        }
+        if self.session.as_ref().is_some_and(|session| session.orders.order(order.id).is_some())
+        {
+            return self.reject(order.id, symbol, order.side, "Duplicate order id");
+        }
+        let timestamp = self.clock().now();
+        let start = Instant::now();
+        let checked = self.risk.check(SYMBOL, &order, timestamp);
+        self.metrics.latency(Stage::Risk).observe(start.elapsed());
+        if let Err(reason) = checked
+        {
+            return self.reject(order.id, symbol, order.side, &reason.to_string());
+        }
+
+        let entered = order;
+        let start = Instant::now();
+        let inserted = self.engine.insert_order(SYMBOL, &mut order);
+        self.metrics.latency(Stage::Match).observe(start.elapsed());
+        if let Err(reason) = inserted
+        {
+            return self.reject(order.id, symbol, order.side, reason);
+        }
+        // The acknowledgment comes before the fills
+        if let Some(session) = self.session.as_mut()
+        {
+            session.orders.accept(symbol, &entered, timestamp, &mut self.reports);
+        }
+        self.publish_events();
+        if let Some(session) = self.session.as_mut()
+        {
+            session.orders.complete(&order, timestamp, &mut self.reports);
+        }
+    }
+
+    /// cancel cancels an order of the SBE session
+    fn cancel(&mut self, symbol : Symbol, order_id : u32)
+    {
+        let order = match self.session.as_ref().and_then(|session| session.orders.order(order_id))
+        {
+            Some(order) if symbol.as_str() == SYMBOL => *order,
+            _ => return self.reject(order_id, symbol, Side::Buy, "Unknown order"),
+        };
+        match self.engine.cancel_order(SYMBOL, &order)
+        {
+            Ok(_) => self.publish_events(),
+            Err(reason) => self.reject(order_id, symbol, order.side, reason),
+        }
+    }
+
+    /// replace changes price and quantity of an order of the SBE session
+    fn replace(&mut self, symbol : Symbol, order_id : u32, price : f32, qty : u32)
+    {
+        let order = match self.session.as_ref().and_then(|session| session.orders.order(order_id))
+        {
+            Some(order) if symbol.as_str() == SYMBOL => *order,
+            _ => return self.reject(order_id, symbol, Side::Buy, "Unknown order"),
+        };
+        match self.engine.amend_order(SYMBOL, &order, price, qty)
+        {
+            Ok(_) => self.publish_events(),
+            Err(reason) => self.reject(order_id, symbol, order.side, reason),
+        }
+    }
+
+    /// handle applies a request of a connection and sends the resulting reports to the SBE session
+    fn handle(&mut self, request : Request)
+    {
+        match request
+        {
+            Request::New { symbol, order } =>
+            {
+                let span = info_span!("submit", order_id = order.id, %symbol).entered();
+                self.submit(symbol, order);
+                drop(span);
+            },
+            Request::Cancel { symbol, order_id } => self.cancel(symbol, order_id),
+            Request::Replace { symbol, order_id, price, qty } => self.replace(symbol, order_id, price, qty),
+            Request::Session(reports) => self.session = Some(SbeSession { orders : sbe::Session::new(), reports }),
+        }
+        self.send_reports();
+    }
+
+    /// send_reports sends the execution reports of the last command to the SBE session
+    fn send_reports(&mut self)
+    {
+        if let Some(session) = self.session.as_ref().filter(|_| !self.reports.is_empty())
+        {
+            // The connection may be gone already
+            let _ = session.reports.send(self.reports.split().freeze());
+        }
+        self.reports.clear();
+    }
+
+    /// admin applies, journals and publishes an operator command
+    ///
+    /// # Return
+    ///
+    /// The number of orders cancelled by the command
+    fn admin(&mut self, command : &AdminCommand) -> Result<usize, &'static str>
+    {
+        let cancelled = self.engine.admin(command)?;
+        let timestamp = self.itch_timestamp();
+        if let Err(e) = self.journal.record(timestamp, command)
+        {
+            warn!(%command, error = ?e, "failed to journal the admin command");
+        }
+
+        // The account halts are private, they are not published on the market data feed
+        let messages = match command
+        {
+            AdminCommand::HaltSymbol(symbol) => self.publisher.trading_action(timestamp, symbol, true),
+            AdminCommand::ResumeSymbol(symbol) => self.publisher.trading_action(timestamp, symbol, false),
+            AdminCommand::DisableNewOrders => vec![self.publisher.system_event(timestamp, itch::NEW_ORDERS_DISABLED)],
+            AdminCommand::EnableNewOrders => vec![self.publisher.system_event(timestamp, itch::NEW_ORDERS_ENABLED)],
+            AdminCommand::KillSwitch => vec![self.publisher.system_event(timestamp, itch::KILL_SWITCH)],
+            AdminCommand::CloseSession => vec![self.publisher.system_event(timestamp, itch::END_OF_MARKET_HOURS)],
+            AdminCommand::HaltAccount(_) | AdminCommand::ResumeAccount(_) => vec![],
+        };
+        self.publish(messages, &[]);
+        self.publish_events();
+        self.send_reports();
+        Ok(cancelled)
+    }
+
+    /// expire_orders removes the GTD orders reached by the clock and publishes their deletes
+    fn expire_orders(&mut self)
+    {
+        if self.engine.expire_orders() > 0
+        {
+            self.publish_events();
+            self.send_reports();
+        }
+    }
+
+    /// summary logs the book and the P&L of the accounts at the end of a connection
+    fn summary(&self)
+    {
+        self.engine.book(SYMBOL).unwrap().summary();
+        for account in self.risk.positions().accounts()
+        {
+            let (realized, unrealized) = self.risk.positions().total_pnl(account);
+            info!(account, position = self.risk.position(SYMBOL, account), realized, unrealized, "account P&L");
+        }
+    }
+
+    /// run applies the requests of the ring in batches, in between it serves the admin commands,
+    /// answers the REST API and checks the GTD orders, until the connections side of the ring is dropped
+    fn run(mut self, mut requests : Consumer<Option<Request>>, admin : mpsc::Receiver<AdminRequest>,
+           queries : mpsc::Receiver<RestRequest>)
+    {
+        let mut last_expiry = Instant::now();
+        while !(requests.is_closed() && requests.is_empty())
+        {
+            requests.consume(Some(IDLE_WAIT), |slot| match slot.take()
+            {
+                Some(request) => self.handle(request),
+                None =>
+                {
+                    self.summary();
+                    // Closes the report channel of an SBE connection
+                    self.session = None;
+                },
+            });
+
+            while let Ok((command, response)) = admin.try_recv()
+            {
+                // The admin connection may be gone already
+                let _ = response.send(self.admin(&command));
+            }
+            while let Ok((route, reply)) = queries.try_recv()
+            {
+                // The HTTP connection may be gone already
+                let _ = reply.send(match route
+                {
+                    Route::Query(query) => rest::answer(&self.engine, &query),
+                    Route::Admin(command) => Reply::admin(self.admin(&command)),
+                });
+            }
+            if last_expiry.elapsed() >= EXPIRY_INTERVAL
+            {
+                last_expiry = Instant::now();
+                self.expire_orders();
+            }
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
+    logging::init();
     // Bind the listener to the address
     let listener = TcpListener::bind("127.0.0.1:6001").await.unwrap();
-    let mut order_book = Box::new(OrderBook::new("TSLA"));
-    
+    let feed_listener = TcpListener::bind("127.0.0.1:6002").await.unwrap();
+    let admin_listener = TcpListener::bind("127.0.0.1:6005").await.unwrap();
+    let metrics_listener = TcpListener::bind("127.0.0.1:6006").await.unwrap();
+    let sbe_listener = TcpListener::bind("127.0.0.1:6007").await.unwrap();
+    let sbe_feed_listener = TcpListener::bind("127.0.0.1:6008").await.unwrap();
+    let rest_listener = TcpListener::bind("127.0.0.1:6009").await.unwrap();
+    let clock : Arc<dyn Clock> = Arc::new(MonotonicClock::new());
+    let mut engine = Engine::with_clock(clock.clone());
+    engine.add_symbol(SYMBOL);
+    replay_journal(&mut engine, ADMIN_JOURNAL);
+    let risk = RiskChecker::with_mark_method(RiskLimits::exchange_defaults(), MarkMethod::Mid);
+    let (feed, _) = broadcast::channel(1024);
+    let metrics = Arc::new(Metrics::default());
+    tokio::spawn(metrics::serve_metrics(metrics_listener, metrics.clone()));
+    tokio::spawn(serve_feed(feed_listener, feed.clone(), metrics.clone()));
+    let (sbe_feed, _) = broadcast::channel(1024);
+    tokio::spawn(serve_feed(sbe_feed_listener, sbe_feed.clone(), metrics.clone()));
+
+    // The same feed is sent as UDP packets, the gaps are recovered on the recovery port
+    let store = Arc::new(std::sync::Mutex::new(RecoveryStore::new(10000)));
+    let recovery_listener = TcpListener::bind("127.0.0.1:6004").await.unwrap();
+    tokio::spawn(market_data::serve_recovery(recovery_listener, store.clone(), clock));
+    let udp_feed = Arc::new(FeedPublisher::bind("127.0.0.1:6003".parse().unwrap(), SYMBOL, store).await.unwrap());
+    tokio::spawn(send_heartbeats(udp_feed.clone()));
+
+    // The connections decode the orders for the matching thread, which hands the resulting
+    // messages over to the publishing thread
+    let (mut orders, order_ring) = spsc::channel(RING_CAPACITY, WaitStrategy::Park);
+    let (messages, message_ring) = spsc::channel(RING_CAPACITY, WaitStrategy::Park);
+    let (admin, admin_requests) = mpsc::channel();
+    let (queries, rest_requests) = mpsc::channel();
+    let mut exchange = Exchange {
+        engine,
+        risk,
+        publisher : ItchPublisher::new(),
+        journal : Journal::open(ADMIN_JOURNAL).unwrap(),
+        messages,
+        metrics : metrics.clone(),
+        book_gauges : metrics.book(SYMBOL),
+        session : None,
+        reports : BytesMut::new() };
+    let start = vec![exchange.publisher.system_event(exchange.itch_timestamp(), itch::START_OF_MESSAGES)];
+    exchange.publish(start, &[]);
+
+    let runtime = Handle::current();
+    let publisher_metrics = metrics.clone();
+    thread::Builder::new().name("publisher".to_string())
+        .spawn(move || publish(message_ring, feed, sbe_feed, udp_feed, publisher_metrics, runtime)).unwrap();
+    thread::Builder::new().name("matching".to_string())
+        .spawn(move || exchange.run(order_ring, admin_requests, rest_requests)).unwrap();
+    tokio::spawn(serve_admin(admin_listener, admin));
+    tokio::spawn(rest::serve_rest(rest_listener, queries));
+    // The connections are served one at a time, as the only producer of the ring
     loop {
-        // The second item contains the IP and port of the new connection.
-        let (mut socket, _) = listener.accept().await.unwrap();
-        process(&mut socket, &mut order_book).await;
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (mut socket, _) = accepted.unwrap();
+                process(&mut socket, &mut orders, &metrics).await;
+            },
+            accepted = sbe_listener.accept() => {
+                let (mut socket, _) = accepted.unwrap();
+                process_sbe(&mut socket, &mut orders, &metrics).await;
+            },
+        }
+    }
+}
+
+/// replay_journal applies the commands journaled by the previous runs, so that the halts and
+/// the new orders switch survive a restart. The books are empty at this point, the events of
+/// the replayed commands are dropped rather than published
+fn replay_journal(engine : &mut Engine, path : &str)
+{
+    let file = match File::open(path)
+    {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return,
+        Err(e) => panic!("cannot open the admin journal: {:?}", e),
+    };
+    let commands = Journal::read(std::io::BufReader::new(file)).expect("the admin journal is corrupted");
+    for (_, command) in commands.iter()
+    {
+        if let Err(reason) = engine.admin(command)
+        {
+            warn!(%command, reason, "failed to replay the admin command");
+        }
+    }
+    engine.drain_events();
+    info!(commands = commands.len(), "replayed the admin journal");
+}
+
+/// publish sends the messages of the matching thread on the TCP and UDP feeds and the SBE
+/// market data on its TCP feed, the slots are left empty with their buffers for the next messages
+fn publish(mut messages : Consumer<Publication>, feed : broadcast::Sender<Bytes>, sbe_feed : broadcast::Sender<Bytes>,
+           udp_feed : Arc<FeedPublisher>, metrics : Arc<Metrics>, runtime : Handle)
+{
+    while !(messages.is_closed() && messages.is_empty())
+    {
+        messages.consume(None, |slot|
+        {
+            let start = Instant::now();
+            if !slot.itch.is_empty()
+            {
+                // Nobody is subscribed when the send fails
+                let _ = feed.send(encode_feed(&slot.itch));
+                if let Err(e) = runtime.block_on(udp_feed.publish(&slot.itch))
+                {
+                    warn!(error = ?e, "failed to publish the UDP feed");
+                }
+            }
+            if !slot.sbe.is_empty()
+            {
+                let _ = sbe_feed.send(Bytes::copy_from_slice(&slot.sbe));
+            }
+            metrics.latency(Stage::Publish).observe(start.elapsed());
+            slot.itch.clear();
+            slot.sbe.clear();
+        });
+    }
+}
+
+/// serve_admin answers the operator commands, one command per line, with
+/// either `OK <cancelled orders>` or `ERR <reason>`
+async fn serve_admin(listener : TcpListener, admin : mpsc::Sender<AdminRequest>)
+{
+    loop
+    {
+        let (socket, _) = match listener.accept().await
+        {
+            Ok(connection) => connection,
+            Err(_) => continue,
+        };
+        let admin = admin.clone();
+        tokio::spawn(async move
+        {
+            let (reader, mut writer) = socket.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await
+            {
+                let response = match AdminCommand::parse(&line)
+                {
+                    Ok(command) =>
+                    {
+                        let (sender, receiver) = oneshot::channel();
+                        let applied = match admin.send((command, sender))
+                        {
+                            Ok(()) => receiver.await.unwrap_or(Err("The matching thread is stopped")),
+                            Err(_) => Err("The matching thread is stopped"),
+                        };
+                        match applied
+                        {
+                            Ok(cancelled) => format!("OK {}\n", cancelled),
+                            Err(reason) => format!("ERR {}\n", reason),
+                        }
+                    },
+                    Err(reason) => format!("ERR {}\n", reason),
+                };
+                if writer.write_all(response.as_bytes()).await.is_err()
+                {
+                    break;
+                }
+            }
+        });
     }
 }
 
-fn decode_order(buf : &mut BytesMut) -> Option<Order>
+/// send_heartbeats lets the UDP subscribers detect the loss of the last packets
+async fn send_heartbeats(udp_feed : Arc<FeedPublisher>)
 {
-    // Decode ID
-    let mut id_arr = [0u8; 4];
-    let mut pos_id = buf.split_to(4);
-    id_arr.copy_from_slice(&pos_id[..4]);
-    let received_id =  u32::from_be_bytes(id_arr);
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop
+    {
+        interval.tick().await;
+        let _ = udp_feed.heartbeat().await;
+    }
+}
 
-    // Decode SIDE
-    let mut side = Side::Buy;
-    let side_mut = buf.split_to(1);
-    let received_side = u8::from_be(side_mut[0]);
-    if received_side == 0x2
+fn encode_feed(messages : &[itch::SequencedMessage]) -> Bytes
+{
+    let mut buf = BytesMut::new();
+    for message in messages
     {
-        side = Side::Sell;
+        message.encode(&mut buf);
     }
+    buf.freeze()
+}
 
-    // Decode price
-    let mut price_arr = [0u8; 4];
-    let price_buf = buf.split_to(4);
-    price_arr.copy_from_slice(&price_buf[..4]);
-    let received_price = f32::from_be_bytes(price_arr);
+/// serve_feed streams the market data to every connected subscriber,
+/// a subscriber which cannot keep up is disconnected
+async fn serve_feed(listener : TcpListener, feed : broadcast::Sender<Bytes>, metrics : Arc<Metrics>)
+{
+    loop
+    {
+        let (mut socket, _) = match listener.accept().await
+        {
+            Ok(connection) => connection,
+            Err(_) => continue,
+        };
+        let mut subscription = feed.subscribe();
+        let queue_length = metrics.feed_queue();
+        tokio::spawn(async move
+        {
+            while let Ok(bytes) = subscription.recv().await
+            {
+                queue_length.set(subscription.len() as i64);
+                if socket.write_all(&bytes).await.is_err()
+                {
+                    break;
+                }
+            }
+        });
+    }
+}
 
-    // Decode qty
-    let mut qty_arr = [0u8; 4];
-    let qty_buf = buf.split_to(4);
-    qty_arr.copy_from_slice(&qty_buf[..4]);
-    let received_qty = u32::from_be_bytes(qty_arr);
+/// push_request publishes a request into the ring of the matching thread. While the ring is
+/// full the task yields, then sleeps, so that the tokio worker keeps running the other tasks.
+/// The request is given back when the matching thread has stopped
+async fn push_request(orders : &mut Producer<Option<Request>>, request : Option<Request>) -> Result<(), Option<Request>>
+{
+    let mut request = request;
+    let mut attempts = 0;
+    loop
+    {
+        match orders.try_push(request)
+        {
+            Ok(()) => return Ok(()),
+            Err(rejected) if orders.is_closed() => return Err(rejected),
+            Err(rejected) => request = rejected,
+        }
+        attempts += 1;
+        if attempts < FULL_RING_YIELDS
+        {
+            tokio::task::yield_now().await;
+        }
+        else
+        {
+            tokio::time::sleep(FULL_RING_BACKOFF).await;
+        }
+    }
+}
 
-    Some(Order::new(received_id, side, received_price, received_qty))
+/// process decodes the orders of a connection into the ring of the matching thread as they
+/// are received, the messages are read in place from the receive buffer. The task waits
+/// asynchronously for room when the ring is full
+async fn process(socket: &mut TcpStream, orders : &mut Producer<Option<Request>>, metrics : &Metrics) {
+    debug!(peer = ?socket.peer_addr().ok(), "order connection");
+    let mut buffer = FrameBuffer::with_capacity(RECEIVE_BUFFER);
+    loop
+    {
+        let received = match socket.read(buffer.spare()).await
+        {
+            Ok(0) => break,
+            Ok(received) => received,
+            Err(e) =>
+            {
+                warn!(error = ?e, "failed to read from socket");
+                break;
+            },
+        };
+        trace!(bytes = received, "received orders");
+        buffer.filled(received);
+        while let Some(message) = buffer.next_message()
+        {
+            let start = Instant::now();
+            let decoded = message.to_order();
+            metrics.latency(Stage::Decode).observe(start.elapsed());
+            match decoded
+            {
+                Ok(order) => { let _ = push_request(orders, Some(Request::New { symbol : Symbol::new(SYMBOL), order })).await; },
+                Err(reason) => warn!(order_id = message.id(), reason, "malformed order"),
+            }
+        }
+    }
+    if buffer.pending() > 0
+    {
+        warn!(bytes = buffer.pending(), "connection closed in the middle of an order");
+    }
+    // The end of the connection, the matching thread logs the book and the P&L
+    let _ = push_request(orders, None).await;
 }
 
-async fn process(socket: &mut TcpStream, order_book : &mut OrderBook) {
-    println!("socket {:?}", socket);
-    let mut rx_bytes = Vec::new();
-    socket.read_to_end(&mut rx_bytes).await;
-    println!("Received bytes: {:?}", rx_bytes);
-    let mut bytes_mut = BytesMut::from(rx_bytes.as_slice());
-    while !bytes_mut.is_empty()
+/// decode_sbe converts an SBE order entry message into a request of the matching thread
+fn decode_sbe(message : Message) -> Result<Request, &'static str>
+{
+    match message
     {
-        let received_order = decode_order(&mut bytes_mut);
-        println!("Received order: {:?}", received_order);
-        order_book.insert_order_at_level(&mut received_order.unwrap());
+        Message::NewOrderSingle(new) => Ok(Request::New { symbol : new.symbol(), order : new.to_order()? }),
+        Message::OrderCancelRequest(cancel) => Ok(Request::Cancel { symbol : cancel.symbol(), order_id : cancel.order_id() }),
+        Message::OrderCancelReplaceRequest(replace) => Ok(Request::Replace {
+            symbol : replace.symbol(),
+            order_id : replace.order_id(),
+            price : replace.price(),
+            qty : replace.order_qty() }),
+        Message::ExecutionReport(..) | Message::MarketDataIncrementalRefresh(..) => Err("not an order entry message"),
     }
+}
 
-    order_book.summary();
+/// process_sbe serves an SBE order entry connection: the framed messages are read in place and
+/// decoded into the ring of the matching thread, which sends back the execution reports. A
+/// malformed frame closes the connection as the stream cannot be delimited anymore
+async fn process_sbe(socket : &mut TcpStream, orders : &mut Producer<Option<Request>>, metrics : &Metrics)
+{
+    debug!(peer = ?socket.peer_addr().ok(), "SBE order connection");
+    let (reports, mut receiver) = unbounded_channel();
+    let _ = push_request(orders, Some(Request::Session(reports))).await;
+    let (mut reader, mut writer) = socket.split();
+    let mut buf = BytesMut::with_capacity(RECEIVE_BUFFER);
+    'connection: loop
+    {
+        tokio::select!
+        {
+            received = reader.read_buf(&mut buf) =>
+            {
+                match received
+                {
+                    Ok(0) => break,
+                    Ok(_) => {},
+                    Err(e) =>
+                    {
+                        warn!(error = ?e, "failed to read from socket");
+                        break;
+                    },
+                }
+                loop
+                {
+                    let len = match sbe::frame_length(&buf)
+                    {
+                        Ok(Some(len)) => len,
+                        Ok(None) => break,
+                        Err(reason) =>
+                        {
+                            warn!(reason, "malformed SBE frame");
+                            break 'connection;
+                        },
+                    };
+                    let start = Instant::now();
+                    let decoded = sbe::decode(&buf[..len]).and_then(decode_sbe);
+                    metrics.latency(Stage::Decode).observe(start.elapsed());
+                    match decoded
+                    {
+                        Ok(request) => { let _ = push_request(orders, Some(request)).await; },
+                        Err(reason) => warn!(reason, "malformed SBE message"),
+                    }
+                    buf.advance(len);
+                }
+            },
+            Some(report) = receiver.recv() =>
+            {
+                if writer.write_all(&report).await.is_err()
+                {
+                    break;
+                }
+            },
+        }
+    }
+    // The matching thread closes the channel once it handled the end of the connection,
+    // the reports of the last requests are still sent
+    let _ = push_request(orders, None).await;
+    while let Some(report) = receiver.recv().await
+    {
+        if writer.write_all(&report).await.is_err()
+        {
+            break;
+        }
+    }
 }
@@ -1,40 +1,21 @@
 use rand_distr::Bernoulli;
 use tokio::net::TcpStream;
 use bytes::BytesMut;
-use bytes::BufMut;
 use tokio::io::{AsyncWriteExt};
 use std::error::Error;
 use matching_engine::data_types::{Order, Side};
+use matching_engine::logging;
+use matching_engine::order_entry::NewOrderMessage;
+use tracing::{debug, trace};
 use rand_distr::{Distribution, Normal, Uniform};
 use rand::thread_rng;
 
 const MAX_SEQ : u32 = 1000u32;
 
-trait Encoder
-{
-    fn encode(&self, buf : &mut BytesMut);
-}
-impl Encoder for Order
-{
-    fn encode(&self, buf : &mut BytesMut)
-    {
-        buf.put_u32(self.id);
-
-        match self.side
-        {
-            Side::Buy => { buf.put_u8(0x1); },
-            Side::Sell => { buf.put_u8(0x2); },
-        }
-
-        buf.put_f32(self.price);
-        buf.put_u32(self.qty);
-        println!("buffer size: {}", buf.len());
-    }
-}
-
-
 #[tokio::main]
+#[allow(clippy::bool_comparison)]
 async fn main() -> Result<(), Box<dyn Error>> {
+    logging::init();
     // Connect to a peer
     let mut stream = TcpStream::connect("127.0.0.1:6001").await?;
     let mut i = 0u32;
@@ -61,19 +42,19 @@ async fn main() -> Result<(), Box<dyn Error>> {
         evolving_price += v;
 
         let _qty = qty_distr.sample(&mut rng);
-        let order = Order{id:i, side: side, price: evolving_price, qty: _qty};
-        println!("[CLIENT] -> Sending order: {:#?}", order);
+        let order = Order::new(i, side, evolving_price, _qty);
+        debug!(order_id = order.id, ?order.side, order.price, order.qty, "sending order");
         
         // Write the message.
         let mut buffer = BytesMut::new();
-        // Encode order
-        order.encode(&mut buffer);
+        NewOrderMessage::encode(&order, &mut buffer);
+        trace!(bytes = buffer.len(), "encoded order");
 
         stream.write_all(&buffer).await?;
         i += 1;
     }
 
-    stream.flush();
+    stream.flush().await?;
 
     Ok(())
 }
\ No newline at end of file
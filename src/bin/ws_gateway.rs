@@ -0,0 +1,596 @@
+use matching_engine::clock::{Clock, MonotonicClock};
+use matching_engine::data_types::{Order, OrderType};
+use matching_engine::depth::DepthTracker;
+use matching_engine::engine::Engine;
+use matching_engine::events::Event;
+use matching_engine::json::{ExecType, ExecutionReport, Request, Response};
+use matching_engine::logging;
+use futures_util::{SinkExt, StreamExt};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::time::{interval, Duration};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{info, warn};
+
+const DEFAULT_ADDRESS : &str = "127.0.0.1:9880";
+/// Number of price levels per side streamed to the depth subscribers
+const DEPTH_LEVELS : usize = 10;
+
+/// OrderState tracks a live order entered through the gateway
+#[derive(Debug)]
+struct OrderState
+{
+    /// Connection which entered the order
+    session : u64,
+    /// Id of the order chosen by the client
+    id : u32,
+    symbol : String,
+    /// The order as it rests in the book, qty is the leaves quantity
+    order : Order,
+    cum_qty : u32,
+}
+
+/// Gateway maps the JSON requests onto the engine, routes the execution reports to the
+/// connections owning the orders and streams the depth of the books to their subscribers
+#[derive(Default)]
+struct Gateway
+{
+    _engine : Engine,
+    _orders : HashMap<u32, OrderState>,
+    _ids : HashMap<(u64, u32), u32>,
+    _sessions : HashMap<u64, mpsc::UnboundedSender<Response>>,
+    _subscribers : HashMap<String, HashSet<u64>>,
+    _depth : BTreeMap<String, DepthTracker>,
+    _next_order_id : u32,
+    _next_session : u64,
+}
+
+impl Gateway
+{
+    fn new(symbols : &[&str], clock : Arc<dyn Clock>) -> Gateway
+    {
+        let mut gateway = Gateway { _engine : Engine::with_clock(clock), _next_order_id : 1, ..Default::default() };
+        for symbol in symbols
+        {
+            gateway._engine.add_symbol(symbol);
+            gateway._depth.insert(symbol.to_string(), DepthTracker::new(DEPTH_LEVELS));
+        }
+        gateway
+    }
+
+    /// connect registers a connection, its responses are sent to the channel
+    ///
+    /// # Return
+    ///
+    /// The id of the connection
+    fn connect(&mut self, responses : mpsc::UnboundedSender<Response>) -> u64
+    {
+        self._next_session += 1;
+        self._sessions.insert(self._next_session, responses);
+        self._next_session
+    }
+
+    /// disconnect forgets a connection, its orders keep resting in the books
+    fn disconnect(&mut self, session : u64)
+    {
+        self._sessions.remove(&session);
+        for subscribers in self._subscribers.values_mut()
+        {
+            subscribers.remove(&session);
+        }
+    }
+
+    fn send(&self, session : u64, response : Response)
+    {
+        if let Some(responses) = self._sessions.get(&session)
+        {
+            // The connection may be closing
+            let _ = responses.send(response);
+        }
+    }
+
+    fn reject(&self, session : u64, id : Option<u32>, reason : &str)
+    {
+        self.send(session, Response::Reject { id, reason : reason.to_string() });
+    }
+
+    /// report builds an execution report with the current state of the order
+    fn report(&self, order_id : u32, exec_type : ExecType, last : Option<(f32, u32)>) -> ExecutionReport
+    {
+        let state = &self._orders[&order_id];
+        ExecutionReport {
+            id : state.id,
+            order_id,
+            symbol : state.symbol.clone(),
+            side : state.order.side,
+            exec_type,
+            price : state.order.price,
+            leaves_qty : if matches!(exec_type, ExecType::Cancelled | ExecType::Expired) { 0 } else { state.order.qty },
+            cum_qty : state.cum_qty,
+            last_price : last.map(|(price, _)| price),
+            last_qty : last.map(|(_, qty)| qty),
+            timestamp : self._engine.clock().now(),
+            reason : None }
+    }
+
+    /// send_report sends an execution report to the connection owning the order
+    fn send_report(&self, order_id : u32, exec_type : ExecType, last : Option<(f32, u32)>)
+    {
+        let report = self.report(order_id, exec_type, last);
+        self.send(self._orders[&order_id].session, Response::ExecutionReport(report));
+    }
+
+    fn remove_order(&mut self, order_id : u32)
+    {
+        if let Some(state) = self._orders.remove(&order_id)
+        {
+            self._ids.remove(&(state.session, state.id));
+        }
+    }
+
+    /// on_request applies a request of a connection
+    fn on_request(&mut self, session : u64, request : Request)
+    {
+        match request
+        {
+            Request::NewOrder { id, symbol, side, price, qty, account, order_type, time_in_force } =>
+            {
+                let mut order = Order::new(self._next_order_id, side, price, qty);
+                order.account = account;
+                order.order_type = order_type;
+                order.time_in_force = time_in_force;
+                self.new_order(session, id, symbol, order);
+            },
+            Request::Cancel { id } => self.cancel_order(session, id),
+            Request::Amend { id, price, qty } => self.amend_order(session, id, price, qty),
+            Request::Subscribe { symbol } => match self._depth.get(&symbol)
+            {
+                Some(tracker) =>
+                {
+                    let snapshot = Response::DepthSnapshot { symbol : symbol.clone(), depth : tracker.snapshot().clone() };
+                    self._subscribers.entry(symbol).or_default().insert(session);
+                    self.send(session, snapshot);
+                },
+                None => self.reject(session, None, "Unknown symbol"),
+            },
+        }
+        self.publish_depth();
+    }
+
+    /// new_order acknowledges, matches and rests an order, the fills are reported to both sides
+    fn new_order(&mut self, session : u64, id : u32, symbol : String, mut order : Order)
+    {
+        let reject = |reason : &str| Response::ExecutionReport(ExecutionReport {
+            id,
+            order_id : 0,
+            symbol : symbol.clone(),
+            side : order.side,
+            exec_type : ExecType::Rejected,
+            price : order.price,
+            leaves_qty : 0,
+            cum_qty : 0,
+            last_price : None,
+            last_qty : None,
+            timestamp : self._engine.clock().now(),
+            reason : Some(reason.to_string()) });
+        if self._ids.contains_key(&(session, id))
+        {
+            return self.send(session, reject("Duplicate order id"));
+        }
+        if order.qty == 0
+        {
+            return self.send(session, reject("Quantity must be positive"));
+        }
+        if let Err(reason) = self._engine.book(&symbol).ok_or("Unknown symbol")
+            .and_then(|_| self._engine.controls().check_new_order(&symbol, order.account))
+        {
+            return self.send(session, reject(reason));
+        }
+
+        let order_id = order.id;
+        self._next_order_id += 1;
+        self._ids.insert((session, id), order_id);
+        self._orders.insert(order_id, OrderState { session, id, symbol : symbol.clone(), order, cum_qty : 0 });
+        self.send_report(order_id, ExecType::New, None);
+
+        if let Err(reason) = self._engine.insert_order(&symbol, &mut order)
+        {
+            let report = ExecutionReport { leaves_qty : 0, reason : Some(reason.to_string()), ..self.report(order_id, ExecType::Rejected, None) };
+            self.send(session, Response::ExecutionReport(report));
+            return self.remove_order(order_id);
+        }
+        self.dispatch_events();
+        // The unfilled quantity of a market order is not rested in the book
+        if self._orders.get(&order_id).is_some_and(|state| state.order.order_type == OrderType::Market)
+        {
+            self.send_report(order_id, ExecType::Cancelled, None);
+            self.remove_order(order_id);
+        }
+    }
+
+    /// find_order returns the order the client knows by the id, with its symbol
+    fn find_order(&self, session : u64, id : u32) -> Option<(String, Order)>
+    {
+        let state = &self._orders[self._ids.get(&(session, id))?];
+        Some((state.symbol.clone(), state.order))
+    }
+
+    fn cancel_order(&mut self, session : u64, id : u32)
+    {
+        let (symbol, order) = match self.find_order(session, id)
+        {
+            Some(order) => order,
+            None => return self.reject(session, Some(id), "Unknown order"),
+        };
+        match self._engine.cancel_order(&symbol, &order)
+        {
+            Ok(_) => self.dispatch_events(),
+            Err(reason) => self.reject(session, Some(id), reason),
+        }
+    }
+
+    fn amend_order(&mut self, session : u64, id : u32, price : f32, qty : u32)
+    {
+        let (symbol, order) = match self.find_order(session, id)
+        {
+            Some(order) => order,
+            None => return self.reject(session, Some(id), "Unknown order"),
+        };
+        match self._engine.amend_order(&symbol, &order, price, qty)
+        {
+            Ok(_) => self.dispatch_events(),
+            Err(reason) => self.reject(session, Some(id), reason),
+        }
+    }
+
+    /// dispatch_events turns the engine events into execution reports for the owners of the orders
+    fn dispatch_events(&mut self)
+    {
+        for (_, event) in self._engine.drain_events()
+        {
+            match event
+            {
+                Event::Traded(trade) =>
+                {
+                    for order_id in [trade.aggressive_id, trade.passive_id]
+                    {
+                        let state = match self._orders.get_mut(&order_id)
+                        {
+                            Some(state) => state,
+                            None => continue,
+                        };
+                        state.order.qty -= trade.qty;
+                        state.cum_qty += trade.qty;
+                        let filled = state.order.qty == 0;
+                        self.send_report(order_id, ExecType::Trade, Some((trade.price, trade.qty)));
+                        if filled
+                        {
+                            self.remove_order(order_id);
+                        }
+                    }
+                },
+                Event::Replaced { new, .. } =>
+                {
+                    if let Some(state) = self._orders.get_mut(&new.id)
+                    {
+                        state.order = new;
+                        self.send_report(new.id, ExecType::Replaced, None);
+                    }
+                },
+                Event::Cancelled(order) | Event::Expired(order) =>
+                {
+                    let exec_type = if matches!(event, Event::Cancelled(_)) { ExecType::Cancelled } else { ExecType::Expired };
+                    if self._orders.contains_key(&order.id)
+                    {
+                        self.send_report(order.id, exec_type, None);
+                        self.remove_order(order.id);
+                    }
+                },
+                Event::Added(_) | Event::MassCancelled { .. } => {},
+            }
+        }
+    }
+
+    /// publish_depth sends the changes of the top levels of the books to their subscribers
+    fn publish_depth(&mut self)
+    {
+        for (symbol, tracker) in self._depth.iter_mut()
+        {
+            let updates = tracker.update(self._engine.book(symbol).unwrap().depth(DEPTH_LEVELS));
+            if updates.is_empty()
+            {
+                continue;
+            }
+            for session in self._subscribers.get(symbol).into_iter().flatten()
+            {
+                if let Some(responses) = self._sessions.get(session)
+                {
+                    let _ = responses.send(Response::DepthUpdate { symbol : symbol.clone(), updates : updates.clone() });
+                }
+            }
+        }
+    }
+
+    /// on_timer expires the GTD orders reached by the engine clock
+    fn on_timer(&mut self)
+    {
+        if self._engine.expire_orders() > 0
+        {
+            self.dispatch_events();
+            self.publish_depth();
+        }
+    }
+}
+
+/// run_session reads the JSON requests of a WebSocket connection and writes back its responses
+async fn run_session(socket : TcpStream, gateway : Arc<Mutex<Gateway>>)
+{
+    let websocket = match tokio_tungstenite::accept_async(socket).await
+    {
+        Ok(websocket) => websocket,
+        Err(e) =>
+        {
+            warn!(error = ?e, "WebSocket handshake failed");
+            return;
+        },
+    };
+    let (mut sink, mut stream) = websocket.split();
+    let (responses, mut outgoing) = mpsc::unbounded_channel();
+    let session = gateway.lock().unwrap().connect(responses);
+
+    loop
+    {
+        tokio::select!
+        {
+            message = stream.next() =>
+            {
+                let text = match message
+                {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    // The pings are answered by the stream
+                    Some(Ok(_)) => continue,
+                };
+                let mut gateway = gateway.lock().unwrap();
+                match serde_json::from_str::<Request>(&text)
+                {
+                    Ok(request) => gateway.on_request(session, request),
+                    Err(e) => gateway.reject(session, None, &format!("Invalid request: {}", e)),
+                }
+            },
+            Some(response) = outgoing.recv() =>
+            {
+                let text = serde_json::to_string(&response).expect("responses are serializable");
+                if sink.send(Message::Text(text)).await.is_err()
+                {
+                    break;
+                }
+            },
+        }
+    }
+    gateway.lock().unwrap().disconnect(session);
+}
+
+/// expire_orders runs the expiry checks of the gateway every 100 milliseconds
+async fn expire_orders(gateway : Arc<Mutex<Gateway>>)
+{
+    let mut timer = interval(Duration::from_millis(100));
+    loop
+    {
+        timer.tick().await;
+        gateway.lock().unwrap().on_timer();
+    }
+}
+
+async fn serve(listener : TcpListener, gateway : Arc<Mutex<Gateway>>)
+{
+    tokio::spawn(expire_orders(gateway.clone()));
+    loop
+    {
+        let (socket, address) = match listener.accept().await
+        {
+            Ok(connection) => connection,
+            Err(e) =>
+            {
+                warn!(error = ?e, "failed to accept connection");
+                continue;
+            },
+        };
+        info!(%address, "accepted WebSocket connection");
+        tokio::spawn(run_session(socket, gateway.clone()));
+    }
+}
+
+/// ws_gateway [address] [symbol...]
+#[tokio::main]
+async fn main()
+{
+    logging::init();
+    let args : Vec<String> = std::env::args().skip(1).collect();
+    let address = args.first().map(|address| address.as_str()).unwrap_or(DEFAULT_ADDRESS);
+    let mut symbols : Vec<&str> = args.iter().skip(1).map(|symbol| symbol.as_str()).collect();
+    if symbols.is_empty()
+    {
+        symbols.push("TSLA");
+    }
+
+    let listener = TcpListener::bind(address).await.unwrap();
+    info!(address, ?symbols, "WebSocket gateway listening");
+    serve(listener, Arc::new(Mutex::new(Gateway::new(&symbols, Arc::new(MonotonicClock::new()))))).await;
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use matching_engine::clock::ManualClock;
+    use matching_engine::data_types::Side;
+    use matching_engine::depth::{DepthLevel, LevelUpdate};
+    use tokio::time::timeout;
+    use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+    const NOW : u64 = 34_200_000_000_000;
+
+    /// TestClient is a WebSocket client speaking JSON to the gateway
+    struct TestClient
+    {
+        websocket : WebSocketStream<MaybeTlsStream<TcpStream>>,
+    }
+
+    impl TestClient
+    {
+        async fn connect(address : std::net::SocketAddr) -> TestClient
+        {
+            let (websocket, _) = tokio_tungstenite::connect_async(format!("ws://{}", address)).await.unwrap();
+            TestClient { websocket }
+        }
+
+        async fn send_text(&mut self, text : &str)
+        {
+            self.websocket.send(Message::Text(text.to_string())).await.unwrap();
+        }
+
+        async fn send(&mut self, request : Request)
+        {
+            self.send_text(&serde_json::to_string(&request).unwrap()).await;
+        }
+
+        async fn recv(&mut self) -> Response
+        {
+            loop
+            {
+                let message = timeout(Duration::from_secs(5), self.websocket.next()).await.unwrap().unwrap().unwrap();
+                if let Message::Text(text) = message
+                {
+                    return serde_json::from_str(&text).unwrap();
+                }
+            }
+        }
+
+        async fn recv_report(&mut self) -> ExecutionReport
+        {
+            match self.recv().await
+            {
+                Response::ExecutionReport(report) => report,
+                response => panic!("unexpected response {:?}", response),
+            }
+        }
+    }
+
+    async fn start_gateway() -> std::net::SocketAddr
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Arc::new(Mutex::new(Gateway::new(&["TSLA"], Arc::new(ManualClock::new(NOW)))))));
+        address
+    }
+
+    fn new_order(id : u32, side : Side, price : f32, qty : u32) -> Request
+    {
+        Request::NewOrder {
+            id,
+            symbol : "TSLA".to_string(),
+            side,
+            price,
+            qty,
+            account : 7,
+            order_type : OrderType::Limit,
+            time_in_force : Default::default() }
+    }
+
+    #[tokio::test]
+    async fn orders_are_acknowledged_and_filled()
+    {
+        let address = start_gateway().await;
+        let mut buyer = TestClient::connect(address).await;
+        let mut seller = TestClient::connect(address).await;
+
+        buyer.send(new_order(1, Side::Buy, 122.5f32, 100)).await;
+        let ack = buyer.recv_report().await;
+        assert_eq!((ack.id, ack.exec_type, ack.leaves_qty, ack.timestamp), (1, ExecType::New, 100, NOW));
+
+        // Both clients use the same id, the engine ids differ
+        seller.send_text(r#"{"type":"new_order","id":1,"symbol":"TSLA","side":"sell","price":122.0,"qty":30}"#).await;
+        let ack = seller.recv_report().await;
+        assert_eq!(ack.exec_type, ExecType::New);
+        let fill = seller.recv_report().await;
+        assert_eq!((fill.exec_type, fill.leaves_qty, fill.cum_qty, fill.last_price, fill.last_qty),
+                   (ExecType::Trade, 0, 30, Some(122.5), Some(30)));
+
+        let fill = buyer.recv_report().await;
+        assert_eq!((fill.id, fill.exec_type, fill.leaves_qty, fill.cum_qty), (1, ExecType::Trade, 70, 30));
+        assert_ne!(fill.order_id, ack.order_id);
+    }
+
+    #[tokio::test]
+    async fn orders_can_be_amended_and_cancelled()
+    {
+        let address = start_gateway().await;
+        let mut client = TestClient::connect(address).await;
+
+        client.send(new_order(1, Side::Buy, 122.5f32, 100)).await;
+        assert_eq!(client.recv_report().await.exec_type, ExecType::New);
+        client.send(Request::Amend { id : 1, price : 122.4, qty : 50 }).await;
+        let replaced = client.recv_report().await;
+        assert_eq!((replaced.exec_type, replaced.price, replaced.leaves_qty), (ExecType::Replaced, 122.4, 50));
+
+        client.send(Request::Cancel { id : 1 }).await;
+        let cancelled = client.recv_report().await;
+        assert_eq!((cancelled.exec_type, cancelled.leaves_qty), (ExecType::Cancelled, 0));
+        client.send(Request::Cancel { id : 1 }).await;
+        assert_eq!(client.recv().await, Response::Reject { id : Some(1), reason : "Unknown order".to_string() });
+    }
+
+    #[tokio::test]
+    async fn invalid_requests_are_rejected()
+    {
+        let address = start_gateway().await;
+        let mut client = TestClient::connect(address).await;
+
+        client.send_text("not json").await;
+        assert!(matches!(client.recv().await, Response::Reject { id : None, .. }));
+        client.send_text(r#"{"type":"new_order","id":1,"symbol":"AAPL","side":"buy","price":122.5,"qty":10}"#).await;
+        let reject = client.recv_report().await;
+        assert_eq!((reject.exec_type, reject.reason), (ExecType::Rejected, Some("Unknown symbol".to_string())));
+
+        client.send(new_order(2, Side::Buy, 122.5f32, 10)).await;
+        client.recv_report().await;
+        client.send(new_order(2, Side::Buy, 122.5f32, 10)).await;
+        assert_eq!(client.recv_report().await.reason, Some("Duplicate order id".to_string()));
+        client.send(Request::Subscribe { symbol : "AAPL".to_string() }).await;
+        assert!(matches!(client.recv().await, Response::Reject { .. }));
+    }
+
+    #[tokio::test]
+    async fn subscribers_receive_the_depth_updates()
+    {
+        let address = start_gateway().await;
+        let mut trader = TestClient::connect(address).await;
+        let mut viewer = TestClient::connect(address).await;
+
+        trader.send(new_order(1, Side::Buy, 122.5f32, 100)).await;
+        trader.recv_report().await;
+        viewer.send(Request::Subscribe { symbol : "TSLA".to_string() }).await;
+        match viewer.recv().await
+        {
+            Response::DepthSnapshot { symbol, depth } =>
+            {
+                assert_eq!(symbol, "TSLA");
+                assert_eq!(depth.bids, vec![DepthLevel { price : 122.5, qty : 100, orders : 1 }]);
+                assert!(depth.asks.is_empty());
+            },
+            response => panic!("unexpected response {:?}", response),
+        }
+
+        trader.send(new_order(2, Side::Sell, 122.6f32, 20)).await;
+        assert_eq!(viewer.recv().await, Response::DepthUpdate {
+            symbol : "TSLA".to_string(),
+            updates : vec![LevelUpdate::Add { side : Side::Sell, level : DepthLevel { price : 122.6, qty : 20, orders : 1 } }] });
+        trader.send(Request::Cancel { id : 1 }).await;
+        assert_eq!(viewer.recv().await, Response::DepthUpdate {
+            symbol : "TSLA".to_string(),
+            updates : vec![LevelUpdate::Delete { side : Side::Buy, price : 122.5 }] });
+    }
+}
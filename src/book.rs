@@ -0,0 +1,316 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+use crate::clock::Clock;
+use crate::data_types::*;
+use crate::depth::Depth;
+use crate::events::Event;
+use crate::fees::FeeCalculator;
+
+/// Book is the order book of a symbol as seen by the engine, the implementations are
+/// free to choose their data structures and matching algorithm as long as they pass
+/// the conformance tests of this module. Every change of the book is reported by an
+/// event, in the order in which it happened
+pub trait Book : Debug + Send
+{
+    /// symbol returns the symbol tracked by the book
+    fn symbol(&self) -> &str;
+
+    /// submit matches the order against the opposite side of the book and rests the
+    /// remaining quantity, market orders are never rested
+    ///
+    /// # Arguments
+    /// * order: the incoming order, it is stamped with the entry time and sequence
+    ///   number and its quantity is reduced by the matched quantity
+    fn submit(&mut self, order : &mut Order) -> Result<(), &'static str>;
+
+    /// cancel removes a resting order, only id, side and price of the order are used
+    fn cancel(&mut self, order : &Order) -> Result<Order, &'static str>;
+
+    /// amend changes price and quantity of a resting order. A quantity decrease at the
+    /// same price keeps the time priority, any other change loses it and can match
+    ///
+    /// # Return
+    ///
+    /// The amended order, with its quantity reduced by any resulting match
+    fn amend(&mut self, order : &Order, price : f32, qty : u32) -> Result<Order, &'static str>;
+
+    /// mass_cancel removes the resting orders selected by the filter, bids first
+    fn mass_cancel(&mut self, filter : &MassCancelFilter) -> Vec<Order>;
+
+    /// expire_orders removes the GTD orders whose expiry time is reached by the clock
+    fn expire_orders(&mut self) -> Vec<Order>;
+
+    /// close_session removes the DAY orders at the end of the trading session
+    fn close_session(&mut self) -> Vec<Order>;
+
+    /// best_bid returns the bid level with the highest price
+    fn best_bid(&self) -> Option<&Limit>;
+
+    /// best_ask returns the ask level with the lowest price
+    fn best_ask(&self) -> Option<&Limit>;
+
+    /// level_counts returns the number of price levels of the bid and ask sides
+    fn level_counts(&self) -> (usize, usize);
+
+    /// depth returns the top price levels of both sides, best price first
+    fn depth(&self, levels : usize) -> Depth;
+
+    /// order returns a resting order as it stands in the book, None once it is
+    /// filled, cancelled or expired
+    fn order(&self, order_id : u32) -> Option<Order>;
+
+    /// drain_events returns all the events collected since the last call
+    fn drain_events(&mut self) -> Vec<Event>;
+
+    /// trades returns the last trades executed by the book, oldest first
+    fn trades(&self) -> &[Trade];
+
+    /// trade_count returns the number of trades executed by the book
+    fn trade_count(&self) -> u64;
+
+    fn clock(&self) -> &Arc<dyn Clock>;
+
+    fn fees(&self) -> &FeeCalculator;
+
+    fn fees_mut(&mut self) -> &mut FeeCalculator;
+
+    /// logs a summary of the book: best prices, number of trades and spread
+    fn summary(&self);
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::sync::Arc;
+    use crate::clock::{ManualClock, Sequencer};
+    use crate::data_types::*;
+    use crate::depth::{Depth, DepthLevel};
+    use crate::events::Event;
+    use crate::order_book::{LadderBook, OrderBook};
+    use super::Book;
+
+    /// Time of the frozen clock of the test books
+    const NOW : u64 = 34_200_000_000_000;
+
+    /// Creates an empty book timestamped by the clock
+    type Implementation = fn(Arc<ManualClock>) -> Box<dyn Book>;
+
+    /// conformance runs the test against an empty book of every implementation,
+    /// each book is timestamped by its own frozen clock. The test is given the name
+    /// of the implementation for its assertion messages
+    fn conformance(test : impl Fn(&mut dyn Book, &ManualClock, &str))
+    {
+        let implementations : [(&str, Implementation); 2] = [
+            ("map", |clock| Box::new(OrderBook::with_clock("TSLA", clock, Arc::new(Sequencer::new())))),
+            ("ladder", |clock| Box::new(LadderBook::with_ladder("TSLA", (100.0, 150.0, 0.05), clock, Arc::new(Sequencer::new())).unwrap())),
+        ];
+        for (name, create) in implementations
+        {
+            let clock = Arc::new(ManualClock::new(NOW));
+            let mut book = create(clock.clone());
+            test(book.as_mut(), &clock, name);
+        }
+    }
+
+    /// submit sends a new limit order to the book and returns it as stamped by the book
+    fn submit(book : &mut dyn Book, id : u32, side : Side, price : f32, qty : u32) -> Order
+    {
+        let mut order = Order::new(id, side, price, qty);
+        book.submit(&mut order).unwrap();
+        order
+    }
+
+    /// fills returns the passive order id, price and quantity of the traded events
+    fn fills(events : &[Event]) -> Vec<(u32, f32, u32)>
+    {
+        events.iter().filter_map(|event| match event
+        {
+            Event::Traded(trade) => Some((trade.passive_id, trade.price, trade.qty)),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn resting_orders_are_aggregated_by_price_level()
+    {
+        conformance(|book, _, name|
+        {
+            let first = submit(book, 1, Side::Buy, 122.2f32, 100);
+            submit(book, 2, Side::Buy, 122.2f32, 50);
+            submit(book, 3, Side::Buy, 122.1f32, 25);
+            submit(book, 4, Side::Sell, 122.5f32, 30);
+
+            assert_eq!(book.depth(10), Depth {
+                bids : vec![DepthLevel { price : 122.2, qty : 150, orders : 2 }, DepthLevel { price : 122.1, qty : 25, orders : 1 }],
+                asks : vec![DepthLevel { price : 122.5, qty : 30, orders : 1 }] }, "{}", name);
+            assert_eq!(book.depth(1).bids.len(), 1, "{}", name);
+            assert_eq!((book.best_bid().unwrap().price, book.best_bid().unwrap().qty), (122.2, 150), "{}", name);
+            assert_eq!(book.best_ask().unwrap().price, 122.5, "{}", name);
+
+            let events = book.drain_events();
+            assert_eq!(events.len(), 4, "{}", name);
+            assert_eq!(events[0], Event::Added(first), "{}", name);
+            assert!(events.iter().all(|event| matches!(event, Event::Added(_))), "{}", name);
+            assert!(book.drain_events().is_empty(), "{}", name);
+        });
+    }
+
+    #[test]
+    fn orders_match_by_price_then_time()
+    {
+        conformance(|book, _, name|
+        {
+            submit(book, 1, Side::Sell, 122.5f32, 10);
+            submit(book, 2, Side::Sell, 122.4f32, 10);
+            submit(book, 3, Side::Sell, 122.4f32, 10);
+            submit(book, 4, Side::Sell, 122.6f32, 10);
+            book.drain_events();
+
+            let buy = submit(book, 5, Side::Buy, 122.5f32, 25);
+            assert_eq!(buy.qty, 0, "{}", name);
+            let events = book.drain_events();
+            assert_eq!(fills(&events), vec![(2, 122.4, 10), (3, 122.4, 10), (1, 122.5, 5)], "{}", name);
+            let trades = book.trades();
+            assert!(trades.iter().all(|trade| trade.aggressive_id == 5 && trade.aggressor_side == Side::Buy
+                                              && trade.match_id == trades[0].match_id && trade.timestamp == NOW), "{}", name);
+
+            assert!(book.best_bid().is_none(), "{}", name);
+            assert_eq!(book.depth(10).asks, vec![DepthLevel { price : 122.5, qty : 5, orders : 1 }, DepthLevel { price : 122.6, qty : 10, orders : 1 }], "{}", name);
+        });
+    }
+
+    #[test]
+    fn unfilled_quantity_rests_unless_market_order()
+    {
+        conformance(|book, _, name|
+        {
+            submit(book, 1, Side::Sell, 122.5f32, 10);
+            let buy = submit(book, 2, Side::Buy, 122.6f32, 15);
+            assert_eq!(buy.qty, 5, "{}", name);
+            assert_eq!(book.depth(10).bids, vec![DepthLevel { price : 122.6, qty : 5, orders : 1 }], "{}", name);
+            assert!(book.best_ask().is_none(), "{}", name);
+
+            let mut market = Order::new(3, Side::Sell, 0.0f32, 20);
+            market.order_type = OrderType::Market;
+            book.submit(&mut market).unwrap();
+            assert_eq!(market.qty, 15, "{}", name);
+            assert!(book.best_bid().is_none(), "{}", name);
+            assert!(book.best_ask().is_none(), "{}", name);
+            assert_eq!(fills(&book.drain_events()), vec![(1, 122.5, 10), (2, 122.6, 5)], "{}", name);
+        });
+    }
+
+    #[test]
+    fn cancel_removes_a_resting_order()
+    {
+        conformance(|book, _, name|
+        {
+            let order = submit(book, 1, Side::Buy, 122.2f32, 100);
+            submit(book, 2, Side::Buy, 122.2f32, 50);
+            book.drain_events();
+
+            assert_eq!(book.cancel(&order), Ok(order), "{}", name);
+            assert_eq!(book.drain_events(), vec![Event::Cancelled(order)], "{}", name);
+            assert_eq!(book.depth(10).bids, vec![DepthLevel { price : 122.2, qty : 50, orders : 1 }], "{}", name);
+            assert!(book.cancel(&order).is_err(), "{}", name);
+            assert!(book.cancel(&Order::new(3, Side::Sell, 122.9f32, 10)).is_err(), "{}", name);
+            assert!(book.drain_events().is_empty(), "{}", name);
+        });
+    }
+
+    #[test]
+    fn resting_orders_are_found_by_id()
+    {
+        conformance(|book, _, name|
+        {
+            let bid = submit(book, 1, Side::Buy, 122.2f32, 100);
+            submit(book, 2, Side::Sell, 122.5f32, 30);
+            submit(book, 3, Side::Buy, 122.5f32, 10);
+
+            assert_eq!(book.order(1), Some(bid), "{}", name);
+            assert_eq!(book.order(2).map(|order| order.qty), Some(20), "{}", name);
+            assert_eq!(book.order(3), None, "{}", name);
+            book.cancel(&bid).unwrap();
+            assert_eq!(book.order(1), None, "{}", name);
+        });
+    }
+
+    #[test]
+    fn amend_keeps_priority_on_quantity_decrease_only()
+    {
+        conformance(|book, _, name|
+        {
+            let first = submit(book, 1, Side::Buy, 122.2f32, 100);
+            let second = submit(book, 2, Side::Buy, 122.2f32, 100);
+            book.drain_events();
+
+            // The decrease keeps the order ahead, the increase sends it behind
+            let reduced = book.amend(&first, 122.2f32, 50).unwrap();
+            assert_eq!(reduced, Order { qty : 50, ..first }, "{}", name);
+            let increased = book.amend(&second, 122.2f32, 120).unwrap();
+            assert_eq!(increased.qty, 120, "{}", name);
+            assert!(increased.seq > second.seq, "{}", name);
+            assert_eq!(book.drain_events(), vec![Event::Replaced { old : first, new : reduced },
+                                                 Event::Replaced { old : second, new : increased },
+                                                 Event::Added(increased)], "{}", name);
+            submit(book, 3, Side::Sell, 122.2f32, 60);
+            assert_eq!(fills(&book.drain_events()), vec![(1, 122.2, 50), (2, 122.2, 10)], "{}", name);
+
+            // A new price can match the opposite side
+            submit(book, 4, Side::Sell, 122.5f32, 30);
+            let crossed = book.amend(&increased, 122.5f32, 110).unwrap();
+            assert_eq!(crossed.qty, 80, "{}", name);
+            assert_eq!(book.depth(10).bids, vec![DepthLevel { price : 122.5, qty : 80, orders : 1 }], "{}", name);
+            assert!(book.best_ask().is_none(), "{}", name);
+            assert!(book.amend(&first, 122.2f32, 10).is_err(), "{}", name);
+            assert!(book.amend(&crossed, 122.5f32, 0).is_err(), "{}", name);
+        });
+    }
+
+    #[test]
+    fn mass_cancel_removes_the_selected_orders()
+    {
+        conformance(|book, _, name|
+        {
+            for (id, side, price, account) in [(1, Side::Buy, 122.2f32, 7), (2, Side::Buy, 122.1f32, 8),
+                                               (3, Side::Sell, 122.5f32, 7), (4, Side::Sell, 123.0f32, 7)]
+            {
+                let mut order = Order::new(id, side, price, 10);
+                order.account = account;
+                book.submit(&mut order).unwrap();
+            }
+            book.drain_events();
+
+            let filter = MassCancelFilter { account : Some(7), price_range : Some((122.0, 122.5)), ..Default::default() };
+            let cancelled : Vec<u32> = book.mass_cancel(&filter).iter().map(|order| order.id).collect();
+            assert_eq!(cancelled, vec![1, 3], "{}", name);
+            assert_eq!(book.drain_events().last(), Some(&Event::MassCancelled { cancelled_orders : 2, cancelled_qty : 20 }), "{}", name);
+            assert_eq!(book.best_bid().unwrap().price, 122.1, "{}", name);
+            assert_eq!(book.best_ask().unwrap().price, 123.0, "{}", name);
+        });
+    }
+
+    #[test]
+    fn orders_expire_by_their_time_in_force()
+    {
+        conformance(|book, clock, name|
+        {
+            let mut gtd = Order::new(1, Side::Buy, 122.2f32, 10);
+            gtd.time_in_force = TimeInForce::GoodTillDate(NOW + 100);
+            let mut day = Order::new(2, Side::Sell, 122.5f32, 10);
+            day.time_in_force = TimeInForce::Day;
+            book.submit(&mut gtd).unwrap();
+            book.submit(&mut day).unwrap();
+            let gtc = submit(book, 3, Side::Buy, 122.1f32, 10);
+            book.drain_events();
+
+            assert!(book.expire_orders().is_empty(), "{}", name);
+            clock.advance(100);
+            assert_eq!(book.expire_orders(), vec![gtd], "{}", name);
+            assert_eq!(book.close_session(), vec![day], "{}", name);
+            assert_eq!(book.drain_events(), vec![Event::Expired(gtd), Event::Expired(day)], "{}", name);
+            assert_eq!(book.best_bid().unwrap().price, gtc.price, "{}", name);
+            assert!(book.best_ask().is_none(), "{}", name);
+        });
+    }
+}
@@ -0,0 +1,203 @@
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Clock gives the time of the engine in nanoseconds since the UNIX epoch, it is
+/// injected so that the tests and the replays control the time
+pub trait Clock : Debug + Send + Sync
+{
+    fn now(&self) -> u64;
+
+    /// system_time returns the current time of the clock as a SystemTime
+    fn system_time(&self) -> SystemTime
+    {
+        UNIX_EPOCH + Duration::from_nanos(self.now())
+    }
+}
+
+/// WallClock reads the system time, in nanoseconds since the UNIX epoch
+#[derive(Copy, Clone, Debug, Default)]
+pub struct WallClock;
+
+impl Clock for WallClock
+{
+    fn now(&self) -> u64
+    {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64
+    }
+}
+
+/// MonotonicClock reads the system time once and then advances with the
+/// monotonic clock of the host, its time never goes backwards when the
+/// system time is adjusted
+#[derive(Copy, Clone, Debug)]
+pub struct MonotonicClock
+{
+    _start : Instant,
+    _start_nanos : u64,
+}
+
+impl Default for MonotonicClock
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+impl MonotonicClock
+{
+    pub fn new() -> MonotonicClock
+    {
+        MonotonicClock { _start : Instant::now(), _start_nanos : WallClock.now() }
+    }
+}
+
+impl Clock for MonotonicClock
+{
+    fn now(&self) -> u64
+    {
+        self._start_nanos + self._start.elapsed().as_nanos() as u64
+    }
+}
+
+/// ManualClock only moves when told to
+#[derive(Debug, Default)]
+pub struct ManualClock
+{
+    _now : AtomicU64,
+}
+
+impl ManualClock
+{
+    pub fn new(now : u64) -> ManualClock
+    {
+        ManualClock { _now : AtomicU64::new(now) }
+    }
+
+    pub fn set(&self, now : u64)
+    {
+        self._now.store(now, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, nanos : u64)
+    {
+        self._now.fetch_add(nanos, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock
+{
+    fn now(&self) -> u64
+    {
+        self._now.load(Ordering::SeqCst)
+    }
+}
+
+/// Sequencer assigns the engine sequence numbers, it is shared by all the
+/// books of an engine so that the numbers are unique across symbols
+/// * _next_order_seq is the sequence of the next order entering a book
+/// * _next_trade_id is the id of the next fill
+/// * _next_match_id is the id of the next match, grouping the fills of an aggressive order
+/// * _stride is the step between two numbers, the sequencers of the threads of a sharded
+///   engine interleave their numbers so that they are unique without being shared, they
+///   then only increase within a thread
+#[derive(Debug)]
+pub struct Sequencer
+{
+    _next_order_seq : AtomicU64,
+    _next_trade_id : AtomicU64,
+    _next_match_id : AtomicU64,
+    _stride : u64,
+}
+
+impl Default for Sequencer
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+impl Sequencer
+{
+    pub fn new() -> Sequencer
+    {
+        Sequencer::interleaved(1, 1)
+    }
+
+    /// interleaved creates a sequencer numbering first, first + stride, first + 2 * stride...
+    ///
+    /// # Arguments
+    ///
+    /// * `first` - The first number of each sequence
+    /// * `stride` - The step between two numbers, the number of interleaved sequencers
+    pub fn interleaved(first : u64, stride : u64) -> Sequencer
+    {
+        Sequencer { _next_order_seq : AtomicU64::new(first),
+                    _next_trade_id : AtomicU64::new(first),
+                    _next_match_id : AtomicU64::new(first),
+                    _stride : stride.max(1) }
+    }
+
+    pub fn next_order_seq(&self) -> u64
+    {
+        self._next_order_seq.fetch_add(self._stride, Ordering::Relaxed)
+    }
+
+    pub fn next_trade_id(&self) -> u64
+    {
+        self._next_trade_id.fetch_add(self._stride, Ordering::Relaxed)
+    }
+
+    pub fn next_match_id(&self) -> u64
+    {
+        self._next_match_id.fetch_add(self._stride, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn manual_clock_moves_on_request()
+    {
+        let clock = ManualClock::new(100);
+        assert_eq!(clock.now(), 100);
+        clock.advance(50);
+        assert_eq!(clock.now(), 150);
+        clock.set(10);
+        assert_eq!(clock.now(), 10);
+        assert!(WallClock.now() > 0);
+        assert_eq!(clock.system_time(), UNIX_EPOCH + Duration::from_nanos(10));
+    }
+
+    #[test]
+    fn monotonic_clock_never_goes_backwards()
+    {
+        let clock = MonotonicClock::new();
+        let mut last = clock.now();
+        assert!(last.abs_diff(WallClock.now()) < 1_000_000_000);
+        for _ in 0..1000
+        {
+            let now = clock.now();
+            assert!(now >= last);
+            last = now;
+        }
+    }
+
+    #[test]
+    fn sequencer_numbers_are_monotonic()
+    {
+        let sequencer = Sequencer::new();
+        assert_eq!((sequencer.next_order_seq(), sequencer.next_order_seq()), (1, 2));
+        assert_eq!((sequencer.next_trade_id(), sequencer.next_trade_id()), (1, 2));
+        assert_eq!(sequencer.next_match_id(), 1);
+
+        let (first, second) = (Sequencer::interleaved(1, 2), Sequencer::interleaved(2, 2));
+        assert_eq!((first.next_trade_id(), first.next_trade_id()), (1, 3));
+        assert_eq!((second.next_trade_id(), second.next_trade_id()), (2, 4));
+    }
+}
@@ -1,64 +1,226 @@
+// The crate level attribute of the original sources has no effect in a module
+#![allow(unused_attributes)]
 #![crate_name = "doc"]
 use std::cmp;
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use tracing::trace;
+use crate::slab::{OrderHandle, OrderQueue, OrderSlab};
 
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Side
 {
     Buy,
     Sell
 }
 
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderType
+{
+    /// Rests in the book at its limit price once it cannot be matched anymore
+    Limit,
+    /// Trades at any available price, the unfilled quantity is never rested
+    Market,
+}
+
+/// TimeInForce tells how long an order may rest in the book
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeInForce
+{
+    /// Rests until it is filled or cancelled
+    #[default]
+    GoodTillCancel,
+    /// Expires at the close of the trading session
+    Day,
+    /// Expires at the given time, in nanoseconds from the engine clock
+    GoodTillDate(u64),
+}
+
+/// Symbol is the name of an instrument stored inline, up to 8 characters,
+/// so that the types carrying it stay Copy
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Symbol([u8; 8]);
+
+impl Symbol
+{
+    /// Creates a symbol, the name is truncated to 8 characters
+    pub fn new(symbol : &str) -> Symbol
+    {
+        let mut bytes = [0u8; 8];
+        for (dst, src) in bytes.iter_mut().zip(symbol.bytes())
+        {
+            *dst = src;
+        }
+        Symbol(bytes)
+    }
+
+    /// Creates a symbol from its name padded with NUL characters
+    pub fn from_bytes(bytes : [u8; 8]) -> Symbol
+    {
+        Symbol(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 8]
+    {
+        &self.0
+    }
+
+    pub fn as_str(&self) -> &str
+    {
+        let len = self.0.iter().position(|byte| *byte == 0).unwrap_or(self.0.len());
+        std::str::from_utf8(&self.0[..len]).unwrap_or("")
+    }
+}
+
+impl fmt::Debug for Symbol
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl fmt::Display for Symbol
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result
+    {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for Symbol
+{
+    fn serialize<S : serde::Serializer>(&self, serializer : S) -> Result<S::Ok, S::Error>
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
 pub struct Order
 {
     pub id : u32,
     pub side : Side,
     pub price : f32,
     pub qty : u32,
+    pub account : u32,
+    pub order_type : OrderType,
+    pub time_in_force : TimeInForce,
+    /// Time the order entered the book, in nanoseconds from the engine clock
+    pub timestamp : u64,
+    /// Engine sequence number assigned when the order entered the book
+    pub seq : u64,
 }
 
 impl Order
 {
+    /// Creates a new limit order for the default account (0)
     pub fn new(id: u32, side : Side, price : f32, qty : u32) -> Order
     {
         Order{
-            id : id,
-            side : side,
-            price : price,
-            qty : qty,
+            id,
+            side,
+            price,
+            qty,
+            account : 0,
+            order_type : OrderType::Limit,
+            time_in_force : TimeInForce::GoodTillCancel,
+            timestamp : 0,
+            seq : 0,
         }
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
 pub struct Trade
 {
     pub aggressive_id : u32,
     pub passive_id : u32,
     pub price : f32,
     pub qty : u32,
+    /// Account of the buying order, either the aggressive or the passive one
+    pub buy_account : u32,
+    /// Account of the selling order
+    pub sell_account : u32,
+    /// Fee charged to the passive order, negative for a rebate
+    pub maker_fee : f64,
+    /// Fee charged to the aggressive order
+    pub taker_fee : f64,
+    /// Engine assigned id, unique and increasing
+    pub trade_id : u64,
+    /// Id shared by all the fills of the same aggressive order
+    pub match_id : u64,
+    /// Time of the match, in nanoseconds from the engine clock
+    pub timestamp : u64,
+    pub aggressor_side : Side,
+    pub symbol : Symbol,
 }
 
 impl Trade
 {
+    /// Creates a new trade between orders of the default account (0)
     pub fn new(aggressive_id : u32, passive_id : u32, price : f32, qty : u32) -> Trade
     {
-        Trade { aggressive_id: aggressive_id, 
-                passive_id: passive_id, price: price, qty: qty }
+        Trade {
+            aggressive_id,
+            passive_id,
+            price,
+            qty,
+            buy_account : 0,
+            sell_account : 0,
+            maker_fee : 0.0,
+            taker_fee : 0.0,
+            trade_id : 0,
+            match_id : 0,
+            timestamp : 0,
+            aggressor_side : Side::Buy,
+            symbol : Symbol::default() }
     }
 }
 
+/// MassCancelFilter selects the resting orders hit by a mass cancel,
+/// every criteria left to None matches all the orders
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct MassCancelFilter
+{
+    pub account : Option<u32>,
+    pub side : Option<Side>,
+    /// Inclusive (low, high) price range
+    pub price_range : Option<(f32, f32)>,
+    pub order_type : Option<OrderType>,
+}
+
+impl MassCancelFilter
+{
+    /// Returns true if the order is selected by the filter
+    /// 
+    /// # Arguments
+    /// 
+    /// * `order` - The resting order to check
+    pub fn matches(&self, order : &Order) -> bool
+    {
+        self.account.is_none_or(|account| account == order.account)
+            && self.side.is_none_or(|side| side == order.side)
+            && self.order_type.is_none_or(|order_type| order_type == order.order_type)
+            && self.price_range.is_none_or(|(low, high)| order.price >= low && order.price <= high)
+    }
+}
+
+/// Limit is a price level, its orders are queued by time priority in the slab of the book
 #[derive(Debug)]
 pub struct Limit
 {
     pub price : f32,
     pub qty : u32,
-    pub orders: Vec<Order>,
+    _queue : OrderQueue,
 }
 
 impl Limit
 {
-    /// Creates a new limit object which represents a limit with a vector of orders
+    /// Creates a new limit object which represents a limit with a queue of orders
     /// 
     /// # Arguments
     /// 
@@ -68,98 +230,183 @@ impl Limit
         Self{
             price,
             qty: 0,
-            orders : vec![],
+            _queue : OrderQueue::default(),
         }
     }
 
-    /// Adds a new order to the vector of orders, the orders are added in FIFO fashion
+    /// Adds a new order to the queue of orders, the orders are added in FIFO fashion
     /// 
     /// # Arguments
     /// 
+    /// * `orders` - The slab storing the orders of the book
     /// * `order` - The order to be added at that specific limit
-    pub fn add_order(&mut self, order : Order)
+    pub fn add_order(&mut self, orders : &mut OrderSlab, order : Order)
     {
-        self.qty = self.qty + order.qty;
-        self.orders.push(order);
+        self.qty += order.qty;
+        self._queue.push_back(orders, order);
     }
 
-    /// Removes an order given a certain order-id
+    /// Returns the handle of the order of this limit with the given id. The order is
+    /// found through the index of the slab, among the orders reusing the id on that side
     /// 
     /// # Arguments
     /// 
+    /// * `orders` - The slab storing the orders of the book
+    /// * `side` - The side of the book holding this limit
+    /// * `order_id` - The order id to be found
+    pub fn find(&self, orders : &OrderSlab, side : Side, order_id : u32) -> Option<OrderHandle>
+    {
+        orders.handles(side, order_id).find(|handle| self._queue.contains(orders, *handle))
+    }
+
+    /// Removes an order given a certain order-id, the order must rest at this limit
+    /// 
+    /// # Arguments
+    /// 
+    /// * `orders` - The slab storing the orders of the book
+    /// * `side` - The side of the book holding this limit
     /// * `order_id` - The order id to be removed
-    pub fn remove_order(&mut self, order_id: u32) -> Result<Order, &str>
+    pub fn remove_order(&mut self, orders : &mut OrderSlab, side : Side, order_id: u32) -> Result<Order, &'static str>
+    {
+        let handle = self.find(orders, side, order_id).ok_or("cannot remove order from limit")?;
+        let order = self._queue.remove(orders, handle).ok_or("cannot remove order from limit")?;
+        self.qty -= order.qty;
+        Ok(order)
+    }
+
+    /// Reduces in place the quantity of an order, keeping its position in the queue.
+    /// The order must rest at this limit
+    /// 
+    /// # Arguments
+    /// 
+    /// * `orders` - The slab storing the orders of the book
+    /// * `side` - The side of the book holding this limit
+    /// * `order_id` - The order id to be reduced
+    /// * `qty` - The new quantity of the order
+    /// 
+    /// # Return
+    /// 
+    /// The order before the change, None if the quantity is not a reduction
+    pub fn reduce_order(&mut self, orders : &mut OrderSlab, side : Side, order_id : u32, qty : u32) -> Result<Option<Order>, &'static str>
     {
-        let pos = self.orders.iter().position(|&ord| ord.id == order_id);
-        match pos
+        let handle = self.find(orders, side, order_id).ok_or("cannot find order in limit")?;
+        let order = orders.get_mut(handle);
+        if qty > order.qty
         {
-            Some(upos) => 
-            {
-                self.qty -= self.orders[upos].qty;
-                Ok(self.orders.remove(upos))
-            },
-            None => Err("cannot remove order from limit"),
+            return Ok(None);
         }
+
+        let old = *order;
+        self.qty -= order.qty - qty;
+        order.qty = qty;
+        Ok(Some(old))
+    }
+
+    /// Removes all the orders selected by the filter, the remaining orders
+    /// keep their time priority
+    /// 
+    /// # Arguments
+    /// 
+    /// * `orders` - The slab storing the orders of the book
+    /// * `filter` - The filter selecting the orders to be removed
+    /// * `removed` - The vector collecting the removed orders
+    pub fn remove_orders(&mut self, orders : &mut OrderSlab, filter : &MassCancelFilter, removed : &mut Vec<Order>)
+    {
+        self.remove_orders_if(orders, |order| filter.matches(order), removed);
     }
 
-    pub fn make_trades(&mut self, aggressive_order : &mut Order) -> Vec<Trade>
+    /// Removes all the orders selected by the predicate, the remaining orders
+    /// keep their time priority
+    /// 
+    /// # Arguments
+    /// 
+    /// * `orders` - The slab storing the orders of the book
+    /// * `predicate` - The function returning true for the orders to be removed
+    /// * `removed` - The vector collecting the removed orders
+    pub fn remove_orders_if(&mut self, orders : &mut OrderSlab, predicate : impl Fn(&Order) -> bool, removed : &mut Vec<Order>)
+    {
+        let first_removed = removed.len();
+        self._queue.remove_if(orders, predicate, removed);
+        self.qty -= removed[first_removed..].iter().map(|order| order.qty).sum::<u32>();
+    }
+
+    pub fn make_trades(&mut self, orders : &mut OrderSlab, aggressive_order : &mut Order) -> Vec<Trade>
     {
-        // Cannot make any trade if the price do not match or is empty
         let mut trades = Vec::new();
+        self.make_trades_into(orders, aggressive_order, &mut trades);
+        trades
+    }
 
-        loop
+    /// Matches the aggressive order against the front of the queue, removing the
+    /// filled orders, until either the order or the level is exhausted
+    /// 
+    /// # Arguments
+    /// 
+    /// * `orders` - The slab storing the orders of the book
+    /// * `aggressive_order` - The incoming order, its quantity is reduced by the traded quantity
+    /// * `trades` - The vector collecting the trades
+    pub fn make_trades_into(&mut self, orders : &mut OrderSlab, aggressive_order : &mut Order, trades : &mut Vec<Trade>)
+    {
+        while aggressive_order.qty != 0
         {
-            if aggressive_order.qty == 0 || self.orders.is_empty()
-            {
-                break;
-            }
+            let Some(handle) = self._queue.front() else { break };
+            let passive_order = orders.get_mut(handle);
 
-            let mut need_to_remove = false;
-            if let Some(passive_order) = self.orders.first_mut()
+            let traded_quantity = cmp::min(aggressive_order.qty, passive_order.qty);
+            aggressive_order.qty -= traded_quantity;
+            passive_order.qty -= traded_quantity;
+            self.qty -= traded_quantity;
+            trace!(passive_id = passive_order.id, aggressive_id = aggressive_order.id, price = passive_order.price,
+                   qty = traded_quantity, passive_left = passive_order.qty, aggressive_left = aggressive_order.qty, "fill");
+            let (buy_account, sell_account) = match aggressive_order.side
             {
-                let traded_quantity = cmp::min(aggressive_order.qty, passive_order.qty);
-                aggressive_order.qty -= traded_quantity;
-                passive_order.qty -= traded_quantity;
-                self.qty -= traded_quantity;
-                if passive_order.qty == 0
-                {
-                    need_to_remove = true;
-                }
-                println!("passive_order {:?}", passive_order);
-                println!("aggressive_order {:?}", aggressive_order);
-                trades.push(Trade{aggressive_id : aggressive_order.id, 
-                    passive_id : passive_order.id, 
-                    price : passive_order.price, 
-                    qty : traded_quantity})
-            }
+                Side::Buy => (aggressive_order.account, passive_order.account),
+                Side::Sell => (passive_order.account, aggressive_order.account),
+            };
+            trades.push(Trade{buy_account, 
+                sell_account, 
+                ..Trade::new(aggressive_order.id, passive_order.id, passive_order.price, traded_quantity)});
 
-            if need_to_remove
+            if passive_order.qty == 0
             {
-                self.orders.remove(0);
+                self._queue.remove(orders, handle);
             }
         }
-        
-        return trades;
     }
 
+    /// Returns the orders of the limit by time priority
+    /// 
+    /// # Arguments
+    /// 
+    /// * `orders` - The slab storing the orders of the book
+    pub fn orders<'a>(&self, orders : &'a OrderSlab) -> impl Iterator<Item = &'a Order> + 'a
+    {
+        self._queue.handles(orders).map(|handle| orders.get(handle))
+    }
 
     pub fn num_orders(&self) -> usize
     {
-        self.orders.len()
+        self._queue.len()
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests
 {
     use crate::data_types::Order;
     use crate::data_types::Limit;
     use crate::data_types::Side;
+    use crate::data_types::OrderType;
+    use crate::data_types::TimeInForce;
+    use crate::data_types::Trade;
+    use crate::data_types::MassCancelFilter;
+    use crate::slab::OrderSlab;
 
     #[test]
     fn try_order()
     {
-        let order = Order{id:1, side: Side::Buy, price:12.2f32, qty:100};
+        let order = Order{id:1, side: Side::Buy, price:12.2f32, qty:100, account:0, order_type: OrderType::Limit, time_in_force: TimeInForce::GoodTillCancel, timestamp:0, seq:0};
         let created_order = Order::new(1, Side::Buy, 12.2f32, 100);
         println!("{:?}", order);
         assert_eq!(created_order, order);
@@ -168,39 +415,42 @@ mod tests
     #[test]
     fn can_add_order()
     {
+        let mut orders = OrderSlab::with_capacity(4);
         let mut limit = Limit::new(12.12);
-        let order = Order{id:1, side:Side::Sell, price:12.2f32, qty:100};
-        limit.add_order(order);
+        let order = Order::new(1, Side::Sell, 12.2f32, 100);
+        limit.add_order(&mut orders, order);
         assert_eq!(limit.qty, 100);
-        assert_eq!(limit.orders.len(), 1);
+        assert_eq!(limit.num_orders(), 1);
     }
 
     #[test]
     fn can_add_multiple_order()
     {
+        let mut orders = OrderSlab::with_capacity(4);
         let mut limit = Limit::new(12.12);
-        let order = Order{id:1, side: Side::Buy, price:12.2f32, qty:100};
-        let order2 = Order{id:2, side: Side::Buy, price:12.2f32, qty:22};
-        limit.add_order(order);
-        limit.add_order(order2);
+        let order = Order::new(1, Side::Buy, 12.2f32, 100);
+        let order2 = Order::new(2, Side::Buy, 12.2f32, 22);
+        limit.add_order(&mut orders, order);
+        limit.add_order(&mut orders, order2);
         assert_eq!(limit.qty, 122);
-        assert_eq!(limit.orders.len(), 2);
+        assert_eq!(limit.num_orders(), 2);
     }
 
     #[test]
     fn can_remove_order_at_limit()
     {
+        let mut orders = OrderSlab::with_capacity(4);
         let mut limit = Limit::new(12.2f32);
-        let order = Order{id:1, side: Side::Buy, price:12.2f32, qty:100};
-        let order2 = Order{id:2, side: Side::Buy, price:12.2f32, qty:22};
-        limit.add_order(order);
-        limit.add_order(order2);
+        let order = Order::new(1, Side::Buy, 12.2f32, 100);
+        let order2 = Order::new(2, Side::Buy, 12.2f32, 22);
+        limit.add_order(&mut orders, order);
+        limit.add_order(&mut orders, order2);
         assert_eq!(limit.qty, 122);
-        let res = limit.remove_order(1);
+        let res = limit.remove_order(&mut orders, Side::Buy, 1);
 
         println!("{:?}",res.unwrap());
         assert_eq!(res.unwrap(), order);
-        assert_eq!(limit.orders.len(), 1);
+        assert_eq!(limit.num_orders(), 1);
         assert_eq!(limit.qty, 22);
     }
 
@@ -208,19 +458,20 @@ mod tests
     #[test]
     fn can_make_trade()
     {
+        let mut orders = OrderSlab::with_capacity(4);
         let mut limit = Limit::new(12.2f32);
-        let order =  Order{id:1, side: Side::Buy, price:12.2f32, qty:100};
-        let order2 = Order{id:2, side: Side::Buy, price:12.2f32, qty:22};
-        let order3 = Order{id:3, side: Side::Buy, price:12.2f32, qty:44};
+        let order =  Order::new(1, Side::Buy, 12.2f32, 100);
+        let order2 = Order::new(2, Side::Buy, 12.2f32, 22);
+        let order3 = Order::new(3, Side::Buy, 12.2f32, 44);
 
-        limit.add_order(order);
-        limit.add_order(order2);
-        limit.add_order(order3);
+        limit.add_order(&mut orders, order);
+        limit.add_order(&mut orders, order2);
+        limit.add_order(&mut orders, order3);
 
         assert_eq!(limit.qty, 166);
 
-        let mut order_to_match = Order{id : 4, side : Side::Sell, price:12.2f32, qty : 90};
-        let trades = limit.make_trades(&mut order_to_match);
+        let mut order_to_match = Order::new(4, Side::Sell, 12.2f32, 90);
+        let trades = limit.make_trades(&mut orders, &mut order_to_match);
         assert_eq!(trades.len(), 1);
         assert_eq!(limit.num_orders(), 3);
         assert_eq!(limit.qty, 166 - 90);
@@ -228,12 +479,107 @@ mod tests
         println!("trades = {:?}", trades);
     }
 
+    #[test]
+    fn trades_record_buy_and_sell_accounts()
+    {
+        let mut orders = OrderSlab::with_capacity(4);
+        let mut limit = Limit::new(12.2f32);
+        let mut passive = Order::new(1, Side::Buy, 12.2f32, 100);
+        passive.account = 7;
+        limit.add_order(&mut orders, passive);
+
+        let mut order_to_match = Order::new(2, Side::Sell, 12.2f32, 40);
+        order_to_match.account = 8;
+        let trades = limit.make_trades(&mut orders, &mut order_to_match);
+        assert_eq!(trades, vec![Trade { buy_account : 7, sell_account : 8, ..Trade::new(2, 1, 12.2f32, 40) }]);
+    }
+
     #[test]
     fn removing_empty_order_gives_error()
     {
+        let mut orders = OrderSlab::with_capacity(4);
         let mut limit = Limit::new(12.2f32);
-        let val = limit.remove_order(0);
+        let val = limit.remove_order(&mut orders, Side::Buy, 0);
         assert_eq!(val.is_err(), true);
         assert_eq!(val, Err("cannot remove order from limit"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn orders_of_another_limit_are_not_removed()
+    {
+        let mut orders = OrderSlab::with_capacity(4);
+        let mut limit = Limit::new(12.2f32);
+        let mut other = Limit::new(12.1f32);
+        let order = Order::new(1, Side::Buy, 12.2f32, 100);
+        let order2 = Order::new(2, Side::Buy, 12.1f32, 22);
+        limit.add_order(&mut orders, order);
+        other.add_order(&mut orders, order2);
+
+        assert_eq!(limit.remove_order(&mut orders, Side::Buy, 2), Err("cannot remove order from limit"));
+        assert_eq!(limit.reduce_order(&mut orders, Side::Buy, 2, 10), Err("cannot find order in limit"));
+        assert_eq!((limit.qty, other.qty), (100, 22));
+        assert_eq!(other.orders(&orders).copied().collect::<Vec<Order>>(), vec![order2]);
+        assert_eq!(other.remove_order(&mut orders, Side::Buy, 2), Ok(order2));
+
+        // The id reused at another limit is indexed last, each limit still finds its own
+        let reused = Order::new(1, Side::Buy, 12.1f32, 50);
+        other.add_order(&mut orders, reused);
+        assert_eq!(limit.find(&orders, Side::Buy, 1).map(|handle| *orders.get(handle)), Some(order));
+        assert_eq!(limit.find(&orders, Side::Sell, 1), None);
+        assert_eq!(limit.remove_order(&mut orders, Side::Buy, 1), Ok(order));
+        assert_eq!(other.remove_order(&mut orders, Side::Buy, 1), Ok(reused));
+    }
+
+    #[test]
+    fn filter_matches_selected_orders()
+    {
+        let mut order = Order::new(1, Side::Buy, 12.2f32, 100);
+        order.account = 7;
+
+        assert!(MassCancelFilter::default().matches(&order));
+        assert!(MassCancelFilter{account: Some(7), side: Some(Side::Buy), ..Default::default()}.matches(&order));
+        assert!(MassCancelFilter{price_range: Some((12.2f32, 12.5f32)), ..Default::default()}.matches(&order));
+        assert!(!MassCancelFilter{account: Some(8), ..Default::default()}.matches(&order));
+        assert!(!MassCancelFilter{side: Some(Side::Sell), ..Default::default()}.matches(&order));
+        assert!(!MassCancelFilter{price_range: Some((12.3f32, 12.5f32)), ..Default::default()}.matches(&order));
+        assert!(!MassCancelFilter{order_type: Some(OrderType::Market), ..Default::default()}.matches(&order));
+    }
+
+    #[test]
+    fn can_remove_orders_matching_filter()
+    {
+        let mut orders = OrderSlab::with_capacity(4);
+        let mut limit = Limit::new(12.2f32);
+        let mut order = Order::new(1, Side::Buy, 12.2f32, 100);
+        order.account = 7;
+        let order2 = Order::new(2, Side::Buy, 12.2f32, 22);
+        let mut order3 = Order::new(3, Side::Buy, 12.2f32, 44);
+        order3.account = 7;
+        limit.add_order(&mut orders, order);
+        limit.add_order(&mut orders, order2);
+        limit.add_order(&mut orders, order3);
+
+        let mut removed = vec![];
+        limit.remove_orders(&mut orders, &MassCancelFilter{account: Some(7), ..Default::default()}, &mut removed);
+        assert_eq!(removed, vec![order, order3]);
+        assert_eq!(limit.orders(&orders).copied().collect::<Vec<Order>>(), vec![order2]);
+        assert_eq!(limit.qty, 22);
+    }
+
+    #[test]
+    fn can_reduce_order_in_place()
+    {
+        let mut orders = OrderSlab::with_capacity(4);
+        let mut limit = Limit::new(12.2f32);
+        let order = Order::new(1, Side::Buy, 12.2f32, 100);
+        let order2 = Order::new(2, Side::Buy, 12.2f32, 22);
+        limit.add_order(&mut orders, order);
+        limit.add_order(&mut orders, order2);
+
+        assert_eq!(limit.reduce_order(&mut orders, Side::Buy, 1, 40), Ok(Some(order)));
+        assert_eq!(limit.orders(&orders).copied().collect::<Vec<Order>>(), vec![Order::new(1, Side::Buy, 12.2f32, 40), order2]);
+        assert_eq!(limit.qty, 62);
+        assert_eq!(limit.reduce_order(&mut orders, Side::Buy, 2, 50), Ok(None));
+        assert_eq!(limit.reduce_order(&mut orders, Side::Buy, 3, 10), Err("cannot find order in limit"));
+    }
+}
@@ -0,0 +1,182 @@
+use serde::{Deserialize, Serialize};
+use crate::data_types::{Limit, Side};
+
+/// DepthLevel is a price level aggregated over all its resting orders
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct DepthLevel
+{
+    pub price : f32,
+    pub qty : u32,
+    pub orders : u32,
+}
+
+impl DepthLevel
+{
+    pub fn from_limit(limit : &Limit) -> DepthLevel
+    {
+        DepthLevel { price : limit.price, qty : limit.qty, orders : limit.num_orders() as u32 }
+    }
+}
+
+/// Depth is a snapshot of the top price levels of both sides, best price first
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct Depth
+{
+    pub bids : Vec<DepthLevel>,
+    pub asks : Vec<DepthLevel>,
+}
+
+/// LevelUpdate is an incremental change of the aggregated depth
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum LevelUpdate
+{
+    /// A new price level entered the tracked depth
+    Add { side : Side, level : DepthLevel },
+    /// Quantity or number of orders of a tracked price level changed
+    Change { side : Side, level : DepthLevel },
+    /// A price level left the tracked depth, either emptied or pushed out by better levels
+    Delete { side : Side, price : f32 },
+}
+
+/// DepthTracker generates the incremental updates between consecutive depth snapshots,
+/// the WebSocket gateway keeps one per symbol to stream the depth of its subscribers
+/// * _levels is the number of price levels tracked per side
+/// * _last is the last snapshot sent to the subscribers
+#[derive(Debug)]
+pub struct DepthTracker
+{
+    _levels : usize,
+    _last : Depth,
+}
+
+/// diff_side appends the updates turning the previous levels into the current ones,
+/// the deletes come first so that a consumer never holds more than the tracked levels
+fn diff_side(side : Side, previous : &[DepthLevel], current : &[DepthLevel], updates : &mut Vec<LevelUpdate>)
+{
+    for level in previous
+    {
+        if !current.iter().any(|curr| curr.price == level.price)
+        {
+            updates.push(LevelUpdate::Delete { side, price : level.price });
+        }
+    }
+
+    for level in current
+    {
+        match previous.iter().find(|prev| prev.price == level.price)
+        {
+            None => updates.push(LevelUpdate::Add { side, level : *level }),
+            Some(prev) if prev != level => updates.push(LevelUpdate::Change { side, level : *level }),
+            Some(_) => {},
+        }
+    }
+}
+
+impl DepthTracker
+{
+    /// Creates a new tracker starting from an empty book
+    ///
+    /// # Arguments
+    ///
+    /// * `levels` - The number of price levels tracked per side
+    pub fn new(levels : usize) -> DepthTracker
+    {
+        DepthTracker { _levels : levels, _last : Depth::default() }
+    }
+
+    pub fn levels(&self) -> usize
+    {
+        self._levels
+    }
+
+    /// snapshot returns the last depth the updates have been generated from
+    pub fn snapshot(&self) -> &Depth
+    {
+        &self._last
+    }
+
+    /// update compares the new depth with the last one and returns the
+    /// level updates, bids first
+    ///
+    /// # Arguments
+    ///
+    /// * `depth` - The current depth of the book, usually taken after each command
+    pub fn update(&mut self, mut depth : Depth) -> Vec<LevelUpdate>
+    {
+        depth.bids.truncate(self._levels);
+        depth.asks.truncate(self._levels);
+
+        let mut updates = vec![];
+        diff_side(Side::Buy, &self._last.bids, &depth.bids, &mut updates);
+        diff_side(Side::Sell, &self._last.asks, &depth.asks, &mut updates);
+        self._last = depth;
+        updates
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::data_types::{Order, Side};
+    use crate::order_book::OrderBook;
+    use super::*;
+
+    fn level(price : f32, qty : u32, orders : u32) -> DepthLevel
+    {
+        DepthLevel { price, qty, orders }
+    }
+
+    #[test]
+    fn depth_aggregates_top_levels()
+    {
+        let mut order_book = OrderBook::new("TSLA");
+        order_book.insert_order_at_level(&mut Order::new(1, Side::Buy, 122.2f32, 100)).unwrap();
+        order_book.insert_order_at_level(&mut Order::new(2, Side::Buy, 122.2f32, 50)).unwrap();
+        order_book.insert_order_at_level(&mut Order::new(3, Side::Buy, 122.1f32, 25)).unwrap();
+        order_book.insert_order_at_level(&mut Order::new(4, Side::Buy, 122.0f32, 10)).unwrap();
+        order_book.insert_order_at_level(&mut Order::new(5, Side::Sell, 122.5f32, 30)).unwrap();
+
+        let depth = order_book.depth(2);
+        assert_eq!(depth.bids, vec![level(122.2f32, 150, 2), level(122.1f32, 25, 1)]);
+        assert_eq!(depth.asks, vec![level(122.5f32, 30, 1)]);
+    }
+
+    #[test]
+    fn tracker_emits_level_updates()
+    {
+        let mut order_book = OrderBook::new("TSLA");
+        let mut tracker = DepthTracker::new(2);
+
+        order_book.insert_order_at_level(&mut Order::new(1, Side::Buy, 122.2f32, 100)).unwrap();
+        assert_eq!(tracker.update(order_book.depth(tracker.levels())),
+                   vec![LevelUpdate::Add { side : Side::Buy, level : level(122.2f32, 100, 1) }]);
+
+        order_book.insert_order_at_level(&mut Order::new(2, Side::Buy, 122.2f32, 50)).unwrap();
+        assert_eq!(tracker.update(order_book.depth(tracker.levels())),
+                   vec![LevelUpdate::Change { side : Side::Buy, level : level(122.2f32, 150, 2) }]);
+
+        order_book.insert_order_at_level(&mut Order::new(3, Side::Buy, 122.0f32, 10)).unwrap();
+        assert_eq!(tracker.update(order_book.depth(tracker.levels())),
+                   vec![LevelUpdate::Add { side : Side::Buy, level : level(122.0f32, 10, 1) }]);
+
+        // Nothing changed in the tracked levels
+        order_book.insert_order_at_level(&mut Order::new(4, Side::Buy, 121.0f32, 10)).unwrap();
+        assert!(tracker.update(order_book.depth(tracker.levels())).is_empty());
+
+        // A better level pushes the worst one out of the tracked depth
+        order_book.insert_order_at_level(&mut Order::new(5, Side::Buy, 122.3f32, 5)).unwrap();
+        assert_eq!(tracker.update(order_book.depth(tracker.levels())), vec![
+            LevelUpdate::Delete { side : Side::Buy, price : 122.0f32 },
+            LevelUpdate::Add { side : Side::Buy, level : level(122.3f32, 5, 1) },
+        ]);
+
+        // A sell sweeps the best level
+        order_book.insert_order_at_level(&mut Order::new(6, Side::Sell, 122.3f32, 5)).unwrap();
+        assert_eq!(tracker.update(order_book.depth(tracker.levels())), vec![
+            LevelUpdate::Delete { side : Side::Buy, price : 122.3f32 },
+            LevelUpdate::Add { side : Side::Buy, level : level(122.0f32, 10, 1) },
+        ]);
+        assert_eq!(tracker.snapshot(), &order_book.depth(2));
+    }
+}
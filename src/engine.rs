@@ -0,0 +1,456 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use crate::admin::{AdminCommand, TradingControls};
+use crate::book::Book;
+use crate::clock::{Clock, MonotonicClock, Sequencer};
+use crate::data_types::*;
+use crate::events::Event;
+use crate::order_book::{LadderBook, OrderBook};
+use tracing::{debug_span, info};
+
+/// Engine routes the orders to the order book of their symbol, it contains
+/// * _books is the map containing an order book for each traded symbol, each symbol
+///   chooses its book implementation
+/// * _controls are the halts requested by the operators
+/// * _clock and _sequencer are shared by all the books
+#[derive(Debug)]
+pub struct Engine
+{
+    _books : BTreeMap<String, Box<dyn Book>>,
+    _controls : TradingControls,
+    _clock : Arc<dyn Clock>,
+    _sequencer : Arc<Sequencer>,
+}
+
+impl Default for Engine
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+impl Engine
+{
+    /// new function creates an engine without any symbol, timestamped by a monotonic clock
+    pub fn new() -> Engine
+    {
+        Engine::with_clock(Arc::new(MonotonicClock::new()))
+    }
+
+    /// with_clock creates an engine without any symbol
+    /// 
+    /// # Arguments
+    /// * clock: the clock timestamping the orders and trades of all the books
+    pub fn with_clock(clock : Arc<dyn Clock>) -> Engine
+    {
+        Engine::with_sequencer(clock, Arc::new(Sequencer::new()))
+    }
+
+    /// with_sequencer creates an engine without any symbol, numbering its orders and
+    /// trades with a sequencer which can be shared with other engines
+    ///
+    /// # Arguments
+    /// * clock: the clock timestamping the orders and trades of all the books
+    /// * sequencer: the sequencer of the orders, trades and matches of all the books
+    pub fn with_sequencer(clock : Arc<dyn Clock>, sequencer : Arc<Sequencer>) -> Engine
+    {
+        Engine {
+            _books : BTreeMap::new(),
+            _controls : TradingControls::default(),
+            _clock : clock,
+            _sequencer : sequencer }
+    }
+
+    pub fn clock(&self) -> &Arc<dyn Clock>
+    {
+        &self._clock
+    }
+
+    /// add_symbol creates an empty order book for the symbol, if it is not already traded
+    /// 
+    /// # Arguments
+    /// * symbol: the symbol to be traded
+    pub fn add_symbol(&mut self, symbol : &str)
+    {
+        if !self._books.contains_key(symbol)
+        {
+            let book = OrderBook::with_clock(symbol, self._clock.clone(), self._sequencer.clone());
+            self._books.insert(symbol.to_string(), Box::new(book));
+        }
+    }
+
+    /// add_ladder_symbol creates an empty ladder book for a symbol traded in a price band,
+    /// if it is not already traded
+    /// 
+    /// # Arguments
+    /// * symbol: the symbol to be traded
+    /// * band: the lowest price, the highest price and the tick size of the symbol
+    pub fn add_ladder_symbol(&mut self, symbol : &str, band : (f32, f32, f32)) -> Result<(), &'static str>
+    {
+        if !self._books.contains_key(symbol)
+        {
+            let book = LadderBook::with_ladder(symbol, band, self._clock.clone(), self._sequencer.clone())?;
+            self._books.insert(symbol.to_string(), Box::new(book));
+        }
+        Ok(())
+    }
+
+    /// symbols returns the traded symbols in alphabetical order
+    pub fn symbols(&self) -> impl Iterator<Item = &str>
+    {
+        self._books.keys().map(|symbol| symbol.as_str())
+    }
+
+    pub fn book(&self, symbol : &str) -> Option<&dyn Book>
+    {
+        self._books.get(symbol).map(|book| book.as_ref())
+    }
+
+    pub fn book_mut(&mut self, symbol : &str) -> Option<&mut (dyn Book + 'static)>
+    {
+        self._books.get_mut(symbol).map(|book| book.as_mut())
+    }
+
+    /// quotes returns the best bid and ask prices of the symbol
+    pub fn quotes(&self, symbol : &str) -> (Option<f32>, Option<f32>)
+    {
+        match self._books.get(symbol)
+        {
+            Some(book) => (book.best_bid().map(|limit| limit.price), book.best_ask().map(|limit| limit.price)),
+            None => (None, None),
+        }
+    }
+
+    /// order looks up a resting order in the books of all the symbols
+    ///
+    /// # Arguments
+    /// * order_id: the id of the order
+    /// # Return
+    ///
+    /// The symbol of the order and the order as it rests in the book
+    pub fn order(&self, order_id : u32) -> Option<(&str, Order)>
+    {
+        self._books.iter().find_map(|(symbol, book)| book.order(order_id).map(|order| (symbol.as_str(), order)))
+    }
+
+    pub fn controls(&self) -> &TradingControls
+    {
+        &self._controls
+    }
+
+    /// insert_order matches and rests the order in the order book of the symbol,
+    /// unless the symbol, the account or all the new orders are halted
+    /// 
+    /// # Arguments
+    /// * symbol: the symbol of the order
+    /// * order: the incoming order, its quantity is reduced by the matched quantity
+    pub fn insert_order(&mut self, symbol : &str, order : &mut Order) -> Result<(), &'static str>
+    {
+        let _span = debug_span!("insert_order", symbol, order_id = order.id, account = order.account).entered();
+        let book = self._books.get_mut(symbol).ok_or("Symbol is not traded by the Engine")?;
+        self._controls.check_new_order(symbol, order.account)?;
+        book.submit(order)
+    }
+
+    /// cancel_order cancels a resting order from the order book of the symbol
+    /// 
+    /// # Arguments
+    /// * symbol: the symbol of the order
+    /// * order: the order to be cancelled
+    pub fn cancel_order(&mut self, symbol : &str, order : &Order) -> Result<Order, &'static str>
+    {
+        let _span = debug_span!("cancel_order", symbol, order_id = order.id).entered();
+        let book = self._books.get_mut(symbol).ok_or("Symbol is not traded by the Engine")?;
+        book.cancel(order)
+    }
+
+    /// amend_order changes price and quantity of a resting order of the symbol,
+    /// it is subject to the same halts as the new orders. The account halts apply
+    /// to the account of the resting order, whatever the account of the request
+    /// 
+    /// # Arguments
+    /// * symbol: the symbol of the order
+    /// * order: the order to be amended, only id, side and price are used
+    /// * price: the new limit price
+    /// * qty: the new quantity left to be executed
+    pub fn amend_order(&mut self, symbol : &str, order : &Order, price : f32, qty : u32) -> Result<Order, &'static str>
+    {
+        let _span = debug_span!("amend_order", symbol, order_id = order.id, price, qty).entered();
+        let book = self._books.get_mut(symbol).ok_or("Symbol is not traded by the Engine")?;
+        let resting = book.order(order.id).filter(|resting| resting.side == order.side && resting.price == order.price);
+        self._controls.check_new_order(symbol, resting.map_or(order.account, |resting| resting.account))?;
+        book.amend(resting.as_ref().unwrap_or(order), price, qty)
+    }
+
+    /// admin applies an operator command, the kill switch cancels every resting order
+    /// 
+    /// # Arguments
+    /// * command: the command to be applied
+    /// # Return
+    /// 
+    /// The number of cancelled orders
+    pub fn admin(&mut self, command : &AdminCommand) -> Result<usize, &'static str>
+    {
+        if let AdminCommand::HaltSymbol(symbol) | AdminCommand::ResumeSymbol(symbol) = command
+        {
+            if !self._books.contains_key(symbol)
+            {
+                return Err("Symbol is not traded by the Engine");
+            }
+        }
+
+        info!(%command, "admin command");
+        self._controls.apply(command);
+        match command
+        {
+            AdminCommand::KillSwitch => self.mass_cancel(None, &MassCancelFilter::default()),
+            AdminCommand::CloseSession => Ok(self.close_session()),
+            _ => Ok(0),
+        }
+    }
+
+    /// mass_cancel cancels the orders selected by the filter, either on a single symbol
+    /// or on all the traded symbols. Each book emits its cancel events and summary ack
+    /// 
+    /// # Arguments
+    /// * symbol: the symbol to be cleaned up, None selects all the symbols
+    /// * filter: the filter selecting the orders to be cancelled
+    /// # Return
+    /// 
+    /// The number of cancelled orders
+    pub fn mass_cancel(&mut self, symbol : Option<&str>, filter : &MassCancelFilter) -> Result<usize, &'static str>
+    {
+        match symbol
+        {
+            Some(symbol) =>
+            {
+                let book = self._books.get_mut(symbol).ok_or("Symbol is not traded by the Engine")?;
+                Ok(book.mass_cancel(filter).len())
+            },
+            None => Ok(self._books.values_mut().map(|book| book.mass_cancel(filter).len()).sum()),
+        }
+    }
+
+    /// expire_orders removes from all the books the GTD orders whose expiry time is
+    /// reached by the engine clock
+    /// 
+    /// # Return
+    /// 
+    /// The number of expired orders
+    pub fn expire_orders(&mut self) -> usize
+    {
+        self._books.values_mut().map(|book| book.expire_orders().len()).sum()
+    }
+
+    /// close_session removes the DAY orders from all the books at the end of the trading session
+    /// 
+    /// # Return
+    /// 
+    /// The number of expired orders
+    pub fn close_session(&mut self) -> usize
+    {
+        self._books.values_mut().map(|book| book.close_session().len()).sum()
+    }
+
+    /// drain_events returns the events collected by all the order books, paired with their symbol
+    pub fn drain_events(&mut self) -> Vec<(String, Event)>
+    {
+        let mut events = vec![];
+        for (symbol, book) in self._books.iter_mut()
+        {
+            events.extend(book.drain_events().into_iter().map(|event| (symbol.clone(), event)));
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::sync::Arc;
+    use crate::admin::AdminCommand;
+    use crate::clock::ManualClock;
+    use crate::data_types::*;
+    use crate::events::Event;
+    use super::Engine;
+
+    fn order_for_account(id : u32, side : Side, price : f32, qty : u32, account : u32) -> Order
+    {
+        let mut order = Order::new(id, side, price, qty);
+        order.account = account;
+        order
+    }
+
+    #[test]
+    fn cannot_insert_order_on_unknown_symbol()
+    {
+        let mut engine = Engine::new();
+        engine.add_symbol("AAPL");
+        let mut order = Order::new(1, Side::Buy, 12.2f32, 100);
+        assert_eq!(engine.insert_order("TSLA", &mut order), Err("Symbol is not traded by the Engine"));
+        assert_eq!(engine.insert_order("AAPL", &mut order), Ok(()));
+        assert_eq!(engine.book("AAPL").unwrap().best_bid().unwrap().qty, 100);
+    }
+
+    #[test]
+    fn ladder_symbols_reject_prices_outside_of_their_band()
+    {
+        let mut engine = Engine::new();
+        assert!(engine.add_ladder_symbol("AAPL", (12.0, 11.0, 0.01)).is_err());
+        assert!(engine.symbols().next().is_none());
+        engine.add_ladder_symbol("AAPL", (10.0, 20.0, 0.01)).unwrap();
+        engine.add_symbol("TSLA");
+
+        assert!(engine.insert_order("AAPL", &mut Order::new(1, Side::Buy, 21.0f32, 100)).is_err());
+        assert!(engine.insert_order("TSLA", &mut Order::new(2, Side::Buy, 21.0f32, 100)).is_ok());
+        engine.insert_order("AAPL", &mut Order::new(3, Side::Buy, 12.2f32, 100)).unwrap();
+        engine.insert_order("AAPL", &mut Order::new(4, Side::Sell, 12.2f32, 40)).unwrap();
+        assert_eq!(engine.book("AAPL").unwrap().best_bid().unwrap().qty, 60);
+        assert_eq!(engine.symbols().collect::<Vec<&str>>(), vec!["AAPL", "TSLA"]);
+    }
+
+    #[test]
+    fn books_share_the_engine_clock()
+    {
+        let clock = Arc::new(ManualClock::new(1_000));
+        let mut engine = Engine::with_clock(clock.clone());
+        engine.add_symbol("AAPL");
+        engine.add_symbol("TSLA");
+        let mut order1 = Order::new(1, Side::Buy, 12.2f32, 100);
+        engine.insert_order("AAPL", &mut order1).unwrap();
+        clock.advance(500);
+        let mut order2 = Order::new(2, Side::Sell, 122.2f32, 10);
+        engine.insert_order("TSLA", &mut order2).unwrap();
+        clock.advance(500);
+        engine.insert_order("AAPL", &mut Order::new(3, Side::Sell, 12.2f32, 100)).unwrap();
+
+        assert_eq!((order1.timestamp, order1.seq), (1_000, 1));
+        assert_eq!((order2.timestamp, order2.seq), (1_500, 2));
+        let trades = engine.book("AAPL").unwrap().trades().to_vec();
+        assert_eq!(trades.len(), 1);
+        assert_eq!((trades[0].timestamp, trades[0].aggressor_side), (2_000, Side::Sell));
+    }
+
+    #[test]
+    fn can_mass_cancel_account_on_all_symbols()
+    {
+        let mut engine = Engine::new();
+        engine.add_symbol("AAPL");
+        engine.add_symbol("TSLA");
+        // The orders are stamped with their entry time and sequence on insertion
+        let mut order1 = order_for_account(1, Side::Buy, 12.2f32, 100, 7);
+        let mut order3 = order_for_account(3, Side::Sell, 122.2f32, 10, 7);
+        engine.insert_order("AAPL", &mut order1).unwrap();
+        engine.insert_order("AAPL", &mut order_for_account(2, Side::Buy, 12.2f32, 50, 8)).unwrap();
+        engine.insert_order("TSLA", &mut order3).unwrap();
+        assert_eq!((order1.seq, order3.seq), (1, 3));
+        engine.drain_events();
+
+        let filter = MassCancelFilter{account: Some(7), ..Default::default()};
+        assert_eq!(engine.mass_cancel(None, &filter), Ok(2));
+        assert_eq!(engine.book("AAPL").unwrap().best_bid().unwrap().qty, 50);
+        assert!(engine.book("TSLA").unwrap().best_ask().is_none());
+
+        let events = engine.drain_events();
+        assert_eq!(events, vec![
+            ("AAPL".to_string(), Event::Cancelled(order1)),
+            ("AAPL".to_string(), Event::MassCancelled { cancelled_orders: 1, cancelled_qty: 100 }),
+            ("TSLA".to_string(), Event::Cancelled(order3)),
+            ("TSLA".to_string(), Event::MassCancelled { cancelled_orders: 1, cancelled_qty: 10 }),
+        ]);
+    }
+
+    #[test]
+    fn halts_block_new_orders_but_not_cancels()
+    {
+        let mut engine = Engine::new();
+        engine.add_symbol("AAPL");
+        engine.add_symbol("TSLA");
+        let mut resting = order_for_account(1, Side::Buy, 122.2f32, 100, 7);
+        engine.insert_order("TSLA", &mut resting).unwrap();
+
+        assert_eq!(engine.admin(&AdminCommand::HaltSymbol("MSFT".to_string())), Err("Symbol is not traded by the Engine"));
+        assert_eq!(engine.admin(&AdminCommand::HaltSymbol("TSLA".to_string())), Ok(0));
+        assert_eq!(engine.insert_order("TSLA", &mut Order::new(2, Side::Sell, 122.2f32, 10)), Err("Symbol is halted"));
+        assert_eq!(engine.amend_order("TSLA", &resting, 122.3f32, 100), Err("Symbol is halted"));
+        assert_eq!(engine.insert_order("AAPL", &mut Order::new(3, Side::Sell, 12.2f32, 10)), Ok(()));
+
+        engine.admin(&AdminCommand::HaltAccount(7)).unwrap();
+        assert_eq!(engine.insert_order("AAPL", &mut order_for_account(4, Side::Sell, 12.2f32, 10, 7)), Err("Account is halted"));
+        assert_eq!(engine.cancel_order("TSLA", &resting), Ok(resting));
+
+        engine.admin(&AdminCommand::ResumeSymbol("TSLA".to_string())).unwrap();
+        assert_eq!(engine.insert_order("TSLA", &mut Order::new(5, Side::Sell, 122.2f32, 10)), Ok(()));
+
+        // The amend of an order of a halted account is rejected whatever the account sent
+        let mut halted = order_for_account(6, Side::Buy, 12.1f32, 100, 8);
+        engine.insert_order("AAPL", &mut halted).unwrap();
+        engine.admin(&AdminCommand::HaltAccount(8)).unwrap();
+        assert_eq!(engine.amend_order("AAPL", &Order { account : 9, ..halted }, 12.1f32, 50), Err("Account is halted"));
+        assert_eq!(engine.order(6), Some(("AAPL", halted)));
+    }
+
+    #[test]
+    fn kill_switch_cancels_everything()
+    {
+        let mut engine = Engine::new();
+        engine.add_symbol("AAPL");
+        engine.add_symbol("TSLA");
+        engine.insert_order("AAPL", &mut Order::new(1, Side::Buy, 12.2f32, 100)).unwrap();
+        engine.insert_order("TSLA", &mut Order::new(2, Side::Sell, 122.2f32, 100)).unwrap();
+
+        assert_eq!(engine.admin(&AdminCommand::KillSwitch), Ok(2));
+        assert!(engine.book("AAPL").unwrap().best_bid().is_none());
+        assert!(engine.book("TSLA").unwrap().best_ask().is_none());
+        assert_eq!(engine.insert_order("AAPL", &mut Order::new(3, Side::Buy, 12.2f32, 100)), Err("New orders are disabled"));
+
+        engine.admin(&AdminCommand::EnableNewOrders).unwrap();
+        assert_eq!(engine.insert_order("AAPL", &mut Order::new(3, Side::Buy, 12.2f32, 100)), Ok(()));
+    }
+
+    #[test]
+    fn resting_orders_are_found_on_any_symbol()
+    {
+        let mut engine = Engine::new();
+        engine.add_symbol("AAPL");
+        engine.add_symbol("TSLA");
+        let mut order = Order::new(2, Side::Sell, 122.2f32, 100);
+        engine.insert_order("AAPL", &mut Order::new(1, Side::Buy, 12.2f32, 100)).unwrap();
+        engine.insert_order("TSLA", &mut order).unwrap();
+
+        assert_eq!(engine.order(2), Some(("TSLA", order)));
+        assert_eq!(engine.order(1).map(|(symbol, _)| symbol), Some("AAPL"));
+        assert_eq!(engine.order(3), None);
+    }
+
+    #[test]
+    fn close_rejects_new_orders_until_enabled()
+    {
+        let mut engine = Engine::new();
+        engine.add_symbol("TSLA");
+        let mut day = Order::new(1, Side::Buy, 122.2f32, 100);
+        day.time_in_force = TimeInForce::Day;
+        engine.insert_order("TSLA", &mut day).unwrap();
+
+        assert_eq!(engine.admin(&AdminCommand::CloseSession), Ok(1));
+        assert_eq!(engine.insert_order("TSLA", &mut Order::new(2, Side::Buy, 122.2f32, 100)), Err("Trading session is closed"));
+        engine.admin(&AdminCommand::EnableNewOrders).unwrap();
+        assert_eq!(engine.insert_order("TSLA", &mut Order::new(2, Side::Buy, 122.2f32, 100)), Ok(()));
+    }
+
+    #[test]
+    fn can_mass_cancel_single_symbol()
+    {
+        let mut engine = Engine::new();
+        engine.add_symbol("AAPL");
+        engine.add_symbol("TSLA");
+        engine.insert_order("AAPL", &mut Order::new(1, Side::Buy, 12.2f32, 100)).unwrap();
+        engine.insert_order("TSLA", &mut Order::new(2, Side::Buy, 122.2f32, 100)).unwrap();
+
+        assert_eq!(engine.mass_cancel(Some("TSLA"), &MassCancelFilter::default()), Ok(1));
+        assert!(engine.book("AAPL").unwrap().best_bid().is_some());
+        assert!(engine.book("TSLA").unwrap().best_bid().is_none());
+        assert_eq!(engine.mass_cancel(Some("MSFT"), &MassCancelFilter::default()), Err("Symbol is not traded by the Engine"));
+    }
+}
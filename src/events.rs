@@ -0,0 +1,21 @@
+use crate::data_types::*;
+
+/// Event represents a change in the state of the order book, the events are
+/// collected by the book in the order in which they happened
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Event
+{
+    /// The unmatched quantity of an order has been rested in the book
+    Added(Order),
+    /// An incoming order matched against a resting one
+    Traded(Trade),
+    /// A resting order changed price or quantity, new holds the amended order
+    /// before it is matched against the book
+    Replaced { old : Order, new : Order },
+    /// A resting order has been removed from the book upon request
+    Cancelled(Order),
+    /// A resting order has been removed from the book by its time in force
+    Expired(Order),
+    /// Summary ack of a mass cancel request, sent after all the cancel events
+    MassCancelled { cancelled_orders : u32, cancelled_qty : u64 },
+}
@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use crate::data_types::*;
+
+/// FeeTier gives the rates applied once an account traded at least min_volume shares,
+/// the rates are fractions of the notional and a negative maker rate is a rebate
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct FeeTier
+{
+    pub min_volume : u64,
+    pub maker_rate : f64,
+    pub taker_rate : f64,
+}
+
+/// FeeSchedule is a set of volume tiers plus the minimum fee charged on a fill,
+/// the free fills and the rebates are never subject to the minimum
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct FeeSchedule
+{
+    pub tiers : Vec<FeeTier>,
+    pub min_fee : f64,
+}
+
+impl FeeSchedule
+{
+    /// flat creates a schedule with a single tier
+    pub fn flat(maker_rate : f64, taker_rate : f64, min_fee : f64) -> FeeSchedule
+    {
+        FeeSchedule { tiers : vec![FeeTier { min_volume : 0, maker_rate, taker_rate }], min_fee }
+    }
+
+    /// tier returns the tier reached by the traded volume, the tiers may be given in any order
+    pub fn tier(&self, volume : u64) -> Option<&FeeTier>
+    {
+        self.tiers.iter().filter(|tier| tier.min_volume <= volume).max_by_key(|tier| tier.min_volume)
+    }
+
+    /// fee computes the fee of a fill, negative for a rebate
+    ///
+    /// # Arguments
+    ///
+    /// * `volume` - The volume traded by the account before the fill
+    /// * `notional` - The price times quantity of the fill
+    /// * `maker` - Whether the account provided the liquidity
+    pub fn fee(&self, volume : u64, notional : f64, maker : bool) -> f64
+    {
+        let tier = match self.tier(volume)
+        {
+            Some(tier) => tier,
+            None => return 0.0,
+        };
+        let rate = if maker { tier.maker_rate } else { tier.taker_rate };
+        // A zero rate means the fill is free, the minimum only applies to a charged fee
+        if rate <= 0.0
+        {
+            notional * rate
+        }
+        else
+        {
+            (notional * rate).max(self.min_fee)
+        }
+    }
+}
+
+/// FeeCalculator charges the fills of an instrument, every account gets its own
+/// schedule or the default one and climbs the tiers with its traded volume
+/// * _volumes is the volume traded by each account on the instrument
+#[derive(Clone, Debug, Default)]
+pub struct FeeCalculator
+{
+    _default : FeeSchedule,
+    _accounts : HashMap<u32, FeeSchedule>,
+    _volumes : HashMap<u32, u64>,
+}
+
+impl FeeCalculator
+{
+    pub fn new(default : FeeSchedule) -> FeeCalculator
+    {
+        FeeCalculator { _default : default, ..Default::default() }
+    }
+
+    pub fn set_default_schedule(&mut self, schedule : FeeSchedule)
+    {
+        self._default = schedule;
+    }
+
+    pub fn set_account_schedule(&mut self, account : u32, schedule : FeeSchedule)
+    {
+        self._accounts.insert(account, schedule);
+    }
+
+    pub fn schedule(&self, account : u32) -> &FeeSchedule
+    {
+        self._accounts.get(&account).unwrap_or(&self._default)
+    }
+
+    /// volume returns the volume traded by the account
+    pub fn volume(&self, account : u32) -> u64
+    {
+        self._volumes.get(&account).copied().unwrap_or(0)
+    }
+
+    fn charge_account(&mut self, account : u32, trade : &Trade, maker : bool) -> f64
+    {
+        let volume = self._volumes.entry(account).or_insert(0);
+        let schedule = self._accounts.get(&account).unwrap_or(&self._default);
+        let fee = schedule.fee(*volume, trade.price as f64 * trade.qty as f64, maker);
+        *volume += trade.qty as u64;
+        fee
+    }
+
+    /// charge fills the maker and taker fees of the trades of an incoming order
+    ///
+    /// # Arguments
+    ///
+    /// * `taker` - The aggressive order, the passive orders are the makers
+    /// * `trades` - The trades of the aggressive order
+    pub fn charge(&mut self, taker : &Order, trades : &mut [Trade])
+    {
+        for trade in trades.iter_mut()
+        {
+            let maker_account = match taker.side
+            {
+                Side::Buy => trade.sell_account,
+                Side::Sell => trade.buy_account,
+            };
+            trade.taker_fee = self.charge_account(taker.account, trade, false);
+            trade.maker_fee = self.charge_account(maker_account, trade, true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn tiered() -> FeeSchedule
+    {
+        FeeSchedule {
+            tiers : vec![
+                FeeTier { min_volume : 1000, maker_rate : -0.0002, taker_rate : 0.0002 },
+                FeeTier { min_volume : 0, maker_rate : 0.0, taker_rate : 0.0003 },
+            ],
+            min_fee : 0.05 }
+    }
+
+    #[test]
+    fn applies_tiers_and_minimum_fee()
+    {
+        let schedule = tiered();
+        assert_eq!(schedule.tier(999).unwrap().min_volume, 0);
+        assert_eq!(schedule.tier(1000).unwrap().min_volume, 1000);
+        assert!((schedule.fee(0, 10000.0, false) - 3.0).abs() < 1e-9);
+        assert!((schedule.fee(1000, 10000.0, false) - 2.0).abs() < 1e-9);
+        assert_eq!(schedule.fee(0, 100.0, false), 0.05);
+        // The fills at a zero rate are free and the rebates are paid in full
+        assert_eq!(schedule.fee(0, 10000.0, true), 0.0);
+        assert!((schedule.fee(1000, 100.0, true) + 0.02).abs() < 1e-9);
+        assert_eq!(FeeSchedule::default().fee(0, 10000.0, false), 0.0);
+    }
+
+    #[test]
+    fn minimum_fee_only_applies_to_charged_fills()
+    {
+        let schedule = FeeSchedule::flat(0.0, 0.0001, 1.0);
+        assert_eq!(schedule.fee(0, 100.0, true), 0.0);
+        assert_eq!(schedule.fee(0, 100.0, false), 1.0);
+        assert!((schedule.fee(0, 100000.0, false) - 10.0).abs() < 1e-9);
+        assert_eq!(FeeSchedule::flat(0.0, 0.0, 1.0).fee(0, 100.0, false), 0.0);
+    }
+
+    #[test]
+    fn charges_makers_and_takers_per_account()
+    {
+        let mut fees = FeeCalculator::new(tiered());
+        fees.set_account_schedule(9, FeeSchedule::flat(-0.001, 0.001, 0.0));
+
+        let mut taker = Order::new(3, Side::Buy, 100.0f32, 1500);
+        taker.account = 7;
+        let mut trades = vec![
+            Trade { buy_account : 7, sell_account : 8, ..Trade::new(3, 1, 100.0f32, 1000) },
+            Trade { buy_account : 7, sell_account : 9, ..Trade::new(3, 2, 100.0f32, 500) },
+        ];
+        fees.charge(&taker, &mut trades);
+
+        // The second fill of the taker reaches the next tier
+        assert!((trades[0].taker_fee - 30.0).abs() < 1e-9);
+        assert_eq!(trades[0].maker_fee, 0.0);
+        assert!((trades[1].taker_fee - 10.0).abs() < 1e-9);
+        assert!((trades[1].maker_fee + 50.0).abs() < 1e-9);
+        assert_eq!(fees.volume(7), 1500);
+        assert_eq!(fees.volume(9), 500);
+    }
+}
@@ -0,0 +1,390 @@
+use bytes::{Buf, BytesMut};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const BEGIN_STRING : &str = "FIX.4.4";
+const SOH : u8 = 0x01;
+
+// Standard header and trailer tags
+pub const BEGIN_STRING_TAG : u32 = 8;
+pub const BODY_LENGTH : u32 = 9;
+pub const CHECK_SUM : u32 = 10;
+pub const MSG_SEQ_NUM : u32 = 34;
+pub const MSG_TYPE : u32 = 35;
+pub const POSS_DUP_FLAG : u32 = 43;
+pub const SENDER_COMP_ID : u32 = 49;
+pub const SENDING_TIME : u32 = 52;
+pub const TARGET_COMP_ID : u32 = 56;
+pub const ORIG_SENDING_TIME : u32 = 122;
+
+// Session level tags
+pub const BEGIN_SEQ_NO : u32 = 7;
+pub const END_SEQ_NO : u32 = 16;
+pub const NEW_SEQ_NO : u32 = 36;
+pub const REF_SEQ_NUM : u32 = 45;
+pub const TEXT : u32 = 58;
+pub const ENCRYPT_METHOD : u32 = 98;
+pub const HEART_BT_INT : u32 = 108;
+pub const TEST_REQ_ID : u32 = 112;
+pub const GAP_FILL_FLAG : u32 = 123;
+
+// Application level tags
+pub const ACCOUNT : u32 = 1;
+pub const AVG_PX : u32 = 6;
+pub const CL_ORD_ID : u32 = 11;
+pub const COMMISSION : u32 = 12;
+pub const COMM_TYPE : u32 = 13;
+pub const CUM_QTY : u32 = 14;
+pub const EXEC_ID : u32 = 17;
+pub const LAST_PX : u32 = 31;
+pub const LAST_QTY : u32 = 32;
+pub const ORDER_ID : u32 = 37;
+pub const ORDER_QTY : u32 = 38;
+pub const ORD_STATUS : u32 = 39;
+pub const ORD_TYPE : u32 = 40;
+pub const ORIG_CL_ORD_ID : u32 = 41;
+pub const PRICE : u32 = 44;
+pub const SIDE : u32 = 54;
+pub const SYMBOL : u32 = 55;
+pub const TIME_IN_FORCE : u32 = 59;
+pub const TRANSACT_TIME : u32 = 60;
+pub const EXPIRE_TIME : u32 = 126;
+pub const EXEC_TYPE : u32 = 150;
+pub const LEAVES_QTY : u32 = 151;
+pub const CXL_REJ_RESPONSE_TO : u32 = 434;
+pub const TRD_MATCH_ID : u32 = 880;
+
+// Message types
+pub const HEARTBEAT : &str = "0";
+pub const TEST_REQUEST : &str = "1";
+pub const RESEND_REQUEST : &str = "2";
+pub const REJECT : &str = "3";
+pub const SEQUENCE_RESET : &str = "4";
+pub const LOGOUT : &str = "5";
+pub const EXECUTION_REPORT : &str = "8";
+pub const ORDER_CANCEL_REJECT : &str = "9";
+pub const LOGON : &str = "A";
+pub const NEW_ORDER_SINGLE : &str = "D";
+pub const ORDER_CANCEL_REQUEST : &str = "F";
+pub const ORDER_CANCEL_REPLACE_REQUEST : &str = "G";
+
+/// FixMessage is a FIX tag=value message, the fields are kept in the order
+/// in which they have been added or received
+#[derive(Clone, PartialEq, Debug)]
+pub struct FixMessage
+{
+    pub fields : Vec<(u32, String)>,
+}
+
+/// is_admin returns true for the session level message types
+pub fn is_admin(msg_type : &str) -> bool
+{
+    matches!(msg_type, HEARTBEAT | TEST_REQUEST | RESEND_REQUEST | REJECT | SEQUENCE_RESET | LOGOUT | LOGON)
+}
+
+impl FixMessage
+{
+    /// Creates a new message of the given type without any other field
+    ///
+    /// # Arguments
+    ///
+    /// * `msg_type` - The value of the MsgType(35) field
+    pub fn new(msg_type : &str) -> FixMessage
+    {
+        FixMessage { fields : vec![(MSG_TYPE, msg_type.to_string())] }
+    }
+
+    /// Appends a field to the message, returning the message itself
+    pub fn with<T : ToString>(mut self, tag : u32, value : T) -> FixMessage
+    {
+        self.push(tag, value);
+        self
+    }
+
+    pub fn push<T : ToString>(&mut self, tag : u32, value : T)
+    {
+        self.fields.push((tag, value.to_string()));
+    }
+
+    /// Returns the value of the first occurrence of the tag
+    pub fn get(&self, tag : u32) -> Option<&str>
+    {
+        self.fields.iter().find(|(t, _)| *t == tag).map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the value of the tag parsed to the requested type,
+    /// None if the tag is missing or cannot be parsed
+    pub fn get_as<T : FromStr>(&self, tag : u32) -> Option<T>
+    {
+        self.get(tag).and_then(|value| value.parse().ok())
+    }
+
+    pub fn msg_type(&self) -> &str
+    {
+        self.get(MSG_TYPE).unwrap_or("")
+    }
+
+    pub fn seq_num(&self) -> u64
+    {
+        self.get_as(MSG_SEQ_NUM).unwrap_or(0)
+    }
+
+    /// Encodes the message adding the standard header and trailer,
+    /// the header and trailer fields already present in the message are skipped
+    ///
+    /// # Arguments
+    ///
+    /// * `sender` - The SenderCompID(49) of the message
+    /// * `target` - The TargetCompID(56) of the message
+    /// * `seq_num` - The MsgSeqNum(34) of the message
+    /// * `sending_time` - The SendingTime(52) of the message
+    pub fn encode(&self, sender : &str, target : &str, seq_num : u64, sending_time : &str) -> Vec<u8>
+    {
+        let mut body = Vec::with_capacity(128);
+        let mut put = |tag : u32, value : &str|
+        {
+            body.extend_from_slice(tag.to_string().as_bytes());
+            body.push(b'=');
+            body.extend_from_slice(value.as_bytes());
+            body.push(SOH);
+        };
+
+        put(MSG_TYPE, self.msg_type());
+        put(SENDER_COMP_ID, sender);
+        put(TARGET_COMP_ID, target);
+        put(MSG_SEQ_NUM, &seq_num.to_string());
+        put(SENDING_TIME, sending_time);
+        for (tag, value) in self.fields.iter()
+        {
+            match *tag
+            {
+                BEGIN_STRING_TAG | BODY_LENGTH | CHECK_SUM | MSG_TYPE | SENDER_COMP_ID
+                    | TARGET_COMP_ID | MSG_SEQ_NUM | SENDING_TIME => {},
+                _ => put(*tag, value),
+            }
+        }
+
+        let mut buf = format!("{}={}\x01{}={}\x01", BEGIN_STRING_TAG, BEGIN_STRING, BODY_LENGTH, body.len()).into_bytes();
+        buf.append(&mut body);
+        let check_sum = checksum(&buf);
+        buf.extend_from_slice(format!("{}={:03}\x01", CHECK_SUM, check_sum).as_bytes());
+        buf
+    }
+
+    /// Decodes the first complete message in the buffer and consumes its bytes
+    ///
+    /// # Arguments
+    ///
+    /// * `buf` - The buffer containing the received bytes
+    ///
+    /// # Return
+    ///
+    /// None if the buffer does not contain a complete message yet, an error if
+    /// the bytes cannot be a valid FIX message
+    pub fn decode(buf : &mut BytesMut) -> Result<Option<FixMessage>, &'static str>
+    {
+        let prefix = format!("{}={}\x01{}=", BEGIN_STRING_TAG, BEGIN_STRING, BODY_LENGTH);
+        let prefix = prefix.as_bytes();
+        let available = buf.len().min(prefix.len());
+        if buf[..available] != prefix[..available]
+        {
+            return Err("message does not start with BeginString and BodyLength");
+        }
+
+        let length_end = match buf[prefix.len().min(buf.len())..].iter().position(|&b| b == SOH)
+        {
+            Some(pos) => prefix.len() + pos,
+            None if buf.len() > prefix.len() + 8 => return Err("BodyLength is too long"),
+            None => return Ok(None),
+        };
+
+        let body_length : usize = std::str::from_utf8(&buf[prefix.len()..length_end]).ok()
+            .and_then(|length| length.parse().ok())
+            .ok_or("BodyLength is not a number")?;
+
+        // 10=xxx<SOH> trailer
+        let body_end = length_end + 1 + body_length;
+        let total_length = body_end + 7;
+        if buf.len() < total_length
+        {
+            return Ok(None);
+        }
+
+        let trailer = &buf[body_end..total_length];
+        if &trailer[..3] != b"10=" || trailer[6] != SOH
+        {
+            return Err("CheckSum is not at the end of the message");
+        }
+
+        let received_check_sum : u8 = std::str::from_utf8(&trailer[3..6]).ok()
+            .and_then(|value| value.parse().ok())
+            .ok_or("CheckSum is not a number")?;
+        if received_check_sum != checksum(&buf[..body_end])
+        {
+            return Err("CheckSum does not match");
+        }
+
+        let mut fields = vec![];
+        for field in buf[..body_end].split(|&b| b == SOH).filter(|field| !field.is_empty())
+        {
+            let field = std::str::from_utf8(field).map_err(|_| "field is not valid UTF-8")?;
+            let (tag, value) = field.split_once('=').ok_or("field without tag separator")?;
+            let tag = tag.parse().map_err(|_| "tag is not a number")?;
+            if tag != BEGIN_STRING_TAG && tag != BODY_LENGTH
+            {
+                fields.push((tag, value.to_string()));
+            }
+        }
+
+        buf.advance(total_length);
+        let message = FixMessage { fields };
+        if message.get(MSG_TYPE).is_none()
+        {
+            return Err("MsgType is missing");
+        }
+        Ok(Some(message))
+    }
+}
+
+/// checksum computes the FIX checksum, the sum of all the bytes modulo 256
+fn checksum(bytes : &[u8]) -> u8
+{
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+/// utc_timestamp formats a time in the FIX UTCTimestamp format YYYYMMDD-HH:MM:SS.sss
+pub fn utc_timestamp(time : SystemTime) -> String
+{
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+
+    // Civil from days, proleptic gregorian calendar
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}{:02}{:02}-{:02}:{:02}:{:02}.{:03}", year, month, day,
+            secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60, since_epoch.subsec_millis())
+}
+
+/// parse_utc_timestamp reads a time in the FIX UTCTimestamp format YYYYMMDD-HH:MM:SS[.sss]
+pub fn parse_utc_timestamp(value : &str) -> Option<SystemTime>
+{
+    let (date, time) = value.split_once('-')?;
+    let (time, millis) = match time.split_once('.')
+    {
+        Some((time, millis)) if millis.len() == 3 => (time, millis.parse::<u64>().ok()?),
+        Some(_) => return None,
+        None => (time, 0),
+    };
+    if date.len() != 8 || time.len() != 8 || !date.bytes().all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+    let (year, month, day) = (date[..4].parse::<i64>().ok()?, date[4..6].parse::<i64>().ok()?, date[6..].parse::<i64>().ok()?);
+    let mut fields = time.split(':').map(|field| field.parse::<u64>().ok());
+    let (hours, minutes, secs) = (fields.next()??, fields.next()??, fields.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hours > 23 || minutes > 59 || secs > 60
+    {
+        return None;
+    }
+
+    // Days from civil, proleptic gregorian calendar
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = u64::try_from(era * 146097 + doe - 719468).ok()?;
+
+    let secs = days * 86400 + hours * 3600 + minutes * 60 + secs;
+    Some(UNIX_EPOCH + Duration::from_millis(secs * 1000 + millis))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use bytes::BytesMut;
+    use std::time::{Duration, UNIX_EPOCH};
+    use super::*;
+
+    #[test]
+    fn can_encode_and_decode_message()
+    {
+        let message = FixMessage::new(NEW_ORDER_SINGLE)
+            .with(CL_ORD_ID, "ord-1")
+            .with(SYMBOL, "TSLA")
+            .with(SIDE, 1)
+            .with(ORDER_QTY, 100)
+            .with(PRICE, 122.5f32);
+
+        let encoded = message.encode("CLIENT", "ENGINE", 7, "20240102-10:11:12.123");
+        let text = String::from_utf8(encoded.clone()).unwrap().replace('\x01', "|");
+        assert!(text.starts_with("8=FIX.4.4|9="));
+        assert!(text.contains("|35=D|49=CLIENT|56=ENGINE|34=7|52=20240102-10:11:12.123|11=ord-1|"));
+
+        let mut buf = BytesMut::from(encoded.as_slice());
+        let decoded = FixMessage::decode(&mut buf).unwrap().unwrap();
+        assert!(buf.is_empty());
+        assert_eq!(decoded.msg_type(), NEW_ORDER_SINGLE);
+        assert_eq!(decoded.seq_num(), 7);
+        assert_eq!(decoded.get(SENDER_COMP_ID), Some("CLIENT"));
+        assert_eq!(decoded.get_as::<u32>(ORDER_QTY), Some(100));
+        assert_eq!(decoded.get_as::<f32>(PRICE), Some(122.5f32));
+    }
+
+    #[test]
+    fn decode_waits_for_complete_message()
+    {
+        let encoded = FixMessage::new(HEARTBEAT).encode("CLIENT", "ENGINE", 1, "20240102-10:11:12.123");
+        let mut buf = BytesMut::new();
+        for chunk in encoded.chunks(5)
+        {
+            assert_eq!(FixMessage::decode(&mut buf), Ok(None));
+            buf.extend_from_slice(chunk);
+        }
+        buf.extend_from_slice(&encoded);
+
+        assert_eq!(FixMessage::decode(&mut buf).unwrap().unwrap().msg_type(), HEARTBEAT);
+        assert_eq!(FixMessage::decode(&mut buf).unwrap().unwrap().msg_type(), HEARTBEAT);
+        assert_eq!(FixMessage::decode(&mut buf), Ok(None));
+    }
+
+    #[test]
+    fn decode_rejects_corrupted_message()
+    {
+        let mut encoded = FixMessage::new(HEARTBEAT).encode("CLIENT", "ENGINE", 1, "20240102-10:11:12.123");
+        let pos = encoded.len() - 10;
+        encoded[pos] = b'X';
+        assert_eq!(FixMessage::decode(&mut BytesMut::from(encoded.as_slice())), Err("CheckSum does not match"));
+        assert_eq!(FixMessage::decode(&mut BytesMut::from(&b"GET / HTTP/1.1"[..])),
+                   Err("message does not start with BeginString and BodyLength"));
+    }
+
+    #[test]
+    fn can_format_utc_timestamp()
+    {
+        let time = UNIX_EPOCH + Duration::from_millis(1_704_190_272_123);
+        assert_eq!(utc_timestamp(time), "20240102-10:11:12.123");
+        assert_eq!(utc_timestamp(UNIX_EPOCH), "19700101-00:00:00.000");
+    }
+
+    #[test]
+    fn can_parse_utc_timestamp()
+    {
+        let time = UNIX_EPOCH + Duration::from_millis(1_704_190_272_123);
+        assert_eq!(parse_utc_timestamp("20240102-10:11:12.123"), Some(time));
+        assert_eq!(parse_utc_timestamp("20240229-23:59:59"), Some(UNIX_EPOCH + Duration::from_secs(1_709_251_199)));
+        assert_eq!(parse_utc_timestamp(&utc_timestamp(UNIX_EPOCH)), Some(UNIX_EPOCH));
+        assert_eq!(parse_utc_timestamp("20241302-10:11:12"), None);
+        assert_eq!(parse_utc_timestamp("20240102 10:11:12"), None);
+        assert_eq!(parse_utc_timestamp("20240102-10:11"), None);
+    }
+}
@@ -0,0 +1,852 @@
+use bytes::{Buf, BufMut, BytesMut};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::data_types::Side;
+use crate::events::Event;
+
+// System event codes
+pub const START_OF_MESSAGES : u8 = b'O';
+pub const START_OF_SYSTEM_HOURS : u8 = b'S';
+pub const START_OF_MARKET_HOURS : u8 = b'Q';
+pub const END_OF_MARKET_HOURS : u8 = b'M';
+pub const END_OF_SYSTEM_HOURS : u8 = b'E';
+pub const END_OF_MESSAGES : u8 = b'C';
+// Extensions announcing the engine wide admin actions
+pub const NEW_ORDERS_DISABLED : u8 = b'D';
+pub const NEW_ORDERS_ENABLED : u8 = b'N';
+pub const KILL_SWITCH : u8 = b'K';
+
+// Trading states of the stock trading action
+pub const HALTED : u8 = b'H';
+pub const TRADING : u8 = b'T';
+
+/// Prices are sent as integers with 4 implied decimal places
+const PRICE_SCALE : f32 = 10000.0;
+
+pub fn encode_price(price : f32) -> u32
+{
+    (price * PRICE_SCALE).round() as u32
+}
+
+pub fn decode_price(price : u32) -> f32
+{
+    price as f32 / PRICE_SCALE
+}
+
+/// nanos_since_midnight converts a wall clock time into an ITCH timestamp (UTC)
+pub fn nanos_since_midnight(time : SystemTime) -> u64
+{
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    (since_epoch.as_nanos() % 86_400_000_000_000) as u64
+}
+
+/// ItchBody contains the fields specific to each ITCH message type
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ItchBody
+{
+    /// 'S' - Signals a market or data feed handler event
+    SystemEvent { event_code : u8 },
+    /// 'R' - Binds a stock locate code to its symbol
+    StockDirectory { stock : [u8; 8] },
+    /// 'H' - The trading state of a stock changed, either halted or trading
+    TradingAction { stock : [u8; 8], trading_state : u8, reason : [u8; 4] },
+    /// 'A' - A new order has been accepted and added to the book
+    AddOrder { order_ref : u64, side : Side, shares : u32, stock : [u8; 8], price : u32 },
+    /// 'E' - A resting order has been executed in whole or in part
+    OrderExecuted { order_ref : u64, executed_shares : u32, match_number : u64 },
+    /// 'X' - A resting order has been cancelled in part
+    OrderCancel { order_ref : u64, cancelled_shares : u32 },
+    /// 'D' - A resting order has been removed from the book
+    OrderDelete { order_ref : u64 },
+    /// 'U' - A resting order has been replaced, the replacement loses the time priority
+    OrderReplace { orig_order_ref : u64, new_order_ref : u64, shares : u32, price : u32 },
+    /// 'P' - Execution of an order which has never been displayed on the feed
+    Trade { order_ref : u64, side : Side, shares : u32, stock : [u8; 8], price : u32, match_number : u64 },
+}
+
+/// ItchMessage is a single message of the order by order feed, the layout
+/// follows NASDAQ TotalView-ITCH 5.0 with a reduced stock directory
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ItchMessage
+{
+    pub stock_locate : u16,
+    /// Nanoseconds since midnight
+    pub timestamp : u64,
+    pub body : ItchBody,
+}
+
+/// SequencedMessage pairs a message with its position in the feed
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SequencedMessage
+{
+    pub seq : u64,
+    pub message : ItchMessage,
+}
+
+/// Pads or truncates the symbol to the 8 characters stock field
+pub fn encode_stock(symbol : &str) -> [u8; 8]
+{
+    let mut stock = [b' '; 8];
+    for (dst, src) in stock.iter_mut().zip(symbol.bytes())
+    {
+        *dst = src;
+    }
+    stock
+}
+
+pub fn decode_stock(stock : &[u8; 8]) -> String
+{
+    String::from_utf8_lossy(stock).trim_end().to_string()
+}
+
+fn encode_side(side : Side) -> u8
+{
+    match side
+    {
+        Side::Buy => b'B',
+        Side::Sell => b'S',
+    }
+}
+
+fn decode_side(side : u8) -> Result<Side, &'static str>
+{
+    match side
+    {
+        b'B' => Ok(Side::Buy),
+        b'S' => Ok(Side::Sell),
+        _ => Err("unknown buy/sell indicator"),
+    }
+}
+
+impl ItchMessage
+{
+    pub fn message_type(&self) -> u8
+    {
+        match self.body
+        {
+            ItchBody::SystemEvent { .. } => b'S',
+            ItchBody::StockDirectory { .. } => b'R',
+            ItchBody::TradingAction { .. } => b'H',
+            ItchBody::AddOrder { .. } => b'A',
+            ItchBody::OrderExecuted { .. } => b'E',
+            ItchBody::OrderCancel { .. } => b'X',
+            ItchBody::OrderDelete { .. } => b'D',
+            ItchBody::OrderReplace { .. } => b'U',
+            ItchBody::Trade { .. } => b'P',
+        }
+    }
+
+    /// Appends the big-endian encoding of the message to the buffer
+    pub fn encode(&self, buf : &mut BytesMut)
+    {
+        buf.put_u8(self.message_type());
+        buf.put_u16(self.stock_locate);
+        // Tracking number, unused
+        buf.put_u16(0);
+        buf.put_uint(self.timestamp, 6);
+
+        match self.body
+        {
+            ItchBody::SystemEvent { event_code } => buf.put_u8(event_code),
+            ItchBody::StockDirectory { stock } => buf.put_slice(&stock),
+            ItchBody::TradingAction { stock, trading_state, reason } =>
+            {
+                buf.put_slice(&stock);
+                buf.put_u8(trading_state);
+                // Reserved
+                buf.put_u8(b' ');
+                buf.put_slice(&reason);
+            },
+            ItchBody::AddOrder { order_ref, side, shares, stock, price } =>
+            {
+                buf.put_u64(order_ref);
+                buf.put_u8(encode_side(side));
+                buf.put_u32(shares);
+                buf.put_slice(&stock);
+                buf.put_u32(price);
+            },
+            ItchBody::OrderExecuted { order_ref, executed_shares, match_number } =>
+            {
+                buf.put_u64(order_ref);
+                buf.put_u32(executed_shares);
+                buf.put_u64(match_number);
+            },
+            ItchBody::OrderCancel { order_ref, cancelled_shares } =>
+            {
+                buf.put_u64(order_ref);
+                buf.put_u32(cancelled_shares);
+            },
+            ItchBody::OrderDelete { order_ref } => buf.put_u64(order_ref),
+            ItchBody::OrderReplace { orig_order_ref, new_order_ref, shares, price } =>
+            {
+                buf.put_u64(orig_order_ref);
+                buf.put_u64(new_order_ref);
+                buf.put_u32(shares);
+                buf.put_u32(price);
+            },
+            ItchBody::Trade { order_ref, side, shares, stock, price, match_number } =>
+            {
+                buf.put_u64(order_ref);
+                buf.put_u8(encode_side(side));
+                buf.put_u32(shares);
+                buf.put_slice(&stock);
+                buf.put_u32(price);
+                buf.put_u64(match_number);
+            },
+        }
+    }
+
+    /// Decodes a single message, the slice must contain exactly one message
+    pub fn decode(mut buf : &[u8]) -> Result<ItchMessage, &'static str>
+    {
+        let expected_length = match buf.first()
+        {
+            Some(b'S') => 12,
+            Some(b'R') => 19,
+            Some(b'H') => 25,
+            Some(b'A') => 36,
+            Some(b'E') => 31,
+            Some(b'X') => 23,
+            Some(b'D') => 19,
+            Some(b'U') => 35,
+            Some(b'P') => 44,
+            Some(_) => return Err("unknown message type"),
+            None => return Err("empty message"),
+        };
+        if buf.len() != expected_length
+        {
+            return Err("message length does not match its type");
+        }
+
+        let message_type = buf.get_u8();
+        let stock_locate = buf.get_u16();
+        let _tracking_number = buf.get_u16();
+        let timestamp = buf.get_uint(6);
+        let get_stock = |buf : &mut &[u8]|
+        {
+            let mut stock = [0u8; 8];
+            buf.copy_to_slice(&mut stock);
+            stock
+        };
+
+        let body = match message_type
+        {
+            b'S' => ItchBody::SystemEvent { event_code : buf.get_u8() },
+            b'R' => ItchBody::StockDirectory { stock : get_stock(&mut buf) },
+            b'H' =>
+            {
+                let stock = get_stock(&mut buf);
+                let trading_state = buf.get_u8();
+                let _reserved = buf.get_u8();
+                let mut reason = [0u8; 4];
+                buf.copy_to_slice(&mut reason);
+                ItchBody::TradingAction { stock, trading_state, reason }
+            },
+            b'A' => ItchBody::AddOrder {
+                order_ref : buf.get_u64(),
+                side : decode_side(buf.get_u8())?,
+                shares : buf.get_u32(),
+                stock : get_stock(&mut buf),
+                price : buf.get_u32() },
+            b'E' => ItchBody::OrderExecuted { order_ref : buf.get_u64(), executed_shares : buf.get_u32(), match_number : buf.get_u64() },
+            b'X' => ItchBody::OrderCancel { order_ref : buf.get_u64(), cancelled_shares : buf.get_u32() },
+            b'D' => ItchBody::OrderDelete { order_ref : buf.get_u64() },
+            b'U' => ItchBody::OrderReplace {
+                orig_order_ref : buf.get_u64(),
+                new_order_ref : buf.get_u64(),
+                shares : buf.get_u32(),
+                price : buf.get_u32() },
+            _ => ItchBody::Trade {
+                order_ref : buf.get_u64(),
+                side : decode_side(buf.get_u8())?,
+                shares : buf.get_u32(),
+                stock : get_stock(&mut buf),
+                price : buf.get_u32(),
+                match_number : buf.get_u64() },
+        };
+
+        Ok(ItchMessage { stock_locate, timestamp, body })
+    }
+}
+
+impl SequencedMessage
+{
+    /// Appends the message framed as length (u16), sequence number (u64) and ITCH message
+    pub fn encode(&self, buf : &mut BytesMut)
+    {
+        let mut message = BytesMut::with_capacity(64);
+        self.message.encode(&mut message);
+        buf.put_u16((message.len() + 8) as u16);
+        buf.put_u64(self.seq);
+        buf.put_slice(&message);
+    }
+
+    /// Decodes the first complete framed message of the buffer and consumes its bytes
+    ///
+    /// # Return
+    ///
+    /// None if the buffer does not contain a complete frame yet
+    pub fn decode(buf : &mut BytesMut) -> Result<Option<SequencedMessage>, &'static str>
+    {
+        if buf.len() < 2
+        {
+            return Ok(None);
+        }
+        let length = u16::from_be_bytes([buf[0], buf[1]]) as usize;
+        if length < 8
+        {
+            return Err("frame is too short");
+        }
+        if buf.len() < length + 2
+        {
+            return Ok(None);
+        }
+
+        buf.advance(2);
+        let frame = buf.split_to(length);
+        let seq = u64::from_be_bytes(frame[..8].try_into().unwrap());
+        let message = ItchMessage::decode(&frame[8..])?;
+        Ok(Some(SequencedMessage { seq, message }))
+    }
+}
+
+/// ItchPublisher turns the events of the order books into sequenced ITCH messages.
+/// It contains the following data
+/// * _next_seq is the sequence number of the next published message
+/// * _next_match_number identifies the next execution
+/// * _locates is the stock locate code assigned to each published symbol
+/// * _pending_replaces are the replaced orders waiting for their new resting quantity
+#[derive(Debug)]
+pub struct ItchPublisher
+{
+    _next_seq : u64,
+    _next_match_number : u64,
+    _locates : HashMap<String, u16>,
+    _pending_replaces : HashSet<(u16, u32)>,
+}
+
+impl Default for ItchPublisher
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+impl ItchPublisher
+{
+    pub fn new() -> ItchPublisher
+    {
+        ItchPublisher {
+            _next_seq : 1,
+            _next_match_number : 1,
+            _locates : HashMap::new(),
+            _pending_replaces : HashSet::new() }
+    }
+
+    /// next_seq returns the sequence number of the next message
+    pub fn next_seq(&self) -> u64
+    {
+        self._next_seq
+    }
+
+    fn sequence(&mut self, stock_locate : u16, timestamp : u64, body : ItchBody, messages : &mut Vec<SequencedMessage>)
+    {
+        messages.push(SequencedMessage { seq : self._next_seq, message : ItchMessage { stock_locate, timestamp, body } });
+        self._next_seq += 1;
+    }
+
+    /// Returns the locate code of the symbol, a stock directory message is
+    /// published the first time a symbol is seen
+    fn locate(&mut self, symbol : &str, timestamp : u64, messages : &mut Vec<SequencedMessage>) -> u16
+    {
+        if let Some(stock_locate) = self._locates.get(symbol)
+        {
+            return *stock_locate;
+        }
+
+        let stock_locate = self._locates.len() as u16 + 1;
+        self._locates.insert(symbol.to_string(), stock_locate);
+        self.sequence(stock_locate, timestamp, ItchBody::StockDirectory { stock : encode_stock(symbol) }, messages);
+        stock_locate
+    }
+
+    /// system_event publishes a system event, e.g. start or end of market hours
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp` - Nanoseconds since midnight
+    /// * `event_code` - One of the system event codes
+    pub fn system_event(&mut self, timestamp : u64, event_code : u8) -> SequencedMessage
+    {
+        let mut messages = Vec::with_capacity(1);
+        self.sequence(0, timestamp, ItchBody::SystemEvent { event_code }, &mut messages);
+        messages[0]
+    }
+
+    /// trading_action publishes the halt or the resumption of a symbol
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp` - Nanoseconds since midnight
+    /// * `symbol` - The symbol halted or resumed
+    /// * `halted` - Whether the trading is halted
+    pub fn trading_action(&mut self, timestamp : u64, symbol : &str, halted : bool) -> Vec<SequencedMessage>
+    {
+        let mut messages = vec![];
+        let stock_locate = self.locate(symbol, timestamp, &mut messages);
+        let body = ItchBody::TradingAction {
+            stock : encode_stock(symbol),
+            trading_state : if halted { HALTED } else { TRADING },
+            // Operator action
+            reason : *b"OPER" };
+        self.sequence(stock_locate, timestamp, body, &mut messages);
+        messages
+    }
+
+    /// publish converts the events generated by one command into ITCH messages.
+    /// The events of a command must be published together, a replaced order which
+    /// is re-inserted is announced once its resting quantity is known
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp` - Nanoseconds since midnight
+    /// * `events` - The events paired with the symbol of their book
+    pub fn publish(&mut self, timestamp : u64, events : &[(String, Event)]) -> Vec<SequencedMessage>
+    {
+        let mut messages = vec![];
+        for (symbol, event) in events
+        {
+            let stock_locate = self.locate(symbol, timestamp, &mut messages);
+            let body = match *event
+            {
+                Event::Added(order) if self._pending_replaces.remove(&(stock_locate, order.id)) =>
+                    ItchBody::OrderReplace {
+                        orig_order_ref : order.id as u64,
+                        new_order_ref : order.id as u64,
+                        shares : order.qty,
+                        price : encode_price(order.price) },
+                Event::Added(order) => ItchBody::AddOrder {
+                    order_ref : order.id as u64,
+                    side : order.side,
+                    shares : order.qty,
+                    stock : encode_stock(symbol),
+                    price : encode_price(order.price) },
+                Event::Traded(trade) =>
+                {
+                    let match_number = self._next_match_number;
+                    self._next_match_number += 1;
+                    ItchBody::OrderExecuted { order_ref : trade.passive_id as u64, executed_shares : trade.qty, match_number }
+                },
+                Event::Replaced { old, new } if old.price == new.price && new.qty < old.qty =>
+                    ItchBody::OrderCancel { order_ref : old.id as u64, cancelled_shares : old.qty - new.qty },
+                Event::Replaced { old, .. } =>
+                {
+                    self._pending_replaces.insert((stock_locate, old.id));
+                    continue;
+                },
+                Event::Cancelled(order) | Event::Expired(order) => ItchBody::OrderDelete { order_ref : order.id as u64 },
+                Event::MassCancelled { .. } => continue,
+            };
+            self.sequence(stock_locate, timestamp, body, &mut messages);
+        }
+
+        // Replaced orders fully executed on re-insertion never rest again
+        let pending : Vec<(u16, u32)> = self._pending_replaces.drain().collect();
+        for (stock_locate, order_id) in pending
+        {
+            self.sequence(stock_locate, timestamp, ItchBody::OrderDelete { order_ref : order_id as u64 }, &mut messages);
+        }
+        messages
+    }
+}
+
+/// FeedOrder is an order as seen by a feed consumer
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct FeedOrder
+{
+    pub side : Side,
+    pub shares : u32,
+    pub price : u32,
+    /// Time priority of the order, the sequence number of the message which queued it
+    pub priority : u64,
+}
+
+/// FeedBook rebuilds the order by order book of all the symbols from the feed,
+/// it is the reference implementation of a downstream consumer
+#[derive(Clone, Debug, Default)]
+pub struct FeedBook
+{
+    pub next_seq : u64,
+    pub symbols : HashMap<u16, String>,
+    pub orders : HashMap<(u16, u64), FeedOrder>,
+    pub halted : HashSet<u16>,
+    pub last_event_code : Option<u8>,
+}
+
+impl FeedBook
+{
+    pub fn new() -> FeedBook
+    {
+        FeedBook { next_seq : 1, ..Default::default() }
+    }
+
+    /// restore rebuilds a book from a snapshot
+    ///
+    /// # Arguments
+    ///
+    /// * `last_seq` - The sequence number of the last message included in the snapshot
+    /// * `messages` - The snapshot messages, as generated by snapshot
+    pub fn restore(last_seq : u64, messages : &[ItchMessage]) -> Result<FeedBook, &'static str>
+    {
+        let mut book = FeedBook::new();
+        for (priority, message) in messages.iter().enumerate()
+        {
+            book.apply_message(priority as u64, message)?;
+        }
+        book.next_seq = last_seq + 1;
+        Ok(book)
+    }
+
+    /// snapshot returns the messages rebuilding the current state of the book:
+    /// the stock directory followed by the resting orders in time priority
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp` - Nanoseconds since midnight
+    pub fn snapshot(&self, timestamp : u64) -> Vec<ItchMessage>
+    {
+        let mut messages = vec![];
+        if let Some(event_code) = self.last_event_code
+        {
+            messages.push(ItchMessage { stock_locate : 0, timestamp, body : ItchBody::SystemEvent { event_code } });
+        }
+
+        let mut symbols : Vec<(&u16, &String)> = self.symbols.iter().collect();
+        symbols.sort();
+        for (stock_locate, symbol) in symbols
+        {
+            messages.push(ItchMessage { stock_locate : *stock_locate, timestamp, body : ItchBody::StockDirectory { stock : encode_stock(symbol) } });
+            if self.halted.contains(stock_locate)
+            {
+                let body = ItchBody::TradingAction { stock : encode_stock(symbol), trading_state : HALTED, reason : *b"OPER" };
+                messages.push(ItchMessage { stock_locate : *stock_locate, timestamp, body });
+            }
+        }
+
+        let mut orders : Vec<(&(u16, u64), &FeedOrder)> = self.orders.iter().collect();
+        orders.sort_by_key(|(_, order)| order.priority);
+        for ((stock_locate, order_ref), order) in orders
+        {
+            let stock = encode_stock(self.symbols.get(stock_locate).map(|symbol| symbol.as_str()).unwrap_or(""));
+            messages.push(ItchMessage {
+                stock_locate : *stock_locate,
+                timestamp,
+                body : ItchBody::AddOrder { order_ref : *order_ref, side : order.side, shares : order.shares, stock, price : order.price } });
+        }
+        messages
+    }
+
+    /// apply updates the book with the next message of the feed
+    ///
+    /// # Return
+    ///
+    /// An error if the message is not the next one in sequence or references an unknown order
+    pub fn apply(&mut self, sequenced : &SequencedMessage) -> Result<(), &'static str>
+    {
+        if sequenced.seq != self.next_seq
+        {
+            return Err("message is out of sequence");
+        }
+        self.next_seq += 1;
+        self.apply_message(sequenced.seq, &sequenced.message)
+    }
+
+    fn apply_message(&mut self, priority : u64, message : &ItchMessage) -> Result<(), &'static str>
+    {
+        let locate = message.stock_locate;
+        match message.body
+        {
+            ItchBody::SystemEvent { event_code } => self.last_event_code = Some(event_code),
+            ItchBody::StockDirectory { stock } =>
+            {
+                self.symbols.insert(locate, decode_stock(&stock));
+            },
+            ItchBody::TradingAction { trading_state, .. } =>
+            {
+                if trading_state == HALTED
+                {
+                    self.halted.insert(locate);
+                }
+                else
+                {
+                    self.halted.remove(&locate);
+                }
+            },
+            ItchBody::AddOrder { order_ref, side, shares, price, .. } =>
+            {
+                self.orders.insert((locate, order_ref), FeedOrder { side, shares, price, priority });
+            },
+            ItchBody::OrderExecuted { order_ref, executed_shares : shares, .. }
+                | ItchBody::OrderCancel { order_ref, cancelled_shares : shares } =>
+            {
+                let order = self.orders.get_mut(&(locate, order_ref)).ok_or("unknown order reference")?;
+                order.shares = order.shares.saturating_sub(shares);
+                if order.shares == 0
+                {
+                    self.orders.remove(&(locate, order_ref));
+                }
+            },
+            ItchBody::OrderDelete { order_ref } =>
+            {
+                self.orders.remove(&(locate, order_ref)).ok_or("unknown order reference")?;
+            },
+            ItchBody::OrderReplace { orig_order_ref, new_order_ref, shares, price } =>
+            {
+                let orig = self.orders.remove(&(locate, orig_order_ref)).ok_or("unknown order reference")?;
+                self.orders.insert((locate, new_order_ref), FeedOrder { side : orig.side, shares, price, priority });
+            },
+            ItchBody::Trade { .. } => {},
+        }
+        Ok(())
+    }
+
+    /// levels aggregates the resting orders of one side of a symbol into
+    /// (price, shares) levels sorted from the best price
+    pub fn levels(&self, symbol : &str, side : Side) -> Vec<(f32, u32)>
+    {
+        let locate = match self.symbols.iter().find(|(_, s)| s.as_str() == symbol)
+        {
+            Some((locate, _)) => *locate,
+            None => return vec![],
+        };
+
+        let mut levels : BTreeMap<u32, u32> = BTreeMap::new();
+        for ((order_locate, _), order) in self.orders.iter()
+        {
+            if *order_locate == locate && order.side == side
+            {
+                *levels.entry(order.price).or_insert(0) += order.shares;
+            }
+        }
+
+        let levels = levels.into_iter().map(|(price, shares)| (decode_price(price), shares));
+        match side
+        {
+            Side::Buy => levels.rev().collect(),
+            Side::Sell => levels.collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use bytes::BytesMut;
+    use crate::data_types::*;
+    use crate::engine::Engine;
+    use super::*;
+
+    type Levels = Vec<(f32, u32)>;
+
+    fn book_levels(engine : &Engine, symbol : &str) -> (Levels, Levels)
+    {
+        let book = engine.book(symbol).unwrap();
+        (book.depth(usize::MAX).bids.iter().map(|level| (level.price, level.qty)).collect(),
+         book.depth(usize::MAX).asks.iter().map(|level| (level.price, level.qty)).collect())
+    }
+
+    #[test]
+    fn can_encode_and_decode_all_messages()
+    {
+        let stock = encode_stock("TSLA");
+        let bodies = [
+            ItchBody::SystemEvent { event_code : START_OF_MESSAGES },
+            ItchBody::StockDirectory { stock },
+            ItchBody::TradingAction { stock, trading_state : HALTED, reason : *b"OPER" },
+            ItchBody::AddOrder { order_ref : 1, side : Side::Buy, shares : 100, stock, price : 1222000 },
+            ItchBody::OrderExecuted { order_ref : 1, executed_shares : 10, match_number : 4 },
+            ItchBody::OrderCancel { order_ref : 1, cancelled_shares : 20 },
+            ItchBody::OrderDelete { order_ref : 1 },
+            ItchBody::OrderReplace { orig_order_ref : 1, new_order_ref : 2, shares : 30, price : 1223000 },
+            ItchBody::Trade { order_ref : 3, side : Side::Sell, shares : 5, stock, price : 1222000, match_number : 5 },
+        ];
+
+        let mut buf = BytesMut::new();
+        for (seq, body) in bodies.iter().enumerate()
+        {
+            let message = ItchMessage { stock_locate : 1, timestamp : 34_200_000_000_123, body : *body };
+            SequencedMessage { seq : seq as u64 + 1, message }.encode(&mut buf);
+        }
+
+        for (seq, body) in bodies.iter().enumerate()
+        {
+            let decoded = SequencedMessage::decode(&mut buf).unwrap().unwrap();
+            assert_eq!(decoded.seq, seq as u64 + 1);
+            assert_eq!(decoded.message.timestamp, 34_200_000_000_123);
+            assert_eq!(decoded.message.body, *body);
+        }
+        assert_eq!(SequencedMessage::decode(&mut buf), Ok(None));
+        assert_eq!(ItchMessage::decode(b"Z"), Err("unknown message type"));
+    }
+
+    #[test]
+    fn publisher_converts_events_into_messages()
+    {
+        let mut engine = Engine::new();
+        engine.add_symbol("TSLA");
+        let mut publisher = ItchPublisher::new();
+
+        let mut order = Order::new(1, Side::Buy, 122.2f32, 100);
+        engine.insert_order("TSLA", &mut order).unwrap();
+        engine.insert_order("TSLA", &mut Order::new(2, Side::Sell, 122.1f32, 30)).unwrap();
+        engine.amend_order("TSLA", &Order::new(1, Side::Buy, 122.2f32, 70), 122.2f32, 50).unwrap();
+        engine.cancel_order("TSLA", &Order::new(1, Side::Buy, 122.2f32, 50)).unwrap();
+
+        let messages = publisher.publish(10, &engine.drain_events());
+        let bodies : Vec<ItchBody> = messages.iter().map(|sequenced| sequenced.message.body).collect();
+        assert_eq!(bodies, vec![
+            ItchBody::StockDirectory { stock : encode_stock("TSLA") },
+            ItchBody::AddOrder { order_ref : 1, side : Side::Buy, shares : 100, stock : encode_stock("TSLA"), price : 1222000 },
+            ItchBody::OrderExecuted { order_ref : 1, executed_shares : 30, match_number : 1 },
+            ItchBody::OrderCancel { order_ref : 1, cancelled_shares : 20 },
+            ItchBody::OrderDelete { order_ref : 1 },
+        ]);
+        let seqs : Vec<u64> = messages.iter().map(|sequenced| sequenced.seq).collect();
+        assert_eq!(seqs, vec![1, 2, 3, 4, 5]);
+        assert_eq!(publisher.system_event(20, END_OF_MESSAGES).seq, 6);
+    }
+
+    #[test]
+    fn replaced_order_is_announced_with_resting_quantity()
+    {
+        let mut engine = Engine::new();
+        engine.add_symbol("TSLA");
+        let mut publisher = ItchPublisher::new();
+
+        engine.insert_order("TSLA", &mut Order::new(1, Side::Buy, 122.2f32, 100)).unwrap();
+        engine.insert_order("TSLA", &mut Order::new(2, Side::Sell, 122.5f32, 30)).unwrap();
+        engine.insert_order("TSLA", &mut Order::new(3, Side::Sell, 122.6f32, 10)).unwrap();
+        publisher.publish(0, &engine.drain_events());
+
+        engine.amend_order("TSLA", &Order::new(1, Side::Buy, 122.2f32, 100), 122.5f32, 100).unwrap();
+        let bodies : Vec<ItchBody> = publisher.publish(0, &engine.drain_events()).iter().map(|sequenced| sequenced.message.body).collect();
+        assert_eq!(bodies, vec![
+            ItchBody::OrderExecuted { order_ref : 2, executed_shares : 30, match_number : 1 },
+            ItchBody::OrderReplace { orig_order_ref : 1, new_order_ref : 1, shares : 70, price : 1225000 },
+        ]);
+
+        engine.amend_order("TSLA", &Order::new(1, Side::Buy, 122.5f32, 70), 122.6f32, 10).unwrap();
+        let bodies : Vec<ItchBody> = publisher.publish(0, &engine.drain_events()).iter().map(|sequenced| sequenced.message.body).collect();
+        assert_eq!(bodies, vec![
+            ItchBody::OrderExecuted { order_ref : 3, executed_shares : 10, match_number : 2 },
+            ItchBody::OrderDelete { order_ref : 1 },
+        ]);
+    }
+
+    #[test]
+    fn consumer_can_rebuild_the_book_from_the_feed()
+    {
+        let mut engine = Engine::new();
+        engine.add_symbol("TSLA");
+        engine.add_symbol("AAPL");
+        let mut publisher = ItchPublisher::new();
+        let mut feed = BytesMut::new();
+        publisher.system_event(0, START_OF_MESSAGES).encode(&mut feed);
+
+        let mut price = 122.0f32;
+        for i in 0..200u32
+        {
+            let id = i + 1;
+            let symbol = if i % 3 == 0 { "AAPL" } else { "TSLA" };
+            let side = if i % 2 == 0 { Side::Buy } else { Side::Sell };
+            price += if i % 5 < 2 { 0.1 } else { -0.05 };
+            let mut order = Order::new(id, side, (price * 10.0).round() / 10.0, 10 + i % 7 * 5);
+            engine.insert_order(symbol, &mut order).unwrap();
+            if i % 37 == 0
+            {
+                engine.mass_cancel(Some(symbol), &MassCancelFilter { side : Some(side), ..Default::default() }).unwrap();
+            }
+            for message in publisher.publish(i as u64, &engine.drain_events())
+            {
+                message.encode(&mut feed);
+            }
+        }
+
+        let mut feed_book = FeedBook::new();
+        while let Some(message) = SequencedMessage::decode(&mut feed).unwrap()
+        {
+            feed_book.apply(&message).unwrap();
+        }
+
+        assert_eq!(feed_book.last_event_code, Some(START_OF_MESSAGES));
+        for symbol in ["TSLA", "AAPL"]
+        {
+            let (bids, asks) = book_levels(&engine, symbol);
+            assert!(!bids.is_empty() || !asks.is_empty());
+            assert_eq!(feed_book.levels(symbol, Side::Buy), bids);
+            assert_eq!(feed_book.levels(symbol, Side::Sell), asks);
+        }
+    }
+
+    #[test]
+    fn consumer_detects_sequence_gaps()
+    {
+        let mut publisher = ItchPublisher::new();
+        let first = publisher.system_event(0, START_OF_SYSTEM_HOURS);
+        let _lost = publisher.system_event(1, START_OF_MARKET_HOURS);
+        let third = publisher.system_event(2, END_OF_MARKET_HOURS);
+
+        let mut feed_book = FeedBook::new();
+        assert_eq!(feed_book.apply(&first), Ok(()));
+        assert_eq!(feed_book.apply(&third), Err("message is out of sequence"));
+    }
+
+    #[test]
+    fn snapshot_restores_orders_in_time_priority()
+    {
+        let mut engine = Engine::new();
+        engine.add_symbol("TSLA");
+        let mut publisher = ItchPublisher::new();
+        let mut feed_book = FeedBook::new();
+
+        engine.insert_order("TSLA", &mut Order::new(1, Side::Buy, 122.2f32, 100)).unwrap();
+        engine.insert_order("TSLA", &mut Order::new(2, Side::Buy, 122.2f32, 50)).unwrap();
+        engine.amend_order("TSLA", &Order::new(1, Side::Buy, 122.2f32, 100), 122.1f32, 100).unwrap();
+        engine.amend_order("TSLA", &Order::new(1, Side::Buy, 122.1f32, 100), 122.2f32, 100).unwrap();
+        for message in publisher.publish(0, &engine.drain_events())
+        {
+            feed_book.apply(&message).unwrap();
+        }
+
+        let snapshot = feed_book.snapshot(5);
+        let order_refs : Vec<u64> = snapshot.iter().filter_map(|message| match message.body
+        {
+            ItchBody::AddOrder { order_ref, .. } => Some(order_ref),
+            _ => None,
+        }).collect();
+        assert_eq!(order_refs, vec![2, 1]);
+
+        let restored = FeedBook::restore(feed_book.next_seq - 1, &snapshot).unwrap();
+        assert_eq!(restored.next_seq, feed_book.next_seq);
+        assert_eq!(restored.levels("TSLA", Side::Buy), feed_book.levels("TSLA", Side::Buy));
+    }
+
+    #[test]
+    fn trading_actions_are_kept_in_snapshots()
+    {
+        let mut publisher = ItchPublisher::new();
+        let mut feed_book = FeedBook::new();
+        let mut messages = publisher.trading_action(0, "TSLA", true);
+        messages.extend(publisher.trading_action(0, "AAPL", true));
+        messages.extend(publisher.trading_action(1, "AAPL", false));
+        for message in messages.iter()
+        {
+            feed_book.apply(message).unwrap();
+        }
+        assert_eq!(feed_book.halted, HashSet::from([1]));
+
+        let restored = FeedBook::restore(feed_book.next_seq - 1, &feed_book.snapshot(2)).unwrap();
+        assert_eq!(restored.halted, feed_book.halted);
+    }
+}
@@ -0,0 +1,154 @@
+use serde::{Deserialize, Serialize};
+use crate::data_types::{OrderType, Side, TimeInForce};
+use crate::depth::{Depth, LevelUpdate};
+
+/// Request is a message of a JSON client, tagged by its `type`
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request
+{
+    /// Enters an order, its id is chosen by the client and unique on the connection.
+    /// The price is ignored by the market orders
+    NewOrder
+    {
+        id : u32,
+        symbol : String,
+        side : Side,
+        #[serde(default)]
+        price : f32,
+        qty : u32,
+        #[serde(default)]
+        account : u32,
+        #[serde(default = "limit")]
+        order_type : OrderType,
+        #[serde(default)]
+        time_in_force : TimeInForce,
+    },
+    /// Cancels an order of the connection
+    Cancel { id : u32 },
+    /// Changes price and quantity left to be executed of an order of the connection
+    Amend { id : u32, price : f32, qty : u32 },
+    /// Streams the depth of the symbol, starting with a snapshot
+    Subscribe { symbol : String },
+}
+
+fn limit() -> OrderType
+{
+    OrderType::Limit
+}
+
+/// ExecType tells the change of an order reported by an execution report
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecType
+{
+    New,
+    Trade,
+    Replaced,
+    Cancelled,
+    Expired,
+    Rejected,
+}
+
+/// ExecutionReport tells the state of an order after a change
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ExecutionReport
+{
+    /// Id of the order chosen by the client
+    pub id : u32,
+    /// Id of the order in the engine, zero for a rejected order
+    pub order_id : u32,
+    pub symbol : String,
+    pub side : Side,
+    pub exec_type : ExecType,
+    /// Limit price of the order
+    pub price : f32,
+    pub leaves_qty : u32,
+    pub cum_qty : u32,
+    /// Price and quantity of the fill of a trade
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_price : Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_qty : Option<u32>,
+    /// Time of the change, in nanoseconds from the engine clock
+    pub timestamp : u64,
+    /// Reason of a reject
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason : Option<String>,
+}
+
+/// Response is a message sent to a JSON client, tagged by its `type`
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response
+{
+    ExecutionReport(ExecutionReport),
+    /// The top levels of a book, sent upon subscription
+    DepthSnapshot
+    {
+        symbol : String,
+        #[serde(flatten)]
+        depth : Depth,
+    },
+    /// The changes of the top levels of a book since the previous message
+    DepthUpdate { symbol : String, updates : Vec<LevelUpdate> },
+    /// A cancel, an amend or a subscription which could not be applied, or a message
+    /// which could not be parsed
+    Reject
+    {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id : Option<u32>,
+        reason : String,
+    },
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::depth::DepthLevel;
+    use super::*;
+
+    #[test]
+    fn requests_are_parsed_with_their_defaults()
+    {
+        let request : Request = serde_json::from_str(r#"{"type":"new_order","id":1,"symbol":"TSLA","side":"buy","price":122.5,"qty":100}"#).unwrap();
+        assert_eq!(request, Request::NewOrder {
+            id : 1,
+            symbol : "TSLA".to_string(),
+            side : Side::Buy,
+            price : 122.5,
+            qty : 100,
+            account : 0,
+            order_type : OrderType::Limit,
+            time_in_force : TimeInForce::GoodTillCancel });
+
+        let request : Request = serde_json::from_str(
+            r#"{"type":"new_order","id":2,"symbol":"TSLA","side":"sell","qty":10,"account":7,"order_type":"market","time_in_force":{"good_till_date":1000}}"#).unwrap();
+        assert!(matches!(request, Request::NewOrder { price, order_type : OrderType::Market, time_in_force : TimeInForce::GoodTillDate(1000), .. } if price == 0.0));
+        assert_eq!(serde_json::from_str::<Request>(r#"{"type":"amend","id":1,"price":122.4,"qty":50}"#).unwrap(),
+                   Request::Amend { id : 1, price : 122.4, qty : 50 });
+        assert!(serde_json::from_str::<Request>(r#"{"type":"new_order","id":1,"symbol":"TSLA","side":"short","qty":1}"#).is_err());
+        assert!(serde_json::from_str::<Request>(r#"{"type":"cancel"}"#).is_err());
+    }
+
+    #[test]
+    fn responses_are_tagged_by_type()
+    {
+        let snapshot = Response::DepthSnapshot {
+            symbol : "TSLA".to_string(),
+            depth : Depth { bids : vec![DepthLevel { price : 122.5, qty : 100, orders : 2 }], asks : vec![] } };
+        assert_eq!(serde_json::to_string(&snapshot).unwrap(),
+                   r#"{"type":"depth_snapshot","symbol":"TSLA","bids":[{"price":122.5,"qty":100,"orders":2}],"asks":[]}"#);
+
+        let update = Response::DepthUpdate { symbol : "TSLA".to_string(), updates : vec![LevelUpdate::Delete { side : Side::Sell, price : 122.6 }] };
+        assert_eq!(serde_json::to_string(&update).unwrap(),
+                   r#"{"type":"depth_update","symbol":"TSLA","updates":[{"action":"delete","side":"sell","price":122.6}]}"#);
+
+        let reject = Response::Reject { id : None, reason : "Unknown order".to_string() };
+        assert_eq!(serde_json::to_string(&reject).unwrap(), r#"{"type":"reject","reason":"Unknown order"}"#);
+        for response in [snapshot, update, reject]
+        {
+            assert_eq!(serde_json::from_str::<Response>(&serde_json::to_string(&response).unwrap()).unwrap(), response);
+        }
+    }
+}
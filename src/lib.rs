@@ -1,3 +1,26 @@
+pub mod admin;
+pub mod book;
+pub mod clock;
 pub mod data_types;
+pub mod depth;
+pub mod engine;
+pub mod events;
+pub mod fees;
+pub mod fix;
+pub mod itch;
+pub mod json;
+pub mod logging;
+pub mod market_data;
 pub mod matching;
+pub mod metrics;
+pub mod order_entry;
 pub mod order_book;
+pub mod price_ladder;
+pub mod price_levels;
+pub mod positions;
+pub mod rest;
+pub mod risk;
+pub mod sbe;
+pub mod sharding;
+pub mod slab;
+pub mod spsc;
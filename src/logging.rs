@@ -0,0 +1,11 @@
+use tracing_subscriber::EnvFilter;
+
+/// init installs the log subscriber of the binaries, printing to stdout. The levels
+/// are read from the RUST_LOG variable, e.g. `RUST_LOG=matching_engine=trace`, and
+/// default to info so that the matching loop stays silent
+pub fn init()
+{
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    // A subscriber may already be installed by the caller
+    let _ = tracing_subscriber::fmt().with_env_filter(filter).try_init();
+}
@@ -0,0 +1,666 @@
+use bytes::{Buf, BufMut, BytesMut};
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use crate::clock::Clock;
+use crate::itch::{self, FeedBook, ItchMessage, SequencedMessage};
+
+/// Maximum payload of a packet, it keeps the datagrams below the usual MTU
+const MAX_PACKET_SIZE : usize = 1400;
+const PACKET_HEADER_SIZE : usize = 20;
+
+// Recovery requests and responses
+const SNAPSHOT_REQUEST : u8 = b'S';
+const REPLAY_REQUEST : u8 = b'R';
+const REPLAY_AVAILABLE : u8 = 0;
+const REPLAY_UNAVAILABLE : u8 = 1;
+
+/// Packet is a MoldUDP64 style datagram: the session, the sequence number of the
+/// first message and the messages themselves. A packet without messages is a
+/// heartbeat announcing the next sequence number
+#[derive(Clone, PartialEq, Debug)]
+pub struct Packet
+{
+    pub session : [u8; 10],
+    pub seq : u64,
+    pub messages : Vec<ItchMessage>,
+}
+
+impl Packet
+{
+    pub fn encode(&self, buf : &mut BytesMut)
+    {
+        buf.put_slice(&self.session);
+        buf.put_u64(self.seq);
+        buf.put_u16(self.messages.len() as u16);
+        for message in self.messages.iter()
+        {
+            let mut encoded = BytesMut::with_capacity(64);
+            message.encode(&mut encoded);
+            buf.put_u16(encoded.len() as u16);
+            buf.put_slice(&encoded);
+        }
+    }
+
+    pub fn decode(mut buf : &[u8]) -> Result<Packet, &'static str>
+    {
+        if buf.len() < PACKET_HEADER_SIZE
+        {
+            return Err("packet is shorter than its header");
+        }
+
+        let mut session = [0u8; 10];
+        buf.copy_to_slice(&mut session);
+        let seq = buf.get_u64();
+        let count = buf.get_u16();
+        let mut messages = Vec::with_capacity(count as usize);
+        for _ in 0..count
+        {
+            if buf.len() < 2
+            {
+                return Err("packet is truncated");
+            }
+            let length = buf.get_u16() as usize;
+            if buf.len() < length
+            {
+                return Err("packet is truncated");
+            }
+            messages.push(ItchMessage::decode(&buf[..length])?);
+            buf.advance(length);
+        }
+        Ok(Packet { session, seq, messages })
+    }
+
+    /// sequenced returns the messages of the packet with their sequence numbers
+    pub fn sequenced(&self) -> impl Iterator<Item = SequencedMessage> + '_
+    {
+        self.messages.iter().enumerate().map(move |(i, message)| SequencedMessage { seq : self.seq + i as u64, message : *message })
+    }
+}
+
+/// packetize groups consecutive messages into packets fitting in a datagram
+pub fn packetize(session : [u8; 10], messages : &[SequencedMessage]) -> Vec<Packet>
+{
+    let mut packets : Vec<Packet> = vec![];
+    let mut size = MAX_PACKET_SIZE;
+    for sequenced in messages
+    {
+        let mut encoded = BytesMut::with_capacity(64);
+        sequenced.message.encode(&mut encoded);
+        if size + encoded.len() + 2 > MAX_PACKET_SIZE
+        {
+            packets.push(Packet { session, seq : sequenced.seq, messages : vec![] });
+            size = PACKET_HEADER_SIZE;
+        }
+        size += encoded.len() + 2;
+        packets.last_mut().unwrap().messages.push(sequenced.message);
+    }
+    packets
+}
+
+/// RecoveryStore keeps what is needed to recover from a gap
+/// * _current is the book state after the last recorded message
+/// * _snapshot is the book state taken every _snapshot_interval messages
+/// * _increments are the last recorded messages, at least those after the snapshot
+#[derive(Debug)]
+pub struct RecoveryStore
+{
+    _current : FeedBook,
+    _snapshot : FeedBook,
+    _increments : VecDeque<SequencedMessage>,
+    _snapshot_interval : usize,
+    _since_snapshot : usize,
+}
+
+impl RecoveryStore
+{
+    /// Creates a new store
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot_interval` - The number of messages between two snapshots, twice
+    ///   as many messages are kept for replays
+    pub fn new(snapshot_interval : usize) -> RecoveryStore
+    {
+        RecoveryStore {
+            _current : FeedBook::new(),
+            _snapshot : FeedBook::new(),
+            _increments : VecDeque::new(),
+            _snapshot_interval : snapshot_interval.max(1),
+            _since_snapshot : 0 }
+    }
+
+    /// record adds the published messages to the store
+    pub fn record(&mut self, messages : &[SequencedMessage]) -> Result<(), &'static str>
+    {
+        for message in messages
+        {
+            self._current.apply(message)?;
+            self._increments.push_back(*message);
+            self._since_snapshot += 1;
+            if self._since_snapshot >= self._snapshot_interval
+            {
+                self._snapshot = self._current.clone();
+                self._since_snapshot = 0;
+            }
+        }
+
+        while self._increments.len() > 2 * self._snapshot_interval
+        {
+            self._increments.pop_front();
+        }
+        Ok(())
+    }
+
+    /// next_seq returns the sequence number of the next message to be recorded
+    pub fn next_seq(&self) -> u64
+    {
+        self._current.next_seq
+    }
+
+    /// replay returns the recorded messages in [from, to], None if they are not all available anymore
+    pub fn replay(&self, from : u64, to : u64) -> Option<Vec<SequencedMessage>>
+    {
+        let first = self._increments.front()?.seq;
+        if from < first || to >= self.next_seq() || from > to
+        {
+            return None;
+        }
+        Some(self._increments.iter().skip((from - first) as usize).take((to - from + 1) as usize).copied().collect())
+    }
+
+    /// snapshot returns the last snapshot, with the sequence number of its last
+    /// message, and the increments recorded after it
+    pub fn snapshot(&self, timestamp : u64) -> (u64, Vec<ItchMessage>, Vec<SequencedMessage>)
+    {
+        let last_seq = self._snapshot.next_seq - 1;
+        let increments = self._increments.iter().filter(|message| message.seq > last_seq).copied().collect();
+        (last_seq, self._snapshot.snapshot(timestamp), increments)
+    }
+
+    /// handle_request answers a recovery request, the response is appended to the buffer
+    ///
+    /// # Return
+    ///
+    /// The number of request bytes consumed, None if the request is not complete yet
+    pub fn handle_request(&self, request : &[u8], timestamp : u64, response : &mut BytesMut) -> Result<Option<usize>, &'static str>
+    {
+        match request.first()
+        {
+            None => Ok(None),
+            Some(&SNAPSHOT_REQUEST) =>
+            {
+                let (last_seq, snapshot, increments) = self.snapshot(timestamp);
+                response.put_u64(last_seq);
+                response.put_u32(snapshot.len() as u32);
+                for message in snapshot
+                {
+                    let mut encoded = BytesMut::with_capacity(64);
+                    message.encode(&mut encoded);
+                    response.put_u16(encoded.len() as u16);
+                    response.put_slice(&encoded);
+                }
+                put_sequenced(&increments, response);
+                Ok(Some(1))
+            },
+            Some(&REPLAY_REQUEST) if request.len() < 17 => Ok(None),
+            Some(&REPLAY_REQUEST) =>
+            {
+                let from = u64::from_be_bytes(request[1..9].try_into().unwrap());
+                let to = u64::from_be_bytes(request[9..17].try_into().unwrap());
+                match self.replay(from, to)
+                {
+                    Some(messages) =>
+                    {
+                        response.put_u8(REPLAY_AVAILABLE);
+                        put_sequenced(&messages, response);
+                    },
+                    None => response.put_u8(REPLAY_UNAVAILABLE),
+                }
+                Ok(Some(17))
+            },
+            Some(_) => Err("unknown recovery request"),
+        }
+    }
+}
+
+fn put_sequenced(messages : &[SequencedMessage], buf : &mut BytesMut)
+{
+    buf.put_u32(messages.len() as u32);
+    for message in messages
+    {
+        message.encode(buf);
+    }
+}
+
+/// serve_recovery answers the snapshot and replay requests of the subscribers,
+/// the snapshots are timestamped by the clock of the exchange
+pub async fn serve_recovery(listener : TcpListener, store : Arc<Mutex<RecoveryStore>>, clock : Arc<dyn Clock>)
+{
+    loop
+    {
+        let (mut socket, _) = match listener.accept().await
+        {
+            Ok(connection) => connection,
+            Err(_) => continue,
+        };
+
+        let store = store.clone();
+        let clock = clock.clone();
+        tokio::spawn(async move
+        {
+            let mut request = BytesMut::with_capacity(64);
+            while let Ok(read) = socket.read_buf(&mut request).await
+            {
+                if read == 0
+                {
+                    break;
+                }
+
+                let mut response = BytesMut::new();
+                loop
+                {
+                    let handled = store.lock().unwrap().handle_request(&request, itch::nanos_since_midnight(clock.system_time()), &mut response);
+                    match handled
+                    {
+                        Ok(Some(consumed)) => request.advance(consumed),
+                        Ok(None) => break,
+                        Err(_) => return,
+                    }
+                }
+                if socket.write_all(&response).await.is_err()
+                {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+async fn read_sequenced(socket : &mut TcpStream) -> io::Result<Vec<SequencedMessage>>
+{
+    let count = socket.read_u32().await?;
+    let mut messages = Vec::with_capacity(count as usize);
+    for _ in 0..count
+    {
+        let length = socket.read_u16().await?;
+        let mut frame = BytesMut::zeroed(length as usize + 2);
+        frame[..2].copy_from_slice(&length.to_be_bytes());
+        socket.read_exact(&mut frame[2..]).await?;
+        let message = SequencedMessage::decode(&mut frame)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "truncated message"))?;
+        messages.push(message);
+    }
+    Ok(messages)
+}
+
+/// request_snapshot rebuilds the book from the last snapshot of the recovery server
+/// plus the increments published after it
+pub async fn request_snapshot(address : SocketAddr) -> io::Result<FeedBook>
+{
+    let mut socket = TcpStream::connect(address).await?;
+    socket.write_u8(SNAPSHOT_REQUEST).await?;
+
+    let last_seq = socket.read_u64().await?;
+    let count = socket.read_u32().await?;
+    let mut snapshot = Vec::with_capacity(count as usize);
+    for _ in 0..count
+    {
+        let length = socket.read_u16().await?;
+        let mut message = vec![0u8; length as usize];
+        socket.read_exact(&mut message).await?;
+        snapshot.push(ItchMessage::decode(&message).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?);
+    }
+
+    let mut book = FeedBook::restore(last_seq, &snapshot).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    for message in read_sequenced(&mut socket).await?
+    {
+        book.apply(&message).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    }
+    Ok(book)
+}
+
+/// request_replay asks the retransmission of the messages in [from, to]
+///
+/// # Return
+///
+/// None if the messages are not available anymore, a snapshot is needed then
+pub async fn request_replay(address : SocketAddr, from : u64, to : u64) -> io::Result<Option<Vec<SequencedMessage>>>
+{
+    let mut socket = TcpStream::connect(address).await?;
+    let mut request = BytesMut::with_capacity(17);
+    request.put_u8(REPLAY_REQUEST);
+    request.put_u64(from);
+    request.put_u64(to);
+    socket.write_all(&request).await?;
+
+    match socket.read_u8().await?
+    {
+        REPLAY_AVAILABLE => Ok(Some(read_sequenced(&mut socket).await?)),
+        _ => Ok(None),
+    }
+}
+
+/// FeedPublisher sends the sequenced messages as UDP packets and records
+/// them in the recovery store
+pub struct FeedPublisher
+{
+    _socket : UdpSocket,
+    _target : SocketAddr,
+    _session : [u8; 10],
+    _store : Arc<Mutex<RecoveryStore>>,
+}
+
+impl FeedPublisher
+{
+    /// Creates a new publisher
+    ///
+    /// # Arguments
+    ///
+    /// * `target` - The address the packets are sent to, a multicast group or a loopback address
+    /// * `session` - The session name, padded to 10 characters
+    /// * `store` - The recovery store shared with the recovery server
+    pub async fn bind(target : SocketAddr, session : &str, store : Arc<Mutex<RecoveryStore>>) -> io::Result<FeedPublisher>
+    {
+        let local : SocketAddr = if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }.parse().unwrap();
+        let socket = UdpSocket::bind(local).await?;
+        let mut padded = [b' '; 10];
+        for (dst, src) in padded.iter_mut().zip(session.bytes())
+        {
+            *dst = src;
+        }
+        Ok(FeedPublisher { _socket : socket, _target : target, _session : padded, _store : store })
+    }
+
+    /// publish records and sends the messages, the messages are recorded even
+    /// when the datagrams are lost so that they can be retransmitted
+    pub async fn publish(&self, messages : &[SequencedMessage]) -> io::Result<()>
+    {
+        self._store.lock().unwrap().record(messages).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        for packet in packetize(self._session, messages)
+        {
+            self.send(&packet).await?;
+        }
+        Ok(())
+    }
+
+    /// heartbeat announces the next sequence number, it lets the subscribers
+    /// detect the loss of the last packets
+    pub async fn heartbeat(&self) -> io::Result<()>
+    {
+        let seq = self._store.lock().unwrap().next_seq();
+        self.send(&Packet { session : self._session, seq, messages : vec![] }).await
+    }
+
+    async fn send(&self, packet : &Packet) -> io::Result<()>
+    {
+        let mut buf = BytesMut::with_capacity(MAX_PACKET_SIZE);
+        packet.encode(&mut buf);
+        self._socket.send_to(&buf, self._target).await?;
+        Ok(())
+    }
+}
+
+/// FeedSubscriber applies the packets in sequence to its book, buffering the
+/// messages received after a gap until the gap is recovered
+#[derive(Debug)]
+pub struct FeedSubscriber
+{
+    pub book : FeedBook,
+    _pending : BTreeMap<u64, SequencedMessage>,
+}
+
+impl Default for FeedSubscriber
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+impl FeedSubscriber
+{
+    pub fn new() -> FeedSubscriber
+    {
+        FeedSubscriber { book : FeedBook::new(), _pending : BTreeMap::new() }
+    }
+
+    /// on_packet applies a received packet
+    ///
+    /// # Return
+    ///
+    /// The range [from, to] of the missing messages, if any
+    pub fn on_packet(&mut self, packet : &Packet) -> Result<Option<(u64, u64)>, &'static str>
+    {
+        for message in packet.sequenced()
+        {
+            if message.seq >= self.book.next_seq
+            {
+                self._pending.insert(message.seq, message);
+            }
+        }
+        self.apply_pending()?;
+
+        // The packet announces messages which have never been received
+        let next_seq = packet.seq + packet.messages.len() as u64;
+        let gap_end = match self._pending.keys().next()
+        {
+            Some(first_pending) => *first_pending,
+            None => next_seq,
+        };
+        if gap_end > self.book.next_seq
+        {
+            return Ok(Some((self.book.next_seq, gap_end - 1)));
+        }
+        Ok(None)
+    }
+
+    /// on_replay applies the retransmitted messages
+    pub fn on_replay(&mut self, messages : &[SequencedMessage]) -> Result<(), &'static str>
+    {
+        for message in messages
+        {
+            if message.seq >= self.book.next_seq
+            {
+                self._pending.insert(message.seq, *message);
+            }
+        }
+        self.apply_pending()
+    }
+
+    /// on_snapshot replaces the book with one rebuilt from a snapshot
+    pub fn on_snapshot(&mut self, book : FeedBook) -> Result<(), &'static str>
+    {
+        self.book = book;
+        let next_seq = self.book.next_seq;
+        self._pending.retain(|seq, _| *seq >= next_seq);
+        self.apply_pending()
+    }
+
+    fn apply_pending(&mut self) -> Result<(), &'static str>
+    {
+        while let Some(message) = self._pending.remove(&self.book.next_seq)
+        {
+            self.book.apply(&message)?;
+        }
+        Ok(())
+    }
+
+    /// recover fills a gap through the recovery server, the missing messages are
+    /// replayed when still available, otherwise the book is rebuilt from the snapshot
+    pub async fn recover(&mut self, recovery : SocketAddr, from : u64, to : u64) -> io::Result<()>
+    {
+        let result = match request_replay(recovery, from, to).await?
+        {
+            Some(messages) => self.on_replay(&messages),
+            None => self.on_snapshot(request_snapshot(recovery).await?),
+        };
+        result.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::net::{TcpListener, UdpSocket};
+    use tokio::time::timeout;
+    use crate::clock::ManualClock;
+    use crate::data_types::*;
+    use crate::engine::Engine;
+    use crate::itch::{decode_price, encode_price, ItchPublisher, SequencedMessage};
+    use super::*;
+
+    /// Generates the feed of a few orders on a single symbol
+    fn generate_feed(engine : &mut Engine, publisher : &mut ItchPublisher, first_id : u32, orders : u32) -> Vec<SequencedMessage>
+    {
+        let mut messages = vec![];
+        for id in first_id..first_id + orders
+        {
+            let side = if id % 2 == 0 { Side::Buy } else { Side::Sell };
+            let price = if side == Side::Buy { 122.0f32 - (id % 5) as f32 * 0.1 } else { 122.1f32 + (id % 3) as f32 * 0.1 };
+            let price = decode_price(encode_price(price));
+            engine.insert_order("TSLA", &mut Order::new(id, side, price, 10 + id % 4)).unwrap();
+            messages.extend(publisher.publish(id as u64, &engine.drain_events()));
+        }
+        messages
+    }
+
+    async fn start_recovery(store : Arc<Mutex<RecoveryStore>>) -> std::net::SocketAddr
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve_recovery(listener, store, Arc::new(ManualClock::new(0))));
+        address
+    }
+
+    async fn receive(socket : &UdpSocket) -> Packet
+    {
+        let mut buf = vec![0u8; MAX_PACKET_SIZE];
+        let length = timeout(Duration::from_secs(5), socket.recv(&mut buf)).await.unwrap().unwrap();
+        Packet::decode(&buf[..length]).unwrap()
+    }
+
+    #[test]
+    fn can_encode_and_decode_packets()
+    {
+        let mut engine = Engine::new();
+        engine.add_symbol("TSLA");
+        let messages = generate_feed(&mut engine, &mut ItchPublisher::new(), 1, 200);
+
+        let packets = packetize(*b"SESSION001", &messages);
+        assert!(packets.len() > 1);
+        let mut decoded = vec![];
+        for packet in packets.iter()
+        {
+            let mut buf = BytesMut::new();
+            packet.encode(&mut buf);
+            assert!(buf.len() <= MAX_PACKET_SIZE);
+            let packet = Packet::decode(&buf).unwrap();
+            assert_eq!(packet.session, *b"SESSION001");
+            decoded.extend(packet.sequenced());
+        }
+        assert_eq!(decoded, messages);
+    }
+
+    #[test]
+    fn subscriber_reports_and_fills_gaps()
+    {
+        let mut engine = Engine::new();
+        engine.add_symbol("TSLA");
+        let messages = generate_feed(&mut engine, &mut ItchPublisher::new(), 1, 10);
+        let session = *b"SESSION001";
+
+        let mut subscriber = FeedSubscriber::new();
+        let first = Packet { session, seq : 1, messages : messages[..3].iter().map(|m| m.message).collect() };
+        let third = Packet { session, seq : 6, messages : messages[5..].iter().map(|m| m.message).collect() };
+        assert_eq!(subscriber.on_packet(&first), Ok(None));
+        assert_eq!(subscriber.on_packet(&third), Ok(Some((4, 5))));
+        assert_eq!(subscriber.book.next_seq, 4);
+
+        subscriber.on_replay(&messages[3..5]).unwrap();
+        assert_eq!(subscriber.book.next_seq, messages.len() as u64 + 1);
+
+        // A heartbeat reveals the loss of the last packet
+        let heartbeat = Packet { session, seq : messages.len() as u64 + 3, messages : vec![] };
+        assert_eq!(subscriber.on_packet(&heartbeat), Ok(Some((messages.len() as u64 + 1, messages.len() as u64 + 2))));
+    }
+
+    #[test]
+    fn store_replays_recent_messages_only()
+    {
+        let mut engine = Engine::new();
+        engine.add_symbol("TSLA");
+        let messages = generate_feed(&mut engine, &mut ItchPublisher::new(), 1, 30);
+        let mut store = RecoveryStore::new(5);
+        store.record(&messages).unwrap();
+
+        let last = messages.last().unwrap().seq;
+        assert_eq!(store.replay(last - 2, last), Some(messages[messages.len() - 3..].to_vec()));
+        assert_eq!(store.replay(1, 3), None);
+        assert_eq!(store.replay(last, last + 1), None);
+
+        let (snapshot_seq, _, increments) = store.snapshot(0);
+        assert!(snapshot_seq <= last);
+        assert_eq!(increments.first().map(|m| m.seq), if snapshot_seq == last { None } else { Some(snapshot_seq + 1) });
+    }
+
+    #[tokio::test]
+    async fn subscriber_recovers_lost_packets_over_loopback()
+    {
+        let mut engine = Engine::new();
+        engine.add_symbol("TSLA");
+        let mut itch_publisher = ItchPublisher::new();
+        let store = Arc::new(Mutex::new(RecoveryStore::new(1000)));
+        let recovery = start_recovery(store.clone()).await;
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let publisher = FeedPublisher::bind(receiver.local_addr().unwrap(), "TEST", store.clone()).await.unwrap();
+        let mut subscriber = FeedSubscriber::new();
+
+        publisher.publish(&generate_feed(&mut engine, &mut itch_publisher, 1, 5)).await.unwrap();
+        assert_eq!(subscriber.on_packet(&receive(&receiver).await), Ok(None));
+
+        // The next messages are lost, they only reach the recovery store
+        store.lock().unwrap().record(&generate_feed(&mut engine, &mut itch_publisher, 6, 5)).unwrap();
+        publisher.publish(&generate_feed(&mut engine, &mut itch_publisher, 11, 5)).await.unwrap();
+
+        let (from, to) = subscriber.on_packet(&receive(&receiver).await).unwrap().unwrap();
+        subscriber.recover(recovery, from, to).await.unwrap();
+        assert_eq!(subscriber.book.next_seq, itch_publisher.next_seq());
+
+        let book = engine.book("TSLA").unwrap();
+        let bids : Vec<(f32, u32)> = book.depth(usize::MAX).bids.iter().map(|level| (level.price, level.qty)).collect();
+        assert_eq!(subscriber.book.levels("TSLA", Side::Buy), bids);
+    }
+
+    #[tokio::test]
+    async fn late_joiner_recovers_from_snapshot()
+    {
+        let mut engine = Engine::new();
+        engine.add_symbol("TSLA");
+        let mut itch_publisher = ItchPublisher::new();
+        let store = Arc::new(Mutex::new(RecoveryStore::new(7)));
+        let recovery = start_recovery(store.clone()).await;
+
+        store.lock().unwrap().record(&generate_feed(&mut engine, &mut itch_publisher, 1, 40)).unwrap();
+
+        // The replay of the whole session is not available anymore
+        let mut subscriber = FeedSubscriber::new();
+        let heartbeat = Packet { session : *b"TEST      ", seq : itch_publisher.next_seq(), messages : vec![] };
+        let (from, to) = subscriber.on_packet(&heartbeat).unwrap().unwrap();
+        assert_eq!(request_replay(recovery, from, to).await.unwrap(), None);
+
+        subscriber.recover(recovery, from, to).await.unwrap();
+        assert_eq!(subscriber.book.next_seq, itch_publisher.next_seq());
+
+        let book = engine.book("TSLA").unwrap();
+        let asks : Vec<(f32, u32)> = book.depth(usize::MAX).asks.iter().map(|level| (level.price, level.qty)).collect();
+        assert_eq!(subscriber.book.levels("TSLA", Side::Sell), asks);
+    }
+}
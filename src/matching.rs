@@ -1,41 +1,40 @@
-use std::collections::BTreeMap;
 use crate::data_types::*;
-
-pub fn match_order<T : Ord >(curr_side : &mut BTreeMap<T, Limit>, 
-                         can_trade : &dyn Fn(f32, f32) -> bool, 
-                         order : &mut Order) -> Vec<Trade>
+use crate::price_levels::PriceLevels;
+use crate::slab::OrderSlab;
+use tracing::trace;
+
+/// Matches the order against the best levels of the side, one level at a time, removing
+/// each level as soon as it is emptied so that a sweep only touches the levels it trades with
+/// 
+/// # Arguments
+/// 
+/// * `curr_side` - The opposite side of the book, the best level first
+/// * `orders` - The slab storing the orders of the book
+/// * `can_trade` - Tells whether a level price is marketable against the order price
+/// * `order` - The incoming order, its quantity is reduced by the traded quantity
+/// * `trades` - The vector collecting the trades
+pub fn match_order<L : PriceLevels>(curr_side : &mut L, 
+                                    orders : &mut OrderSlab,
+                                    can_trade : &dyn Fn(f32, f32) -> bool, 
+                                    order : &mut Order,
+                                    trades : &mut Vec<Trade>)
 {
-    let mut trades = Vec::new();
-    // Here we need to match first and then we can do the rest
-    loop 
+    while order.qty != 0
     {
-        // If there are no order anymore at a certain level let's clean the level
-        curr_side.retain(|k, level| level.qty != 0 && level.num_orders() != 0);
-
-        if order.qty == 0 || curr_side.is_empty()
-        {
-            break;
-        }
-
-        let price_level = curr_side.iter_mut().next();
-        if price_level.is_none()
-        {
-            break;
-        }
-
-        let (_, limit) = price_level.unwrap();
+        let Some(limit) = curr_side.best_mut() else { break };
         if !can_trade(limit.price, order.price)
         {
             break;
         }
 
-        println!("Can trade @price {0} ", limit.price);
+        trace!(price = limit.price, order_id = order.id, "matching price level");
         // Make trades up until we can and reduce the qty accordingly
-        let mut trades_at_price = limit.make_trades(order);
-        trades.append(&mut trades_at_price);
+        limit.make_trades_into(orders, order, trades);
+        if limit.num_orders() == 0
+        {
+            curr_side.remove_best();
+        }
     }
-
-    return trades;
 }
 
 #[cfg(test)]
@@ -45,6 +44,7 @@ mod tests
     use crate::data_types::{Order, Limit, Side, Trade};
     use std::collections::BTreeMap;
     use crate::order_book::*;
+    use crate::slab::OrderSlab;
 
     // Module under test
     use super::match_order;
@@ -52,15 +52,16 @@ mod tests
     #[test]
     fn can_match_with_bid()
     {
+        let mut orders = OrderSlab::with_capacity(4);
         let mut m = BTreeMap::new();
         let mut limit = Limit::new(12.2f32);
-        let order = Order{id:1, side: Side::Buy, price:12.2f32, qty:100};
-        let order2 = Order{id:2, side: Side::Buy, price:12.2f32, qty:22};
-        let order3 = Order{id:3, side: Side::Buy, price:12.2f32, qty:33};
+        let order = Order::new(1, Side::Buy, 12.2f32, 100);
+        let order2 = Order::new(2, Side::Buy, 12.2f32, 22);
+        let order3 = Order::new(3, Side::Buy, 12.2f32, 33);
 
-        limit.add_order(order);
-        limit.add_order(order2);
-        limit.add_order(order3);
+        limit.add_order(&mut orders, order);
+        limit.add_order(&mut orders, order2);
+        limit.add_order(&mut orders, order3);
         assert_eq!(limit.qty, 155);
         println!("limit = {:?}", limit);
 
@@ -71,8 +72,9 @@ mod tests
             OrderedFloat(best_availiable_price) >= OrderedFloat(current_offered_price)
         };
 
-        let mut order_to_match = Order{id:4, side: Side::Sell, price:12.2f32, qty:50};
-        let trades = match_order(&mut m, &match_strategy, &mut order_to_match);
+        let mut order_to_match = Order::new(4, Side::Sell, 12.2f32, 50);
+        let mut trades = vec![];
+        match_order(&mut m, &mut orders, &match_strategy, &mut order_to_match, &mut trades);
         println!("trades = {:?}", trades);
         assert_eq!(trades.len(), 1);
         let trade = Trade::new(4, 1, 12.2f32, 50);
@@ -83,15 +85,16 @@ mod tests
     #[test]
     fn can_match_multiple_times_with_bid()
     {
+        let mut orders = OrderSlab::with_capacity(4);
         let mut m = BTreeMap::new();
         let mut limit = Limit::new(12.2f32);
-        let order = Order{id:1, side: Side::Buy, price:12.2f32, qty:100};
-        let order2 = Order{id:2, side: Side::Buy, price:12.2f32, qty:22};
-        let order3 = Order{id:3, side: Side::Buy, price:12.2f32, qty:33};
+        let order = Order::new(1, Side::Buy, 12.2f32, 100);
+        let order2 = Order::new(2, Side::Buy, 12.2f32, 22);
+        let order3 = Order::new(3, Side::Buy, 12.2f32, 33);
 
-        limit.add_order(order);
-        limit.add_order(order2);
-        limit.add_order(order3);
+        limit.add_order(&mut orders, order);
+        limit.add_order(&mut orders, order2);
+        limit.add_order(&mut orders, order3);
 
         let bidkey = BidKey::create(12.2f32);
         m.insert(bidkey, limit);
@@ -100,8 +103,9 @@ mod tests
             OrderedFloat(best_availiable_price) >= OrderedFloat(current_offered_price)
         };
 
-        let mut order_to_match = Order{id:4, side: Side::Sell, price:12.2f32, qty:135};
-        let trades = match_order(&mut m, &match_strategy, &mut order_to_match);
+        let mut order_to_match = Order::new(4, Side::Sell, 12.2f32, 135);
+        let mut trades = vec![];
+        match_order(&mut m, &mut orders, &match_strategy, &mut order_to_match, &mut trades);
         println!("trades = {:?}", trades);
         assert_eq!(trades.len(), 3);
         assert_eq!(m.iter().next().unwrap().1.qty, 155-135);
@@ -114,4 +118,30 @@ mod tests
         // let expected_trade = Some(&trade);
         assert_eq!(trades, expected_trades);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn sweep_removes_only_the_emptied_levels()
+    {
+        let mut orders = OrderSlab::with_capacity(4);
+        let mut m = BTreeMap::new();
+        for (id, price) in [(1, 12.2f32), (2, 12.1f32), (3, 12.0f32)]
+        {
+            let mut limit = Limit::new(price);
+            limit.add_order(&mut orders, Order::new(id, Side::Buy, price, 10));
+            m.insert(BidKey::create(price), limit);
+        }
+        let match_strategy = |best_availiable_price, current_offered_price| 
+        {
+            OrderedFloat(best_availiable_price) >= OrderedFloat(current_offered_price)
+        };
+
+        let mut order_to_match = Order::new(4, Side::Sell, 12.0f32, 15);
+        let mut trades = vec![];
+        match_order(&mut m, &mut orders, &match_strategy, &mut order_to_match, &mut trades);
+        assert_eq!(trades, vec![Trade::new(4, 1, 12.2f32, 10), Trade::new(4, 2, 12.1f32, 5)]);
+        assert_eq!(m.len(), 2);
+        let best = m.values().next().unwrap();
+        assert_eq!(best.price, 12.1f32);
+        assert_eq!(best.orders(&orders).copied().collect::<Vec<Order>>(), vec![Order::new(2, Side::Buy, 12.1f32, 5)]);
+    }
+}
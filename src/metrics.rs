@@ -0,0 +1,457 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use crate::data_types::Side;
+use crate::events::Event;
+
+/// Upper bounds of the latency buckets, in nanoseconds
+const LATENCY_BUCKETS : [u64; 13] = [500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000,
+                                     250_000, 500_000, 1_000_000, 10_000_000, 100_000_000];
+
+/// Counter is a monotonic count of events
+#[derive(Debug, Default)]
+pub struct Counter
+{
+    _value : AtomicU64,
+}
+
+impl Counter
+{
+    pub fn inc(&self)
+    {
+        self.add(1);
+    }
+
+    pub fn add(&self, value : u64)
+    {
+        self._value.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64
+    {
+        self._value.load(Ordering::Relaxed)
+    }
+}
+
+/// Gauge is a value which can go up and down
+#[derive(Debug, Default)]
+pub struct Gauge
+{
+    _value : AtomicI64,
+}
+
+impl Gauge
+{
+    pub fn set(&self, value : i64)
+    {
+        self._value.store(value, Ordering::Relaxed);
+    }
+
+    pub fn add(&self, value : i64)
+    {
+        self._value.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64
+    {
+        self._value.load(Ordering::Relaxed)
+    }
+}
+
+/// Histogram counts the observed latencies in fixed buckets
+/// * _buckets holds the number of observations of each bucket, the last one
+///   counts the observations above the largest bound
+/// * _sum is the total of the observations in nanoseconds
+#[derive(Debug, Default)]
+pub struct Histogram
+{
+    _buckets : [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    _sum : AtomicU64,
+    _count : AtomicU64,
+}
+
+impl Histogram
+{
+    pub fn observe(&self, latency : Duration)
+    {
+        let nanos = latency.as_nanos() as u64;
+        let bucket = LATENCY_BUCKETS.iter().position(|bound| nanos <= *bound).unwrap_or(LATENCY_BUCKETS.len());
+        self._buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self._sum.fetch_add(nanos, Ordering::Relaxed);
+        self._count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64
+    {
+        self._count.load(Ordering::Relaxed)
+    }
+
+    /// sum returns the total of the observed latencies
+    pub fn sum(&self) -> Duration
+    {
+        Duration::from_nanos(self._sum.load(Ordering::Relaxed))
+    }
+
+    /// cumulative_counts returns the number of observations below each bucket bound
+    pub fn cumulative_counts(&self) -> Vec<(u64, u64)>
+    {
+        let mut total = 0;
+        LATENCY_BUCKETS.iter().zip(self._buckets.iter()).map(|(bound, count)|
+        {
+            total += count.load(Ordering::Relaxed);
+            (*bound, total)
+        }).collect()
+    }
+}
+
+/// Stage is a step in the processing of a command
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Stage
+{
+    Decode,
+    Risk,
+    Match,
+    Publish,
+}
+
+impl Stage
+{
+    pub const ALL : [Stage; 4] = [Stage::Decode, Stage::Risk, Stage::Match, Stage::Publish];
+
+    pub fn as_str(&self) -> &'static str
+    {
+        match self
+        {
+            Stage::Decode => "decode",
+            Stage::Risk => "risk",
+            Stage::Match => "match",
+            Stage::Publish => "publish",
+        }
+    }
+}
+
+/// BookGauges is the depth of a book, kept up to date by the matching thread: the
+/// resting quantities follow the book events and the level counts are set after them,
+/// so that the book is never walked to be measured
+#[derive(Debug, Default)]
+pub struct BookGauges
+{
+    _bid_levels : Gauge,
+    _ask_levels : Gauge,
+    _bid_qty : Gauge,
+    _ask_qty : Gauge,
+}
+
+impl BookGauges
+{
+    fn qty(&self, side : Side) -> &Gauge
+    {
+        match side
+        {
+            Side::Buy => &self._bid_qty,
+            Side::Sell => &self._ask_qty,
+        }
+    }
+
+    fn add_qty(&self, side : Side, qty : i64)
+    {
+        self.qty(side).add(qty);
+    }
+
+    /// on_event moves the resting quantity of the side changed by the event
+    pub fn on_event(&self, event : &Event)
+    {
+        match event
+        {
+            Event::Added(order) => self.add_qty(order.side, order.qty as i64),
+            Event::Traded(trade) =>
+            {
+                let passive_side = match trade.aggressor_side
+                {
+                    Side::Buy => Side::Sell,
+                    Side::Sell => Side::Buy,
+                };
+                self.add_qty(passive_side, -(trade.qty as i64));
+            },
+            Event::Cancelled(order) | Event::Expired(order) => self.add_qty(order.side, -(order.qty as i64)),
+            // A reduction in place keeps the order, otherwise it is added again once matched
+            Event::Replaced { old, new } if old.price == new.price && new.qty <= old.qty => self.add_qty(old.side, new.qty as i64 - old.qty as i64),
+            Event::Replaced { old, .. } => self.add_qty(old.side, -(old.qty as i64)),
+            Event::MassCancelled { .. } => {},
+        }
+    }
+
+    /// set_levels records the number of price levels of each side
+    pub fn set_levels(&self, bid_levels : usize, ask_levels : usize)
+    {
+        self._bid_levels.set(bid_levels as i64);
+        self._ask_levels.set(ask_levels as i64);
+    }
+}
+
+/// Metrics holds the in-process measures of the engine, shared by the tasks of the server
+/// * _latencies is the latency histogram of each stage, in the order of Stage::ALL
+/// * _feed_queues are the queue lengths of the live feed subscribers
+/// * _books is the depth of each symbol
+#[derive(Debug, Default)]
+pub struct Metrics
+{
+    _latencies : [Histogram; 4],
+    _orders : Counter,
+    _trades : Counter,
+    _cancels : Counter,
+    _rejects : Counter,
+    _feed_queues : Mutex<Vec<Weak<Gauge>>>,
+    _books : Mutex<BTreeMap<String, Arc<BookGauges>>>,
+}
+
+impl Metrics
+{
+    pub fn latency(&self, stage : Stage) -> &Histogram
+    {
+        &self._latencies[stage as usize]
+    }
+
+    /// orders counts the orders submitted to the engine
+    pub fn orders(&self) -> &Counter
+    {
+        &self._orders
+    }
+
+    pub fn trades(&self) -> &Counter
+    {
+        &self._trades
+    }
+
+    /// cancels counts the orders removed from the books without trading, cancelled one by
+    /// one or by a mass cancel, or expired
+    pub fn cancels(&self) -> &Counter
+    {
+        &self._cancels
+    }
+
+    /// rejects counts the orders rejected by the risk checks or the engine
+    pub fn rejects(&self) -> &Counter
+    {
+        &self._rejects
+    }
+
+    /// on_event counts the trades and the cancels reported by the book events, a mass
+    /// cancel is counted by the cancel events of its orders
+    pub fn on_event(&self, event : &Event)
+    {
+        match event
+        {
+            Event::Traded(_) => self._trades.inc(),
+            Event::Cancelled(_) | Event::Expired(_) => self._cancels.inc(),
+            Event::Added(_) | Event::Replaced { .. } | Event::MassCancelled { .. } => {},
+        }
+    }
+
+    /// feed_queue registers a feed subscriber, the subscriber sets the returned gauge to the
+    /// number of messages queued for it and drops it once disconnected
+    pub fn feed_queue(&self) -> Arc<Gauge>
+    {
+        let queue = Arc::new(Gauge::default());
+        let mut queues = self._feed_queues.lock().unwrap();
+        queues.retain(|queue| queue.strong_count() > 0);
+        queues.push(Arc::downgrade(&queue));
+        queue
+    }
+
+    /// feed_queue_length returns the longest queue among the live feed subscribers
+    pub fn feed_queue_length(&self) -> i64
+    {
+        self._feed_queues.lock().unwrap().iter().filter_map(|queue| queue.upgrade()).map(|queue| queue.get()).max().unwrap_or(0)
+    }
+
+    /// book returns the depth gauges of the symbol, created on the first call
+    pub fn book(&self, symbol : &str) -> Arc<BookGauges>
+    {
+        self._books.lock().unwrap().entry(symbol.to_string()).or_default().clone()
+    }
+
+    /// render formats the metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String
+    {
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP engine_command_latency_seconds Latency of each stage of the commands");
+        let _ = writeln!(out, "# TYPE engine_command_latency_seconds histogram");
+        for stage in Stage::ALL
+        {
+            let histogram = self.latency(stage);
+            for (bound, count) in histogram.cumulative_counts()
+            {
+                let _ = writeln!(out, "engine_command_latency_seconds_bucket{{stage=\"{}\",le=\"{}\"}} {}", stage.as_str(), bound as f64 / 1e9, count);
+            }
+            let _ = writeln!(out, "engine_command_latency_seconds_bucket{{stage=\"{}\",le=\"+Inf\"}} {}", stage.as_str(), histogram.count());
+            let _ = writeln!(out, "engine_command_latency_seconds_sum{{stage=\"{}\"}} {}", stage.as_str(), histogram.sum().as_secs_f64());
+            let _ = writeln!(out, "engine_command_latency_seconds_count{{stage=\"{}\"}} {}", stage.as_str(), histogram.count());
+        }
+
+        for (name, help, counter) in [
+            ("engine_orders_total", "Orders submitted", &self._orders),
+            ("engine_trades_total", "Trades executed", &self._trades),
+            ("engine_cancels_total", "Orders cancelled", &self._cancels),
+            ("engine_rejects_total", "Orders rejected", &self._rejects)]
+        {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter\n{} {}", name, help, name, name, counter.get());
+        }
+
+        let _ = writeln!(out, "# HELP engine_feed_queue_length Feed messages waiting to be sent to the slowest subscriber\n# TYPE engine_feed_queue_length gauge");
+        let _ = writeln!(out, "engine_feed_queue_length {}", self.feed_queue_length());
+
+        let books = self._books.lock().unwrap();
+        let _ = writeln!(out, "# HELP engine_book_levels Price levels in the book\n# TYPE engine_book_levels gauge");
+        for (symbol, gauges) in books.iter()
+        {
+            let _ = writeln!(out, "engine_book_levels{{symbol=\"{}\",side=\"bid\"}} {}", symbol, gauges._bid_levels.get());
+            let _ = writeln!(out, "engine_book_levels{{symbol=\"{}\",side=\"ask\"}} {}", symbol, gauges._ask_levels.get());
+        }
+        let _ = writeln!(out, "# HELP engine_book_qty Resting quantity in the book\n# TYPE engine_book_qty gauge");
+        for (symbol, gauges) in books.iter()
+        {
+            let _ = writeln!(out, "engine_book_qty{{symbol=\"{}\",side=\"bid\"}} {}", symbol, gauges._bid_qty.get());
+            let _ = writeln!(out, "engine_book_qty{{symbol=\"{}\",side=\"ask\"}} {}", symbol, gauges._ask_qty.get());
+        }
+        out
+    }
+}
+
+/// serve_metrics answers every HTTP request with the rendered metrics
+pub async fn serve_metrics(listener : TcpListener, metrics : Arc<Metrics>)
+{
+    loop
+    {
+        let (mut socket, _) = match listener.accept().await
+        {
+            Ok(connection) => connection,
+            Err(_) => continue,
+        };
+
+        let metrics = metrics.clone();
+        tokio::spawn(async move
+        {
+            // The request is not parsed, reading its first bytes is enough to answer it
+            let mut request = [0u8; 1024];
+            if socket.read(&mut request).await.is_err()
+            {
+                return;
+            }
+            let body = metrics.render();
+            let response = format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                                   body.len(), body);
+            let _ = socket.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use tokio::net::TcpStream;
+    use crate::data_types::{MassCancelFilter, Order};
+    use crate::engine::Engine;
+    use super::*;
+
+    #[test]
+    fn histogram_counts_observations_in_buckets()
+    {
+        let histogram = Histogram::default();
+        histogram.observe(Duration::from_nanos(400));
+        histogram.observe(Duration::from_nanos(500));
+        histogram.observe(Duration::from_micros(3));
+        histogram.observe(Duration::from_secs(1));
+
+        let counts = histogram.cumulative_counts();
+        assert_eq!(counts[0], (500, 2));
+        assert_eq!(counts[2], (2_500, 2));
+        assert_eq!(counts[3], (5_000, 3));
+        assert_eq!(counts.last(), Some(&(100_000_000, 3)));
+        assert_eq!(histogram.count(), 4);
+    }
+
+    #[test]
+    fn book_gauges_follow_the_events()
+    {
+        let mut engine = Engine::new();
+        engine.add_symbol("TSLA");
+        let metrics = Metrics::default();
+        let gauges = metrics.book("TSLA");
+        let apply = |engine : &mut Engine|
+        {
+            for (_, event) in engine.drain_events()
+            {
+                metrics.on_event(&event);
+                gauges.on_event(&event);
+            }
+            let (bid_levels, ask_levels) = engine.book("TSLA").unwrap().level_counts();
+            gauges.set_levels(bid_levels, ask_levels);
+        };
+
+        let resting = Order::new(1, Side::Buy, 122.2f32, 100);
+        engine.insert_order("TSLA", &mut resting.clone()).unwrap();
+        engine.insert_order("TSLA", &mut Order::new(2, Side::Buy, 122.1f32, 10)).unwrap();
+        engine.insert_order("TSLA", &mut Order::new(3, Side::Sell, 122.2f32, 30)).unwrap();
+        engine.insert_order("TSLA", &mut Order::new(4, Side::Sell, 122.5f32, 20)).unwrap();
+        engine.amend_order("TSLA", &Order { qty : 70, ..resting }, 122.2f32, 50).unwrap();
+        apply(&mut engine);
+        assert_eq!((gauges._bid_levels.get(), gauges._ask_levels.get()), (2, 1));
+        assert_eq!((gauges._bid_qty.get(), gauges._ask_qty.get()), (60, 20));
+
+        // Moving the order to another price takes it out of the book before adding it again
+        engine.amend_order("TSLA", &Order { qty : 50, ..resting }, 122.0f32, 50).unwrap();
+        engine.mass_cancel(Some("TSLA"), &MassCancelFilter::default()).unwrap();
+        apply(&mut engine);
+        assert_eq!((gauges._bid_levels.get(), gauges._ask_levels.get()), (0, 0));
+        assert_eq!((gauges._bid_qty.get(), gauges._ask_qty.get()), (0, 0));
+        assert_eq!(metrics.trades().get(), 1);
+        assert_eq!(metrics.cancels().get(), 3);
+    }
+
+    #[test]
+    fn feed_queue_length_is_the_longest_queue()
+    {
+        let metrics = Metrics::default();
+        let (slow, fast) = (metrics.feed_queue(), metrics.feed_queue());
+        slow.set(7);
+        fast.set(2);
+        assert_eq!(metrics.feed_queue_length(), 7);
+        drop(slow);
+        assert_eq!(metrics.feed_queue_length(), 2);
+    }
+
+    #[tokio::test]
+    async fn metrics_are_served_in_prometheus_format()
+    {
+        let metrics = Arc::new(Metrics::default());
+        metrics.orders().add(3);
+        metrics.trades().inc();
+        metrics.latency(Stage::Match).observe(Duration::from_micros(2));
+        let gauges = metrics.book("TSLA");
+        gauges.set_levels(2, 0);
+        gauges.on_event(&Event::Added(Order::new(1, Side::Buy, 122.2f32, 100)));
+        gauges.on_event(&Event::Added(Order::new(2, Side::Buy, 122.1f32, 10)));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve_metrics(listener, metrics.clone()));
+
+        let mut socket = TcpStream::connect(address).await.unwrap();
+        socket.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        socket.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("engine_orders_total 3\n"));
+        assert!(response.contains("engine_trades_total 1\n"));
+        assert!(response.contains("engine_command_latency_seconds_bucket{stage=\"match\",le=\"0.0000025\"} 1\n"));
+        assert!(response.contains("engine_command_latency_seconds_count{stage=\"decode\"} 0\n"));
+        assert!(response.contains("engine_book_levels{symbol=\"TSLA\",side=\"bid\"} 2\n"));
+        assert!(response.contains("engine_book_qty{symbol=\"TSLA\",side=\"bid\"} 110\n"));
+    }
+}
@@ -1,8 +1,18 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BinaryHeap, HashSet};
+use std::sync::Arc;
+use crate::book::Book;
+use crate::clock::{Clock, MonotonicClock, Sequencer};
 use crate::data_types::*;
+use crate::depth::{Depth, DepthLevel};
+use crate::events::Event;
+use crate::fees::FeeCalculator;
 use crate::matching;
+use crate::price_ladder::PriceLadder;
+use crate::price_levels::PriceLevels;
+use crate::slab::OrderSlab;
 
 use ordered_float::OrderedFloat;
+use tracing::{debug, info};
 use std::cmp::Ord;
 use std::cmp::Ordering;
 
@@ -44,7 +54,7 @@ impl PartialEq for BidKey
 {
     fn eq(&self, other : &Self) -> bool
     {
-        return self.0 == other.0;
+        self.0 == other.0
     }
 }
 
@@ -53,6 +63,7 @@ impl Eq for BidKey
 
 impl Ord for BidKey
 {
+    #[allow(clippy::suspicious_else_formatting)]
     fn cmp(&self, other: &Self) -> Ordering
     {
         if self.0 > other.0
@@ -76,76 +87,301 @@ impl PartialOrd for BidKey
     }
 }
 
+//////////////////////////// EXPIRY /////////////////////////////// 
+
+/// Expiry schedules the removal of a GTD order, the entries are ordered by expiry
+/// time so that the heap of the book pops the earliest one first. The order is
+/// looked up again by id and sequence number, the entries of the orders which
+/// already left the book or entered it again upon an amend are skipped
+#[derive(Debug)]
+struct Expiry
+{
+    at : u64,
+    order : Order,
+}
+
+impl PartialEq for Expiry
+{
+    fn eq(&self, other : &Self) -> bool
+    {
+        (self.at, self.order.seq) == (other.at, other.order.seq)
+    }
+}
+
+impl Eq for Expiry
+{}
+
+impl Ord for Expiry
+{
+    fn cmp(&self, other: &Self) -> Ordering
+    {
+        (other.at, other.order.seq).cmp(&(self.at, self.order.seq))
+    }
+}
+
+impl PartialOrd for Expiry
+{
+    fn partial_cmp(&self, other : &Self) -> Option<Ordering>
+    {
+        Some(self.cmp(other))
+    }
+}
+
 //////////////////////////// ORDERBOOK /////////////////////////////// 
 
 /// Order Book contains an implementation of an order book with the following data
-/// * _bid is the map containing all the bid price levels
-/// * _ask is the map containing all the ask price levels
-/// * _trades are the trades currently collected
+/// * _bid is the side containing all the bid price levels
+/// * _ask is the side containing all the ask price levels
+/// * _trades are the last trades, the oldest ones are dropped once TRADE_HISTORY_CAPACITY
+///   is reached so that the history stays in its preallocated memory, unless a single
+///   order fills more than that many trades
+/// * _trade_count is the number of trades executed since the book was created
+/// * _events are the state changes not yet consumed
+/// * _fees charges the maker and taker fees of the trades
+/// * _clock timestamps the orders and trades
+/// * _sequencer assigns the order sequence numbers and the trade and match ids
+/// * _expiries are the GTD orders scheduled to expire, earliest first
+/// * _orders stores the resting orders, linked in the queues of the price levels
+/// * _fills collects the trades of the incoming order being matched, its memory is reused
 /// 
 /// # Arguments
 /// 
+/// The sides are sorted maps by default, LadderBook backs them with price ladders
+/// for the instruments with a known tick size and price band. Only a warmed up
+/// LadderBook trades without allocating, the sorted maps allocate their nodes as
+/// levels are created and removed
 #[derive(Debug)]
-pub struct OrderBook
+pub struct OrderBook<Bid = BTreeMap<BidKey, Limit>, Ask = BTreeMap<AskKey, Limit>>
 {
     _symbol: String,
-    pub _bid : BTreeMap<BidKey, Limit>,
-    pub _ask : BTreeMap<AskKey, Limit>,
-    pub _trades : Vec<Trade>
+    _bid : Bid,
+    _ask : Ask,
+    _trades : Vec<Trade>,
+    _trade_count : u64,
+    _events : Vec<Event>,
+    _fees : FeeCalculator,
+    _clock : Arc<dyn Clock>,
+    _sequencer : Arc<Sequencer>,
+    _expiries : BinaryHeap<Expiry>,
+    _orders : OrderSlab,
+    _fills : Vec<Trade>,
 }
 
+/// Number of resting orders a book holds before its slab grows
+const ORDERS_CAPACITY : usize = 1024;
+
+/// Number of trades the history of a book holds, the older half is dropped when it is full
+const TRADE_HISTORY_CAPACITY : usize = 2048;
+
+/// Number of expiry entries under which the stale ones are left in the heap
+const EXPIRIES_PURGE_THRESHOLD : usize = 1024;
+
+/// LadderBook is an order book whose sides are price ladders, its levels are preallocated
+/// so that once its slab and buffers are warmed up, matching does not allocate
+pub type LadderBook = OrderBook<PriceLadder, PriceLadder>;
+
 /// insert_order function provides a way to insert order on a certain side of the book
 /// 
 /// # Arguments
-/// * current_side: is the map containing the current side on which we want to add an
-/// order
+/// * current_side: is the side on which we want to add an order
+/// * orders: the slab storing the orders of the book
 /// * order: it's the order that we want to add
 ///  
-fn insert_order<T : Ord + Creator>(curr_side : &mut BTreeMap<T, Limit>, order : Order)
+fn insert_order<L : PriceLevels>(curr_side : &mut L, orders : &mut OrderSlab, events : &mut Vec<Event>, order : Order)
 {
-    let key = T::create(order.price);
-    let curr_limit = curr_side.entry(key).or_insert(Limit::new(order.price));
-    curr_limit.add_order(order);        
+    curr_side.level_or_insert(order.price).add_order(orders, order);
+    events.push(Event::Added(order));
 }
 
 
-fn cancel_order<'a, T : Ord + Creator>(curr_side : &'a mut BTreeMap<T, Limit>, order : &Order) -> Result<Order, &'a str>
+/// order_level returns the level of the price of the order, a resting order with the
+/// same id must be queued at that level
+///
+/// # Arguments
+/// * current_side: is the side of the order
+/// * orders: the slab storing the orders of the book
+/// * order: the order as known by the requester, only id, side and price are used
+///
+fn order_level<'a, L : PriceLevels>(curr_side : &'a mut L, orders : &OrderSlab, order : &Order) -> Result<&'a mut Limit, &'static str>
 {
-    
-    let mut removed_order : Result<Order, &str> = Ok(order.clone());
+    let limit = curr_side.level_mut(order.price).ok_or("Limit is not present in the OrderBook")?;
+    if limit.find(orders, order.side, order.id).is_none()
     {
-        let limit : Option<&mut Limit> = curr_side.get_mut(&T::create(order.price));
-        if limit.is_none()
-        {
-            return Err("Limit is not present in the OrderBook");
-        }
-
-        removed_order= limit.unwrap().remove_order(order.id);
+        return Err("Order is not present at the limit");
     }
+    Ok(limit)
+}
 
-    let order_to_return = removed_order.unwrap().clone();
-    curr_side.retain(|_, limit: &mut Limit| limit.qty != 0);
-    return Ok(order_to_return);
+fn cancel_order<L : PriceLevels>(curr_side : &mut L, orders : &mut OrderSlab, order : &Order) -> Result<Order, &'static str>
+{
+    let limit = order_level(curr_side, orders, order)?;
+    let removed_order = limit.remove_order(orders, order.side, order.id)?;
+    if limit.num_orders() == 0
+    {
+        curr_side.remove_level(order.price);
+    }
+    Ok(removed_order)
 }
 
 impl OrderBook {
 
-
     /// new function creates a new order book
     /// 
     /// # Arguments
     /// * symbol: it's the symbol of that the order book is tracking
     /// 
     pub fn new(symbol: &str) -> OrderBook
+    {
+        OrderBook::with_clock(symbol, Arc::new(MonotonicClock::new()), Arc::new(Sequencer::new()))
+    }
+
+    /// with_clock creates a new order book sharing the clock and the sequencer of an engine
+    /// 
+    /// # Arguments
+    /// * symbol: it's the symbol of that the order book is tracking
+    /// * clock: the clock timestamping the orders and trades
+    /// * sequencer: the sequencer assigning the order sequence numbers and trade ids
+    /// 
+    pub fn with_clock(symbol: &str, clock : Arc<dyn Clock>, sequencer : Arc<Sequencer>) -> OrderBook
+    {
+        OrderBook::with_sides(symbol, BTreeMap::new(), BTreeMap::new(), clock, sequencer)
+    }
+}
+
+impl LadderBook {
+
+    /// with_ladder creates a new order book for an instrument traded in a price band,
+    /// the orders priced outside of the band or off the ticks are rejected
+    /// 
+    /// # Arguments
+    /// * symbol: it's the symbol of that the order book is tracking
+    /// * band: the lowest price, the highest price and the tick size of the instrument
+    /// * clock: the clock timestamping the orders and trades
+    /// * sequencer: the sequencer assigning the order sequence numbers and trade ids
+    /// 
+    pub fn with_ladder(symbol: &str, band : (f32, f32, f32), clock : Arc<dyn Clock>, sequencer : Arc<Sequencer>) -> Result<LadderBook, &'static str>
+    {
+        let (min_price, max_price, tick_size) = band;
+        Ok(OrderBook::with_sides(symbol,
+                                 PriceLadder::new(Side::Buy, min_price, max_price, tick_size)?,
+                                 PriceLadder::new(Side::Sell, min_price, max_price, tick_size)?,
+                                 clock, sequencer))
+    }
+}
+
+impl<Bid : PriceLevels, Ask : PriceLevels> OrderBook<Bid, Ask> {
+
+    /// with_sides creates a new order book from its empty sides
+    fn with_sides(symbol: &str, bid : Bid, ask : Ask, clock : Arc<dyn Clock>, sequencer : Arc<Sequencer>) -> Self
     {
         OrderBook { _symbol : symbol.to_string(), 
-                    _bid: BTreeMap::new(), 
-                    _ask: BTreeMap::new(),
-                    _trades : vec![]}
+                    _bid: bid, 
+                    _ask: ask,
+                    _trades : Vec::with_capacity(TRADE_HISTORY_CAPACITY),
+                    _trade_count : 0,
+                    _events : vec![],
+                    _fees : FeeCalculator::default(),
+                    _clock : clock,
+                    _sequencer : sequencer,
+                    _expiries : BinaryHeap::new(),
+                    _orders : OrderSlab::with_capacity(ORDERS_CAPACITY),
+                    _fills : vec![]}
     }
 
-    pub fn insert_order_at_level(&mut self, order: &mut Order)
+    pub fn clock(&self) -> &Arc<dyn Clock>
     {
+        &self._clock
+    }
+
+    pub fn fees(&self) -> &FeeCalculator
+    {
+        &self._fees
+    }
+
+    pub fn fees_mut(&mut self) -> &mut FeeCalculator
+    {
+        &mut self._fees
+    }
+
+    /// stamp assigns the entry time and the engine sequence number of an order entering the book
+    fn stamp(&self, order : &mut Order)
+    {
+        order.timestamp = self._clock.now();
+        order.seq = self._sequencer.next_order_seq();
+    }
+
+    /// record_trades stamps and charges the fees of the fills of the aggressive order,
+    /// then collects them. All the fills share the match id and time of the aggressive order
+    fn record_trades(&mut self, taker : &Order)
+    {
+        if self._fills.is_empty()
+        {
+            return;
+        }
+        let match_id = self._sequencer.next_match_id();
+        let symbol = Symbol::new(&self._symbol);
+        for trade in self._fills.iter_mut()
+        {
+            trade.trade_id = self._sequencer.next_trade_id();
+            trade.match_id = match_id;
+            trade.timestamp = taker.timestamp;
+            trade.aggressor_side = taker.side;
+            trade.symbol = symbol;
+        }
+        self._fees.charge(taker, &mut self._fills);
+        self._events.extend(self._fills.iter().map(|trade| Event::Traded(*trade)));
+        self._trade_count += self._fills.len() as u64;
+        let len = self._trades.len() + self._fills.len();
+        if len > TRADE_HISTORY_CAPACITY
+        {
+            self._trades.drain(..(len - TRADE_HISTORY_CAPACITY / 2).min(self._trades.len()));
+        }
+        self._trades.append(&mut self._fills);
+    }
+
+    /// check_price tells whether the order can rest at its price, the books with a
+    /// price band reject the limit orders priced outside of it or off its ticks
+    /// 
+    /// # Arguments
+    /// * order: the incoming order
+    pub fn check_price(&self, order : &Order) -> Result<(), &'static str>
+    {
+        if order.order_type == OrderType::Market || self.accepts(order.side, order.price)
+        {
+            return Ok(());
+        }
+        Err("Price is outside of the price band of the OrderBook")
+    }
+
+    fn accepts(&self, side : Side, price : f32) -> bool
+    {
+        match side
+        {
+            Side::Buy => self._bid.accepts(price),
+            Side::Sell => self._ask.accepts(price),
+        }
+    }
+
+    /// insert_order_at_level matches the order against the opposite side of the book
+    /// and rests the remaining quantity, market orders are never rested
+    /// 
+    /// # Arguments
+    /// * order: the incoming order, it is stamped with the entry time and sequence
+    ///   number and its quantity is reduced by the matched quantity
+    /// # Return
+    /// 
+    /// An error, leaving the order untouched, when its price is rejected by check_price
+    pub fn insert_order_at_level(&mut self, order: &mut Order) -> Result<(), &'static str>
+    {
+        self.check_price(order)?;
+        self.stamp(order);
+        self.match_and_rest(order);
+        Ok(())
+    }
+
+    fn match_and_rest(&mut self, order: &mut Order)
+    {
+        let order_type = order.order_type;
         match &order.side
         {
             Side::Buy => 
@@ -153,44 +389,113 @@ impl OrderBook {
                 // If I find something at a lower or equal price respect to what I want to buy
                 let match_bid_strategy = |best_availiable_price, current_offered_price| 
                 {
+                    order_type == OrderType::Market ||
                     OrderedFloat(best_availiable_price) <= OrderedFloat(current_offered_price)
                 };
 
-                let mut bid_trades = matching::match_order(&mut self._ask, &match_bid_strategy, order);
-                if !bid_trades.is_empty()
-                {
-                    self._trades.append(&mut bid_trades);
-                }
+                matching::match_order(&mut self._ask, &mut self._orders, &match_bid_strategy, order, &mut self._fills);
+                self.record_trades(order);
 
-                if order.qty == 0
+                if order.qty == 0 || order_type == OrderType::Market
                 {
                     return
                 }
                 
-                insert_order(&mut self._bid, *order);
+                insert_order(&mut self._bid, &mut self._orders, &mut self._events, *order);
+                self.schedule_expiry(order);
             },
             Side::Sell => 
             {
                 // If I find something at a higher or equal price respect to what I want to buy
                 let match_ask_strategy = |best_availiable_price, current_offered_price| 
                 {
+                    order_type == OrderType::Market ||
                     OrderedFloat(best_availiable_price) >= OrderedFloat(current_offered_price)
                 };
-                let mut ask_trades = matching::match_order(&mut self._bid, &match_ask_strategy, order);
-                if !ask_trades.is_empty()
-                {
-                    self._trades.append(&mut ask_trades);
-                }
-                if order.qty == 0
+                matching::match_order(&mut self._bid, &mut self._orders, &match_ask_strategy, order, &mut self._fills);
+                self.record_trades(order);
+                if order.qty == 0 || order_type == OrderType::Market
                 {
                     return
                 }
 
-                insert_order(&mut self._ask, *order)
+                insert_order(&mut self._ask, &mut self._orders, &mut self._events, *order);
+                self.schedule_expiry(order);
             },
         }
     }
 
+    /// schedule_expiry records the expiry time of a GTD order resting in the book
+    fn schedule_expiry(&mut self, order : &Order)
+    {
+        if let TimeInForce::GoodTillDate(at) = order.time_in_force
+        {
+            if self._expiries.len() >= EXPIRIES_PURGE_THRESHOLD.max(2 * self._orders.len())
+            {
+                self.purge_expiries();
+            }
+            self._expiries.push(Expiry { at, order : *order });
+        }
+    }
+
+    /// purge_expiries drops the entries of the orders which were cancelled, filled or
+    /// amended since they were scheduled. It runs once the heap holds twice as many
+    /// entries as there are resting orders, so that its cost is spread over the pushes
+    fn purge_expiries(&mut self)
+    {
+        let scheduled : HashSet<(u32, u64)> = self._bid.levels().chain(self._ask.levels())
+            .flat_map(|limit| limit.orders(&self._orders))
+            .filter(|order| matches!(order.time_in_force, TimeInForce::GoodTillDate(_)))
+            .map(|order| (order.id, order.seq))
+            .collect();
+        self._expiries.retain(|expiry| scheduled.contains(&(expiry.order.id, expiry.order.seq)));
+        debug!(symbol = %self._symbol, scheduled = self._expiries.len(), "purged the expiry heap");
+    }
+
+    /// expire_orders removes the GTD orders whose expiry time is reached by the clock
+    /// of the book, an expired event is emitted for each order
+    /// 
+    /// # Return
+    /// 
+    /// The expired orders, earliest expiry first
+    pub fn expire_orders(&mut self) -> Vec<Order>
+    {
+        let now = self._clock.now();
+        let mut expired = vec![];
+        while self._expiries.peek().is_some_and(|expiry| expiry.at <= now)
+        {
+            let order = self._expiries.pop().unwrap().order;
+            let is_scheduled = |resting : &Order| resting.id == order.id && resting.seq == order.seq;
+            match order.side
+            {
+                Side::Buy => self._bid.remove_orders(&mut self._orders, Some((order.price, order.price)), is_scheduled, &mut expired),
+                Side::Sell => self._ask.remove_orders(&mut self._orders, Some((order.price, order.price)), is_scheduled, &mut expired),
+            }
+        }
+        if !expired.is_empty()
+        {
+            debug!(symbol = %self._symbol, expired = expired.len(), "expired GTD orders");
+        }
+        self._events.extend(expired.iter().map(|order| Event::Expired(*order)));
+        expired
+    }
+
+    /// close_session removes the DAY orders at the end of the trading session,
+    /// an expired event is emitted for each order
+    /// 
+    /// # Return
+    /// 
+    /// The expired orders, bids first
+    pub fn close_session(&mut self) -> Vec<Order>
+    {
+        let mut expired = vec![];
+        let is_day = |order : &Order| order.time_in_force == TimeInForce::Day;
+        self._bid.remove_orders(&mut self._orders, None, is_day, &mut expired);
+        self._ask.remove_orders(&mut self._orders, None, is_day, &mut expired);
+        self._events.extend(expired.iter().map(|order| Event::Expired(*order)));
+        expired
+    }
+
 
     /// cancel_order cancels an order from the order book
     /// 
@@ -199,22 +504,133 @@ impl OrderBook {
     /// # Return
     /// 
     /// A result data type which either contains order or the string tag
-    fn cancel_order(&mut self, order : &Order) -> Result<Order, &str>
+    pub fn cancel_order(&mut self, order : &Order) -> Result<Order, &'static str>
+    {
+        let cancelled = match order.side
+        {
+            Side::Buy => cancel_order(&mut self._bid, &mut self._orders, order),
+            Side::Sell => cancel_order(&mut self._ask, &mut self._orders, order),
+        }?;
+
+        self._events.push(Event::Cancelled(cancelled));
+        Ok(cancelled)
+    }
+
+    /// amend_order changes price and quantity of a resting order. A quantity decrease
+    /// at the same price keeps the time priority, any other change is executed as a
+    /// cancel and a new insert which can match against the opposite side
+    /// 
+    /// # Arguments
+    /// * order: the resting order to be amended, only id, side and price are used
+    /// * price: the new limit price
+    /// * qty: the new quantity left to be executed, it must be greater than zero
+    /// # Return
+    /// 
+    /// The amended order, with its quantity reduced by any resulting match
+    pub fn amend_order(&mut self, order : &Order, price : f32, qty : u32) -> Result<Order, &'static str>
     {
-        let side = order.side;
+        if qty == 0
+        {
+            return Err("Cannot amend an order to zero quantity");
+        }
+        if !self.accepts(order.side, price)
+        {
+            return Err("Price is outside of the price band of the OrderBook");
+        }
 
-        match side 
+        if OrderedFloat(order.price) == OrderedFloat(price)
         {
-            Side::Buy => 
+            let limit = match order.side
             {
-                return cancel_order(&mut self._bid, order);
-            },
-            Side::Sell =>
+                Side::Buy => order_level(&mut self._bid, &self._orders, order),
+                Side::Sell => order_level(&mut self._ask, &self._orders, order),
+            }?;
+
+            if let Some(old) = limit.reduce_order(&mut self._orders, order.side, order.id, qty)?
             {
-                return cancel_order(&mut self._ask, order);
+                let new = Order{qty, ..old};
+                self._events.push(Event::Replaced { old, new });
+                return Ok(new);
             }
         }
 
+        let old = match order.side
+        {
+            Side::Buy => cancel_order(&mut self._bid, &mut self._orders, order),
+            Side::Sell => cancel_order(&mut self._ask, &mut self._orders, order),
+        }?;
+
+        // The replacement loses the time priority, it enters the book again
+        let mut new = Order{price, qty, ..old};
+        self.stamp(&mut new);
+        self._events.push(Event::Replaced { old, new });
+        self.match_and_rest(&mut new);
+        Ok(new)
+    }
+
+    /// mass_cancel cancels all the resting orders selected by the filter,
+    /// a cancel event is emitted for each order followed by a summary ack
+    /// 
+    /// # Arguments
+    /// * filter: the filter selecting the orders to be cancelled
+    /// # Return
+    /// 
+    /// The cancelled orders, bids first
+    pub fn mass_cancel(&mut self, filter : &MassCancelFilter) -> Vec<Order>
+    {
+        let mut cancelled = vec![];
+        if filter.side != Some(Side::Sell)
+        {
+            self._bid.remove_orders(&mut self._orders, filter.price_range, |order| filter.matches(order), &mut cancelled);
+        }
+        if filter.side != Some(Side::Buy)
+        {
+            self._ask.remove_orders(&mut self._orders, filter.price_range, |order| filter.matches(order), &mut cancelled);
+        }
+
+        let cancelled_qty = cancelled.iter().map(|order| order.qty as u64).sum();
+        self._events.extend(cancelled.iter().map(|order| Event::Cancelled(*order)));
+        self._events.push(Event::MassCancelled { cancelled_orders : cancelled.len() as u32, cancelled_qty });
+        cancelled
+    }
+
+    /// symbol returns the symbol tracked by the order book
+    pub fn symbol(&self) -> &str
+    {
+        &self._symbol
+    }
+
+    /// drain_events returns all the events collected since the last call
+    pub fn drain_events(&mut self) -> Vec<Event>
+    {
+        std::mem::take(&mut self._events)
+    }
+
+    /// drain_events_into moves the events collected since the last call to the vector,
+    /// unlike drain_events the memory of the book queue is kept for the next events
+    pub fn drain_events_into(&mut self, events : &mut Vec<Event>)
+    {
+        events.append(&mut self._events);
+    }
+
+    /// orders returns the slab storing the resting orders, the orders of a level are
+    /// listed by Limit::orders
+    pub fn orders(&self) -> &OrderSlab
+    {
+        &self._orders
+    }
+
+    /// trades returns the last trades executed, oldest first. At least the last
+    /// TRADE_HISTORY_CAPACITY / 2 trades are kept
+    pub fn trades(&self) -> &[Trade]
+    {
+        &self._trades
+    }
+
+    /// trade_count returns the number of trades executed since the book was created
+    pub fn trade_count(&self) -> u64
+    {
+        self._trade_count
     }
 
 
@@ -225,24 +641,41 @@ impl OrderBook {
     /// An optional reference to the limit that is containing the best bid price
     pub fn best_bid(&self) -> Option<&Limit>
     {
-        let val = self._bid.iter().next();
-        match &val
-        {
-            Some((_, l)) => Some(l),
-            None => None,
-        }
+        self._bid.best()
     }
 
     pub fn best_ask(&self) -> Option<&Limit>
     {
-        let val = self._ask.iter().next();
-        match &val
-        {
-            Some((_, l)) => Some(l),
-            None => None,
+        self._ask.best()
+    }
+
+    /// depth returns the top price levels of both sides of the book, with the
+    /// quantity and the number of orders aggregated per level
+    /// 
+    /// # Arguments
+    /// * levels: the maximum number of price levels returned per side
+    /// # Return
+    /// The depth snapshot, best price first
+    pub fn depth(&self, levels : usize) -> Depth
+    {
+        Depth {
+            bids : self._bid.levels().take(levels).map(DepthLevel::from_limit).collect(),
+            asks : self._ask.levels().take(levels).map(DepthLevel::from_limit).collect(),
         }
     }
 
+    /// order returns a resting order found through the index of the slab, the bid first
+    /// when the id rests on both sides, the last one entered when it is reused on a side
+    ///
+    /// # Arguments
+    /// * order_id: the id of the order
+    pub fn order(&self, order_id : u32) -> Option<Order>
+    {
+        [Side::Buy, Side::Sell].into_iter()
+            .find_map(|side| self._orders.handle(side, order_id))
+            .map(|handle| *self._orders.get(handle))
+    }
+
     /// get_spread returns the spread, the difference between
     /// best ask and best bid price
     /// 
@@ -270,35 +703,198 @@ impl OrderBook {
             return best_ask.unwrap().price;
         }
 
-        return self.best_ask().unwrap().price - self.best_bid().unwrap().price;
+        self.best_ask().unwrap().price - self.best_bid().unwrap().price
     }
 
-    /// prints a summary of the  returns the spread, the difference between
-    /// best ask and best bid price
-    /// 
-    /// # Arguments
-    /// # Return
-    /// * spread : f32 = value containing the current spread
-
+    /// logs a summary of the order book: best prices, number of trades and spread
     pub fn summary(&self)
     {
-        let best_ask = self.best_ask();
-        let best_bid = self.best_bid();
+        info!(symbol = %self._symbol,
+              best_bid = ?self.best_bid().map(|limit| (limit.price, limit.qty)),
+              best_ask = ?self.best_ask().map(|limit| (limit.price, limit.qty)),
+              trades = self._trade_count,
+              spread = self.get_spread(),
+              "order book summary");
+    }
+
+}
+
+impl<Bid : PriceLevels, Ask : PriceLevels> Book for OrderBook<Bid, Ask>
+{
+    fn symbol(&self) -> &str
+    {
+        OrderBook::symbol(self)
+    }
+
+    fn submit(&mut self, order : &mut Order) -> Result<(), &'static str>
+    {
+        self.insert_order_at_level(order)
+    }
+
+    fn cancel(&mut self, order : &Order) -> Result<Order, &'static str>
+    {
+        self.cancel_order(order)
+    }
+
+    fn amend(&mut self, order : &Order, price : f32, qty : u32) -> Result<Order, &'static str>
+    {
+        self.amend_order(order, price, qty)
+    }
+
+    fn mass_cancel(&mut self, filter : &MassCancelFilter) -> Vec<Order>
+    {
+        OrderBook::mass_cancel(self, filter)
+    }
+
+    fn expire_orders(&mut self) -> Vec<Order>
+    {
+        OrderBook::expire_orders(self)
+    }
+
+    fn close_session(&mut self) -> Vec<Order>
+    {
+        OrderBook::close_session(self)
+    }
+
+    fn best_bid(&self) -> Option<&Limit>
+    {
+        OrderBook::best_bid(self)
+    }
+
+    fn best_ask(&self) -> Option<&Limit>
+    {
+        OrderBook::best_ask(self)
+    }
+
+    fn level_counts(&self) -> (usize, usize)
+    {
+        (self._bid.len(), self._ask.len())
+    }
+
+    fn depth(&self, levels : usize) -> Depth
+    {
+        OrderBook::depth(self, levels)
+    }
+
+    fn order(&self, order_id : u32) -> Option<Order>
+    {
+        OrderBook::order(self, order_id)
+    }
+
+    fn drain_events(&mut self) -> Vec<Event>
+    {
+        OrderBook::drain_events(self)
+    }
+
+    fn trades(&self) -> &[Trade]
+    {
+        OrderBook::trades(self)
+    }
 
-        println!("Best Ask = {:#?}, Best Bid = {:#?}", best_ask, best_bid);
-        println!("Number of trades = {0}", self._trades.len());
-        println!("Spread = {0}", self.get_spread());
+    fn trade_count(&self) -> u64
+    {
+        OrderBook::trade_count(self)
     }
 
+    fn clock(&self) -> &Arc<dyn Clock>
+    {
+        OrderBook::clock(self)
+    }
+
+    fn fees(&self) -> &FeeCalculator
+    {
+        OrderBook::fees(self)
+    }
+
+    fn fees_mut(&mut self) -> &mut FeeCalculator
+    {
+        OrderBook::fees_mut(self)
+    }
+
+    fn summary(&self)
+    {
+        OrderBook::summary(self)
+    }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod test {
 
-    use crate::order_book::OrderBook;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::sync::Arc;
+    use crate::order_book::{LadderBook, OrderBook, EXPIRIES_PURGE_THRESHOLD, TRADE_HISTORY_CAPACITY};
+    use crate::price_levels::PriceLevels;
+    use crate::clock::{ManualClock, Sequencer};
     use crate::data_types::*;
+    use crate::events::Event;
+    use crate::fees::FeeSchedule;
     use ordered_float::OrderedFloat;
 
+    /// CountingAllocator counts the allocations of each thread, so that a test
+    /// measures its own allocations while the other tests run
+    struct CountingAllocator;
+
+    thread_local!
+    {
+        static ALLOCATIONS : Cell<u64> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator
+    {
+        unsafe fn alloc(&self, layout : Layout) -> *mut u8
+        {
+            let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr : *mut u8, layout : Layout)
+        {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr : *mut u8, layout : Layout, new_size : usize) -> *mut u8
+        {
+            let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL : CountingAllocator = CountingAllocator;
+
+    /// Time of the frozen clock of the test books
+    const NOW : u64 = 34_200_000_000_000;
+
+    fn test_book(symbol : &str) -> OrderBook
+    {
+        OrderBook::with_clock(symbol, Arc::new(ManualClock::new(NOW)), Arc::new(Sequencer::new()))
+    }
+
+    /// entered returns the order as stamped by a test book
+    fn entered(order : Order, seq : u64) -> Order
+    {
+        Order { timestamp : NOW, seq, ..order }
+    }
+
+    /// best_bid_orders returns the orders of the best bid level by time priority
+    fn best_bid_orders<Bid : PriceLevels, Ask : PriceLevels>(order_book : &OrderBook<Bid, Ask>) -> Vec<Order>
+    {
+        order_book.best_bid().unwrap().orders(order_book.orders()).copied().collect()
+    }
+
+    fn best_ask_orders<Bid : PriceLevels, Ask : PriceLevels>(order_book : &OrderBook<Bid, Ask>) -> Vec<Order>
+    {
+        order_book.best_ask().unwrap().orders(order_book.orders()).copied().collect()
+    }
+
+    /// filled returns the trade as stamped by a test book
+    fn filled(trade : Trade, trade_id : u64, match_id : u64, aggressor_side : Side, symbol : &str) -> Trade
+    {
+        Trade { trade_id, match_id, timestamp : NOW, aggressor_side, symbol : Symbol::new(symbol), ..trade }
+    }
+
     #[test]
     fn create_order_book()
     {
@@ -311,24 +907,24 @@ mod test {
     fn insert_order_at_level_is_correct()
     {
         let mut order_book = OrderBook::new("AAPL");
-        let mut order = Order{id:1, side:Side::Sell, price:12.2f32, qty:100};
-        order_book.insert_order_at_level(&mut order);
-        let mut order2 = Order{id:1, side:Side::Sell, price:12.5f32, qty:100};
-        order_book.insert_order_at_level(&mut order2);
+        let mut order = Order::new(1, Side::Sell, 12.2f32, 100);
+        order_book.insert_order_at_level(&mut order).unwrap();
+        let mut order2 = Order::new(1, Side::Sell, 12.5f32, 100);
+        order_book.insert_order_at_level(&mut order2).unwrap();
         let best_price = order_book._ask.iter().next();
         println!("{:?}", order_book);
         println!("Best Price = {:?}", best_price);
-        assert_eq!((*best_price.unwrap().1).price, 12.2f32);
+        assert_eq!(best_price.unwrap().1.price, 12.2f32);
     }
 
     #[test]
     fn insert_multiple_orders()
     {
         let mut order_book = OrderBook::new("AAPL");
-        let mut order = Order{id:1, side:Side::Buy, price:12.2f32, qty:100};
-        order_book.insert_order_at_level(&mut order);
-        let mut order2 = Order{id:2, side:Side::Sell, price:12.5f32, qty:25};
-        order_book.insert_order_at_level(&mut order2);
+        let mut order = Order::new(1, Side::Buy, 12.2f32, 100);
+        order_book.insert_order_at_level(&mut order).unwrap();
+        let mut order2 = Order::new(2, Side::Sell, 12.5f32, 25);
+        order_book.insert_order_at_level(&mut order2).unwrap();
         println!("{:?}", order_book);
         assert_eq!(order_book._bid.len(), 1);
         assert_eq!(order_book._ask.len(), 1);
@@ -338,10 +934,10 @@ mod test {
     fn insert_multiple_orders_at_same_level()
     {
         let mut order_book = OrderBook::new("AAPL");
-        let mut order = Order{id:1, side:Side::Sell, price:12.2f32, qty:100};
-        order_book.insert_order_at_level(&mut order);
-        let mut order2 = Order{id:2, side:Side::Sell, price:12.2f32, qty:25};
-        order_book.insert_order_at_level(&mut order2);
+        let mut order = Order::new(1, Side::Sell, 12.2f32, 100);
+        order_book.insert_order_at_level(&mut order).unwrap();
+        let mut order2 = Order::new(2, Side::Sell, 12.2f32, 25);
+        order_book.insert_order_at_level(&mut order2).unwrap();
         println!("{:?}", order_book);
         assert_eq!(order_book._ask.len(), 1);
         assert_eq!(order_book.best_ask().unwrap().num_orders(), 2);
@@ -352,20 +948,20 @@ mod test {
     fn insert_multiple_orders_at_different_level()
     {
         let mut order_book = OrderBook::new("AAPL");
-        let mut order = Order{id:1, side:Side::Sell, price:12.2f32, qty:100};
-        let mut order2 = Order{id:2, side:Side::Sell, price:12.2f32, qty:25};
-        let mut order3 = Order{id:3, side:Side::Sell, price:12.5f32, qty:25};
-        order_book.insert_order_at_level(&mut order);
-        order_book.insert_order_at_level(&mut order2);
-        order_book.insert_order_at_level(&mut order3);
+        let mut order = Order::new(1, Side::Sell, 12.2f32, 100);
+        let mut order2 = Order::new(2, Side::Sell, 12.2f32, 25);
+        let mut order3 = Order::new(3, Side::Sell, 12.5f32, 25);
+        order_book.insert_order_at_level(&mut order).unwrap();
+        order_book.insert_order_at_level(&mut order2).unwrap();
+        order_book.insert_order_at_level(&mut order3).unwrap();
 
         // Add buy orders
-        let mut order4 = Order{id:4, side:Side::Buy, price:12.1f32, qty:100};
-        let mut order5 = Order{id:5, side:Side::Buy, price:12.1f32, qty:25};
-        let mut order6 = Order{id:6, side:Side::Buy, price:12.15f32, qty:25};
-        order_book.insert_order_at_level(&mut order4);
-        order_book.insert_order_at_level(&mut order5);
-        order_book.insert_order_at_level(&mut order6);
+        let mut order4 = Order::new(4, Side::Buy, 12.1f32, 100);
+        let mut order5 = Order::new(5, Side::Buy, 12.1f32, 25);
+        let mut order6 = Order::new(6, Side::Buy, 12.15f32, 25);
+        order_book.insert_order_at_level(&mut order4).unwrap();
+        order_book.insert_order_at_level(&mut order5).unwrap();
+        order_book.insert_order_at_level(&mut order6).unwrap();
 
         assert_eq!(order_book._ask.len(), 2);
         let best_ask_price = order_book.best_ask().unwrap();
@@ -385,76 +981,76 @@ mod test {
     {
         let mut order_book = OrderBook::new("AAPL");
         let mut _id : u32 = 1;
-        let mut order = Order{id:_id, side:Side::Buy, price:12.2f32, qty:0};
-        order_book.insert_order_at_level(&mut order);
+        let mut order = Order::new(_id, Side::Buy, 12.2f32, 0);
+        order_book.insert_order_at_level(&mut order).unwrap();
         assert_eq!(order_book.best_bid().is_none(), true);
         assert_eq!(order_book.best_ask().is_none(), true);
-        let mut empty_sell_order = Order{id:_id, side:Side::Sell, price:12.2f32, qty:0};
-        order_book.insert_order_at_level(&mut empty_sell_order);
+        let mut empty_sell_order = Order::new(_id, Side::Sell, 12.2f32, 0);
+        order_book.insert_order_at_level(&mut empty_sell_order).unwrap();
     }
 
     #[test]
     fn match_orders_multiple_ask()
     {
-        let mut order_book = OrderBook::new("AAPL");
+        let mut order_book = test_book("AAPL");
         let mut _id : u32 = 1;
-        let mut order = Order{id:_id, side:Side::Buy, price:12.2f32, qty:100};
+        let mut order = Order::new(_id, Side::Buy, 12.2f32, 100);
         _id += 1;
 
-        let mut order2 = Order{id:_id, side:Side::Buy, price:12.2f32, qty:25};
+        let mut order2 = Order::new(_id, Side::Buy, 12.2f32, 25);
         _id += 1;
 
-        let mut order3 = Order{id:_id, side:Side::Buy, price:12.5f32, qty:25};
+        let mut order3 = Order::new(_id, Side::Buy, 12.5f32, 25);
         _id += 1;
 
-        let mut order4 = Order{id:_id, side:Side::Buy, price:12.7f32, qty:25};
+        let mut order4 = Order::new(_id, Side::Buy, 12.7f32, 25);
         _id += 1;
 
-        order_book.insert_order_at_level(&mut order);
-        order_book.insert_order_at_level(&mut order2);
-        order_book.insert_order_at_level(&mut order3);
-        order_book.insert_order_at_level(&mut order4);
+        order_book.insert_order_at_level(&mut order).unwrap();
+        order_book.insert_order_at_level(&mut order2).unwrap();
+        order_book.insert_order_at_level(&mut order3).unwrap();
+        order_book.insert_order_at_level(&mut order4).unwrap();
 
         // Add sell orders
-        let mut order5 = Order{id:_id, side:Side::Sell, price:12.2f32, qty:100};
+        let mut order5 = Order::new(_id, Side::Sell, 12.2f32, 100);
         _id += 1;
 
-        order_book.insert_order_at_level(&mut order5);
-        println!("trades = {:?}", order_book._trades);
-        let t1 = Trade::new(5, 4, 12.7f32, 25);
-        let t2 = Trade::new(5, 3, 12.5f32, 25);
-        let t3 = Trade::new(5, 1, 12.2f32, 50);
+        order_book.insert_order_at_level(&mut order5).unwrap();
+        println!("trades = {:?}", order_book.trades());
+        let t1 = filled(Trade::new(5, 4, 12.7f32, 25), 1, 1, Side::Sell, "AAPL");
+        let t2 = filled(Trade::new(5, 3, 12.5f32, 25), 2, 1, Side::Sell, "AAPL");
+        let t3 = filled(Trade::new(5, 1, 12.2f32, 50), 3, 1, Side::Sell, "AAPL");
         let mut expected_trades = vec![t1,t2,t3];
-        assert_eq!(order_book._trades, expected_trades);
-        assert_eq!(order_book._trades.len(), 3);
+        assert_eq!(order_book.trades(), expected_trades);
+        assert_eq!(order_book.trades().len(), 3);
         assert_eq!(order_book.best_bid().is_some(), true);
         assert_eq!(order_book.best_bid().unwrap().qty, 75);
 
         assert_eq!(order_book.best_bid().unwrap().num_orders(), 2);
-        let exp_order1 = Order{id:1, side:Side::Buy, price:12.2f32, qty:50};
-        let exp_order2 = Order{id:2, side:Side::Buy, price:12.2f32, qty:25};
+        let exp_order1 = entered(Order::new(1, Side::Buy, 12.2f32, 50), 1);
+        let exp_order2 = entered(Order::new(2, Side::Buy, 12.2f32, 25), 2);
         let expected_orders_at_level = vec![exp_order1, exp_order2];
-        assert_eq!(order_book.best_bid().unwrap().orders, expected_orders_at_level);
+        assert_eq!(best_bid_orders(&order_book), expected_orders_at_level);
     
-        let mut order6 = Order{id:_id, side:Side::Sell, price:12.1f32, qty:25};
+        let mut order6 = Order::new(_id, Side::Sell, 12.1f32, 25);
         _id += 1;
 
-        order_book.insert_order_at_level(&mut order6);
-        assert_eq!(order_book._trades.len(), 4);
-        expected_trades.push(Trade::new(6, 1, 12.2, 25));
-        assert_eq!(order_book._trades, expected_trades);
+        order_book.insert_order_at_level(&mut order6).unwrap();
+        assert_eq!(order_book.trades().len(), 4);
+        expected_trades.push(filled(Trade::new(6, 1, 12.2, 25), 4, 2, Side::Sell, "AAPL"));
+        assert_eq!(order_book.trades(), expected_trades);
 
-        let mut order7 = Order{id:_id, side:Side::Sell, price:12.01f32, qty:50};
+        let mut order7 = Order::new(_id, Side::Sell, 12.01f32, 50);
         _id += 1;
 
-        expected_trades.push(Trade::new(7, 1, 12.2, 25));
-        expected_trades.push(Trade::new(7, 2, 12.2, 25));
+        expected_trades.push(filled(Trade::new(7, 1, 12.2, 25), 5, 3, Side::Sell, "AAPL"));
+        expected_trades.push(filled(Trade::new(7, 2, 12.2, 25), 6, 3, Side::Sell, "AAPL"));
 
         // INSERT LAST ORDER IN THE ORDER BOOK
-        order_book.insert_order_at_level(&mut order7);
+        order_book.insert_order_at_level(&mut order7).unwrap();
 
-        assert_eq!(order_book._trades.len(), 6);
-        assert_eq!(order_book._trades, expected_trades);
+        assert_eq!(order_book.trades().len(), 6);
+        assert_eq!(order_book.trades(), expected_trades);
         assert_eq!(order_book.best_bid().is_none(), true);
         assert_eq!(order_book.best_ask().is_none(), true);
     }
@@ -462,29 +1058,29 @@ mod test {
     #[test]
     fn match_orders_multiple_bid()
     {
-        let mut order_book = OrderBook::new("AAPL");
+        let mut order_book = test_book("AAPL");
         let mut _id : u32 = 1;
-        let mut order = Order{id:_id, side:Side::Sell, price:12.2f32, qty:100};
+        let mut order = Order::new(_id, Side::Sell, 12.2f32, 100);
         _id += 1;
 
-        let mut order2 = Order{id:_id, side:Side::Sell, price:12.2f32, qty:25};
+        let mut order2 = Order::new(_id, Side::Sell, 12.2f32, 25);
         _id += 1;
 
-        order_book.insert_order_at_level(&mut order);
-        order_book.insert_order_at_level(&mut order2);
+        order_book.insert_order_at_level(&mut order).unwrap();
+        order_book.insert_order_at_level(&mut order2).unwrap();
 
         // Add sell orders
-        let mut order3 = Order{id:_id, side:Side::Buy, price:12.4f32, qty:50};
+        let mut order3 = Order::new(_id, Side::Buy, 12.4f32, 50);
         _id += 1;
 
-        order_book.insert_order_at_level(&mut order3);
-        println!("trades = {:?}", order_book._trades);
-        let t1 = Trade::new(3, 1, 12.2f32, 50);
+        order_book.insert_order_at_level(&mut order3).unwrap();
+        println!("trades = {:?}", order_book.trades());
+        let t1 = filled(Trade::new(3, 1, 12.2f32, 50), 1, 1, Side::Buy, "AAPL");
 
         let expected_qty = 125 - 50;
-        let mut expected_trades = vec![t1];
-        assert_eq!(order_book._trades, expected_trades);
-        assert_eq!(order_book._trades.len(), 1);
+        let expected_trades = vec![t1];
+        assert_eq!(order_book.trades(), expected_trades);
+        assert_eq!(order_book.trades().len(), 1);
         assert_eq!(order_book.best_ask().is_some(), true);
         assert_eq!(order_book.best_ask().unwrap().qty, expected_qty);
         assert_eq!(order_book.best_bid().is_some(), false);
@@ -495,10 +1091,10 @@ mod test {
     fn can_cancel_orders()
     {
         let mut order_book = OrderBook::new("TSLA");
-        let mut order = Order{id:1, side:Side::Buy, price:122.2f32, qty:100};
-        order_book.insert_order_at_level(&mut order);
-        let mut order2 = Order{id:2, side:Side::Sell, price:122.5f32, qty:25};
-        order_book.insert_order_at_level(&mut order2);
+        let mut order = Order::new(1, Side::Buy, 122.2f32, 100);
+        order_book.insert_order_at_level(&mut order).unwrap();
+        let mut order2 = Order::new(2, Side::Sell, 122.5f32, 25);
+        order_book.insert_order_at_level(&mut order2).unwrap();
         assert_eq!(order_book._bid.len(), 1);
         assert_eq!(order_book._ask.len(), 1);
 
@@ -516,25 +1112,61 @@ mod test {
     fn error_when_cancelling_an_order_which_does_not_exist()
     {
         let mut order_book = OrderBook::new("TSLA");
-        let mut order = Order{id:1, side:Side::Buy, price:122.2f32, qty:100};
-        let order2 = Order{id:2, side:Side::Buy, price:122.55f32, qty:100};
+        let mut order = Order::new(1, Side::Buy, 122.2f32, 100);
+        let order2 = Order::new(2, Side::Buy, 122.55f32, 100);
 
-        order_book.insert_order_at_level(&mut order);
+        order_book.insert_order_at_level(&mut order).unwrap();
 
         let cancelled_order = order_book.cancel_order(&order2);
         assert_eq!(cancelled_order, Err("Limit is not present in the OrderBook"));
         assert_eq!(order_book._bid.is_empty(), false);
     }
 
+    #[test]
+    fn error_when_cancelling_an_order_at_another_limit()
+    {
+        let mut order_book = OrderBook::new("TSLA");
+        let mut order = Order::new(1, Side::Buy, 122.2f32, 100);
+        let mut other = Order::new(2, Side::Buy, 122.1f32, 100);
+        order_book.insert_order_at_level(&mut order).unwrap();
+        order_book.insert_order_at_level(&mut other).unwrap();
+
+        assert_eq!(order_book.cancel_order(&Order { price : 122.1f32, ..order }), Err("Order is not present at the limit"));
+        assert_eq!(order_book.cancel_order(&Order { side : Side::Sell, ..order }), Err("Limit is not present in the OrderBook"));
+        assert_eq!(order_book.amend_order(&Order { price : 122.1f32, ..order }, 122.1f32, 50), Err("Order is not present at the limit"));
+        assert_eq!(best_bid_orders(&order_book), vec![order]);
+        assert_eq!(order_book.cancel_order(&order), Ok(order));
+    }
+
+    #[test]
+    fn reused_ids_can_be_cancelled_and_amended()
+    {
+        let mut order_book = OrderBook::new("TSLA");
+        let mut bid = Order::new(1, Side::Buy, 122.2f32, 100);
+        let mut ask = Order::new(1, Side::Sell, 122.5f32, 25);
+        let mut reused = Order::new(1, Side::Buy, 122.1f32, 50);
+        order_book.insert_order_at_level(&mut bid).unwrap();
+        order_book.insert_order_at_level(&mut ask).unwrap();
+        order_book.insert_order_at_level(&mut reused).unwrap();
+
+        // The first bid is still indexed, behind the order reusing its id
+        let amended = order_book.amend_order(&bid, 122.2f32, 60).unwrap();
+        assert_eq!(amended.qty, 60);
+        assert_eq!(order_book.cancel_order(&ask), Ok(ask));
+        assert_eq!(order_book.cancel_order(&amended), Ok(amended));
+        assert_eq!(order_book.cancel_order(&reused), Ok(reused));
+        assert!(order_book.best_bid().is_none() && order_book.best_ask().is_none());
+    }
+
     #[test]
     fn can_compute_spread()
     {
         let mut order_book = OrderBook::new("TSLA");
-        let mut order = Order{id:1, side:Side::Buy, price:122.2f32, qty:100};
-        let mut order2 = Order{id:2, side:Side::Sell, price:122.55f32, qty:100};
+        let mut order = Order::new(1, Side::Buy, 122.2f32, 100);
+        let mut order2 = Order::new(2, Side::Sell, 122.55f32, 100);
 
-        order_book.insert_order_at_level(&mut order);
-        order_book.insert_order_at_level(&mut order2);
+        order_book.insert_order_at_level(&mut order).unwrap();
+        order_book.insert_order_at_level(&mut order2).unwrap();
 
         assert_eq!(order_book.get_spread(), 122.55f32 - 122.2f32);
     }
@@ -543,9 +1175,9 @@ mod test {
     fn can_compute_spread_when_bid_is_none()
     {
         let mut order_book = OrderBook::new("TSLA");
-        let mut order = Order{id:1, side:Side::Buy, price:122.2f32, qty:100};
+        let mut order = Order::new(1, Side::Buy, 122.2f32, 100);
 
-        order_book.insert_order_at_level(&mut order);
+        order_book.insert_order_at_level(&mut order).unwrap();
 
         assert_eq!(order_book.get_spread(), -122.2f32);
     }
@@ -554,9 +1186,9 @@ mod test {
     fn can_compute_spread_when_ask_is_none()
     {
         let mut order_book = OrderBook::new("TSLA");
-        let mut order = Order{id:1, side:Side::Sell, price:122.2f32, qty:100};
+        let mut order = Order::new(1, Side::Sell, 122.2f32, 100);
 
-        order_book.insert_order_at_level(&mut order);
+        order_book.insert_order_at_level(&mut order).unwrap();
 
         assert_eq!(order_book.get_spread(), 122.2f32);
     }
@@ -567,4 +1199,353 @@ mod test {
         let order_book = OrderBook::new("TSLA");
         order_book.summary();
     }
+
+    #[test]
+    fn cancel_emits_event()
+    {
+        let mut order_book = OrderBook::new("TSLA");
+        let mut order = Order::new(1, Side::Buy, 122.2f32, 100);
+        order_book.insert_order_at_level(&mut order).unwrap();
+
+        order_book.cancel_order(&order).unwrap();
+        assert_eq!(order_book.drain_events(), vec![Event::Added(order), Event::Cancelled(order)]);
+        assert!(order_book.drain_events().is_empty());
+    }
+
+    #[test]
+    fn can_mass_cancel_price_range()
+    {
+        let mut order_book = OrderBook::new("TSLA");
+        let mut bid1 = Order::new(1, Side::Buy, 122.2f32, 100);
+        let mut bid2 = Order::new(2, Side::Buy, 122.1f32, 50);
+        let mut bid3 = Order::new(3, Side::Buy, 121.9f32, 25);
+        let mut ask = Order::new(4, Side::Sell, 122.3f32, 10);
+        order_book.insert_order_at_level(&mut bid1).unwrap();
+        order_book.insert_order_at_level(&mut bid2).unwrap();
+        order_book.insert_order_at_level(&mut bid3).unwrap();
+        order_book.insert_order_at_level(&mut ask).unwrap();
+        order_book.drain_events();
+
+        let filter = MassCancelFilter{price_range: Some((122.0f32, 122.3f32)), ..Default::default()};
+        let cancelled = order_book.mass_cancel(&filter);
+        assert_eq!(cancelled, vec![bid1, bid2, ask]);
+        assert_eq!(order_book._bid.len(), 1);
+        assert_eq!(order_book.best_bid().unwrap().price, 121.9f32);
+        assert!(order_book._ask.is_empty());
+        assert_eq!(order_book.drain_events(), vec![
+            Event::Cancelled(bid1),
+            Event::Cancelled(bid2),
+            Event::Cancelled(ask),
+            Event::MassCancelled { cancelled_orders: 3, cancelled_qty: 160 },
+        ]);
+    }
+
+    #[test]
+    fn mass_cancel_keeps_orders_not_matching_filter()
+    {
+        let mut order_book = OrderBook::new("TSLA");
+        let mut bid1 = Order::new(1, Side::Buy, 122.2f32, 100);
+        bid1.account = 7;
+        let mut bid2 = Order::new(2, Side::Buy, 122.2f32, 50);
+        let mut ask = Order::new(3, Side::Sell, 122.5f32, 10);
+        ask.account = 7;
+        order_book.insert_order_at_level(&mut bid1).unwrap();
+        order_book.insert_order_at_level(&mut bid2).unwrap();
+        order_book.insert_order_at_level(&mut ask).unwrap();
+
+        let filter = MassCancelFilter{account: Some(7), side: Some(Side::Buy), ..Default::default()};
+        assert_eq!(order_book.mass_cancel(&filter), vec![bid1]);
+        assert_eq!(best_bid_orders(&order_book), vec![bid2]);
+        assert_eq!(order_book.best_bid().unwrap().qty, 50);
+        assert_eq!(order_book.best_ask().unwrap().qty, 10);
+
+        // Nothing left to cancel, only the summary ack is emitted
+        order_book.drain_events();
+        assert!(order_book.mass_cancel(&filter).is_empty());
+        assert_eq!(order_book.drain_events(), vec![Event::MassCancelled { cancelled_orders: 0, cancelled_qty: 0 }]);
+    }
+
+    #[test]
+    fn market_order_is_never_rested()
+    {
+        let mut order_book = test_book("TSLA");
+        let mut ask1 = Order::new(1, Side::Sell, 122.2f32, 10);
+        let mut ask2 = Order::new(2, Side::Sell, 125.0f32, 10);
+        order_book.insert_order_at_level(&mut ask1).unwrap();
+        order_book.insert_order_at_level(&mut ask2).unwrap();
+
+        let mut market = Order::new(3, Side::Buy, 0.0f32, 30);
+        market.order_type = OrderType::Market;
+        order_book.insert_order_at_level(&mut market).unwrap();
+
+        assert_eq!(order_book.trades(), vec![
+            filled(Trade::new(3, 1, 122.2f32, 10), 1, 1, Side::Buy, "TSLA"),
+            filled(Trade::new(3, 2, 125.0f32, 10), 2, 1, Side::Buy, "TSLA"),
+        ]);
+        assert_eq!(market.qty, 10);
+        assert!(order_book.best_bid().is_none());
+        assert!(order_book.best_ask().is_none());
+    }
+
+    #[test]
+    fn trades_are_charged_maker_and_taker_fees()
+    {
+        let mut order_book = OrderBook::new("TSLA");
+        order_book.fees_mut().set_default_schedule(FeeSchedule::flat(-0.001, 0.002, 0.0));
+        let mut maker = Order::new(1, Side::Sell, 100.0f32, 10);
+        maker.account = 8;
+        let mut taker = Order::new(2, Side::Buy, 100.0f32, 10);
+        taker.account = 7;
+        order_book.insert_order_at_level(&mut maker).unwrap();
+        order_book.insert_order_at_level(&mut taker).unwrap();
+
+        let trade = order_book.trades()[0];
+        assert_eq!((trade.buy_account, trade.sell_account), (7, 8));
+        assert!((trade.taker_fee - 2.0).abs() < 1e-9);
+        assert!((trade.maker_fee + 1.0).abs() < 1e-9);
+        assert_eq!(order_book.drain_events().last(), Some(&Event::Traded(trade)));
+        assert_eq!(order_book.fees().volume(7), 10);
+    }
+
+    #[test]
+    fn amend_quantity_down_keeps_priority()
+    {
+        let mut order_book = test_book("TSLA");
+        let mut order = Order::new(1, Side::Buy, 122.2f32, 100);
+        let mut order2 = Order::new(2, Side::Buy, 122.2f32, 50);
+        order_book.insert_order_at_level(&mut order).unwrap();
+        order_book.insert_order_at_level(&mut order2).unwrap();
+        order_book.drain_events();
+
+        let amended = order_book.amend_order(&order, 122.2f32, 60).unwrap();
+        assert_eq!(amended, entered(Order::new(1, Side::Buy, 122.2f32, 60), 1));
+        assert_eq!(best_bid_orders(&order_book), vec![amended, order2]);
+        assert_eq!(order_book.best_bid().unwrap().qty, 110);
+        assert_eq!(order_book.drain_events(), vec![Event::Replaced { old: order, new: amended }]);
+    }
+
+    #[test]
+    fn amend_price_loses_priority_and_can_match()
+    {
+        let mut order_book = test_book("TSLA");
+        let mut order = Order::new(1, Side::Buy, 122.2f32, 100);
+        let mut order2 = Order::new(2, Side::Buy, 122.1f32, 50);
+        let mut ask = Order::new(3, Side::Sell, 122.5f32, 30);
+        order_book.insert_order_at_level(&mut order).unwrap();
+        order_book.insert_order_at_level(&mut order2).unwrap();
+        order_book.insert_order_at_level(&mut ask).unwrap();
+        order_book.drain_events();
+
+        let amended = order_book.amend_order(&order2, 122.5f32, 50).unwrap();
+        assert_eq!(amended.qty, 20);
+        assert!(order_book.best_ask().is_none());
+        assert_eq!(best_bid_orders(&order_book), vec![amended]);
+        assert_eq!(order_book.drain_events(), vec![
+            Event::Replaced { old: order2, new: entered(Order::new(2, Side::Buy, 122.5f32, 50), 4) },
+            Event::Traded(filled(Trade::new(2, 3, 122.5f32, 30), 1, 1, Side::Buy, "TSLA")),
+            Event::Added(amended),
+        ]);
+
+        assert_eq!(order_book.amend_order(&order2, 122.1f32, 10), Err("Limit is not present in the OrderBook"));
+        assert_eq!(order_book.amend_order(&amended, 122.5f32, 0), Err("Cannot amend an order to zero quantity"));
+    }
+
+    fn order_with_tif(id : u32, side : Side, price : f32, qty : u32, time_in_force : TimeInForce) -> Order
+    {
+        Order { time_in_force, ..Order::new(id, side, price, qty) }
+    }
+
+    #[test]
+    fn gtd_orders_expire_at_their_expiry_time()
+    {
+        let clock = Arc::new(ManualClock::new(NOW));
+        let mut order_book = OrderBook::with_clock("TSLA", clock.clone(), Arc::new(Sequencer::new()));
+        let mut early = order_with_tif(1, Side::Buy, 122.2f32, 100, TimeInForce::GoodTillDate(NOW + 100));
+        let mut late = order_with_tif(2, Side::Buy, 122.2f32, 50, TimeInForce::GoodTillDate(NOW + 200));
+        let mut gtc = Order::new(3, Side::Sell, 122.5f32, 10);
+        let mut moved = order_with_tif(4, Side::Sell, 123.0f32, 10, TimeInForce::GoodTillDate(NOW + 100));
+        for order in [&mut early, &mut late, &mut gtc, &mut moved]
+        {
+            order_book.insert_order_at_level(order).unwrap();
+        }
+        // The amended order enters the book again and is only expired once
+        let moved = order_book.amend_order(&moved, 123.5f32, 10).unwrap();
+        order_book.drain_events();
+
+        clock.advance(99);
+        assert!(order_book.expire_orders().is_empty());
+
+        clock.advance(1);
+        assert_eq!(order_book.expire_orders(), vec![early, moved]);
+        assert_eq!(best_bid_orders(&order_book), vec![late]);
+        assert_eq!(best_ask_orders(&order_book), vec![gtc]);
+        assert_eq!(order_book.drain_events(), vec![Event::Expired(early), Event::Expired(moved)]);
+
+        // The order is partially filled before it expires, its level is removed once empty
+        order_book.insert_order_at_level(&mut Order::new(5, Side::Sell, 122.2f32, 20)).unwrap();
+        clock.advance(100);
+        let expired = order_book.expire_orders();
+        assert_eq!(expired.len(), 1);
+        assert_eq!((expired[0].id, expired[0].qty), (2, 30));
+        assert!(order_book.best_bid().is_none());
+        assert!(order_book.expire_orders().is_empty());
+    }
+
+    #[test]
+    fn expiries_of_removed_orders_are_purged()
+    {
+        let clock = Arc::new(ManualClock::new(NOW));
+        let mut order_book = OrderBook::with_clock("TSLA", clock.clone(), Arc::new(Sequencer::new()));
+        let mut kept = order_with_tif(1, Side::Buy, 100.0f32, 10, TimeInForce::GoodTillDate(NOW + 100));
+        order_book.insert_order_at_level(&mut kept).unwrap();
+        for id in 2..=(3 * EXPIRIES_PURGE_THRESHOLD as u32)
+        {
+            let mut order = order_with_tif(id, Side::Buy, 122.2f32, 10, TimeInForce::GoodTillDate(NOW + 100));
+            order_book.insert_order_at_level(&mut order).unwrap();
+            order_book.cancel_order(&order).unwrap();
+        }
+        assert!(order_book._expiries.len() <= EXPIRIES_PURGE_THRESHOLD);
+
+        // The resting order is still expired once its time is reached
+        clock.advance(100);
+        assert_eq!(order_book.expire_orders(), vec![kept]);
+        assert!(order_book._expiries.is_empty());
+    }
+
+    #[test]
+    fn day_orders_expire_at_session_close()
+    {
+        let mut order_book = test_book("TSLA");
+        let mut day_bid = order_with_tif(1, Side::Buy, 122.2f32, 100, TimeInForce::Day);
+        let mut gtc_bid = Order::new(2, Side::Buy, 122.2f32, 50);
+        let mut day_ask = order_with_tif(3, Side::Sell, 122.5f32, 10, TimeInForce::Day);
+        let mut gtd_ask = order_with_tif(4, Side::Sell, 123.0f32, 10, TimeInForce::GoodTillDate(NOW + 1));
+        for order in [&mut day_bid, &mut gtc_bid, &mut day_ask, &mut gtd_ask]
+        {
+            order_book.insert_order_at_level(order).unwrap();
+        }
+        order_book.drain_events();
+
+        assert_eq!(order_book.close_session(), vec![day_bid, day_ask]);
+        assert_eq!(order_book.drain_events(), vec![Event::Expired(day_bid), Event::Expired(day_ask)]);
+        assert_eq!(best_bid_orders(&order_book), vec![gtc_bid]);
+        assert_eq!(best_ask_orders(&order_book), vec![gtd_ask]);
+    }
+
+    #[test]
+    fn ladder_book_matches_and_rests_like_the_default_book()
+    {
+        let mut ladder_book = LadderBook::with_ladder("TSLA", (100.0, 150.0, 0.1), Arc::new(ManualClock::new(NOW)), Arc::new(Sequencer::new())).unwrap();
+        let mut order_book = test_book("TSLA");
+        for order in [Order::new(1, Side::Buy, 122.2f32, 100), Order::new(2, Side::Buy, 122.1f32, 50),
+                      Order::new(3, Side::Sell, 122.5f32, 30), Order::new(4, Side::Sell, 122.1f32, 120),
+                      Order::new(5, Side::Sell, 122.6f32, 10)]
+        {
+            let (mut ladder_order, mut tree_order) = (order, order);
+            ladder_book.insert_order_at_level(&mut ladder_order).unwrap();
+            order_book.insert_order_at_level(&mut tree_order).unwrap();
+        }
+        assert_eq!(ladder_book.cancel_order(&Order::new(3, Side::Sell, 122.5f32, 30)).map(|order| order.qty), Ok(30));
+        assert_eq!(order_book.cancel_order(&Order::new(3, Side::Sell, 122.5f32, 30)).map(|order| order.qty), Ok(30));
+
+        assert_eq!(ladder_book.trades(), order_book.trades());
+        assert_eq!(ladder_book.drain_events(), order_book.drain_events());
+        assert_eq!(ladder_book.depth(10), order_book.depth(10));
+        assert_eq!(best_bid_orders(&ladder_book), vec![entered(Order::new(2, Side::Buy, 122.1f32, 30), 2)]);
+        assert_eq!(ladder_book.best_ask().unwrap().price, 122.6f32);
+    }
+
+    #[test]
+    fn ladder_book_rejects_prices_outside_of_its_band()
+    {
+        let mut order_book = LadderBook::with_ladder("TSLA", (100.0, 150.0, 0.1), Arc::new(ManualClock::new(NOW)), Arc::new(Sequencer::new())).unwrap();
+        assert!(LadderBook::with_ladder("TSLA", (100.0, 150.0, -0.1), Arc::new(ManualClock::new(NOW)), Arc::new(Sequencer::new())).is_err());
+
+        let error = "Price is outside of the price band of the OrderBook";
+        assert_eq!(order_book.check_price(&Order::new(1, Side::Buy, 150.1f32, 10)), Err(error));
+        assert_eq!(order_book.check_price(&Order::new(1, Side::Sell, 122.25f32, 10)), Err(error));
+        assert_eq!(order_book.check_price(&Order::new(1, Side::Sell, 122.2f32, 10)), Ok(()));
+        let mut market = Order::new(2, Side::Buy, 0.0f32, 10);
+        market.order_type = OrderType::Market;
+        assert_eq!(order_book.check_price(&market), Ok(()));
+
+        let mut order = Order::new(3, Side::Buy, 122.2f32, 10);
+        order_book.insert_order_at_level(&mut order).unwrap();
+        assert_eq!(order_book.amend_order(&order, 99.9f32, 10), Err(error));
+        assert_eq!(best_bid_orders(&order_book), vec![order]);
+
+        // The unchecked insert rejects the order rather than panicking, it is left untouched
+        let mut outside = Order::new(4, Side::Buy, 150.1f32, 10);
+        let mut off_tick = Order::new(5, Side::Sell, 122.25f32, 10);
+        assert_eq!(order_book.insert_order_at_level(&mut outside), Err(error));
+        assert_eq!(order_book.insert_order_at_level(&mut off_tick), Err(error));
+        assert_eq!((outside, off_tick), (Order::new(4, Side::Buy, 150.1f32, 10), Order::new(5, Side::Sell, 122.25f32, 10)));
+        assert!(order_book.best_ask().is_none());
+    }
+
+    /// Highest number of levels per side of a trading cycle
+    const CYCLE_LEVELS : usize = 16;
+
+    /// trading_cycle rests orders on the given number of levels of each side, then
+    /// cancels, amends and matches all of them, leaving the book empty
+    fn trading_cycle<Bid : PriceLevels, Ask : PriceLevels>(order_book : &mut OrderBook<Bid, Ask>, first_id : u32, levels : usize, events : &mut Vec<Event>)
+    {
+        let levels = levels.clamp(2, CYCLE_LEVELS);
+        let mut bids : [Order; CYCLE_LEVELS] = std::array::from_fn(|i| Order::new(first_id + i as u32, Side::Buy, (1220 - i as u32) as f32 / 10.0, 100));
+        let mut asks : [Order; CYCLE_LEVELS] = std::array::from_fn(|i| Order::new(first_id + (CYCLE_LEVELS + i) as u32, Side::Sell, (1225 + i as u32) as f32 / 10.0, 100));
+        for order in bids[..levels].iter_mut().chain(asks[..levels].iter_mut())
+        {
+            order_book.insert_order_at_level(order).unwrap();
+        }
+
+        let next_id = first_id + 2 * CYCLE_LEVELS as u32;
+        order_book.cancel_order(&bids[levels - 1]).unwrap();
+        let sweep_bids = 100 * (levels as u32 - 1);
+        order_book.insert_order_at_level(&mut Order::new(next_id, Side::Sell, bids[levels - 2].price, sweep_bids)).unwrap();
+        order_book.amend_order(&asks[0], asks[0].price, 50).unwrap();
+        order_book.amend_order(&asks[1], asks[0].price, 100).unwrap();
+        let sweep_asks = 50 + 100 * (levels as u32 - 1);
+        order_book.insert_order_at_level(&mut Order::new(next_id + 1, Side::Buy, asks[levels - 1].price, sweep_asks)).unwrap();
+        assert!(order_book.best_bid().is_none() && order_book.best_ask().is_none());
+
+        order_book.drain_events_into(events);
+        events.clear();
+    }
+
+    /// assert_steady_state_is_allocation_free warms up the book then checks that the
+    /// following trading cycles do not allocate, while the trade history is trimmed
+    fn assert_steady_state_is_allocation_free<Bid : PriceLevels, Ask : PriceLevels>(mut order_book : OrderBook<Bid, Ask>, levels : usize)
+    {
+        let mut events = vec![];
+        let mut first_id = 0;
+        while order_book.trade_count() < 2 * TRADE_HISTORY_CAPACITY as u64
+        {
+            trading_cycle(&mut order_book, first_id, levels, &mut events);
+            first_id += 2 * CYCLE_LEVELS as u32 + 2;
+        }
+
+        let (before, trade_count) = (ALLOCATIONS.with(|allocations| allocations.get()), order_book.trade_count());
+        while order_book.trade_count() < trade_count + 2 * TRADE_HISTORY_CAPACITY as u64
+        {
+            trading_cycle(&mut order_book, first_id, levels, &mut events);
+            first_id += 2 * CYCLE_LEVELS as u32 + 2;
+        }
+        assert_eq!(ALLOCATIONS.with(|allocations| allocations.get()) - before, 0);
+        assert!(order_book.trades().len() <= TRADE_HISTORY_CAPACITY);
+        assert_eq!(order_book.trades().last().map(|trade| trade.aggressive_id), Some(first_id - 1));
+    }
+
+    #[test]
+    fn warmed_up_ladder_book_does_not_allocate()
+    {
+        assert_steady_state_is_allocation_free(LadderBook::with_ladder("TSLA", (100.0, 150.0, 0.1), Arc::new(ManualClock::new(NOW)), Arc::new(Sequencer::new())).unwrap(), CYCLE_LEVELS);
+    }
+
+    #[test]
+    fn warmed_up_book_within_a_leaf_does_not_allocate()
+    {
+        // The levels of the default book are stored in the nodes of a BTreeMap, a node
+        // is only allocated once a side holds more levels than fit in a leaf
+        assert_steady_state_is_allocation_free(test_book("TSLA"), 3);
+    }
 }
@@ -0,0 +1,235 @@
+use bytes::BufMut;
+use crate::data_types::{Order, Side};
+
+/// Length of a new order message: id, side, price and quantity, big endian
+pub const NEW_ORDER_LEN : usize = 13;
+
+const BUY : u8 = 0x1;
+const SELL : u8 = 0x2;
+
+/// NewOrderMessage is the layout of a new order on the order entry connections, it is
+/// read in place from the receive buffer. Its fields are byte arrays so that it has no
+/// alignment and every bit pattern is a valid value, the side is checked when decoded
+#[repr(C)]
+#[derive(Debug)]
+pub struct NewOrderMessage
+{
+    id : [u8; 4],
+    side : u8,
+    price : [u8; 4],
+    qty : [u8; 4],
+}
+
+const _ : () = assert!(std::mem::size_of::<NewOrderMessage>() == NEW_ORDER_LEN && std::mem::align_of::<NewOrderMessage>() == 1);
+
+impl NewOrderMessage
+{
+    /// view reads the message at the start of the buffer without copying it
+    ///
+    /// # Arguments
+    ///
+    /// * `buf` - The received bytes, it must hold at least a complete message
+    pub fn view(buf : &[u8]) -> Result<&NewOrderMessage, &'static str>
+    {
+        if buf.len() < NEW_ORDER_LEN
+        {
+            return Err("new order message is truncated");
+        }
+        // The struct has the size of the message and an alignment of 1
+        Ok(unsafe { &*(buf.as_ptr() as *const NewOrderMessage) })
+    }
+
+    /// encode writes the order in the layout of the message
+    pub fn encode(order : &Order, buf : &mut impl BufMut)
+    {
+        buf.put_u32(order.id);
+        buf.put_u8(match order.side { Side::Buy => BUY, Side::Sell => SELL });
+        buf.put_f32(order.price);
+        buf.put_u32(order.qty);
+    }
+
+    pub fn id(&self) -> u32
+    {
+        u32::from_be_bytes(self.id)
+    }
+
+    pub fn side(&self) -> Result<Side, &'static str>
+    {
+        match self.side
+        {
+            BUY => Ok(Side::Buy),
+            SELL => Ok(Side::Sell),
+            _ => Err("invalid side"),
+        }
+    }
+
+    pub fn price(&self) -> f32
+    {
+        f32::from_be_bytes(self.price)
+    }
+
+    pub fn qty(&self) -> u32
+    {
+        u32::from_be_bytes(self.qty)
+    }
+
+    /// to_order decodes the message into a new limit order, its price must be finite and positive
+    pub fn to_order(&self) -> Result<Order, &'static str>
+    {
+        let price = self.price();
+        if !(price.is_finite() && price > 0.0)
+        {
+            return Err("invalid price");
+        }
+        Ok(Order::new(self.id(), self.side()?, price, self.qty()))
+    }
+}
+
+/// FrameBuffer receives the order entry stream of a connection. The socket reads into its
+/// free space and the complete messages are viewed in place, only the bytes of a message
+/// split across two reads are moved, to the front of the buffer, before the next read
+/// * _buf holds the received bytes
+/// * _start is the position of the first message not yet viewed
+/// * _end is the position after the last received byte
+#[derive(Debug)]
+pub struct FrameBuffer
+{
+    _buf : Box<[u8]>,
+    _start : usize,
+    _end : usize,
+}
+
+impl FrameBuffer
+{
+    /// Creates an empty buffer, it holds at least one message
+    pub fn with_capacity(capacity : usize) -> Self
+    {
+        Self { _buf : vec![0; capacity.max(NEW_ORDER_LEN)].into_boxed_slice(), _start : 0, _end : 0 }
+    }
+
+    /// spare returns the free space for the next read, the partial message left by the
+    /// previous reads is moved to the front when a message does not fit after it. The
+    /// complete messages have to be viewed before, otherwise the space may be empty
+    pub fn spare(&mut self) -> &mut [u8]
+    {
+        if self._start > 0 && self._buf.len() - self._end < NEW_ORDER_LEN
+        {
+            self._buf.copy_within(self._start..self._end, 0);
+            self._end -= self._start;
+            self._start = 0;
+        }
+        &mut self._buf[self._end..]
+    }
+
+    /// filled records the number of bytes read into the spare space
+    pub fn filled(&mut self, len : usize)
+    {
+        self._end = (self._end + len).min(self._buf.len());
+    }
+
+    /// next_message returns the next complete message, None until it is fully received
+    pub fn next_message(&mut self) -> Option<&NewOrderMessage>
+    {
+        if self.pending() < NEW_ORDER_LEN
+        {
+            return None;
+        }
+        let start = self._start;
+        self._start += NEW_ORDER_LEN;
+        if self._start == self._end
+        {
+            // Nothing is left to be moved before the next read
+            (self._start, self._end) = (0, 0);
+        }
+        NewOrderMessage::view(&self._buf[start..start + NEW_ORDER_LEN]).ok()
+    }
+
+    /// pending returns the number of received bytes which are not part of a viewed message
+    pub fn pending(&self) -> usize
+    {
+        self._end - self._start
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use bytes::BytesMut;
+    use super::*;
+
+    fn encoded(orders : &[Order]) -> Vec<u8>
+    {
+        let mut buf = BytesMut::new();
+        for order in orders
+        {
+            NewOrderMessage::encode(order, &mut buf);
+        }
+        buf.to_vec()
+    }
+
+    /// receive copies the bytes into the buffer in reads of at most the given size
+    fn receive(buffer : &mut FrameBuffer, bytes : &[u8], read_size : usize) -> Vec<Order>
+    {
+        let mut orders = vec![];
+        let mut bytes = bytes;
+        while !bytes.is_empty()
+        {
+            let spare = buffer.spare();
+            let len = spare.len().min(read_size).min(bytes.len());
+            spare[..len].copy_from_slice(&bytes[..len]);
+            buffer.filled(len);
+            bytes = &bytes[len..];
+            while let Some(message) = buffer.next_message()
+            {
+                orders.push(message.to_order().unwrap());
+            }
+        }
+        orders
+    }
+
+    #[test]
+    fn messages_are_read_in_place()
+    {
+        let order = Order::new(7, Side::Sell, 122.25f32, 300);
+        let bytes = encoded(&[order]);
+        assert_eq!(bytes.len(), NEW_ORDER_LEN);
+
+        let message = NewOrderMessage::view(&bytes).unwrap();
+        assert_eq!((message.id(), message.side(), message.price(), message.qty()), (7, Ok(Side::Sell), 122.25, 300));
+        assert_eq!(message.to_order(), Ok(order));
+        assert_eq!(message as *const NewOrderMessage as *const u8, bytes.as_ptr());
+    }
+
+    #[test]
+    fn malformed_messages_are_rejected()
+    {
+        let mut bytes = encoded(&[Order::new(7, Side::Buy, 122.25f32, 300)]);
+        assert!(NewOrderMessage::view(&bytes[..NEW_ORDER_LEN - 1]).is_err());
+        bytes[4] = 0x3;
+        assert_eq!(NewOrderMessage::view(&bytes).unwrap().to_order(), Err("invalid side"));
+
+        for price in [f32::NAN, f32::INFINITY, 0.0f32, -122.25f32]
+        {
+            let bytes = encoded(&[Order::new(7, Side::Buy, price, 300)]);
+            assert_eq!(NewOrderMessage::view(&bytes).unwrap().to_order(), Err("invalid price"));
+        }
+    }
+
+    #[test]
+    fn messages_split_across_reads_are_reassembled()
+    {
+        let orders : Vec<Order> = (0..50).map(|id| Order::new(id, Side::Buy, 100.0 + id as f32, id * 10)).collect();
+        let bytes = encoded(&orders);
+        for read_size in [1, 5, NEW_ORDER_LEN, 20, 1024]
+        {
+            // The buffer is smaller than the stream and not a multiple of the message length
+            let mut buffer = FrameBuffer::with_capacity(32);
+            assert_eq!(receive(&mut buffer, &bytes, read_size), orders, "reads of {} bytes", read_size);
+            assert_eq!(buffer.pending(), 0);
+        }
+
+        let mut buffer = FrameBuffer::with_capacity(32);
+        receive(&mut buffer, &bytes[..NEW_ORDER_LEN + 4], 1024);
+        assert_eq!(buffer.pending(), 4);
+    }
+}
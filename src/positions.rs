@@ -0,0 +1,285 @@
+use std::collections::{BTreeMap, HashMap};
+use crate::data_types::*;
+use crate::events::Event;
+
+/// MarkMethod selects the price the open positions are valued at
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum MarkMethod
+{
+    /// Price of the last trade of the symbol
+    #[default]
+    LastTrade,
+    /// Middle of the best bid and ask, the last trade is used when a side is empty
+    Mid,
+}
+
+/// Position is the holding of an account on a symbol
+/// * qty is the signed net quantity, positive when long
+/// * avg_price is the average cost of the open quantity
+/// * realized_pnl is the profit locked in by the trades reducing the position
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Position
+{
+    pub qty : i64,
+    pub avg_price : f64,
+    pub realized_pnl : f64,
+}
+
+impl Position
+{
+    /// apply updates the position with a fill
+    ///
+    /// # Arguments
+    ///
+    /// * `qty` - The signed filled quantity, positive when buying
+    /// * `price` - The fill price
+    pub fn apply(&mut self, qty : i64, price : f64)
+    {
+        if self.qty == 0 || self.qty.signum() == qty.signum()
+        {
+            let open_qty = self.qty.abs() as f64;
+            self.avg_price = (self.avg_price * open_qty + price * qty.abs() as f64) / (open_qty + qty.abs() as f64);
+            self.qty += qty;
+            return;
+        }
+
+        // The fill reduces the position, and may flip it to the other side
+        let closed_qty = qty.abs().min(self.qty.abs());
+        self.realized_pnl += (price - self.avg_price) * (closed_qty * self.qty.signum()) as f64;
+        self.qty += qty;
+        if self.qty == 0
+        {
+            self.avg_price = 0.0;
+        }
+        else if self.qty.signum() == qty.signum()
+        {
+            self.avg_price = price;
+        }
+    }
+
+    /// unrealized_pnl values the open quantity at the mark price
+    pub fn unrealized_pnl(&self, mark_price : f64) -> f64
+    {
+        (mark_price - self.avg_price) * self.qty as f64
+    }
+}
+
+/// Marks holds the prices a symbol can be marked to
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+struct Marks
+{
+    last_trade : Option<f32>,
+    best_bid : Option<f32>,
+    best_ask : Option<f32>,
+}
+
+/// PositionKeeper maintains the positions of every account from the trade events
+/// * _positions is the map of the positions of each account, by symbol
+/// * _marks is the map of the mark prices of each symbol
+#[derive(Debug, Default)]
+pub struct PositionKeeper
+{
+    _mark_method : MarkMethod,
+    _positions : HashMap<u32, BTreeMap<String, Position>>,
+    _marks : HashMap<String, Marks>,
+}
+
+impl PositionKeeper
+{
+    pub fn new(mark_method : MarkMethod) -> PositionKeeper
+    {
+        PositionKeeper { _mark_method : mark_method, ..Default::default() }
+    }
+
+    /// on_trade books the trade in the positions of the buying and selling accounts
+    pub fn on_trade(&mut self, symbol : &str, trade : &Trade)
+    {
+        let price = trade.price as f64;
+        for (account, qty) in [(trade.buy_account, trade.qty as i64), (trade.sell_account, -(trade.qty as i64))]
+        {
+            let positions = self._positions.entry(account).or_default();
+            match positions.get_mut(symbol)
+            {
+                Some(position) => position.apply(qty, price),
+                None =>
+                {
+                    let mut position = Position::default();
+                    position.apply(qty, price);
+                    positions.insert(symbol.to_string(), position);
+                },
+            }
+        }
+        self.marks_mut(symbol).last_trade = Some(trade.price);
+    }
+
+    /// on_event consumes an event of the symbol book, only the trades move the positions
+    pub fn on_event(&mut self, symbol : &str, event : &Event)
+    {
+        if let Event::Traded(trade) = event
+        {
+            self.on_trade(symbol, trade);
+        }
+    }
+
+    /// update_quotes records the best prices of the symbol, used to mark to the mid
+    pub fn update_quotes(&mut self, symbol : &str, best_bid : Option<f32>, best_ask : Option<f32>)
+    {
+        let marks = self.marks_mut(symbol);
+        marks.best_bid = best_bid;
+        marks.best_ask = best_ask;
+    }
+
+    fn marks_mut(&mut self, symbol : &str) -> &mut Marks
+    {
+        if !self._marks.contains_key(symbol)
+        {
+            self._marks.insert(symbol.to_string(), Marks::default());
+        }
+        self._marks.get_mut(symbol).unwrap()
+    }
+
+    /// mark_price returns the price the positions on the symbol are valued at, if any
+    pub fn mark_price(&self, symbol : &str) -> Option<f32>
+    {
+        let marks = self._marks.get(symbol)?;
+        match (self._mark_method, marks.best_bid, marks.best_ask)
+        {
+            (MarkMethod::Mid, Some(bid), Some(ask)) => Some((bid + ask) / 2.0),
+            _ => marks.last_trade,
+        }
+    }
+
+    /// position returns the position of the account on the symbol, flat if it never traded it
+    pub fn position(&self, account : u32, symbol : &str) -> Position
+    {
+        self._positions.get(&account).and_then(|positions| positions.get(symbol)).copied().unwrap_or_default()
+    }
+
+    /// positions returns the positions of the account by symbol
+    pub fn positions(&self, account : u32) -> impl Iterator<Item = (&str, &Position)>
+    {
+        self._positions.get(&account).into_iter().flat_map(|positions| positions.iter().map(|(symbol, position)| (symbol.as_str(), position)))
+    }
+
+    /// accounts returns the accounts which traded
+    pub fn accounts(&self) -> impl Iterator<Item = u32> + '_
+    {
+        self._positions.keys().copied()
+    }
+
+    /// unrealized_pnl values the open position of the account on the symbol at the mark price
+    pub fn unrealized_pnl(&self, account : u32, symbol : &str) -> f64
+    {
+        match self.mark_price(symbol)
+        {
+            Some(mark_price) => self.position(account, symbol).unrealized_pnl(mark_price as f64),
+            None => 0.0,
+        }
+    }
+
+    /// total_pnl returns the realized and unrealized P&L of the account over all the symbols
+    pub fn total_pnl(&self, account : u32) -> (f64, f64)
+    {
+        self.positions(account).fold((0.0, 0.0), |(realized, unrealized), (symbol, position)|
+        {
+            let mark_price = self.mark_price(symbol).map(|price| price as f64).unwrap_or(position.avg_price);
+            (realized + position.realized_pnl, unrealized + position.unrealized_pnl(mark_price))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::engine::Engine;
+    use super::*;
+
+    fn order_for_account(id : u32, side : Side, price : f32, qty : u32, account : u32) -> Order
+    {
+        let mut order = Order::new(id, side, price, qty);
+        order.account = account;
+        order
+    }
+
+    /// Compares the position with the expected values, the prices within a tolerance
+    fn assert_position(position : Position, qty : i64, avg_price : f64, realized_pnl : f64)
+    {
+        assert_eq!(position.qty, qty);
+        assert!((position.avg_price - avg_price).abs() < 1e-9, "avg_price {} != {}", position.avg_price, avg_price);
+        assert!((position.realized_pnl - realized_pnl).abs() < 1e-9, "realized_pnl {} != {}", position.realized_pnl, realized_pnl);
+    }
+
+    fn trade(price : f32, qty : u32, buy_account : u32, sell_account : u32) -> Trade
+    {
+        Trade { buy_account, sell_account, ..Trade::new(1, 2, price, qty) }
+    }
+
+    #[test]
+    fn average_cost_and_realized_pnl()
+    {
+        let mut position = Position::default();
+        position.apply(100, 10.0);
+        position.apply(100, 12.0);
+        assert_position(position, 200, 11.0, 0.0);
+
+        position.apply(-50, 13.0);
+        assert_position(position, 150, 11.0, 100.0);
+        assert!((position.unrealized_pnl(12.0) - 150.0).abs() < 1e-9);
+
+        // Selling more than the position flips it short at the fill price
+        position.apply(-200, 9.0);
+        assert_position(position, -50, 9.0, -200.0);
+
+        position.apply(50, 8.0);
+        assert_position(position, 0, 0.0, -150.0);
+    }
+
+    #[test]
+    fn keeps_positions_from_engine_trades()
+    {
+        let mut engine = Engine::new();
+        engine.add_symbol("AAPL");
+        engine.add_symbol("TSLA");
+        engine.insert_order("TSLA", &mut order_for_account(1, Side::Sell, 100.0f32, 30, 8)).unwrap();
+        engine.insert_order("TSLA", &mut order_for_account(2, Side::Sell, 102.0f32, 30, 9)).unwrap();
+        engine.insert_order("TSLA", &mut order_for_account(3, Side::Buy, 102.0f32, 50, 7)).unwrap();
+        engine.insert_order("AAPL", &mut order_for_account(4, Side::Buy, 10.0f32, 10, 8)).unwrap();
+        engine.insert_order("AAPL", &mut order_for_account(5, Side::Sell, 10.0f32, 10, 7)).unwrap();
+
+        let mut keeper = PositionKeeper::new(MarkMethod::LastTrade);
+        for (symbol, event) in engine.drain_events()
+        {
+            keeper.on_event(&symbol, &event);
+        }
+
+        assert_position(keeper.position(7, "TSLA"), 50, 100.8, 0.0);
+        assert_eq!(keeper.position(8, "TSLA").qty, -30);
+        assert_eq!(keeper.position(9, "TSLA").qty, -20);
+        assert_eq!(keeper.position(7, "AAPL").qty, -10);
+        assert_eq!(keeper.position(9, "AAPL"), Position::default());
+        assert_eq!(keeper.positions(7).map(|(symbol, _)| symbol).collect::<Vec<_>>(), vec!["AAPL", "TSLA"]);
+
+        // Marked to the last trade at 102
+        assert_eq!(keeper.mark_price("TSLA"), Some(102.0f32));
+        assert!((keeper.unrealized_pnl(7, "TSLA") - 60.0).abs() < 1e-6);
+        assert!((keeper.unrealized_pnl(8, "TSLA") + 60.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn marks_to_mid_when_both_sides_are_quoted()
+    {
+        let mut keeper = PositionKeeper::new(MarkMethod::Mid);
+        keeper.on_trade("TSLA", &trade(100.0f32, 10, 7, 8));
+        assert_eq!(keeper.mark_price("TSLA"), Some(100.0f32));
+
+        keeper.update_quotes("TSLA", Some(104.0f32), Some(106.0f32));
+        assert_eq!(keeper.mark_price("TSLA"), Some(105.0f32));
+        assert!((keeper.unrealized_pnl(7, "TSLA") - 50.0).abs() < 1e-9);
+        let (realized, unrealized) = keeper.total_pnl(8);
+        assert!(realized.abs() < 1e-9);
+        assert!((unrealized + 50.0).abs() < 1e-9);
+
+        keeper.update_quotes("TSLA", None, Some(106.0f32));
+        assert_eq!(keeper.mark_price("TSLA"), Some(100.0f32));
+    }
+}
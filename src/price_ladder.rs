@@ -0,0 +1,451 @@
+use crate::data_types::*;
+use crate::slab::OrderSlab;
+use crate::price_levels::PriceLevels;
+
+/// Highest number of levels of a ladder, about 24MB of levels per side. The wider bands
+/// are traded with the sorted map levels of the default book
+pub const MAX_LEVELS : usize = 1 << 20;
+
+/// PriceLadder is a side of a book for an instrument with a known tick size and price band.
+/// The levels are stored in a contiguous array indexed by their tick offset from the lowest
+/// price of the band, so that finding the level of a price is an index computation
+/// * _levels holds one preallocated level per tick of the band, the empty ones are unused.
+///   The levels only link the orders kept in the slab of the book, they are never allocated
+/// * _occupied is a bitmap of the levels holding orders, it is scanned a word at a time
+///   to find the next level when the best one is emptied
+/// * _best is the index of the best level: the highest bid or the lowest ask
+/// * _len is the number of levels holding orders
+#[derive(Debug)]
+pub struct PriceLadder
+{
+    _side : Side,
+    _min_price : f64,
+    _tick_size : f64,
+    _levels : Vec<Limit>,
+    _occupied : Vec<u64>,
+    _best : Option<usize>,
+    _len : usize,
+}
+
+impl PriceLadder
+{
+    /// Creates an empty ladder covering the price band, bounds included
+    ///
+    /// # Arguments
+    ///
+    /// * `side` - The side of the book, the bids are matched from the highest price
+    /// * `min_price` - The lowest price of the band
+    /// * `max_price` - The highest price of the band
+    /// * `tick_size` - The price increment between two levels
+    pub fn new(side : Side, min_price : f32, max_price : f32, tick_size : f32) -> Result<Self, &'static str>
+    {
+        if !(min_price.is_finite() && max_price.is_finite() && tick_size.is_finite()) || tick_size <= 0.0 || max_price < min_price
+        {
+            return Err("Invalid price band for the ladder");
+        }
+
+        let num_ticks = ((max_price as f64 - min_price as f64) / tick_size as f64).round();
+        if num_ticks >= MAX_LEVELS as f64
+        {
+            return Err("Too many price levels for the ladder");
+        }
+        let num_levels = num_ticks as usize + 1;
+        let levels = (0..num_levels).map(|tick| Limit::new((min_price as f64 + tick as f64 * tick_size as f64) as f32)).collect();
+        Ok(Self {
+            _side : side,
+            _min_price : min_price as f64,
+            _tick_size : tick_size as f64,
+            _levels : levels,
+            _occupied : vec![0; num_levels.div_ceil(64)],
+            _best : None,
+            _len : 0,
+        })
+    }
+
+    /// tick_index returns the index of the level of the price, None if the price is
+    /// outside of the band or not a multiple of the tick size. The level prices are
+    /// rounded to f32 like the order prices, so the price must match the one of its
+    /// level within the rounding of an f32 rather than within a fixed tolerance
+    fn tick_index(&self, price : f32) -> Option<usize>
+    {
+        if !price.is_finite()
+        {
+            return None;
+        }
+        let index = ((price as f64 - self._min_price) / self._tick_size).round();
+        if index < 0.0 || index >= self._levels.len() as f64
+        {
+            return None;
+        }
+        let level_price = self._levels[index as usize].price;
+        if (level_price - price).abs() > f32::EPSILON * level_price.abs()
+        {
+            return None;
+        }
+        Some(index as usize)
+    }
+
+    /// nearest_index returns the index of the level closest to the price, clamped to the band
+    fn nearest_index(&self, price : f32) -> usize
+    {
+        ((price as f64 - self._min_price) / self._tick_size).round().clamp(0.0, (self._levels.len() - 1) as f64) as usize
+    }
+
+    /// first_index_from returns the index of the lowest level priced at or above the price
+    fn first_index_from(&self, price : f32) -> Option<usize>
+    {
+        let index = self.nearest_index(price);
+        if self._levels[index].price >= price || self.tick_index(price) == Some(index)
+        {
+            return Some(index);
+        }
+        Some(index + 1).filter(|index| *index < self._levels.len())
+    }
+
+    /// last_index_upto returns the index of the highest level priced at or below the price
+    fn last_index_upto(&self, price : f32) -> Option<usize>
+    {
+        let index = self.nearest_index(price);
+        if self._levels[index].price <= price || self.tick_index(price) == Some(index)
+        {
+            return Some(index);
+        }
+        index.checked_sub(1)
+    }
+
+    fn is_occupied(&self, index : usize) -> bool
+    {
+        self._occupied[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    /// is_better tells whether the level at index has priority over the one at other
+    fn is_better(&self, index : usize, other : usize) -> bool
+    {
+        match self._side
+        {
+            Side::Buy => index > other,
+            Side::Sell => index < other,
+        }
+    }
+
+    /// lowest_from returns the lowest occupied index greater or equal than from
+    fn lowest_from(&self, from : usize) -> Option<usize>
+    {
+        let mut word = from / 64;
+        if word >= self._occupied.len()
+        {
+            return None;
+        }
+
+        let mut bits = self._occupied[word] & (!0u64 << (from % 64));
+        loop
+        {
+            if bits != 0
+            {
+                return Some(word * 64 + bits.trailing_zeros() as usize);
+            }
+            word += 1;
+            if word == self._occupied.len()
+            {
+                return None;
+            }
+            bits = self._occupied[word];
+        }
+    }
+
+    /// highest_upto returns the highest occupied index lower or equal than upto
+    fn highest_upto(&self, upto : usize) -> Option<usize>
+    {
+        let upto = upto.min(self._levels.len() - 1);
+        let mut word = upto / 64;
+        let mut bits = self._occupied[word] & (!0u64 >> (63 - upto % 64));
+        loop
+        {
+            if bits != 0
+            {
+                return Some(word * 64 + 63 - bits.leading_zeros() as usize);
+            }
+            if word == 0
+            {
+                return None;
+            }
+            word -= 1;
+            bits = self._occupied[word];
+        }
+    }
+
+    /// next_level returns the occupied level following the one at index in priority order
+    fn next_level(&self, index : usize) -> Option<usize>
+    {
+        match self._side
+        {
+            Side::Buy => index.checked_sub(1).and_then(|upto| self.highest_upto(upto)),
+            Side::Sell => self.lowest_from(index + 1),
+        }
+    }
+
+    /// clear_level marks the level at index as unused, its orders have already been removed
+    fn clear_level(&mut self, index : usize)
+    {
+        if !self.is_occupied(index)
+        {
+            return;
+        }
+
+        self._occupied[index / 64] &= !(1 << (index % 64));
+        self._len -= 1;
+        if self._best == Some(index)
+        {
+            self._best = self.next_level(index);
+        }
+    }
+}
+
+impl PriceLevels for PriceLadder
+{
+    fn accepts(&self, price : f32) -> bool
+    {
+        self.tick_index(price).is_some()
+    }
+
+    fn level_mut(&mut self, price : f32) -> Option<&mut Limit>
+    {
+        let index = self.tick_index(price)?;
+        if !self.is_occupied(index)
+        {
+            return None;
+        }
+        Some(&mut self._levels[index])
+    }
+
+    fn level_or_insert(&mut self, price : f32) -> &mut Limit
+    {
+        let index = self.tick_index(price).expect("price outside of the ladder band");
+        if !self.is_occupied(index)
+        {
+            self._occupied[index / 64] |= 1 << (index % 64);
+            self._len += 1;
+            // The level takes the price of its first order, as the levels of the other books
+            self._levels[index].price = price;
+            if self._best.is_none_or(|best| self.is_better(index, best))
+            {
+                self._best = Some(index);
+            }
+        }
+        &mut self._levels[index]
+    }
+
+    fn remove_level(&mut self, price : f32)
+    {
+        if let Some(index) = self.tick_index(price)
+        {
+            self.clear_level(index);
+        }
+    }
+
+    fn best(&self) -> Option<&Limit>
+    {
+        self._best.map(|index| &self._levels[index])
+    }
+
+    fn best_mut(&mut self) -> Option<&mut Limit>
+    {
+        self._best.map(|index| &mut self._levels[index])
+    }
+
+    fn remove_best(&mut self)
+    {
+        if let Some(index) = self._best
+        {
+            self.clear_level(index);
+        }
+    }
+
+    fn levels(&self) -> impl Iterator<Item = &Limit>
+    {
+        std::iter::successors(self._best, |&index| self.next_level(index)).map(|index| &self._levels[index])
+    }
+
+    fn remove_orders(&mut self, orders : &mut OrderSlab, range : Option<(f32, f32)>, predicate : impl Fn(&Order) -> bool, removed : &mut Vec<Order>)
+    {
+        let (low, high) = match range
+        {
+            Some((low, high)) =>
+            {
+                if low.is_nan() || high.is_nan()
+                {
+                    return;
+                }
+                match (self.first_index_from(low), self.last_index_upto(high))
+                {
+                    (Some(low), Some(high)) if low <= high => (low, high),
+                    _ => return,
+                }
+            },
+            None => (0, self._levels.len() - 1),
+        };
+
+        let mut index = match self._side
+        {
+            Side::Buy => self.highest_upto(high),
+            Side::Sell => self.lowest_from(low),
+        };
+        while let Some(current) = index.filter(|current| (low..=high).contains(current))
+        {
+            index = self.next_level(current);
+            let level = &mut self._levels[current];
+            level.remove_orders_if(orders, &predicate, removed);
+            if level.num_orders() == 0
+            {
+                self.clear_level(current);
+            }
+        }
+    }
+
+    fn len(&self) -> usize
+    {
+        self._len
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::data_types::*;
+    use crate::slab::OrderSlab;
+    use crate::price_levels::PriceLevels;
+
+/// Highest number of levels of a ladder, about 24MB of levels per side. The wider bands
+/// are traded with the sorted map levels of the default book
+pub const MAX_LEVELS : usize = 1 << 20;
+    use super::PriceLadder;
+
+    /// empty_level removes the orders of the level of the price, then the level
+    fn empty_level(side : &mut PriceLadder, orders : &mut OrderSlab, price : f32)
+    {
+        side.level_mut(price).unwrap().remove_orders_if(orders, |_| true, &mut vec![]);
+        side.remove_level(price);
+    }
+
+    /// empty_best removes the orders of the best level, then the level
+    fn empty_best(side : &mut PriceLadder, orders : &mut OrderSlab)
+    {
+        side.best_mut().unwrap().remove_orders_if(orders, |_| true, &mut vec![]);
+        side.remove_best();
+    }
+
+    #[test]
+    fn rejects_invalid_bands()
+    {
+        assert!(PriceLadder::new(Side::Buy, 10.0, 9.0, 0.01).is_err());
+        assert!(PriceLadder::new(Side::Buy, 10.0, 11.0, 0.0).is_err());
+        assert_eq!(PriceLadder::new(Side::Buy, 1.0, 1e6, 1e-6).err(), Some("Too many price levels for the ladder"));
+        assert!(PriceLadder::new(Side::Buy, 0.0, MAX_LEVELS as f32, 1.0).is_err());
+        assert_eq!(PriceLadder::new(Side::Buy, 1.0, MAX_LEVELS as f32, 1.0).map(|ladder| ladder._levels.len()), Ok(MAX_LEVELS));
+    }
+
+    #[test]
+    fn accepts_prices_on_the_ticks_of_the_band()
+    {
+        let ladder = PriceLadder::new(Side::Buy, 10.0, 11.0, 0.05).unwrap();
+        assert!(ladder.accepts(10.0));
+        assert!(ladder.accepts(10.35));
+        assert!(ladder.accepts(11.0));
+        assert!(!ladder.accepts(10.33));
+        assert!(!ladder.accepts(9.95));
+        assert!(!ladder.accepts(11.05));
+        assert!(!ladder.accepts(f32::NAN));
+        assert!(!ladder.accepts(f32::INFINITY));
+    }
+
+    #[test]
+    fn bids_track_the_highest_level()
+    {
+        let mut orders = OrderSlab::with_capacity(4);
+        let mut bids = PriceLadder::new(Side::Buy, 10.0, 20.0, 0.01).unwrap();
+        for (id, price) in [(1, 12.1f32), (2, 17.3), (3, 10.0), (4, 12.2)]
+        {
+            bids.level_or_insert(price).add_order(&mut orders, Order::new(id, Side::Buy, price, 10));
+        }
+
+        let prices : Vec<f32> = bids.levels().map(|limit| limit.price).collect();
+        assert_eq!(prices, vec![17.3, 12.2, 12.1, 10.0]);
+        assert_eq!(bids.len(), 4);
+
+        // The next best level is found across several empty words of the bitmap
+        empty_best(&mut bids, &mut orders);
+        assert_eq!(bids.best().unwrap().price, 12.2);
+        empty_level(&mut bids, &mut orders, 12.1);
+        empty_best(&mut bids, &mut orders);
+        assert_eq!(bids.best().unwrap().price, 10.0);
+        empty_best(&mut bids, &mut orders);
+        assert!(bids.best().is_none());
+        assert!(bids.is_empty());
+        assert!(orders.is_empty());
+    }
+
+    #[test]
+    fn asks_track_the_lowest_level()
+    {
+        let mut orders = OrderSlab::with_capacity(4);
+        let mut asks = PriceLadder::new(Side::Sell, 10.0, 20.0, 0.01).unwrap();
+        for (id, price) in [(1, 12.1f32), (2, 17.3), (3, 20.0), (4, 12.2)]
+        {
+            asks.level_or_insert(price).add_order(&mut orders, Order::new(id, Side::Sell, price, 10));
+        }
+
+        let prices : Vec<f32> = asks.levels().map(|limit| limit.price).collect();
+        assert_eq!(prices, vec![12.1, 12.2, 17.3, 20.0]);
+
+        empty_level(&mut asks, &mut orders, 12.1);
+        assert_eq!(asks.best().unwrap().price, 12.2);
+        assert!(asks.level_mut(12.1).is_none());
+        assert_eq!(asks.level_mut(17.3).unwrap().qty, 10);
+    }
+
+    #[test]
+    fn remove_orders_visits_the_range_by_priority()
+    {
+        let mut orders = OrderSlab::with_capacity(4);
+        let mut bids = PriceLadder::new(Side::Buy, 10.0, 20.0, 0.01).unwrap();
+        for (id, price) in [(1, 12.1f32), (2, 12.2), (3, 12.3), (4, 12.2)]
+        {
+            bids.level_or_insert(price).add_order(&mut orders, Order::new(id, Side::Buy, price, 10));
+        }
+
+        let mut removed = vec![];
+        bids.remove_orders(&mut orders, Some((12.15, 12.3)), |order| order.id != 4, &mut removed);
+        assert_eq!(removed.iter().map(|order| order.id).collect::<Vec<u32>>(), vec![3, 2]);
+        let prices : Vec<f32> = bids.levels().map(|limit| limit.price).collect();
+        assert_eq!(prices, vec![12.2, 12.1]);
+
+        bids.remove_orders(&mut orders, None, |_| true, &mut removed);
+        assert!(bids.is_empty());
+        assert!(bids.best().is_none());
+    }
+
+    #[test]
+    fn accepts_the_ticks_of_a_band_far_from_zero()
+    {
+        let mut orders = OrderSlab::with_capacity(4);
+        let mut asks = PriceLadder::new(Side::Sell, 1000.0, 1100.0, 0.01).unwrap();
+        for price in [1000.0f32, 1000.01, 1000.02, 1000.03, 1021.2, 1050.55, 1099.99, 1100.0]
+        {
+            assert!(asks.accepts(price), "{} is on a tick", price);
+        }
+        for price in [999.99f32, 1000.015, 1050.555, 1100.01]
+        {
+            assert!(!asks.accepts(price), "{} is off the ticks", price);
+        }
+
+        for (id, price) in [(1, 1000.01f32), (2, 1000.02), (3, 1000.03), (4, 1000.04)]
+        {
+            asks.level_or_insert(price).add_order(&mut orders, Order::new(id, Side::Sell, price, 10));
+        }
+        let mut removed = vec![];
+        asks.remove_orders(&mut orders, Some((1000.02, 1000.03)), |_| true, &mut removed);
+        assert_eq!(removed.iter().map(|order| order.id).collect::<Vec<u32>>(), vec![2, 3]);
+        asks.remove_orders(&mut orders, Some((f32::NEG_INFINITY, 1000.015)), |_| true, &mut removed);
+        assert_eq!(asks.levels().map(|limit| limit.price).collect::<Vec<f32>>(), vec![1000.04]);
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::ops::Bound;
+use crate::data_types::*;
+use crate::slab::OrderSlab;
+use crate::order_book::Creator;
+
+/// PriceLevels is one side of an order book, the price levels are kept sorted by
+/// priority so that the best level, the first one to be matched, is found first.
+/// The order book is generic over its sides so that the backing structure can be
+/// chosen per instrument, see BTreeMap and PriceLadder
+pub trait PriceLevels : Debug + Send
+{
+    /// accepts tells whether an order can rest at the price on this side
+    fn accepts(&self, price : f32) -> bool;
+
+    /// level_mut returns the level of the price, None if there are no orders at that price
+    fn level_mut(&mut self, price : f32) -> Option<&mut Limit>;
+
+    /// level_or_insert returns the level of the price, creating it when empty
+    ///
+    /// # Arguments
+    ///
+    /// * `price` - The price of the level, it must be accepted by the side
+    fn level_or_insert(&mut self, price : f32) -> &mut Limit;
+
+    /// remove_level removes the level of the price, it must hold no orders
+    fn remove_level(&mut self, price : f32);
+
+    /// best returns the level with the highest priority
+    fn best(&self) -> Option<&Limit>;
+
+    fn best_mut(&mut self) -> Option<&mut Limit>;
+
+    /// remove_best removes the level with the highest priority, it must hold no orders
+    fn remove_best(&mut self);
+
+    /// levels iterates the levels by priority, best first
+    fn levels(&self) -> impl Iterator<Item = &Limit>;
+
+    /// remove_orders removes the orders selected by the predicate and the levels left empty
+    ///
+    /// # Arguments
+    ///
+    /// * `orders` - The slab storing the orders of the book
+    /// * `range` - The lowest and highest price of the levels visited, None visits all of them
+    /// * `predicate` - The function returning true for the orders to be removed
+    /// * `removed` - The vector collecting the removed orders, by priority
+    fn remove_orders(&mut self, orders : &mut OrderSlab, range : Option<(f32, f32)>, predicate : impl Fn(&Order) -> bool, removed : &mut Vec<Order>);
+
+    /// len returns the number of price levels
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool
+    {
+        self.len() == 0
+    }
+}
+
+/// The BTreeMap side allocates a node when a level is created past the capacity of
+/// its nodes and frees it when levels are removed, it is not allocation free, use a
+/// PriceLadder for that
+impl<T : Ord + Creator + Debug + Send> PriceLevels for BTreeMap<T, Limit>
+{
+    fn accepts(&self, _price : f32) -> bool
+    {
+        true
+    }
+
+    fn level_mut(&mut self, price : f32) -> Option<&mut Limit>
+    {
+        self.get_mut(&T::create(price))
+    }
+
+    fn level_or_insert(&mut self, price : f32) -> &mut Limit
+    {
+        self.entry(T::create(price)).or_insert_with(|| Limit::new(price))
+    }
+
+    fn remove_level(&mut self, price : f32)
+    {
+        self.remove(&T::create(price));
+    }
+
+    fn best(&self) -> Option<&Limit>
+    {
+        self.values().next()
+    }
+
+    fn best_mut(&mut self) -> Option<&mut Limit>
+    {
+        self.values_mut().next()
+    }
+
+    fn remove_best(&mut self)
+    {
+        self.pop_first();
+    }
+
+    fn levels(&self) -> impl Iterator<Item = &Limit>
+    {
+        self.values()
+    }
+
+    fn remove_orders(&mut self, orders : &mut OrderSlab, range : Option<(f32, f32)>, predicate : impl Fn(&Order) -> bool, removed : &mut Vec<Order>)
+    {
+        let range = match range
+        {
+            Some((low, high)) =>
+            {
+                // The bid keys are sorted in descending price order, take the bounds in key order
+                let (first, last) = (T::create(low), T::create(high));
+                if first <= last
+                {
+                    (Bound::Included(first), Bound::Included(last))
+                }
+                else
+                {
+                    (Bound::Included(last), Bound::Included(first))
+                }
+            },
+            None => (Bound::Unbounded, Bound::Unbounded),
+        };
+
+        let mut empty_levels = vec![];
+        for (_, limit) in self.range_mut(range)
+        {
+            limit.remove_orders_if(orders, &predicate, removed);
+            if limit.num_orders() == 0
+            {
+                empty_levels.push(limit.price);
+            }
+        }
+
+        for price in empty_levels
+        {
+            self.remove(&T::create(price));
+        }
+    }
+
+    fn len(&self) -> usize
+    {
+        BTreeMap::len(self)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::collections::BTreeMap;
+    use crate::data_types::*;
+    use crate::slab::OrderSlab;
+    use crate::order_book::{AskKey, BidKey};
+    use super::PriceLevels;
+
+    #[test]
+    fn bid_levels_are_sorted_best_first()
+    {
+        let mut orders = OrderSlab::with_capacity(4);
+        let mut bids : BTreeMap<BidKey, Limit> = BTreeMap::new();
+        bids.level_or_insert(12.1).add_order(&mut orders, Order::new(1, Side::Buy, 12.1, 10));
+        bids.level_or_insert(12.3).add_order(&mut orders, Order::new(2, Side::Buy, 12.3, 10));
+        bids.level_or_insert(12.2).add_order(&mut orders, Order::new(3, Side::Buy, 12.2, 10));
+
+        let prices : Vec<f32> = bids.levels().map(|limit| limit.price).collect();
+        assert_eq!(prices, vec![12.3, 12.2, 12.1]);
+        assert_eq!(bids.best().unwrap().price, 12.3);
+        bids.best_mut().unwrap().remove_order(&mut orders, Side::Buy, 2).unwrap();
+        bids.remove_best();
+        assert_eq!(bids.best().unwrap().price, 12.2);
+        assert_eq!(bids.len(), 2);
+    }
+
+    #[test]
+    fn remove_orders_visits_the_range_and_drops_empty_levels()
+    {
+        let mut orders = OrderSlab::with_capacity(4);
+        let mut asks : BTreeMap<AskKey, Limit> = BTreeMap::new();
+        for (id, price) in [(1, 12.1f32), (2, 12.2), (3, 12.3)]
+        {
+            asks.level_or_insert(price).add_order(&mut orders, Order::new(id, Side::Sell, price, 10));
+        }
+
+        let mut removed = vec![];
+        asks.remove_orders(&mut orders, Some((12.2, 12.3)), |_| true, &mut removed);
+        assert_eq!(removed.iter().map(|order| order.id).collect::<Vec<u32>>(), vec![2, 3]);
+        assert_eq!(asks.len(), 1);
+        assert!(asks.level_mut(12.2).is_none());
+    }
+}
@@ -0,0 +1,393 @@
+use std::sync::mpsc;
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use crate::admin::{AdminCommand, Phase};
+use crate::data_types::Order;
+use crate::depth::Depth;
+use crate::engine::Engine;
+
+/// Number of price levels per side of a depth query without `levels` parameter
+const DEFAULT_LEVELS : usize = 10;
+/// Number of trades of a trades query without `limit` parameter
+const DEFAULT_TRADES : usize = 100;
+
+/// RestRequest is a request of the REST API sent to the owner of the engine with the channel of its reply
+pub type RestRequest = (Route, oneshot::Sender<Reply>);
+
+/// Query is a read only request of the REST API
+#[derive(Clone, PartialEq, Debug)]
+pub enum Query
+{
+    /// `GET /instruments`: the traded symbols with their best prices
+    Instruments,
+    /// `GET /instruments/<symbol>/depth?levels=<n>`: the top levels of a book
+    Depth { symbol : String, levels : usize },
+    /// `GET /instruments/<symbol>/trades?limit=<n>`: the last trades of a book, oldest first
+    Trades { symbol : String, limit : usize },
+    /// `GET /orders/<id>`: a resting order
+    Order(u32),
+    /// `GET /session`: the phase of the session and the halts
+    Session,
+}
+
+/// Route is a request of the REST API, either a query or an admin command,
+/// e.g. `POST /admin/halt/TSLA` or `POST /admin/kill`
+#[derive(Clone, PartialEq, Debug)]
+pub enum Route
+{
+    Query(Query),
+    Admin(AdminCommand),
+}
+
+impl Route
+{
+    /// parse maps the method and the target of an HTTP request to a route
+    ///
+    /// # Arguments
+    /// * method: the HTTP method, GET for the queries and POST for the admin commands
+    /// * target: the path of the request with its optional query string
+    /// # Return
+    ///
+    /// The route, or the error reply to be sent when the request matches none
+    pub fn parse(method : &str, target : &str) -> Result<Route, Reply>
+    {
+        let (path, parameters) = match target.split_once('?')
+        {
+            Some((path, parameters)) => (path, Some(parameters)),
+            None => (target, None),
+        };
+        let segments : Vec<&str> = path.trim_matches('/').split('/').collect();
+        let route = match (method, segments.as_slice())
+        {
+            ("GET", ["instruments"]) => Route::Query(Query::Instruments),
+            ("GET", ["instruments", symbol, "depth"]) => Route::Query(Query::Depth {
+                symbol : symbol.to_string(),
+                levels : parameter(parameters, "levels", DEFAULT_LEVELS)? }),
+            ("GET", ["instruments", symbol, "trades"]) => Route::Query(Query::Trades {
+                symbol : symbol.to_string(),
+                limit : parameter(parameters, "limit", DEFAULT_TRADES)? }),
+            ("GET", ["orders", id]) => Route::Query(Query::Order(id.parse().map_err(|_| Reply::error(400, "invalid order id"))?)),
+            ("GET", ["session"]) => Route::Query(Query::Session),
+            ("POST", ["admin", command @ ..]) => Route::Admin(AdminCommand::parse(&command.join(" ")).map_err(|reason| Reply::error(400, reason))?),
+            (_, ["instruments"] | ["instruments", _, "depth" | "trades"] | ["orders", _] | ["session"] | ["admin", ..]) =>
+                return Err(Reply::error(405, "method not allowed")),
+            _ => return Err(Reply::error(404, "not found")),
+        };
+        Ok(route)
+    }
+}
+
+/// parameter reads a numeric parameter of the query string
+fn parameter(parameters : Option<&str>, name : &str, default : usize) -> Result<usize, Reply>
+{
+    let value = parameters.into_iter()
+        .flat_map(|parameters| parameters.split('&'))
+        .filter_map(|parameter| parameter.split_once('='))
+        .find(|(key, _)| *key == name);
+    match value
+    {
+        Some((_, value)) => value.parse().map_err(|_| Reply::error(400, "invalid query parameter")),
+        None => Ok(default),
+    }
+}
+
+/// Reply is the status and JSON body of an HTTP response
+#[derive(Clone, PartialEq, Debug)]
+pub struct Reply
+{
+    pub status : u16,
+    pub body : String,
+}
+
+impl Reply
+{
+    pub fn json<T : Serialize + ?Sized>(value : &T) -> Reply
+    {
+        Reply { status : 200, body : serde_json::to_string(value).unwrap_or_default() }
+    }
+
+    /// error replies `{"error":<reason>}` with the status
+    pub fn error(status : u16, reason : &str) -> Reply
+    {
+        Reply { status, body : serde_json::json!({ "error" : reason }).to_string() }
+    }
+
+    /// admin replies the number of orders cancelled by an admin command, or the reason
+    /// why it could not be applied
+    pub fn admin(applied : Result<usize, &'static str>) -> Reply
+    {
+        match applied
+        {
+            Ok(cancelled) => Reply::json(&serde_json::json!({ "cancelled" : cancelled })),
+            Err(reason) => Reply::error(400, reason),
+        }
+    }
+
+    /// to_http formats the reply as an HTTP response closing the connection
+    pub fn to_http(&self) -> String
+    {
+        let reason = match self.status
+        {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Service Unavailable",
+        };
+        format!("HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                self.status, reason, self.body.len(), self.body)
+    }
+}
+
+/// Instrument is a traded symbol as listed by `GET /instruments`
+#[derive(Serialize)]
+struct Instrument<'a>
+{
+    symbol : &'a str,
+    halted : bool,
+    best_bid : Option<f32>,
+    best_ask : Option<f32>,
+    last_price : Option<f32>,
+    trades : u64,
+}
+
+#[derive(Serialize)]
+struct BookDepth<'a>
+{
+    symbol : &'a str,
+    #[serde(flatten)]
+    depth : Depth,
+}
+
+#[derive(Serialize)]
+struct RestingOrder<'a>
+{
+    symbol : &'a str,
+    #[serde(flatten)]
+    order : Order,
+}
+
+#[derive(Serialize)]
+struct Session<'a>
+{
+    phase : Phase,
+    new_orders_enabled : bool,
+    halted_symbols : Vec<&'a str>,
+    halted_accounts : Vec<u32>,
+}
+
+/// answer runs a query against the engine
+pub fn answer(engine : &Engine, query : &Query) -> Reply
+{
+    match query
+    {
+        Query::Instruments =>
+        {
+            let instruments : Vec<Instrument> = engine.symbols().map(|symbol|
+            {
+                let book = engine.book(symbol).unwrap();
+                Instrument {
+                    symbol,
+                    halted : engine.controls().is_symbol_halted(symbol),
+                    best_bid : book.best_bid().map(|limit| limit.price),
+                    best_ask : book.best_ask().map(|limit| limit.price),
+                    last_price : book.trades().last().map(|trade| trade.price),
+                    trades : book.trade_count() }
+            }).collect();
+            Reply::json(&instruments)
+        },
+        Query::Depth { symbol, levels } => match engine.book(symbol)
+        {
+            Some(book) => Reply::json(&BookDepth { symbol, depth : book.depth(*levels) }),
+            None => Reply::error(404, "Symbol is not traded by the Engine"),
+        },
+        Query::Trades { symbol, limit } => match engine.book(symbol)
+        {
+            Some(book) =>
+            {
+                let trades = book.trades();
+                Reply::json(&trades[trades.len().saturating_sub(*limit)..])
+            },
+            None => Reply::error(404, "Symbol is not traded by the Engine"),
+        },
+        Query::Order(order_id) => match engine.order(*order_id)
+        {
+            Some((symbol, order)) => Reply::json(&RestingOrder { symbol, order }),
+            None => Reply::error(404, "Unknown order"),
+        },
+        Query::Session =>
+        {
+            let controls = engine.controls();
+            Reply::json(&Session {
+                phase : controls.phase(),
+                new_orders_enabled : controls.new_orders_enabled(),
+                halted_symbols : controls.halted_symbols(),
+                halted_accounts : controls.halted_accounts() })
+        },
+    }
+}
+
+/// parse_request reads the route from the request line of an HTTP request, the headers
+/// and the body are ignored
+fn parse_request(request : &[u8]) -> Result<Route, Reply>
+{
+    let request = String::from_utf8_lossy(request);
+    let mut words = request.lines().next().unwrap_or("").split_whitespace();
+    match (words.next(), words.next())
+    {
+        (Some(method), Some(target)) => Route::parse(method, target),
+        _ => Err(Reply::error(400, "malformed request")),
+    }
+}
+
+/// serve_rest answers the HTTP requests of the REST API, one request per connection.
+/// The routes are sent to the owner of the engine, which replies through the channel
+/// of the request
+pub async fn serve_rest(listener : TcpListener, requests : mpsc::Sender<RestRequest>)
+{
+    loop
+    {
+        let (mut socket, _) = match listener.accept().await
+        {
+            Ok(connection) => connection,
+            Err(_) => continue,
+        };
+
+        let requests = requests.clone();
+        tokio::spawn(async move
+        {
+            // Only the request line is used, it comes within the first bytes
+            let mut request = [0u8; 1024];
+            let len = match socket.read(&mut request).await
+            {
+                Ok(len) => len,
+                Err(_) => return,
+            };
+            let reply = match parse_request(&request[..len])
+            {
+                Ok(route) =>
+                {
+                    let (sender, receiver) = oneshot::channel();
+                    let stopped = || Reply::error(503, "The matching thread is stopped");
+                    match requests.send((route, sender))
+                    {
+                        Ok(()) => receiver.await.unwrap_or_else(|_| stopped()),
+                        Err(_) => stopped(),
+                    }
+                },
+                Err(reply) => reply,
+            };
+            let _ = socket.write_all(reply.to_http().as_bytes()).await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::net::SocketAddr;
+    use std::thread;
+    use tokio::net::TcpStream;
+    use crate::data_types::{Order, Side};
+    use super::*;
+
+    #[test]
+    fn requests_are_routed_by_method_and_path()
+    {
+        assert_eq!(Route::parse("GET", "/instruments"), Ok(Route::Query(Query::Instruments)));
+        assert_eq!(Route::parse("GET", "/instruments/TSLA/depth?levels=3"),
+                   Ok(Route::Query(Query::Depth { symbol : "TSLA".to_string(), levels : 3 })));
+        assert_eq!(Route::parse("GET", "/instruments/TSLA/trades"),
+                   Ok(Route::Query(Query::Trades { symbol : "TSLA".to_string(), limit : DEFAULT_TRADES })));
+        assert_eq!(Route::parse("GET", "/orders/42"), Ok(Route::Query(Query::Order(42))));
+        assert_eq!(Route::parse("GET", "/session/"), Ok(Route::Query(Query::Session)));
+        assert_eq!(Route::parse("POST", "/admin/halt_account/7"), Ok(Route::Admin(AdminCommand::HaltAccount(7))));
+        assert_eq!(Route::parse("POST", "/admin/kill"), Ok(Route::Admin(AdminCommand::KillSwitch)));
+
+        assert_eq!(Route::parse("GET", "/orders/x").unwrap_err().status, 400);
+        assert_eq!(Route::parse("GET", "/instruments/TSLA/depth?levels=-1").unwrap_err().status, 400);
+        assert_eq!(Route::parse("POST", "/admin/halt").unwrap_err(), Reply::error(400, "missing argument"));
+        assert_eq!(Route::parse("GET", "/admin/kill").unwrap_err().status, 405);
+        assert_eq!(Route::parse("POST", "/session").unwrap_err().status, 405);
+        assert_eq!(Route::parse("GET", "/books").unwrap_err().status, 404);
+    }
+
+    #[test]
+    fn queries_are_answered_in_json()
+    {
+        let mut engine = Engine::new();
+        engine.add_symbol("TSLA");
+        engine.add_symbol("AAPL");
+        engine.insert_order("TSLA", &mut Order::new(1, Side::Buy, 122.2f32, 100)).unwrap();
+        engine.insert_order("TSLA", &mut Order::new(2, Side::Sell, 122.2f32, 40)).unwrap();
+        engine.admin(&AdminCommand::HaltSymbol("AAPL".to_string())).unwrap();
+
+        assert_eq!(answer(&engine, &Query::Instruments).body, concat!(
+            r#"[{"symbol":"AAPL","halted":true,"best_bid":null,"best_ask":null,"last_price":null,"trades":0},"#,
+            r#"{"symbol":"TSLA","halted":false,"best_bid":122.2,"best_ask":null,"last_price":122.2,"trades":1}]"#));
+        assert_eq!(answer(&engine, &Query::Depth { symbol : "TSLA".to_string(), levels : 10 }).body,
+                   r#"{"symbol":"TSLA","bids":[{"price":122.2,"qty":60,"orders":1}],"asks":[]}"#);
+        assert_eq!(answer(&engine, &Query::Depth { symbol : "MSFT".to_string(), levels : 10 }).status, 404);
+
+        let order : serde_json::Value = serde_json::from_str(&answer(&engine, &Query::Order(1)).body).unwrap();
+        assert_eq!((order["symbol"].as_str(), order["side"].as_str(), order["qty"].as_u64()), (Some("TSLA"), Some("buy"), Some(60)));
+        assert_eq!(answer(&engine, &Query::Order(2)), Reply::error(404, "Unknown order"));
+
+        let trades : serde_json::Value = serde_json::from_str(&answer(&engine, &Query::Trades { symbol : "TSLA".to_string(), limit : 5 }).body).unwrap();
+        assert_eq!(trades.as_array().unwrap().len(), 1);
+        assert_eq!((trades[0]["passive_id"].as_u64(), trades[0]["symbol"].as_str()), (Some(1), Some("TSLA")));
+        assert_eq!(answer(&engine, &Query::Trades { symbol : "TSLA".to_string(), limit : 0 }).body, "[]");
+
+        assert_eq!(answer(&engine, &Query::Session).body,
+                   r#"{"phase":"open","new_orders_enabled":true,"halted_symbols":["AAPL"],"halted_accounts":[]}"#);
+    }
+
+    /// request sends an HTTP request and returns the status line and the body of the response
+    async fn request(address : SocketAddr, method : &str, target : &str) -> (String, String)
+    {
+        let mut socket = TcpStream::connect(address).await.unwrap();
+        socket.write_all(format!("{} {} HTTP/1.1\r\nHost: localhost\r\n\r\n", method, target).as_bytes()).await.unwrap();
+        let mut response = String::new();
+        socket.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    }
+
+    #[tokio::test]
+    async fn requests_are_served_by_the_owner_of_the_engine()
+    {
+        let (requests, receiver) = mpsc::channel::<RestRequest>();
+        // Stands for the matching thread
+        thread::spawn(move ||
+        {
+            let mut engine = Engine::new();
+            engine.add_symbol("TSLA");
+            engine.insert_order("TSLA", &mut Order::new(1, Side::Buy, 122.2f32, 100)).unwrap();
+            for (route, reply) in receiver
+            {
+                let _ = reply.send(match route
+                {
+                    Route::Query(query) => answer(&engine, &query),
+                    Route::Admin(command) => Reply::admin(engine.admin(&command)),
+                });
+            }
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve_rest(listener, requests));
+
+        let (status, body) = request(address, "GET", "/instruments/TSLA/depth?levels=1").await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body, r#"{"symbol":"TSLA","bids":[{"price":122.2,"qty":100,"orders":1}],"asks":[]}"#);
+
+        assert_eq!(request(address, "POST", "/admin/kill").await, ("HTTP/1.1 200 OK".to_string(), r#"{"cancelled":1}"#.to_string()));
+        assert_eq!(request(address, "GET", "/orders/1").await.0, "HTTP/1.1 404 Not Found");
+        let (_, body) = request(address, "GET", "/session").await;
+        assert!(body.starts_with(r#"{"phase":"cancel_only","new_orders_enabled":false"#));
+        assert_eq!(request(address, "POST", "/admin/halt/MSFT").await,
+                   ("HTTP/1.1 400 Bad Request".to_string(), r#"{"error":"Symbol is not traded by the Engine"}"#.to_string()));
+        assert_eq!(request(address, "DELETE", "/orders/1").await.0, "HTTP/1.1 405 Method Not Allowed");
+    }
+}
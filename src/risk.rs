@@ -0,0 +1,614 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::data_types::*;
+use crate::events::Event;
+use crate::positions::{MarkMethod, PositionKeeper};
+
+const NANOS_PER_SECOND : u64 = 1_000_000_000;
+
+/// RiskLimits configures the pre-trade checks, a limit left to None is not checked
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct RiskLimits
+{
+    /// Maximum quantity of a single order
+    pub max_order_qty : Option<u32>,
+    /// Maximum price times quantity of a single order, market orders are valued at the reference price
+    pub max_notional : Option<f64>,
+    /// Maximum number of orders resting in the books per account
+    pub max_open_orders : Option<u32>,
+    /// Maximum absolute position per account and symbol, should the order be entirely filled
+    pub max_position : Option<i64>,
+    /// Maximum distance of a limit price from the reference price, as a fraction of the reference price
+    pub max_price_deviation : Option<f32>,
+    /// Maximum number of orders submitted per account within a second
+    pub max_orders_per_second : Option<u32>,
+}
+
+impl RiskLimits
+{
+    /// exchange_defaults returns the limits the exchange binaries run with
+    pub fn exchange_defaults() -> RiskLimits
+    {
+        RiskLimits {
+            max_order_qty : Some(100_000),
+            max_notional : Some(10_000_000.0),
+            max_open_orders : Some(1000),
+            max_position : Some(1_000_000),
+            max_price_deviation : Some(0.1),
+            max_orders_per_second : Some(10_000) }
+    }
+}
+
+/// RejectReason tells which pre-trade check an order failed
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum RejectReason
+{
+    MaxOrderQty,
+    MaxNotional,
+    MaxOpenOrders,
+    MaxPosition,
+    PriceDeviation,
+    RateLimit,
+    InvalidPrice,
+}
+
+impl RejectReason
+{
+    pub fn as_str(&self) -> &'static str
+    {
+        match self
+        {
+            RejectReason::MaxOrderQty => "Order quantity exceeds the limit",
+            RejectReason::MaxNotional => "Order notional exceeds the limit",
+            RejectReason::MaxOpenOrders => "Too many open orders",
+            RejectReason::MaxPosition => "Position would exceed the limit",
+            RejectReason::PriceDeviation => "Price is too far from the reference price",
+            RejectReason::RateLimit => "Order rate exceeds the limit",
+            RejectReason::InvalidPrice => "Price must be finite and positive",
+        }
+    }
+}
+
+impl fmt::Display for RejectReason
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result
+    {
+        f.write_str(self.as_str())
+    }
+}
+
+/// AccountRisk is the state of an account shared by all the symbols
+#[derive(Debug, Default)]
+struct AccountRisk
+{
+    open_orders : u32,
+    window_start : u64,
+    window_orders : u32,
+}
+
+/// RiskOrder is an order followed by the checker to attribute its fills and
+/// count it as open while it rests in a book
+#[derive(Copy, Clone, Debug)]
+struct RiskOrder
+{
+    account : u32,
+    side : Side,
+    qty : u32,
+    resting : bool,
+}
+
+/// RiskChecker sits in front of the order books: the orders are checked before
+/// being inserted, then the book events keep open orders and positions up to date.
+/// Every check is a couple of hash map lookups so that it can stay on the hot path
+/// * _reference_prices are the last traded prices, or the ones set at the opening
+/// * _positions are the positions of the accounts, kept from the trades
+/// * _orders holds the last accepted order and the orders resting in the books, by symbol
+///   and id as the clients choose their ids, which only need to be unique within a book
+/// * _open_qty is the quantity resting in the books by account, symbol and side, it is
+///   projected on the position as if it was filled
+#[derive(Debug, Default)]
+pub struct RiskChecker
+{
+    _limits : RiskLimits,
+    _accounts : HashMap<u32, AccountRisk>,
+    _reference_prices : HashMap<String, f32>,
+    _positions : PositionKeeper,
+    _orders : HashMap<(Symbol, u32), RiskOrder>,
+    _incoming : Option<(Symbol, u32)>,
+    _open_qty : HashMap<(u32, Symbol, Side), u64>,
+}
+
+impl RiskChecker
+{
+    pub fn new(limits : RiskLimits) -> RiskChecker
+    {
+        RiskChecker { _limits : limits, ..Default::default() }
+    }
+
+    /// with_mark_method creates a checker whose positions are valued with the given mark method
+    pub fn with_mark_method(limits : RiskLimits, mark_method : MarkMethod) -> RiskChecker
+    {
+        RiskChecker { _limits : limits, _positions : PositionKeeper::new(mark_method), ..Default::default() }
+    }
+
+    pub fn limits(&self) -> &RiskLimits
+    {
+        &self._limits
+    }
+
+    pub fn set_limits(&mut self, limits : RiskLimits)
+    {
+        self._limits = limits;
+    }
+
+    /// set_reference_price sets the price the limit prices are compared to, it is
+    /// replaced by the price of every trade on the symbol
+    pub fn set_reference_price(&mut self, symbol : &str, price : f32)
+    {
+        self._reference_prices.insert(symbol.to_string(), price);
+    }
+
+    pub fn reference_price(&self, symbol : &str) -> Option<f32>
+    {
+        self._reference_prices.get(symbol).copied()
+    }
+
+    /// positions returns the position keeper the max position check relies on
+    pub fn positions(&self) -> &PositionKeeper
+    {
+        &self._positions
+    }
+
+    /// update_quotes passes the best prices of the symbol to the position keeper,
+    /// it is called once the events of a book change have been consumed
+    pub fn update_quotes(&mut self, symbol : &str, best_bid : Option<f32>, best_ask : Option<f32>)
+    {
+        self._positions.update_quotes(symbol, best_bid, best_ask);
+    }
+
+    /// position returns the signed filled quantity of the account on the symbol
+    pub fn position(&self, symbol : &str, account : u32) -> i64
+    {
+        self._positions.position(account, symbol).qty
+    }
+
+    /// open_orders returns the number of orders of the account resting in the books
+    pub fn open_orders(&self, account : u32) -> u32
+    {
+        self._accounts.get(&account).map(|risk| risk.open_orders).unwrap_or(0)
+    }
+
+    /// check runs the pre-trade checks, an accepted order is expected to be
+    /// inserted in the book before the next check
+    ///
+    /// # Arguments
+    ///
+    /// * `symbol` - The symbol of the order
+    /// * `order` - The incoming order
+    /// * `timestamp` - The time of the request in nanoseconds, it drives the rate limit
+    pub fn check(&mut self, symbol : &str, order : &Order, timestamp : u64) -> Result<(), RejectReason>
+    {
+        self.check_limits(symbol, order, timestamp, true)?;
+
+        // The unfilled quantity of the previous incoming order has not been rested
+        if let Some(incoming) = self._incoming.take()
+        {
+            if self._orders.get(&incoming).is_some_and(|risk_order| !risk_order.resting)
+            {
+                self._orders.remove(&incoming);
+            }
+        }
+        let key = (Symbol::new(symbol), order.id);
+        self._orders.insert(key, RiskOrder { account : order.account, side : order.side, qty : order.qty, resting : false });
+        self._incoming = Some(key);
+        Ok(())
+    }
+
+    /// check_replace runs the pre-trade checks on the replacement of a resting order,
+    /// the order keeps its slot so that the open orders limit is not checked again.
+    /// The book events of the amendment then update the followed order
+    ///
+    /// # Arguments
+    ///
+    /// * `symbol` - The symbol of the order
+    /// * `replacement` - The order with its new price and leaves quantity
+    /// * `timestamp` - The time of the request in nanoseconds, it drives the rate limit
+    pub fn check_replace(&mut self, symbol : &str, replacement : &Order, timestamp : u64) -> Result<(), RejectReason>
+    {
+        self.check_limits(symbol, replacement, timestamp, false)
+    }
+
+    fn check_limits(&mut self, symbol : &str, order : &Order, timestamp : u64, new_order : bool) -> Result<(), RejectReason>
+    {
+        if order.order_type == OrderType::Limit && !(order.price.is_finite() && order.price > 0.0)
+        {
+            return Err(RejectReason::InvalidPrice);
+        }
+
+        let limits = self._limits;
+        let account = self._accounts.entry(order.account).or_default();
+        if let Some(max_orders_per_second) = limits.max_orders_per_second
+        {
+            if timestamp.saturating_sub(account.window_start) >= NANOS_PER_SECOND
+            {
+                account.window_start = timestamp;
+                account.window_orders = 0;
+            }
+            account.window_orders += 1;
+            if account.window_orders > max_orders_per_second
+            {
+                return Err(RejectReason::RateLimit);
+            }
+        }
+
+        if limits.max_order_qty.is_some_and(|max_order_qty| order.qty > max_order_qty)
+        {
+            return Err(RejectReason::MaxOrderQty);
+        }
+
+        let reference_price = self._reference_prices.get(symbol).copied();
+        if let (Some(max_price_deviation), Some(reference_price), OrderType::Limit) = (limits.max_price_deviation, reference_price, order.order_type)
+        {
+            if (order.price - reference_price).abs() > max_price_deviation * reference_price
+            {
+                return Err(RejectReason::PriceDeviation);
+            }
+        }
+
+        let valuation_price = match order.order_type
+        {
+            OrderType::Limit => Some(order.price),
+            OrderType::Market => reference_price,
+        };
+        if let (Some(max_notional), Some(price)) = (limits.max_notional, valuation_price)
+        {
+            if price as f64 * order.qty as f64 > max_notional
+            {
+                return Err(RejectReason::MaxNotional);
+            }
+        }
+
+        if limits.max_open_orders.is_some_and(|max_open_orders| new_order && order.order_type == OrderType::Limit && account.open_orders >= max_open_orders)
+        {
+            return Err(RejectReason::MaxOpenOrders);
+        }
+
+        if let Some(max_position) = limits.max_position
+        {
+            // The resting orders of the account are projected as filled, but the replaced
+            // order which is counted with its new quantity
+            let book = Symbol::new(symbol);
+            let replaced = self._orders.get(&(book, order.id))
+                .filter(|risk_order| !new_order && risk_order.resting)
+                .map_or(0, |risk_order| risk_order.qty as i64);
+            let open_qty = self._open_qty.get(&(order.account, book, order.side)).copied().unwrap_or(0) as i64 - replaced;
+            let position = self._positions.position(order.account, symbol).qty;
+            let projected = match order.side
+            {
+                Side::Buy => position + open_qty + order.qty as i64,
+                Side::Sell => position - open_qty - order.qty as i64,
+            };
+            if projected.abs() > max_position
+            {
+                return Err(RejectReason::MaxPosition);
+            }
+        }
+        Ok(())
+    }
+
+    fn set_resting(&mut self, key : (Symbol, u32), resting : bool)
+    {
+        let risk_order = match self._orders.get_mut(&key)
+        {
+            Some(risk_order) if risk_order.resting != resting => risk_order,
+            _ => return,
+        };
+        risk_order.resting = resting;
+        let open_qty = self._open_qty.entry((risk_order.account, key.0, risk_order.side)).or_default();
+        let account = self._accounts.entry(risk_order.account).or_default();
+        if resting
+        {
+            account.open_orders += 1;
+            *open_qty += risk_order.qty as u64;
+        }
+        else
+        {
+            account.open_orders = account.open_orders.saturating_sub(1);
+            *open_qty = open_qty.saturating_sub(risk_order.qty as u64);
+        }
+    }
+
+    /// set_qty changes the quantity left of a followed order, and the open quantity
+    /// of its account while it rests
+    fn set_qty(&mut self, key : (Symbol, u32), qty : u32)
+    {
+        let risk_order = match self._orders.get_mut(&key)
+        {
+            Some(risk_order) => risk_order,
+            None => return,
+        };
+        if risk_order.resting
+        {
+            let open_qty = self._open_qty.entry((risk_order.account, key.0, risk_order.side)).or_default();
+            *open_qty = (*open_qty + qty as u64).saturating_sub(risk_order.qty as u64);
+        }
+        risk_order.qty = qty;
+    }
+
+    fn on_fill(&mut self, key : (Symbol, u32), qty : u32)
+    {
+        let left = match self._orders.get(&key)
+        {
+            Some(risk_order) => risk_order.qty.saturating_sub(qty),
+            None => return,
+        };
+        self.set_qty(key, left);
+        if left == 0
+        {
+            self.set_resting(key, false);
+            self._orders.remove(&key);
+        }
+    }
+
+    /// on_event updates open orders, positions and reference price from an event of the symbol book
+    pub fn on_event(&mut self, symbol : &str, event : &Event)
+    {
+        let book = Symbol::new(symbol);
+        match event
+        {
+            Event::Added(order) =>
+            {
+                self._orders.entry((book, order.id)).or_insert(RiskOrder { account : order.account, side : order.side, qty : order.qty, resting : false });
+                self.set_resting((book, order.id), true);
+            },
+            Event::Traded(trade) =>
+            {
+                self.on_fill((book, trade.aggressive_id), trade.qty);
+                self.on_fill((book, trade.passive_id), trade.qty);
+                self._positions.on_trade(symbol, trade);
+                self.set_reference_price(symbol, trade.price);
+            },
+            Event::Replaced { old, new } =>
+            {
+                // Unless reduced in place, the amended order leaves the book and is matched again
+                if old.price != new.price || new.qty > old.qty
+                {
+                    self.set_resting((book, new.id), false);
+                    self._incoming = Some((book, new.id));
+                }
+                self.set_qty((book, new.id), new.qty);
+            },
+            Event::Cancelled(order) | Event::Expired(order) =>
+            {
+                self.set_resting((book, order.id), false);
+                self._orders.remove(&(book, order.id));
+            },
+            Event::MassCancelled { .. } => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::engine::Engine;
+    use super::*;
+
+    fn order_for_account(id : u32, side : Side, price : f32, qty : u32, account : u32) -> Order
+    {
+        let mut order = Order::new(id, side, price, qty);
+        order.account = account;
+        order
+    }
+
+    /// Checks and inserts the order, then feeds the events back to the checker
+    fn submit(engine : &mut Engine, risk : &mut RiskChecker, order : &mut Order, timestamp : u64) -> Result<(), RejectReason>
+    {
+        risk.check("TSLA", order, timestamp)?;
+        engine.insert_order("TSLA", order).unwrap();
+        for (symbol, event) in engine.drain_events()
+        {
+            risk.on_event(&symbol, &event);
+        }
+        Ok(())
+    }
+
+    fn engine() -> Engine
+    {
+        let mut engine = Engine::new();
+        engine.add_symbol("TSLA");
+        engine
+    }
+
+    #[test]
+    fn rejects_large_orders()
+    {
+        let mut risk = RiskChecker::new(RiskLimits { max_order_qty : Some(100), max_notional : Some(10000.0), ..Default::default() });
+        assert_eq!(risk.check("TSLA", &Order::new(1, Side::Buy, 10.0f32, 101), 0), Err(RejectReason::MaxOrderQty));
+        assert_eq!(risk.check("TSLA", &Order::new(2, Side::Buy, 101.0f32, 100), 0), Err(RejectReason::MaxNotional));
+        assert_eq!(risk.check("TSLA", &Order::new(3, Side::Buy, 100.0f32, 100), 0), Ok(()));
+
+        // Market orders are valued at the reference price, once known
+        let mut market = Order::new(4, Side::Buy, 0.0f32, 100);
+        market.order_type = OrderType::Market;
+        assert_eq!(risk.check("TSLA", &market, 0), Ok(()));
+        risk.set_reference_price("TSLA", 150.0f32);
+        assert_eq!(risk.check("TSLA", &market, 0), Err(RejectReason::MaxNotional));
+    }
+
+    #[test]
+    fn rejects_prices_far_from_reference()
+    {
+        let mut engine = engine();
+        let mut risk = RiskChecker::new(RiskLimits { max_price_deviation : Some(0.1), ..Default::default() });
+        assert_eq!(risk.check("TSLA", &Order::new(1, Side::Buy, 1.0f32, 10), 0), Ok(()));
+
+        risk.set_reference_price("TSLA", 100.0f32);
+        assert_eq!(risk.check("TSLA", &Order::new(2, Side::Buy, 111.0f32, 10), 0), Err(RejectReason::PriceDeviation));
+        assert_eq!(risk.check("TSLA", &Order::new(3, Side::Sell, 89.0f32, 10), 0), Err(RejectReason::PriceDeviation));
+
+        // The reference follows the trades
+        submit(&mut engine, &mut risk, &mut Order::new(4, Side::Buy, 108.0f32, 10), 0).unwrap();
+        submit(&mut engine, &mut risk, &mut Order::new(5, Side::Sell, 108.0f32, 10), 0).unwrap();
+        assert_eq!(risk.reference_price("TSLA"), Some(108.0f32));
+        assert_eq!(risk.check("TSLA", &Order::new(6, Side::Buy, 118.0f32, 10), 0), Ok(()));
+    }
+
+    #[test]
+    fn limits_open_orders_per_account()
+    {
+        let mut engine = engine();
+        let mut risk = RiskChecker::new(RiskLimits { max_open_orders : Some(2), ..Default::default() });
+        submit(&mut engine, &mut risk, &mut order_for_account(1, Side::Buy, 100.0f32, 10, 7), 0).unwrap();
+        submit(&mut engine, &mut risk, &mut order_for_account(2, Side::Buy, 99.0f32, 10, 7), 0).unwrap();
+        assert_eq!(risk.open_orders(7), 2);
+        assert_eq!(submit(&mut engine, &mut risk, &mut order_for_account(3, Side::Buy, 98.0f32, 10, 7), 0), Err(RejectReason::MaxOpenOrders));
+        submit(&mut engine, &mut risk, &mut order_for_account(4, Side::Buy, 98.0f32, 10, 8), 0).unwrap();
+
+        // A fill and a cancel free both slots
+        submit(&mut engine, &mut risk, &mut order_for_account(5, Side::Sell, 100.0f32, 10, 9), 0).unwrap();
+        engine.cancel_order("TSLA", &order_for_account(2, Side::Buy, 99.0f32, 10, 7)).unwrap();
+        for (symbol, event) in engine.drain_events()
+        {
+            risk.on_event(&symbol, &event);
+        }
+        assert_eq!(risk.open_orders(7), 0);
+        assert_eq!(risk.open_orders(8), 1);
+
+        // An amended order is still counted once
+        submit(&mut engine, &mut risk, &mut order_for_account(6, Side::Buy, 97.0f32, 10, 7), 0).unwrap();
+        engine.amend_order("TSLA", &order_for_account(6, Side::Buy, 97.0f32, 10, 7), 96.0f32, 20).unwrap();
+        for (symbol, event) in engine.drain_events()
+        {
+            risk.on_event(&symbol, &event);
+        }
+        assert_eq!(risk.open_orders(7), 1);
+    }
+
+    #[test]
+    fn ids_reused_on_another_symbol_are_followed_apart()
+    {
+        let mut engine = engine();
+        engine.add_symbol("AAPL");
+        let mut risk = RiskChecker::new(RiskLimits::default());
+        submit(&mut engine, &mut risk, &mut order_for_account(1, Side::Buy, 100.0f32, 10, 7), 0).unwrap();
+        let mut reused = order_for_account(1, Side::Buy, 12.0f32, 30, 8);
+        risk.check("AAPL", &reused, 0).unwrap();
+        engine.insert_order("AAPL", &mut reused).unwrap();
+        engine.cancel_order("AAPL", &reused).unwrap();
+        for (symbol, event) in engine.drain_events()
+        {
+            risk.on_event(&symbol, &event);
+        }
+        assert_eq!((risk.open_orders(7), risk.open_orders(8)), (1, 0));
+
+        // The fill of the first order is attributed to it, freeing its slot
+        submit(&mut engine, &mut risk, &mut order_for_account(2, Side::Sell, 100.0f32, 10, 9), 0).unwrap();
+        assert_eq!(risk.open_orders(7), 0);
+        assert_eq!(risk.position("TSLA", 7), 10);
+    }
+
+    #[test]
+    fn limits_positions_from_fills()
+    {
+        let mut engine = engine();
+        let mut risk = RiskChecker::new(RiskLimits { max_position : Some(100), ..Default::default() });
+        submit(&mut engine, &mut risk, &mut order_for_account(1, Side::Sell, 100.0f32, 80, 8), 0).unwrap();
+        submit(&mut engine, &mut risk, &mut order_for_account(2, Side::Buy, 100.0f32, 60, 7), 0).unwrap();
+        assert_eq!(risk.position("TSLA", 7), 60);
+        assert_eq!(risk.position("TSLA", 8), -60);
+
+        assert_eq!(risk.check("TSLA", &order_for_account(3, Side::Buy, 100.0f32, 41, 7), 0), Err(RejectReason::MaxPosition));
+        assert_eq!(risk.check("TSLA", &order_for_account(4, Side::Sell, 100.0f32, 160, 7), 0), Ok(()));
+        assert_eq!(risk.check("TSLA", &order_for_account(5, Side::Sell, 100.0f32, 41, 8), 0), Err(RejectReason::MaxPosition));
+    }
+
+    #[test]
+    fn limits_positions_with_the_resting_orders()
+    {
+        let mut engine = engine();
+        let mut risk = RiskChecker::new(RiskLimits { max_position : Some(100), ..Default::default() });
+        submit(&mut engine, &mut risk, &mut order_for_account(1, Side::Buy, 99.0f32, 60, 7), 0).unwrap();
+        assert_eq!(submit(&mut engine, &mut risk, &mut order_for_account(2, Side::Buy, 98.0f32, 60, 7), 0), Err(RejectReason::MaxPosition));
+        assert_eq!(risk.check("TSLA", &order_for_account(3, Side::Sell, 101.0f32, 100, 7), 0), Ok(()));
+
+        // A replacement is projected with its new quantity only, a fill moves the quantity to the position
+        engine.amend_order("TSLA", &order_for_account(1, Side::Buy, 99.0f32, 60, 7), 99.0f32, 30).unwrap();
+        for (symbol, event) in engine.drain_events()
+        {
+            risk.on_event(&symbol, &event);
+        }
+        assert_eq!(risk.check_replace("TSLA", &order_for_account(1, Side::Buy, 99.0f32, 100, 7), 0), Ok(()));
+        submit(&mut engine, &mut risk, &mut order_for_account(4, Side::Sell, 99.0f32, 10, 8), 0).unwrap();
+        assert_eq!(risk.position("TSLA", 7), 10);
+        assert_eq!(risk.check("TSLA", &order_for_account(5, Side::Buy, 98.0f32, 70, 7), 0), Ok(()));
+        assert_eq!(risk.check("TSLA", &order_for_account(6, Side::Buy, 98.0f32, 71, 7), 0), Err(RejectReason::MaxPosition));
+    }
+
+    #[test]
+    fn rejects_prices_which_are_not_finite_and_positive()
+    {
+        let mut risk = RiskChecker::new(RiskLimits::exchange_defaults());
+        risk.set_reference_price("TSLA", 100.0f32);
+        assert_eq!(risk.check("TSLA", &Order::new(1, Side::Buy, f32::NAN, 10), 0), Err(RejectReason::InvalidPrice));
+        assert_eq!(risk.check("TSLA", &Order::new(2, Side::Buy, f32::INFINITY, 10), 0), Err(RejectReason::InvalidPrice));
+        assert_eq!(risk.check("TSLA", &Order::new(3, Side::Sell, f32::NEG_INFINITY, 10), 0), Err(RejectReason::InvalidPrice));
+        assert_eq!(risk.check("TSLA", &Order::new(4, Side::Buy, 0.0f32, 10), 0), Err(RejectReason::InvalidPrice));
+        assert_eq!(risk.check("TSLA", &Order::new(5, Side::Sell, -100.0f32, 10), 0), Err(RejectReason::InvalidPrice));
+        assert_eq!(risk.check_replace("TSLA", &Order::new(6, Side::Buy, f32::NAN, 10), 0), Err(RejectReason::InvalidPrice));
+
+        // The price of a market order is not used
+        let mut market = Order::new(7, Side::Buy, 0.0f32, 10);
+        market.order_type = OrderType::Market;
+        assert_eq!(risk.check("TSLA", &market, 0), Ok(()));
+    }
+
+    #[test]
+    fn limits_order_rate_per_second()
+    {
+        let mut risk = RiskChecker::new(RiskLimits { max_orders_per_second : Some(2), ..Default::default() });
+        assert_eq!(risk.check("TSLA", &order_for_account(1, Side::Buy, 100.0f32, 10, 7), 0), Ok(()));
+        assert_eq!(risk.check("TSLA", &order_for_account(2, Side::Buy, 100.0f32, 10, 7), 500_000_000), Ok(()));
+        assert_eq!(risk.check("TSLA", &order_for_account(3, Side::Buy, 100.0f32, 10, 7), 999_999_999), Err(RejectReason::RateLimit));
+        assert_eq!(risk.check("TSLA", &order_for_account(4, Side::Buy, 100.0f32, 10, 8), 999_999_999), Ok(()));
+        assert_eq!(risk.check("TSLA", &order_for_account(5, Side::Buy, 100.0f32, 10, 7), 1_000_000_000), Ok(()));
+    }
+
+    #[test]
+    fn checks_replacements_without_counting_them_again()
+    {
+        let mut engine = engine();
+        let mut risk = RiskChecker::new(RiskLimits { max_order_qty : Some(100), max_open_orders : Some(1), max_price_deviation : Some(0.1), ..Default::default() });
+        risk.set_reference_price("TSLA", 100.0f32);
+        submit(&mut engine, &mut risk, &mut order_for_account(1, Side::Buy, 99.0f32, 10, 7), 0).unwrap();
+        assert_eq!(risk.open_orders(7), 1);
+
+        let resting = order_for_account(1, Side::Buy, 99.0f32, 10, 7);
+        assert_eq!(risk.check_replace("TSLA", &order_for_account(1, Side::Buy, 98.0f32, 20, 7), 0), Ok(()));
+        assert_eq!(risk.check_replace("TSLA", &order_for_account(1, Side::Buy, 98.0f32, 101, 7), 0), Err(RejectReason::MaxOrderQty));
+        assert_eq!(risk.check_replace("TSLA", &order_for_account(1, Side::Buy, 80.0f32, 20, 7), 0), Err(RejectReason::PriceDeviation));
+
+        engine.amend_order("TSLA", &resting, 98.0f32, 20).unwrap();
+        for (symbol, event) in engine.drain_events()
+        {
+            risk.on_event(&symbol, &event);
+        }
+        assert_eq!(risk.open_orders(7), 1);
+    }
+
+    #[test]
+    fn positions_are_marked_to_the_quotes()
+    {
+        let mut engine = engine();
+        let mut risk = RiskChecker::with_mark_method(RiskLimits::default(), MarkMethod::Mid);
+        submit(&mut engine, &mut risk, &mut order_for_account(1, Side::Sell, 100.0f32, 10, 8), 0).unwrap();
+        submit(&mut engine, &mut risk, &mut order_for_account(2, Side::Buy, 100.0f32, 10, 7), 0).unwrap();
+        submit(&mut engine, &mut risk, &mut order_for_account(3, Side::Buy, 104.0f32, 10, 9), 0).unwrap();
+        submit(&mut engine, &mut risk, &mut order_for_account(4, Side::Sell, 106.0f32, 10, 9), 0).unwrap();
+
+        let (best_bid, best_ask) = engine.quotes("TSLA");
+        risk.update_quotes("TSLA", best_bid, best_ask);
+        assert_eq!(risk.positions().mark_price("TSLA"), Some(105.0f32));
+        assert!((risk.positions().unrealized_pnl(7, "TSLA") - 50.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,1054 @@
+use std::collections::HashMap;
+use std::mem::size_of;
+use bytes::{BufMut, BytesMut};
+use crate::data_types::{Order, OrderType, Side, Symbol, TimeInForce};
+use crate::events::Event;
+
+/// Id and version of the schema in schema/matching_engine.xml
+pub const SCHEMA_ID : u16 = 1;
+pub const SCHEMA_VERSION : u16 = 0;
+
+/// Simple Open Framing Header: message length (u32, header included) and encoding type, big endian
+pub const FRAME_HEADER_LEN : usize = 6;
+/// Encoding type of SBE 1.0 little endian in the framing header
+pub const SBE_LITTLE_ENDIAN : u16 = 0xEB50;
+/// Length of the message header: block length, template id, schema id and version
+pub const MESSAGE_HEADER_LEN : usize = 8;
+/// Longest frame accepted from a peer
+pub const MAX_FRAME_LEN : usize = 64 * 1024;
+
+pub const NEW_ORDER_SINGLE : u16 = 1;
+pub const ORDER_CANCEL_REQUEST : u16 = 2;
+pub const ORDER_CANCEL_REPLACE_REQUEST : u16 = 3;
+pub const EXECUTION_REPORT : u16 = 4;
+pub const MARKET_DATA_INCREMENTAL_REFRESH : u16 = 5;
+
+/// The prices are sent as a mantissa with a constant exponent of -4
+const PRICE_SCALE : f64 = 10_000.0;
+
+const BUY : u8 = b'1';
+const SELL : u8 = b'2';
+const MARKET : u8 = b'1';
+const LIMIT : u8 = b'2';
+const DAY : u8 = b'0';
+const GOOD_TILL_CANCEL : u8 = b'1';
+const GOOD_TILL_DATE : u8 = b'6';
+
+pub const EXEC_TYPE_NEW : u8 = b'0';
+pub const EXEC_TYPE_CANCELED : u8 = b'4';
+pub const EXEC_TYPE_REPLACED : u8 = b'5';
+pub const EXEC_TYPE_REJECTED : u8 = b'8';
+pub const EXEC_TYPE_EXPIRED : u8 = b'C';
+pub const EXEC_TYPE_TRADE : u8 = b'F';
+
+pub const ORD_STATUS_NEW : u8 = b'0';
+pub const ORD_STATUS_PARTIALLY_FILLED : u8 = b'1';
+pub const ORD_STATUS_FILLED : u8 = b'2';
+
+pub const MD_NEW : u8 = 0;
+pub const MD_CHANGE : u8 = 1;
+pub const MD_DELETE : u8 = 2;
+
+pub const MD_BID : u8 = b'0';
+pub const MD_OFFER : u8 = b'1';
+pub const MD_TRADE : u8 = b'2';
+
+pub fn encode_price(price : f32) -> i64
+{
+    (price as f64 * PRICE_SCALE).round() as i64
+}
+
+pub fn decode_price(price : i64) -> f32
+{
+    (price as f64 / PRICE_SCALE) as f32
+}
+
+fn encode_side(side : Side) -> u8
+{
+    match side
+    {
+        Side::Buy => BUY,
+        Side::Sell => SELL,
+    }
+}
+
+fn decode_side(side : u8) -> Result<Side, &'static str>
+{
+    match side
+    {
+        BUY => Ok(Side::Buy),
+        SELL => Ok(Side::Sell),
+        _ => Err("invalid side"),
+    }
+}
+
+/// Block is implemented by the layouts of the blocks of this module
+///
+/// # Safety
+///
+/// The layout must be `#[repr(C)]` and made of byte arrays, so that it has an alignment
+/// of 1 and every bit pattern is a valid value
+unsafe trait Block : Sized
+{
+    /// view reads the block at the start of the bytes without copying it
+    fn view(bytes : &[u8]) -> Result<&Self, &'static str>
+    {
+        if bytes.len() < size_of::<Self>()
+        {
+            return Err("message is truncated");
+        }
+        Ok(unsafe { &*(bytes.as_ptr() as *const Self) })
+    }
+}
+
+/// frame writes a framed message, the block is written by the closure
+///
+/// # Arguments
+///
+/// * `block_length` - The length of the root block
+/// * `template_id` - The id of the message in the schema
+/// * `buf` - The buffer the frame is appended to
+fn frame(block_length : usize, template_id : u16, buf : &mut BytesMut, write : impl FnOnce(&mut BytesMut))
+{
+    let start = buf.len();
+    // The length is known once the message is written
+    buf.put_u32(0);
+    buf.put_u16(SBE_LITTLE_ENDIAN);
+    buf.put_u16_le(block_length as u16);
+    buf.put_u16_le(template_id);
+    buf.put_u16_le(SCHEMA_ID);
+    buf.put_u16_le(SCHEMA_VERSION);
+    write(buf);
+    let len = (buf.len() - start) as u32;
+    buf[start..start + 4].copy_from_slice(&len.to_be_bytes());
+}
+
+/// frame_length returns the length of the first frame of the buffer, header included
+///
+/// # Return
+///
+/// None if the frame is not complete yet, an error if the bytes cannot be an SBE frame
+pub fn frame_length(buf : &[u8]) -> Result<Option<usize>, &'static str>
+{
+    if buf.len() < FRAME_HEADER_LEN
+    {
+        return Ok(None);
+    }
+    let len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+    if u16::from_be_bytes([buf[4], buf[5]]) != SBE_LITTLE_ENDIAN
+    {
+        return Err("frame is not encoded in SBE little endian");
+    }
+    if len < FRAME_HEADER_LEN + MESSAGE_HEADER_LEN
+    {
+        return Err("frame is too short for a message");
+    }
+    if len > MAX_FRAME_LEN
+    {
+        return Err("frame is too long");
+    }
+    Ok(if buf.len() < len { None } else { Some(len) })
+}
+
+/// Message is a decoded message, its blocks are read in place from the frame
+#[derive(Debug)]
+pub enum Message<'a>
+{
+    NewOrderSingle(&'a NewOrderSingle),
+    OrderCancelRequest(&'a OrderCancelRequest),
+    OrderCancelReplaceRequest(&'a OrderCancelReplaceRequest),
+    /// The report and its text
+    ExecutionReport(&'a ExecutionReport, &'a str),
+    MarketDataIncrementalRefresh(&'a MarketDataIncrementalRefresh, MdEntries<'a>),
+}
+
+/// decode reads the message of a complete frame. A block longer than known by this
+/// version of the schema is accepted, the fields appended by the later versions are skipped
+///
+/// # Arguments
+///
+/// * `frame` - A frame, as delimited by frame_length
+pub fn decode(frame : &[u8]) -> Result<Message<'_>, &'static str>
+{
+    if frame.len() < FRAME_HEADER_LEN + MESSAGE_HEADER_LEN
+    {
+        return Err("message is truncated");
+    }
+    let header = &frame[FRAME_HEADER_LEN..FRAME_HEADER_LEN + MESSAGE_HEADER_LEN];
+    let field = |index : usize| u16::from_le_bytes([header[2 * index], header[2 * index + 1]]);
+    let (block_length, template_id, schema_id) = (field(0) as usize, field(1), field(2));
+    if schema_id != SCHEMA_ID
+    {
+        return Err("unknown schema");
+    }
+    let body = &frame[FRAME_HEADER_LEN + MESSAGE_HEADER_LEN..];
+    if body.len() < block_length
+    {
+        return Err("message is truncated");
+    }
+    let (root, rest) = body.split_at(block_length);
+    match template_id
+    {
+        NEW_ORDER_SINGLE => Ok(Message::NewOrderSingle(Block::view(root)?)),
+        ORDER_CANCEL_REQUEST => Ok(Message::OrderCancelRequest(Block::view(root)?)),
+        ORDER_CANCEL_REPLACE_REQUEST => Ok(Message::OrderCancelReplaceRequest(Block::view(root)?)),
+        EXECUTION_REPORT =>
+        {
+            if rest.len() < 2
+            {
+                return Err("message is truncated");
+            }
+            let len = u16::from_le_bytes([rest[0], rest[1]]) as usize;
+            let text = rest.get(2..2 + len).ok_or("message is truncated")?;
+            Ok(Message::ExecutionReport(Block::view(root)?, std::str::from_utf8(text).map_err(|_| "text is not UTF-8")?))
+        },
+        MARKET_DATA_INCREMENTAL_REFRESH => Ok(Message::MarketDataIncrementalRefresh(Block::view(root)?, MdEntries::view(rest)?)),
+        _ => Err("unknown template"),
+    }
+}
+
+/// NewOrderSingle enters an order, its id is chosen by the client and unique on the session
+#[repr(C)]
+#[derive(Debug)]
+pub struct NewOrderSingle
+{
+    price : [u8; 8],
+    expire_time : [u8; 8],
+    order_id : [u8; 4],
+    account : [u8; 4],
+    order_qty : [u8; 4],
+    symbol : [u8; 8],
+    side : u8,
+    ord_type : u8,
+    time_in_force : u8,
+}
+
+const _ : () = assert!(size_of::<NewOrderSingle>() == 39 && std::mem::align_of::<NewOrderSingle>() == 1);
+
+unsafe impl Block for NewOrderSingle {}
+
+impl NewOrderSingle
+{
+    /// encode writes a framed message entering the order on the symbol
+    pub fn encode(order : &Order, symbol : &str, buf : &mut BytesMut)
+    {
+        frame(size_of::<NewOrderSingle>(), NEW_ORDER_SINGLE, buf, |buf|
+        {
+            let (time_in_force, expire_time) = match order.time_in_force
+            {
+                TimeInForce::Day => (DAY, 0),
+                TimeInForce::GoodTillCancel => (GOOD_TILL_CANCEL, 0),
+                TimeInForce::GoodTillDate(time) => (GOOD_TILL_DATE, time),
+            };
+            buf.put_i64_le(encode_price(order.price));
+            buf.put_u64_le(expire_time);
+            buf.put_u32_le(order.id);
+            buf.put_u32_le(order.account);
+            buf.put_u32_le(order.qty);
+            buf.put_slice(Symbol::new(symbol).as_bytes());
+            buf.put_u8(encode_side(order.side));
+            buf.put_u8(match order.order_type { OrderType::Market => MARKET, OrderType::Limit => LIMIT });
+            buf.put_u8(time_in_force);
+        });
+    }
+
+    pub fn order_id(&self) -> u32
+    {
+        u32::from_le_bytes(self.order_id)
+    }
+
+    pub fn symbol(&self) -> Symbol
+    {
+        Symbol::from_bytes(self.symbol)
+    }
+
+    /// to_order decodes the message into a new order
+    pub fn to_order(&self) -> Result<Order, &'static str>
+    {
+        let mut order = Order::new(self.order_id(), decode_side(self.side)?, decode_price(i64::from_le_bytes(self.price)),
+                                   u32::from_le_bytes(self.order_qty));
+        order.account = u32::from_le_bytes(self.account);
+        order.order_type = match self.ord_type
+        {
+            MARKET => OrderType::Market,
+            LIMIT => OrderType::Limit,
+            _ => return Err("invalid order type"),
+        };
+        order.time_in_force = match self.time_in_force
+        {
+            DAY => TimeInForce::Day,
+            GOOD_TILL_CANCEL => TimeInForce::GoodTillCancel,
+            GOOD_TILL_DATE => TimeInForce::GoodTillDate(u64::from_le_bytes(self.expire_time)),
+            _ => return Err("invalid time in force"),
+        };
+        Ok(order)
+    }
+}
+
+/// OrderCancelRequest cancels an order of the session
+#[repr(C)]
+#[derive(Debug)]
+pub struct OrderCancelRequest
+{
+    order_id : [u8; 4],
+    symbol : [u8; 8],
+}
+
+const _ : () = assert!(size_of::<OrderCancelRequest>() == 12 && std::mem::align_of::<OrderCancelRequest>() == 1);
+
+unsafe impl Block for OrderCancelRequest {}
+
+impl OrderCancelRequest
+{
+    pub fn encode(order_id : u32, symbol : &str, buf : &mut BytesMut)
+    {
+        frame(size_of::<OrderCancelRequest>(), ORDER_CANCEL_REQUEST, buf, |buf|
+        {
+            buf.put_u32_le(order_id);
+            buf.put_slice(Symbol::new(symbol).as_bytes());
+        });
+    }
+
+    pub fn order_id(&self) -> u32
+    {
+        u32::from_le_bytes(self.order_id)
+    }
+
+    pub fn symbol(&self) -> Symbol
+    {
+        Symbol::from_bytes(self.symbol)
+    }
+}
+
+/// OrderCancelReplaceRequest changes price and quantity of an order of the session, the order
+/// loses its time priority unless the quantity decreases at the same price
+#[repr(C)]
+#[derive(Debug)]
+pub struct OrderCancelReplaceRequest
+{
+    price : [u8; 8],
+    order_id : [u8; 4],
+    order_qty : [u8; 4],
+    symbol : [u8; 8],
+}
+
+const _ : () = assert!(size_of::<OrderCancelReplaceRequest>() == 24 && std::mem::align_of::<OrderCancelReplaceRequest>() == 1);
+
+unsafe impl Block for OrderCancelReplaceRequest {}
+
+impl OrderCancelReplaceRequest
+{
+    /// encode writes a framed request
+    ///
+    /// # Arguments
+    ///
+    /// * `order_id` - The order to be replaced
+    /// * `symbol` - The symbol of the order
+    /// * `price` - The new limit price
+    /// * `qty` - The new quantity left to be executed
+    /// * `buf` - The buffer the frame is appended to
+    pub fn encode(order_id : u32, symbol : &str, price : f32, qty : u32, buf : &mut BytesMut)
+    {
+        frame(size_of::<OrderCancelReplaceRequest>(), ORDER_CANCEL_REPLACE_REQUEST, buf, |buf|
+        {
+            buf.put_i64_le(encode_price(price));
+            buf.put_u32_le(order_id);
+            buf.put_u32_le(qty);
+            buf.put_slice(Symbol::new(symbol).as_bytes());
+        });
+    }
+
+    pub fn order_id(&self) -> u32
+    {
+        u32::from_le_bytes(self.order_id)
+    }
+
+    pub fn symbol(&self) -> Symbol
+    {
+        Symbol::from_bytes(self.symbol)
+    }
+
+    pub fn price(&self) -> f32
+    {
+        decode_price(i64::from_le_bytes(self.price))
+    }
+
+    pub fn order_qty(&self) -> u32
+    {
+        u32::from_le_bytes(self.order_qty)
+    }
+}
+
+/// Execution holds the fields of an execution report
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Execution
+{
+    /// Time of the execution, in nanoseconds from the engine clock
+    pub transact_time : u64,
+    pub exec_id : u64,
+    pub order_id : u32,
+    pub symbol : Symbol,
+    pub side : Side,
+    pub exec_type : u8,
+    pub ord_status : u8,
+    /// Limit price of the order
+    pub price : f32,
+    pub leaves_qty : u32,
+    pub cum_qty : u32,
+    /// Price and quantity of the fill, zero unless the report is a trade
+    pub last_px : f32,
+    pub last_qty : u32,
+}
+
+/// ExecutionReport reports a change of the state of an order to its session
+#[repr(C)]
+#[derive(Debug)]
+pub struct ExecutionReport
+{
+    transact_time : [u8; 8],
+    exec_id : [u8; 8],
+    price : [u8; 8],
+    last_px : [u8; 8],
+    order_id : [u8; 4],
+    leaves_qty : [u8; 4],
+    cum_qty : [u8; 4],
+    last_qty : [u8; 4],
+    symbol : [u8; 8],
+    side : u8,
+    exec_type : u8,
+    ord_status : u8,
+}
+
+const _ : () = assert!(size_of::<ExecutionReport>() == 59 && std::mem::align_of::<ExecutionReport>() == 1);
+
+unsafe impl Block for ExecutionReport {}
+
+impl ExecutionReport
+{
+    /// encode writes a framed report followed by its text, the reason of a reject
+    pub fn encode(execution : &Execution, text : &str, buf : &mut BytesMut)
+    {
+        frame(size_of::<ExecutionReport>(), EXECUTION_REPORT, buf, |buf|
+        {
+            buf.put_u64_le(execution.transact_time);
+            buf.put_u64_le(execution.exec_id);
+            buf.put_i64_le(encode_price(execution.price));
+            buf.put_i64_le(encode_price(execution.last_px));
+            buf.put_u32_le(execution.order_id);
+            buf.put_u32_le(execution.leaves_qty);
+            buf.put_u32_le(execution.cum_qty);
+            buf.put_u32_le(execution.last_qty);
+            buf.put_slice(execution.symbol.as_bytes());
+            buf.put_u8(encode_side(execution.side));
+            buf.put_u8(execution.exec_type);
+            buf.put_u8(execution.ord_status);
+            let text = &text.as_bytes()[..text.len().min(u16::MAX as usize)];
+            buf.put_u16_le(text.len() as u16);
+            buf.put_slice(text);
+        });
+    }
+
+    pub fn to_execution(&self) -> Result<Execution, &'static str>
+    {
+        Ok(Execution {
+            transact_time : u64::from_le_bytes(self.transact_time),
+            exec_id : u64::from_le_bytes(self.exec_id),
+            order_id : u32::from_le_bytes(self.order_id),
+            symbol : Symbol::from_bytes(self.symbol),
+            side : decode_side(self.side)?,
+            exec_type : self.exec_type,
+            ord_status : self.ord_status,
+            price : decode_price(i64::from_le_bytes(self.price)),
+            leaves_qty : u32::from_le_bytes(self.leaves_qty),
+            cum_qty : u32::from_le_bytes(self.cum_qty),
+            last_px : decode_price(i64::from_le_bytes(self.last_px)),
+            last_qty : u32::from_le_bytes(self.last_qty) })
+    }
+}
+
+/// BookUpdate is an entry of a market data incremental refresh
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct BookUpdate
+{
+    /// MD_NEW, MD_CHANGE or MD_DELETE
+    pub action : u8,
+    /// MD_BID, MD_OFFER or MD_TRADE, a trade reduces the passive order by its quantity
+    pub entry_type : u8,
+    pub order_id : u32,
+    pub price : f32,
+    /// Resting quantity of the order, or traded quantity
+    pub qty : u32,
+}
+
+/// book_updates converts the events generated by one command into order by order book
+/// updates. As on the ITCH feed, a replaced order which is re-inserted is changed once its
+/// resting quantity is known, and deleted when it is fully executed on re-insertion
+pub fn book_updates<'a>(events : impl IntoIterator<Item = &'a Event>) -> Vec<BookUpdate>
+{
+    let entry_type = |side : Side| if side == Side::Buy { MD_BID } else { MD_OFFER };
+    let update = |action : u8, order : &Order| BookUpdate {
+        action, entry_type : entry_type(order.side), order_id : order.id, price : order.price, qty : order.qty };
+
+    let mut updates = vec![];
+    let mut pending_replaces = vec![];
+    for event in events
+    {
+        match event
+        {
+            Event::Added(order) => match pending_replaces.iter().position(|replaced : &Order| replaced.id == order.id)
+            {
+                Some(position) =>
+                {
+                    pending_replaces.swap_remove(position);
+                    updates.push(update(MD_CHANGE, order));
+                },
+                None => updates.push(update(MD_NEW, order)),
+            },
+            Event::Traded(trade) => updates.push(BookUpdate {
+                action : MD_NEW,
+                entry_type : MD_TRADE,
+                order_id : trade.passive_id,
+                price : trade.price,
+                qty : trade.qty }),
+            Event::Replaced { old, new } if old.price == new.price && new.qty < old.qty => updates.push(update(MD_CHANGE, new)),
+            Event::Replaced { old, .. } => pending_replaces.push(*old),
+            Event::Cancelled(order) | Event::Expired(order) => updates.push(update(MD_DELETE, order)),
+            Event::MassCancelled { .. } => {},
+        }
+    }
+    updates.extend(pending_replaces.iter().map(|order| update(MD_DELETE, order)));
+    updates
+}
+
+/// MarketDataIncrementalRefresh carries the book updates of a symbol caused by one command
+#[repr(C)]
+#[derive(Debug)]
+pub struct MarketDataIncrementalRefresh
+{
+    transact_time : [u8; 8],
+    symbol : [u8; 8],
+}
+
+const _ : () = assert!(size_of::<MarketDataIncrementalRefresh>() == 16 && std::mem::align_of::<MarketDataIncrementalRefresh>() == 1);
+
+unsafe impl Block for MarketDataIncrementalRefresh {}
+
+impl MarketDataIncrementalRefresh
+{
+    /// encode writes a framed message with the updates in the mdEntries group
+    ///
+    /// # Arguments
+    ///
+    /// * `transact_time` - Nanoseconds from the engine clock
+    /// * `symbol` - The symbol of the book
+    /// * `updates` - The updates, at most u16::MAX
+    /// * `buf` - The buffer the frame is appended to
+    pub fn encode(transact_time : u64, symbol : &str, updates : &[BookUpdate], buf : &mut BytesMut)
+    {
+        frame(size_of::<MarketDataIncrementalRefresh>(), MARKET_DATA_INCREMENTAL_REFRESH, buf, |buf|
+        {
+            buf.put_u64_le(transact_time);
+            buf.put_slice(Symbol::new(symbol).as_bytes());
+            let updates = &updates[..updates.len().min(u16::MAX as usize)];
+            buf.put_u16_le(size_of::<MdEntry>() as u16);
+            buf.put_u16_le(updates.len() as u16);
+            for update in updates
+            {
+                buf.put_i64_le(encode_price(update.price));
+                buf.put_u32_le(update.order_id);
+                buf.put_u32_le(update.qty);
+                buf.put_u8(update.action);
+                buf.put_u8(update.entry_type);
+            }
+        });
+    }
+
+    pub fn transact_time(&self) -> u64
+    {
+        u64::from_le_bytes(self.transact_time)
+    }
+
+    pub fn symbol(&self) -> Symbol
+    {
+        Symbol::from_bytes(self.symbol)
+    }
+}
+
+/// MdEntry is an entry of the mdEntries group
+#[repr(C)]
+#[derive(Debug)]
+pub struct MdEntry
+{
+    price : [u8; 8],
+    order_id : [u8; 4],
+    size : [u8; 4],
+    update_action : u8,
+    entry_type : u8,
+}
+
+const _ : () = assert!(size_of::<MdEntry>() == 18 && std::mem::align_of::<MdEntry>() == 1);
+
+unsafe impl Block for MdEntry {}
+
+impl MdEntry
+{
+    pub fn to_update(&self) -> BookUpdate
+    {
+        BookUpdate {
+            action : self.update_action,
+            entry_type : self.entry_type,
+            order_id : u32::from_le_bytes(self.order_id),
+            price : decode_price(i64::from_le_bytes(self.price)),
+            qty : u32::from_le_bytes(self.size) }
+    }
+}
+
+/// MdEntries iterates over the entries of the mdEntries group, read in place
+/// * _entries holds the entries
+/// * _block_length is the length of an entry, it may be longer than an MdEntry
+#[derive(Clone, Debug)]
+pub struct MdEntries<'a>
+{
+    _entries : &'a [u8],
+    _block_length : usize,
+}
+
+impl<'a> MdEntries<'a>
+{
+    /// view reads the group header and delimits the entries
+    fn view(bytes : &'a [u8]) -> Result<MdEntries<'a>, &'static str>
+    {
+        if bytes.len() < 4
+        {
+            return Err("message is truncated");
+        }
+        let block_length = u16::from_le_bytes([bytes[0], bytes[1]]) as usize;
+        let count = u16::from_le_bytes([bytes[2], bytes[3]]) as usize;
+        if block_length < size_of::<MdEntry>()
+        {
+            return Err("group entries are too short");
+        }
+        let entries = bytes.get(4..4 + block_length * count).ok_or("message is truncated")?;
+        Ok(MdEntries { _entries : entries, _block_length : block_length })
+    }
+}
+
+impl<'a> Iterator for MdEntries<'a>
+{
+    type Item = &'a MdEntry;
+
+    fn next(&mut self) -> Option<&'a MdEntry>
+    {
+        if self._entries.is_empty()
+        {
+            return None;
+        }
+        let (entry, rest) = self._entries.split_at(self._block_length);
+        self._entries = rest;
+        MdEntry::view(entry).ok()
+    }
+}
+
+/// SessionOrder is a live order of a session
+#[derive(Copy, Clone, Debug)]
+struct SessionOrder
+{
+    symbol : Symbol,
+    /// The order as it rests in the book, qty is the leaves quantity
+    order : Order,
+    cum_qty : u32,
+}
+
+/// Session tracks the live orders entered on an SBE connection and writes their execution reports
+/// * _orders holds the live orders by id
+/// * _next_exec_id is the id of the next execution report
+#[derive(Debug, Default)]
+pub struct Session
+{
+    _orders : HashMap<u32, SessionOrder>,
+    _next_exec_id : u64,
+}
+
+impl Session
+{
+    pub fn new() -> Session
+    {
+        Session { _next_exec_id : 1, ..Default::default() }
+    }
+
+    /// order returns the live order of the session with the id, as it rests in the book
+    pub fn order(&self, order_id : u32) -> Option<&Order>
+    {
+        self._orders.get(&order_id).map(|state| &state.order)
+    }
+
+    fn next_exec_id(&mut self) -> u64
+    {
+        let exec_id = self._next_exec_id;
+        self._next_exec_id += 1;
+        exec_id
+    }
+
+    /// report writes an execution report with the current state of a live order
+    fn report(&mut self, order_id : u32, exec_type : u8, last : (f32, u32), transact_time : u64, buf : &mut BytesMut)
+    {
+        let exec_id = self.next_exec_id();
+        let state = &self._orders[&order_id];
+        let done = matches!(exec_type, EXEC_TYPE_CANCELED | EXEC_TYPE_EXPIRED);
+        let ord_status = match exec_type
+        {
+            _ if done => exec_type,
+            _ if state.order.qty == 0 => ORD_STATUS_FILLED,
+            _ if state.cum_qty > 0 => ORD_STATUS_PARTIALLY_FILLED,
+            _ => ORD_STATUS_NEW,
+        };
+        let execution = Execution {
+            transact_time,
+            exec_id,
+            order_id,
+            symbol : state.symbol,
+            side : state.order.side,
+            exec_type,
+            ord_status,
+            price : state.order.price,
+            leaves_qty : if done { 0 } else { state.order.qty },
+            cum_qty : state.cum_qty,
+            last_px : last.0,
+            last_qty : last.1 };
+        ExecutionReport::encode(&execution, "", buf);
+    }
+
+    /// reject writes the report of a request which did not reach the book
+    ///
+    /// # Arguments
+    ///
+    /// * `order_id` - The order of the request
+    /// * `symbol` - The symbol of the request
+    /// * `side` - The side of the order, when known
+    /// * `reason` - The text of the report
+    /// * `transact_time` - Nanoseconds from the engine clock
+    /// * `buf` - The buffer the report is appended to
+    pub fn reject(&mut self, order_id : u32, symbol : Symbol, side : Side, reason : &str, transact_time : u64, buf : &mut BytesMut)
+    {
+        let execution = Execution {
+            transact_time,
+            exec_id : self.next_exec_id(),
+            order_id,
+            symbol,
+            side,
+            exec_type : EXEC_TYPE_REJECTED,
+            ord_status : EXEC_TYPE_REJECTED,
+            price : 0.0,
+            leaves_qty : 0,
+            cum_qty : 0,
+            last_px : 0.0,
+            last_qty : 0 };
+        ExecutionReport::encode(&execution, reason, buf);
+    }
+
+    /// accept tracks a new order and acknowledges it, the order must be accepted before
+    /// the events of its insertion are reported
+    ///
+    /// # Arguments
+    ///
+    /// * `symbol` - The symbol of the order
+    /// * `order` - The order as entered, before it is matched
+    /// * `transact_time` - Nanoseconds from the engine clock
+    /// * `buf` - The buffer the report is appended to
+    pub fn accept(&mut self, symbol : Symbol, order : &Order, transact_time : u64, buf : &mut BytesMut)
+    {
+        self._orders.insert(order.id, SessionOrder { symbol, order : *order, cum_qty : 0 });
+        self.report(order.id, EXEC_TYPE_NEW, (0.0, 0), transact_time, buf);
+    }
+
+    /// complete ends the insertion of an order once its events are reported, the unfilled
+    /// quantity of a market order is cancelled as it is not rested in the book
+    pub fn complete(&mut self, order : &Order, transact_time : u64, buf : &mut BytesMut)
+    {
+        if order.order_type == OrderType::Market && self._orders.contains_key(&order.id)
+        {
+            self.report(order.id, EXEC_TYPE_CANCELED, (0.0, 0), transact_time, buf);
+            self._orders.remove(&order.id);
+        }
+    }
+
+    /// on_event reports the event to the owners of the orders it changes, the orders
+    /// of other sessions are ignored
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - An event of the engine
+    /// * `transact_time` - Nanoseconds from the engine clock
+    /// * `buf` - The buffer the reports are appended to
+    pub fn on_event(&mut self, event : &Event, transact_time : u64, buf : &mut BytesMut)
+    {
+        match *event
+        {
+            Event::Traded(trade) =>
+            {
+                for order_id in [trade.aggressive_id, trade.passive_id]
+                {
+                    let state = match self._orders.get_mut(&order_id)
+                    {
+                        Some(state) => state,
+                        None => continue,
+                    };
+                    state.order.qty -= trade.qty;
+                    state.cum_qty += trade.qty;
+                    self.report(order_id, EXEC_TYPE_TRADE, (trade.price, trade.qty), trade.timestamp, buf);
+                    if self._orders[&order_id].order.qty == 0
+                    {
+                        self._orders.remove(&order_id);
+                    }
+                }
+            },
+            Event::Replaced { new, .. } =>
+            {
+                if let Some(state) = self._orders.get_mut(&new.id)
+                {
+                    state.order = new;
+                    self.report(new.id, EXEC_TYPE_REPLACED, (0.0, 0), transact_time, buf);
+                }
+            },
+            Event::Cancelled(order) | Event::Expired(order) =>
+            {
+                let exec_type = if matches!(event, Event::Cancelled(_)) { EXEC_TYPE_CANCELED } else { EXEC_TYPE_EXPIRED };
+                if self._orders.contains_key(&order.id)
+                {
+                    self.report(order.id, exec_type, (0.0, 0), transact_time, buf);
+                    self._orders.remove(&order.id);
+                }
+            },
+            Event::Added(_) | Event::MassCancelled { .. } => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::sync::Arc;
+    use crate::clock::ManualClock;
+    use crate::engine::Engine;
+    use super::*;
+
+    const NOW : u64 = 34_200_000_000_000;
+
+    /// frames splits the buffer into its frames
+    fn frames(buf : &[u8]) -> Vec<&[u8]>
+    {
+        let mut frames = vec![];
+        let mut rest = buf;
+        while let Some(len) = frame_length(rest).unwrap()
+        {
+            frames.push(&rest[..len]);
+            rest = &rest[len..];
+        }
+        assert!(rest.is_empty());
+        frames
+    }
+
+    /// executions decodes the execution reports of the buffer with their text
+    fn executions(buf : &[u8]) -> Vec<(Execution, String)>
+    {
+        frames(buf).into_iter().map(|frame| match decode(frame).unwrap()
+        {
+            Message::ExecutionReport(report, text) => (report.to_execution().unwrap(), text.to_string()),
+            message => panic!("unexpected message {:?}", message),
+        }).collect()
+    }
+
+    #[test]
+    fn order_entry_messages_are_read_in_place()
+    {
+        let mut order = Order::new(7, Side::Sell, 122.25f32, 300);
+        order.account = 42;
+        order.time_in_force = TimeInForce::GoodTillDate(NOW + 1000);
+        let mut buf = BytesMut::new();
+        NewOrderSingle::encode(&order, "TSLA", &mut buf);
+        assert_eq!(buf.len(), FRAME_HEADER_LEN + MESSAGE_HEADER_LEN + 39);
+        assert_eq!(frame_length(&buf), Ok(Some(buf.len())));
+        match decode(&buf).unwrap()
+        {
+            Message::NewOrderSingle(message) =>
+            {
+                assert_eq!(message.to_order(), Ok(order));
+                assert_eq!(message.symbol().as_str(), "TSLA");
+                assert_eq!(message as *const NewOrderSingle as *const u8, buf[FRAME_HEADER_LEN + MESSAGE_HEADER_LEN..].as_ptr());
+            },
+            message => panic!("unexpected message {:?}", message),
+        }
+
+        let mut buf = BytesMut::new();
+        OrderCancelRequest::encode(7, "TSLA", &mut buf);
+        OrderCancelReplaceRequest::encode(7, "TSLA", 122.5f32, 200, &mut buf);
+        let frames = frames(&buf);
+        assert!(matches!(decode(frames[0]), Ok(Message::OrderCancelRequest(cancel)) if cancel.order_id() == 7 && cancel.symbol().as_str() == "TSLA"));
+        match decode(frames[1]).unwrap()
+        {
+            Message::OrderCancelReplaceRequest(replace) =>
+                assert_eq!((replace.order_id(), replace.price(), replace.order_qty(), replace.symbol().as_str()), (7, 122.5, 200, "TSLA")),
+            message => panic!("unexpected message {:?}", message),
+        }
+    }
+
+    #[test]
+    fn frames_are_delimited_and_checked()
+    {
+        let mut buf = BytesMut::new();
+        OrderCancelRequest::encode(7, "TSLA", &mut buf);
+        for len in 0..buf.len()
+        {
+            assert_eq!(frame_length(&buf[..len]), Ok(None), "{} bytes", len);
+        }
+
+        let mut invalid = buf.to_vec();
+        invalid[4] = 0x5B;
+        assert!(frame_length(&invalid).is_err());
+        let mut invalid = buf.to_vec();
+        invalid[FRAME_HEADER_LEN + 4] = 2;
+        assert_eq!(decode(&invalid).unwrap_err(), "unknown schema");
+        let mut invalid = buf.to_vec();
+        invalid[FRAME_HEADER_LEN + 2] = 9;
+        assert_eq!(decode(&invalid).unwrap_err(), "unknown template");
+        let mut invalid = buf.to_vec();
+        invalid[FRAME_HEADER_LEN] = 4;
+        assert_eq!(decode(&invalid).unwrap_err(), "message is truncated");
+        assert!(frame_length(&(MAX_FRAME_LEN as u32 + 1).to_be_bytes().iter().chain(&buf[4..]).copied().collect::<Vec<u8>>()).is_err());
+    }
+
+    #[test]
+    fn blocks_extended_by_a_later_version_are_decoded()
+    {
+        // A later version appends a field to the root block and to the group entries
+        let mut buf = BytesMut::new();
+        OrderCancelRequest::encode(7, "TSLA", &mut buf);
+        buf.put_u32_le(0xdead);
+        buf[FRAME_HEADER_LEN] += 4;
+        let len = buf.len() as u32;
+        buf[..4].copy_from_slice(&len.to_be_bytes());
+        assert!(matches!(decode(&buf), Ok(Message::OrderCancelRequest(cancel)) if cancel.order_id() == 7));
+
+        let updates = [BookUpdate { action : MD_NEW, entry_type : MD_BID, order_id : 1, price : 122.5, qty : 10 },
+                       BookUpdate { action : MD_DELETE, entry_type : MD_OFFER, order_id : 2, price : 122.6, qty : 20 }];
+        let mut buf = BytesMut::new();
+        MarketDataIncrementalRefresh::encode(NOW, "TSLA", &updates, &mut buf);
+        let group = FRAME_HEADER_LEN + MESSAGE_HEADER_LEN + 16;
+        let mut extended = buf[..group].to_vec();
+        extended.extend_from_slice(&(size_of::<MdEntry>() as u16 + 2).to_le_bytes());
+        extended.extend_from_slice(&2u16.to_le_bytes());
+        for entry in buf[group + 4..].chunks(size_of::<MdEntry>())
+        {
+            extended.extend_from_slice(entry);
+            extended.extend_from_slice(&[0xff, 0xff]);
+        }
+        let len = extended.len() as u32;
+        extended[..4].copy_from_slice(&len.to_be_bytes());
+        for frame in [&buf[..], &extended[..]]
+        {
+            match decode(frame).unwrap()
+            {
+                Message::MarketDataIncrementalRefresh(message, entries) =>
+                {
+                    assert_eq!((message.transact_time(), message.symbol().as_str()), (NOW, "TSLA"));
+                    assert_eq!(entries.map(|entry| entry.to_update()).collect::<Vec<BookUpdate>>(), updates);
+                },
+                message => panic!("unexpected message {:?}", message),
+            }
+        }
+    }
+
+    #[test]
+    fn book_updates_follow_the_events()
+    {
+        let mut engine = Engine::with_clock(Arc::new(ManualClock::new(NOW)));
+        engine.add_symbol("TSLA");
+        let mut sell = Order::new(1, Side::Sell, 122.5f32, 10);
+        let mut other = Order::new(2, Side::Sell, 122.6f32, 10);
+        let mut buy = Order::new(3, Side::Buy, 122.5f32, 15);
+        engine.insert_order("TSLA", &mut sell).unwrap();
+        engine.insert_order("TSLA", &mut other).unwrap();
+        engine.insert_order("TSLA", &mut buy).unwrap();
+        let events : Vec<Event> = engine.drain_events().into_iter().map(|(_, event)| event).collect();
+        assert_eq!(book_updates(&events), vec![
+            BookUpdate { action : MD_NEW, entry_type : MD_OFFER, order_id : 1, price : 122.5, qty : 10 },
+            BookUpdate { action : MD_NEW, entry_type : MD_OFFER, order_id : 2, price : 122.6, qty : 10 },
+            BookUpdate { action : MD_NEW, entry_type : MD_TRADE, order_id : 1, price : 122.5, qty : 10 },
+            BookUpdate { action : MD_NEW, entry_type : MD_BID, order_id : 3, price : 122.5, qty : 5 }]);
+
+        // A new price re-inserts the order, here it is fully executed and never rests again
+        engine.amend_order("TSLA", &buy, 122.6f32, 5).unwrap();
+        let events : Vec<Event> = engine.drain_events().into_iter().map(|(_, event)| event).collect();
+        assert_eq!(book_updates(&events), vec![
+            BookUpdate { action : MD_NEW, entry_type : MD_TRADE, order_id : 2, price : 122.6, qty : 5 },
+            BookUpdate { action : MD_DELETE, entry_type : MD_BID, order_id : 3, price : 122.5, qty : 5 }]);
+
+        engine.amend_order("TSLA", &Order { qty : 5, ..other }, 122.6f32, 2).unwrap();
+        engine.cancel_order("TSLA", &other).unwrap();
+        let events : Vec<Event> = engine.drain_events().into_iter().map(|(_, event)| event).collect();
+        assert_eq!(book_updates(&events), vec![
+            BookUpdate { action : MD_CHANGE, entry_type : MD_OFFER, order_id : 2, price : 122.6, qty : 2 },
+            BookUpdate { action : MD_DELETE, entry_type : MD_OFFER, order_id : 2, price : 122.6, qty : 2 }]);
+    }
+
+    #[test]
+    fn session_reports_the_life_of_its_orders()
+    {
+        let mut engine = Engine::with_clock(Arc::new(ManualClock::new(NOW)));
+        engine.add_symbol("TSLA");
+        let symbol = Symbol::new("TSLA");
+        let mut session = Session::new();
+        let mut buf = BytesMut::new();
+
+        // Only the orders of the session are reported
+        let mut resting = Order::new(1, Side::Sell, 122.5f32, 10);
+        engine.insert_order("TSLA", &mut resting).unwrap();
+        engine.drain_events();
+
+        let entered = Order::new(2, Side::Buy, 122.5f32, 30);
+        let mut order = entered;
+        session.accept(symbol, &entered, NOW, &mut buf);
+        engine.insert_order("TSLA", &mut order).unwrap();
+        for (_, event) in engine.drain_events()
+        {
+            session.on_event(&event, NOW, &mut buf);
+        }
+        session.complete(&order, NOW, &mut buf);
+        let reports = executions(&buf);
+        assert_eq!(reports.len(), 2);
+        let (ack, fill) = (reports[0].0, reports[1].0);
+        assert_eq!((ack.exec_type, ack.ord_status, ack.order_id, ack.leaves_qty, ack.exec_id), (EXEC_TYPE_NEW, ORD_STATUS_NEW, 2, 30, 1));
+        assert_eq!(ack.symbol, symbol);
+        assert_eq!((fill.exec_type, fill.ord_status, fill.leaves_qty, fill.cum_qty), (EXEC_TYPE_TRADE, ORD_STATUS_PARTIALLY_FILLED, 20, 10));
+        assert_eq!((fill.last_px, fill.last_qty, fill.exec_id), (122.5, 10, 2));
+        assert_eq!(session.order(2).unwrap().qty, 20);
+
+        buf.clear();
+        let order = *session.order(2).unwrap();
+        engine.amend_order("TSLA", &order, 122.4f32, 15).unwrap();
+        engine.cancel_order("TSLA", &Order { price : 122.4, ..order }).unwrap();
+        for (_, event) in engine.drain_events()
+        {
+            session.on_event(&event, NOW, &mut buf);
+        }
+        let reports = executions(&buf);
+        assert_eq!(reports.len(), 2);
+        assert_eq!((reports[0].0.exec_type, reports[0].0.price, reports[0].0.leaves_qty), (EXEC_TYPE_REPLACED, 122.4, 15));
+        assert_eq!((reports[1].0.exec_type, reports[1].0.ord_status, reports[1].0.leaves_qty, reports[1].0.cum_qty),
+                   (EXEC_TYPE_CANCELED, EXEC_TYPE_CANCELED, 0, 10));
+        assert!(session.order(2).is_none());
+
+        // The unfilled quantity of a market order is cancelled
+        buf.clear();
+        let mut market = Order::new(3, Side::Sell, 0.0f32, 5);
+        market.order_type = OrderType::Market;
+        session.accept(symbol, &market, NOW, &mut buf);
+        engine.insert_order("TSLA", &mut market).unwrap();
+        session.complete(&market, NOW, &mut buf);
+        session.reject(4, symbol, Side::Buy, "Unknown symbol", NOW, &mut buf);
+        let reports = executions(&buf);
+        assert_eq!(reports.iter().map(|(report, _)| report.exec_type).collect::<Vec<u8>>(), vec![EXEC_TYPE_NEW, EXEC_TYPE_CANCELED, EXEC_TYPE_REJECTED]);
+        assert_eq!((reports[2].0.order_id, reports[2].1.as_str()), (4, "Unknown symbol"));
+        assert!(session.order(3).is_none());
+    }
+}
@@ -0,0 +1,618 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use crate::admin::AdminCommand;
+use crate::clock::{Clock, Sequencer};
+use crate::data_types::*;
+use crate::engine::Engine;
+use crate::events::Event;
+use crate::spsc::{self, Consumer, Producer, WaitStrategy};
+use tracing::{info, warn};
+
+/// Time a matching thread waits for room in its full output queue before checking
+/// whether the engine is being stopped
+const STOP_CHECK_INTERVAL : Duration = Duration::from_millis(10);
+
+/// ShardConfig describes how the symbols are partitioned across the matching threads
+/// * groups holds the symbols traded by each thread, a symbol belongs to a single group
+/// * cores holds the core each thread is pinned to, by group, the threads are not
+///   pinned when it is empty
+/// * queue_capacity is the number of commands, and of outputs, queued for each thread
+/// * wait_strategy tells how the threads wait for commands, busy spinning is only worth it
+///   on pinned cores
+#[derive(Clone, Debug)]
+pub struct ShardConfig
+{
+    pub groups : Vec<Vec<String>>,
+    pub cores : Vec<usize>,
+    pub queue_capacity : usize,
+    pub wait_strategy : WaitStrategy,
+}
+
+impl ShardConfig
+{
+    /// round_robin spreads the symbols over the given number of unpinned threads
+    pub fn round_robin(symbols : &[&str], shards : usize) -> ShardConfig
+    {
+        let shards = shards.max(1);
+        let mut groups = vec![vec![]; shards];
+        for (index, symbol) in symbols.iter().enumerate()
+        {
+            groups[index % shards].push(symbol.to_string());
+        }
+        ShardConfig { groups, cores : vec![], queue_capacity : 4096, wait_strategy : WaitStrategy::Park }
+    }
+}
+
+/// Command is a request of the gateway to a matching thread, the symbols are given
+/// by their index in the group of the thread so that no string is copied. The default
+/// command only fills the slots of the ring before their first use
+#[derive(Debug, Default)]
+enum Command
+{
+    Insert { symbol : usize, order : Order },
+    Cancel { symbol : usize, order : Order },
+    Amend { symbol : usize, order : Order, price : f32, qty : u32 },
+    Admin(AdminCommand),
+    #[default]
+    ExpireOrders,
+}
+
+/// Report is the outcome of a command on a matching thread
+#[derive(Clone, PartialEq, Debug)]
+pub enum Report
+{
+    /// The events of the books of the thread resulting from a command, paired with their
+    /// symbol, in the order in which they happened
+    Events(Vec<(String, Event)>),
+    /// The order has been rejected by the engine of the thread
+    Rejected { symbol : String, order : Order, reason : &'static str },
+    /// The admin command has been applied by the thread, it is reported by every thread
+    /// affected, after the events it caused
+    Admin { command : AdminCommand, result : Result<usize, &'static str> },
+}
+
+impl Default for Report
+{
+    fn default() -> Self
+    {
+        Report::Events(vec![])
+    }
+}
+
+/// Output is a report sequenced by the publisher
+/// * seq is the global output sequence, the reports of a symbol are sequenced in the
+///   order in which its commands were submitted
+/// * shard is the index of the thread which produced the report
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Output
+{
+    pub seq : u64,
+    pub shard : usize,
+    pub report : Report,
+}
+
+/// ShardedEngine is the gateway side of an engine whose symbols are partitioned across
+/// several matching threads. Each thread owns the engine of its group of symbols and is
+/// fed by a lock-free single producer single consumer queue, the order of the commands
+/// of a symbol is thus preserved. The reports flow back through one queue per thread,
+/// merged and sequenced by ShardOutputs
+/// * _routes maps a symbol to its thread and its index in the group of the thread
+/// * _commands holds the queue feeding each thread
+/// * _workers holds the handles of the threads, they stop once their queue is dropped
+/// * _stopping tells the threads to drop the reports nobody polls while the engine is dropped
+#[derive(Debug)]
+pub struct ShardedEngine
+{
+    _routes : HashMap<String, (usize, usize)>,
+    _commands : Vec<Producer<Command>>,
+    _workers : Vec<JoinHandle<()>>,
+    _stopping : Arc<AtomicBool>,
+}
+
+/// ShardOutputs is the publisher side of a sharded engine, it polls the threads in turn
+/// and stamps their reports with the global output sequence
+/// * _queues holds the queue fed by each thread
+/// * _next_shard is the thread polled first by the next call
+/// * _next_seq is the sequence of the next output
+#[derive(Debug)]
+pub struct ShardOutputs
+{
+    _queues : Vec<Consumer<Output>>,
+    _next_shard : usize,
+    _next_seq : u64,
+}
+
+impl ShardedEngine
+{
+    /// start spawns one matching thread per group of symbols. The books of all the threads
+    /// share the clock, each thread has its own sequencer so that the threads never write
+    /// the same counters. The sequencers interleave their numbers, the order sequences, the
+    /// trade ids and the match ids are unique across the threads but only increase within
+    /// each thread: an output may carry lower ids than an earlier output of another thread,
+    /// the global order of the outputs is given by their seq
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The partition of the symbols across the threads
+    /// * `clock` - The clock timestamping the orders and trades of all the books
+    ///
+    /// # Return
+    ///
+    /// The gateway and the publisher sides of the engine, they can be moved to their own threads
+    pub fn start(config : &ShardConfig, clock : Arc<dyn Clock>) -> Result<(ShardedEngine, ShardOutputs), &'static str>
+    {
+        if config.groups.is_empty()
+        {
+            return Err("The sharded engine needs at least one group of symbols");
+        }
+        if !config.cores.is_empty() && config.cores.len() != config.groups.len()
+        {
+            return Err("The sharded engine needs one core per group of symbols");
+        }
+
+        let mut routes = HashMap::new();
+        for (shard, group) in config.groups.iter().enumerate()
+        {
+            for (index, symbol) in group.iter().enumerate()
+            {
+                if routes.insert(symbol.clone(), (shard, index)).is_some()
+                {
+                    return Err("A symbol belongs to several groups");
+                }
+            }
+        }
+
+        let stopping = Arc::new(AtomicBool::new(false));
+        let mut engine = ShardedEngine { _routes : routes, _commands : vec![], _workers : vec![], _stopping : stopping.clone() };
+        let mut queues = vec![];
+        for (shard, group) in config.groups.iter().enumerate()
+        {
+            let (command_sender, command_receiver) = spsc::channel(config.queue_capacity, config.wait_strategy);
+            let (output_sender, output_receiver) = spsc::channel(config.queue_capacity, config.wait_strategy);
+            let worker = Worker {
+                _shard : shard,
+                _symbols : group.clone(),
+                _core : config.cores.get(shard).copied(),
+                _commands : command_receiver,
+                _outputs : output_sender,
+                _stopping : stopping.clone(),
+            };
+            let clock = clock.clone();
+            let sequencer = Arc::new(Sequencer::interleaved(shard as u64 + 1, config.groups.len() as u64));
+            let handle = thread::Builder::new()
+                .name(format!("matching-{}", shard))
+                .spawn(move || worker.run(clock, sequencer))
+                .map_err(|_| "Failed to spawn a matching thread")?;
+            engine._commands.push(command_sender);
+            engine._workers.push(handle);
+            queues.push(output_receiver);
+        }
+        Ok((engine, ShardOutputs { _queues : queues, _next_shard : 0, _next_seq : 1 }))
+    }
+
+    pub fn num_shards(&self) -> usize
+    {
+        self._commands.len()
+    }
+
+    /// shard returns the index of the thread trading the symbol
+    pub fn shard(&self, symbol : &str) -> Option<usize>
+    {
+        self._routes.get(symbol).map(|(shard, _)| *shard)
+    }
+
+    /// send queues the command for the thread, waiting for room when its queue is full.
+    /// It fails once the thread is stopped, nothing reads the queue anymore
+    fn send(&mut self, shard : usize, command : Command) -> Result<(), &'static str>
+    {
+        if self._commands[shard].push(command).is_err()
+        {
+            warn!(shard, "the matching thread is stopped, the command is dropped");
+            return Err("The matching thread is stopped");
+        }
+        Ok(())
+    }
+
+    /// route returns the thread and the index in its group of the symbol
+    fn route(&self, symbol : &str) -> Result<(usize, usize), &'static str>
+    {
+        self._routes.get(symbol).copied().ok_or("Symbol is not traded by the Engine")
+    }
+
+    /// insert_order queues the order for the thread trading its symbol, the outcome is reported
+    /// on the outputs
+    ///
+    /// # Arguments
+    /// * symbol: the symbol of the order
+    /// * order: the incoming order
+    pub fn insert_order(&mut self, symbol : &str, order : Order) -> Result<(), &'static str>
+    {
+        let (shard, symbol) = self.route(symbol)?;
+        self.send(shard, Command::Insert { symbol, order })
+    }
+
+    /// cancel_order queues the cancel of a resting order for the thread trading its symbol
+    pub fn cancel_order(&mut self, symbol : &str, order : Order) -> Result<(), &'static str>
+    {
+        let (shard, symbol) = self.route(symbol)?;
+        self.send(shard, Command::Cancel { symbol, order })
+    }
+
+    /// amend_order queues the new price and quantity of a resting order for the thread trading
+    /// its symbol
+    pub fn amend_order(&mut self, symbol : &str, order : Order, price : f32, qty : u32) -> Result<(), &'static str>
+    {
+        let (shard, symbol) = self.route(symbol)?;
+        self.send(shard, Command::Amend { symbol, order, price, qty })
+    }
+
+    /// admin queues an operator command for the thread trading its symbol, the commands
+    /// which are not about a symbol are sent to every thread
+    pub fn admin(&mut self, command : &AdminCommand) -> Result<(), &'static str>
+    {
+        if let AdminCommand::HaltSymbol(symbol) | AdminCommand::ResumeSymbol(symbol) = command
+        {
+            let (shard, _) = self.route(symbol)?;
+            return self.send(shard, Command::Admin(command.clone()));
+        }
+
+        for shard in 0..self.num_shards()
+        {
+            self.send(shard, Command::Admin(command.clone()))?;
+        }
+        Ok(())
+    }
+
+    /// expire_orders asks every thread to remove the GTD orders reached by the clock
+    pub fn expire_orders(&mut self) -> Result<(), &'static str>
+    {
+        for shard in 0..self.num_shards()
+        {
+            self.send(shard, Command::ExpireOrders)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ShardedEngine
+{
+    /// Stops the threads once they have processed the queued commands. The reports which
+    /// find the output queue of their thread full are dropped rather than waiting for
+    /// the outputs to be polled
+    fn drop(&mut self)
+    {
+        self._stopping.store(true, Ordering::Release);
+        self._commands.clear();
+        for worker in self._workers.drain(..)
+        {
+            if worker.join().is_err()
+            {
+                warn!("a matching thread panicked");
+            }
+        }
+    }
+}
+
+impl ShardOutputs
+{
+    /// poll returns the next report of the threads, each thread is polled in turn so that
+    /// a busy thread does not delay the others
+    pub fn poll(&mut self) -> Option<Output>
+    {
+        for _ in 0..self._queues.len()
+        {
+            let shard = self._next_shard;
+            self._next_shard = (shard + 1) % self._queues.len();
+            if let Some(mut output) = self._queues[shard].try_pop()
+            {
+                output.seq = self._next_seq;
+                self._next_seq += 1;
+                return Some(output);
+            }
+        }
+        None
+    }
+
+    /// is_closed tells whether all the threads are stopped and their reports have been polled
+    pub fn is_closed(&self) -> bool
+    {
+        self._queues.iter().all(|queue| queue.is_closed() && queue.is_empty())
+    }
+}
+
+/// Worker is a matching thread, it owns the engine of its group of symbols
+struct Worker
+{
+    _shard : usize,
+    _symbols : Vec<String>,
+    _core : Option<usize>,
+    _commands : Consumer<Command>,
+    _outputs : Producer<Output>,
+    _stopping : Arc<AtomicBool>,
+}
+
+impl Worker
+{
+    /// run processes the commands until it is stopped, the engine is created by the thread
+    /// so that its memory is allocated close to its core
+    fn run(mut self, clock : Arc<dyn Clock>, sequencer : Arc<Sequencer>)
+    {
+        if let Some(core) = self._core
+        {
+            if !pin_to_core(core)
+            {
+                warn!(shard = self._shard, core, "failed to pin the matching thread");
+            }
+        }
+        let mut engine = Engine::with_sequencer(clock, sequencer);
+        for symbol in self._symbols.iter()
+        {
+            engine.add_symbol(symbol);
+        }
+        info!(shard = self._shard, symbols = ?self._symbols, core = ?self._core, "matching thread started");
+
+        while let Some(command) = self._commands.pop()
+        {
+            let rejected = match command
+            {
+                Command::Insert { symbol, mut order } =>
+                    engine.insert_order(&self._symbols[symbol], &mut order).err().map(|reason| (symbol, order, reason)),
+                Command::Cancel { symbol, order } =>
+                    engine.cancel_order(&self._symbols[symbol], &order).err().map(|reason| (symbol, order, reason)),
+                Command::Amend { symbol, order, price, qty } =>
+                    engine.amend_order(&self._symbols[symbol], &order, price, qty).err().map(|reason| (symbol, order, reason)),
+                Command::Admin(command) =>
+                {
+                    let result = engine.admin(&command);
+                    self.report_events(&mut engine);
+                    self.report(Report::Admin { command, result });
+                    continue;
+                },
+                Command::ExpireOrders =>
+                {
+                    engine.expire_orders();
+                    None
+                },
+            };
+            match rejected
+            {
+                Some((symbol, order, reason)) => self.report(Report::Rejected { symbol : self._symbols[symbol].clone(), order, reason }),
+                None => self.report_events(&mut engine),
+            }
+        }
+        info!(shard = self._shard, "matching thread stopped");
+    }
+
+    fn report_events(&mut self, engine : &mut Engine)
+    {
+        let events = engine.drain_events();
+        if !events.is_empty()
+        {
+            self.report(Report::Events(events));
+        }
+    }
+
+    /// report queues the report for the publisher, waiting for room when its queue is full.
+    /// The report is dropped when nobody polls the outputs anymore, or when the queue is
+    /// still full while the engine is being stopped
+    fn report(&mut self, report : Report)
+    {
+        let shard = self._shard;
+        let mut report = Some(report);
+        while !self._outputs.publish_timeout(|output| *output = Output { seq : 0, shard, report : report.take().unwrap() }, STOP_CHECK_INTERVAL)
+        {
+            if self._outputs.is_closed()
+            {
+                return;
+            }
+            if self._stopping.load(Ordering::Acquire)
+            {
+                warn!(shard, "the outputs are not polled, the report is dropped");
+                return;
+            }
+        }
+    }
+}
+
+/// pin_to_core restricts the calling thread to the core, false when it is not supported
+#[cfg(target_os = "linux")]
+fn pin_to_core(core : usize) -> bool
+{
+    unsafe
+    {
+        let mut set : libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(core, &mut set);
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) == 0
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_to_core(_core : usize) -> bool
+{
+    false
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::collections::HashSet;
+    use std::time::{Duration, Instant};
+    use crate::clock::ManualClock;
+    use super::*;
+
+    fn start(symbols : &[&str], shards : usize) -> (ShardedEngine, ShardOutputs)
+    {
+        ShardedEngine::start(&ShardConfig::round_robin(symbols, shards), Arc::new(ManualClock::new(1_000))).unwrap()
+    }
+
+    /// collect polls the outputs until the expected number of reports is received
+    fn collect(outputs : &mut ShardOutputs, count : usize) -> Vec<Output>
+    {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut collected = vec![];
+        while collected.len() < count
+        {
+            assert!(Instant::now() < deadline, "only {} reports out of {}", collected.len(), count);
+            match outputs.poll()
+            {
+                Some(output) => collected.push(output),
+                None => thread::yield_now(),
+            }
+        }
+        collected
+    }
+
+    /// events returns the events of the symbol in the order of the outputs
+    fn events<'a>(outputs : &'a [Output], symbol : &'a str) -> impl Iterator<Item = &'a Event> + 'a
+    {
+        outputs.iter().flat_map(move |output| match &output.report
+        {
+            Report::Events(events) => events.iter().filter(|(event_symbol, _)| event_symbol == symbol).map(|(_, event)| event).collect(),
+            _ => vec![],
+        })
+    }
+
+    #[test]
+    fn symbols_are_partitioned_across_threads()
+    {
+        let (engine, _outputs) = start(&["AAPL", "MSFT", "TSLA"], 2);
+        assert_eq!(engine.num_shards(), 2);
+        assert_eq!((engine.shard("AAPL"), engine.shard("MSFT"), engine.shard("TSLA")), (Some(0), Some(1), Some(0)));
+        assert_eq!(engine.shard("AMZN"), None);
+
+        let clock : Arc<dyn Clock> = Arc::new(ManualClock::new(0));
+        let mut config = ShardConfig::round_robin(&["AAPL", "TSLA"], 2);
+        config.cores = vec![0];
+        assert!(ShardedEngine::start(&config, clock.clone()).is_err());
+        config.cores = vec![0, 0];
+        let (mut pinned, mut outputs) = ShardedEngine::start(&config, clock.clone()).unwrap();
+        pinned.insert_order("TSLA", Order::new(1, Side::Buy, 122.2f32, 10)).unwrap();
+        assert_eq!(collect(&mut outputs, 1)[0].shard, 1);
+        config.cores = vec![];
+        config.groups[1].push("AAPL".to_string());
+        assert!(ShardedEngine::start(&config, clock.clone()).is_err());
+        assert!(ShardedEngine::start(&ShardConfig { groups : vec![], ..ShardConfig::round_robin(&[], 1) }, clock).is_err());
+    }
+
+    #[test]
+    fn orders_of_a_symbol_are_matched_in_submission_order()
+    {
+        const ORDERS : u32 = 500;
+        let (mut engine, mut outputs) = start(&["AAPL", "TSLA"], 2);
+        for id in 0..ORDERS
+        {
+            let side = if id % 2 == 0 { Side::Buy } else { Side::Sell };
+            engine.insert_order("AAPL", Order::new(id, side, 12.2f32, 10)).unwrap();
+            engine.insert_order("TSLA", Order::new(id, side, 122.2f32, 10)).unwrap();
+        }
+        assert_eq!(engine.insert_order("AMZN", Order::new(1, Side::Buy, 1.0f32, 10)), Err("Symbol is not traded by the Engine"));
+
+        let collected = collect(&mut outputs, 2 * ORDERS as usize);
+        let sequences : Vec<u64> = collected.iter().map(|output| output.seq).collect();
+        assert_eq!(sequences, (1..=2 * ORDERS as u64).collect::<Vec<u64>>());
+
+        // Each sell matches the buy submitted just before it on the same symbol
+        let mut trade_ids = HashSet::new();
+        for symbol in ["AAPL", "TSLA"]
+        {
+            let mut expected = 0;
+            for event in events(&collected, symbol)
+            {
+                match event
+                {
+                    Event::Added(order) => assert_eq!((order.id, order.side), (expected, Side::Buy)),
+                    Event::Traded(trade) =>
+                    {
+                        assert_eq!((trade.passive_id, trade.aggressive_id), (expected - 1, expected));
+                        assert!(trade_ids.insert(trade.trade_id), "trade ids are unique across the threads");
+                    },
+                    _ => panic!("unexpected event {:?}", event),
+                }
+                expected += 1;
+            }
+            assert_eq!(expected, ORDERS);
+        }
+        drop(engine);
+        assert!(outputs.poll().is_none());
+        assert!(outputs.is_closed());
+    }
+
+    #[test]
+    fn ids_only_increase_within_a_thread()
+    {
+        let (mut engine, mut outputs) = start(&["AAPL", "TSLA"], 2);
+        for id in 0..6
+        {
+            let side = if id % 2 == 0 { Side::Buy } else { Side::Sell };
+            engine.insert_order("TSLA", Order::new(id, side, 122.2f32, 10)).unwrap();
+        }
+        let mut collected = collect(&mut outputs, 6);
+        engine.insert_order("AAPL", Order::new(0, Side::Buy, 12.2f32, 10)).unwrap();
+        engine.insert_order("AAPL", Order::new(1, Side::Sell, 12.2f32, 10)).unwrap();
+        collected.extend(collect(&mut outputs, 2));
+
+        let ids = |symbol| events(&collected, symbol).filter_map(|event| match event
+        {
+            Event::Traded(trade) => Some((trade.trade_id, trade.match_id)),
+            _ => None,
+        }).collect::<Vec<(u64, u64)>>();
+        // Each thread numbers with its own stride, the later trade of the first thread has lower ids
+        assert_eq!(ids("TSLA"), vec![(2, 2), (4, 4), (6, 6)]);
+        assert_eq!(ids("AAPL"), vec![(1, 1)]);
+        assert!(collected.windows(2).all(|pair| pair[0].seq < pair[1].seq));
+    }
+
+    #[test]
+    fn rejects_and_admin_commands_are_reported()
+    {
+        let (mut engine, mut outputs) = start(&["AAPL", "TSLA"], 2);
+        engine.insert_order("AAPL", Order::new(1, Side::Buy, 12.2f32, 10)).unwrap();
+        engine.admin(&AdminCommand::HaltSymbol("TSLA".to_string())).unwrap();
+        engine.insert_order("TSLA", Order::new(2, Side::Buy, 122.2f32, 10)).unwrap();
+        engine.cancel_order("AAPL", Order::new(3, Side::Buy, 12.2f32, 10)).unwrap();
+        assert!(engine.admin(&AdminCommand::HaltSymbol("AMZN".to_string())).is_err());
+
+        let collected = collect(&mut outputs, 4);
+        let reports : Vec<&Report> = collected.iter().map(|output| &output.report).collect();
+        assert!(reports.contains(&&Report::Admin { command : AdminCommand::HaltSymbol("TSLA".to_string()), result : Ok(0) }));
+        assert!(reports.iter().any(|report| matches!(report,
+            Report::Rejected { symbol, order, reason : "Symbol is halted" } if symbol == "TSLA" && order.id == 2)));
+        assert!(reports.iter().any(|report| matches!(report,
+            Report::Rejected { symbol, order, .. } if symbol == "AAPL" && order.id == 3)));
+
+        // The kill switch is applied by every thread, after their cancels and mass cancel acks
+        engine.admin(&AdminCommand::KillSwitch).unwrap();
+        let collected = collect(&mut outputs, 4);
+        let cancels = collected.iter().position(|output| matches!(&output.report,
+            Report::Events(events) if matches!(events[0], (_, Event::Cancelled(order)) if order.id == 1))).unwrap();
+        let ack = collected.iter().position(|output| output.shard == 0 && matches!(output.report, Report::Admin { .. })).unwrap();
+        assert!(cancels < ack);
+        let shards : HashSet<usize> = collected.iter()
+            .filter(|output| matches!(output.report, Report::Admin { command : AdminCommand::KillSwitch, .. }))
+            .map(|output| output.shard).collect();
+        assert_eq!(shards, HashSet::from([0, 1]));
+    }
+
+    #[test]
+    fn dropping_the_engine_does_not_wait_for_the_outputs()
+    {
+        let mut config = ShardConfig::round_robin(&["AAPL", "TSLA"], 2);
+        config.queue_capacity = 4;
+        let (mut engine, mut outputs) = ShardedEngine::start(&config, Arc::new(ManualClock::new(1_000))).unwrap();
+        for id in 0..8
+        {
+            engine.insert_order("AAPL", Order::new(id, Side::Buy, 12.2f32 - id as f32 * 0.01, 10)).unwrap();
+        }
+
+        // The output queue fills up and is never polled, the thread drops the last reports
+        let start = Instant::now();
+        drop(engine);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        let collected = std::iter::from_fn(|| outputs.poll()).count();
+        assert!(collected < 8);
+        assert!(outputs.is_closed());
+    }
+}
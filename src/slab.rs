@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+use crate::data_types::{Order, Side};
+
+/// Index of the slab terminating the queues and the free list
+const NIL : u32 = u32::MAX;
+
+/// OrderHandle is the stable position of an order in the slab, it stays valid
+/// until the order is removed even when the slab grows
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct OrderHandle(u32);
+
+/// Node is a slot of the slab, prev and next link the order in the queue of its
+/// level, a free slot is linked in the free list through next
+/// * queue is the id of the queue linking the order, NIL for a free slot
+/// * alias is the slot of the previous stored order with the same side and id, NIL if none
+#[derive(Debug)]
+struct Node
+{
+    order : Order,
+    prev : u32,
+    next : u32,
+    queue : u32,
+    alias : u32,
+}
+
+/// OrderSlab stores the resting orders of a book in a contiguous array, the slots of
+/// the removed orders are reused so that a book which reached its working size does
+/// not allocate anymore
+/// * _nodes holds the slots, both used and free
+/// * _free is the first free slot
+/// * _len is the number of orders stored
+/// * _index maps the side and the id of every stored order to its slot, so that an order
+///   is found without walking the queue of its level. An id reused on the same side points
+///   to the order stored last, which links the previous ones through their alias
+/// * _next_queue is the id given to the next queue storing its first order
+#[derive(Debug)]
+pub struct OrderSlab
+{
+    _nodes : Vec<Node>,
+    _free : u32,
+    _len : usize,
+    _index : HashMap<(Side, u32), u32>,
+    _next_queue : u32,
+}
+
+impl OrderSlab
+{
+    /// Creates an empty slab with room for the given number of orders
+    pub fn with_capacity(capacity : usize) -> Self
+    {
+        Self { _nodes : Vec::with_capacity(capacity), _free : NIL, _len : 0, _index : HashMap::with_capacity(capacity), _next_queue : 0 }
+    }
+
+    /// insert stores an order which is linked in the given queue by the caller
+    fn insert(&mut self, order : Order, queue : u32) -> OrderHandle
+    {
+        self._len += 1;
+        let node = Node { order, prev : NIL, next : NIL, queue, alias : NIL };
+        let index = if self._free == NIL
+        {
+            self._nodes.push(node);
+            self._nodes.len() as u32 - 1
+        }
+        else
+        {
+            let index = self._free;
+            self._free = self._nodes[index as usize].next;
+            self._nodes[index as usize] = node;
+            index
+        };
+        if let Some(alias) = self._index.insert((order.side, order.id), index)
+        {
+            self._nodes[index as usize].alias = alias;
+        }
+        OrderHandle(index)
+    }
+
+    /// release frees the slot of an order already unlinked from its queue
+    fn release(&mut self, handle : OrderHandle) -> Order
+    {
+        self._len -= 1;
+        let node = &mut self._nodes[handle.0 as usize];
+        node.next = self._free;
+        node.queue = NIL;
+        self._free = handle.0;
+        let (order, alias) = (node.order, node.alias);
+        // The order is unlinked from the orders stored with the same side and id
+        let key = (order.side, order.id);
+        match self._index.get(&key)
+        {
+            Some(&last) if last == handle.0 && alias == NIL => { self._index.remove(&key); },
+            Some(&last) if last == handle.0 => { self._index.insert(key, alias); },
+            Some(&last) =>
+            {
+                let mut current = last;
+                while self._nodes[current as usize].alias != handle.0
+                {
+                    current = self._nodes[current as usize].alias;
+                }
+                self._nodes[current as usize].alias = alias;
+            },
+            None => {},
+        }
+        order
+    }
+
+    /// handle returns the handle of the stored order with the given side and id, the
+    /// order stored last when the id is reused on that side
+    pub fn handle(&self, side : Side, order_id : u32) -> Option<OrderHandle>
+    {
+        self.handles(side, order_id).next()
+    }
+
+    /// handles iterates the stored orders with the given side and id, the last stored first
+    pub fn handles(&self, side : Side, order_id : u32) -> impl Iterator<Item = OrderHandle> + '_
+    {
+        std::iter::successors(self._index.get(&(side, order_id)).map(|index| OrderHandle(*index)), |handle|
+        {
+            let alias = self._nodes[handle.0 as usize].alias;
+            (alias != NIL).then_some(OrderHandle(alias))
+        })
+    }
+
+    pub fn get(&self, handle : OrderHandle) -> &Order
+    {
+        &self._nodes[handle.0 as usize].order
+    }
+
+    pub fn get_mut(&mut self, handle : OrderHandle) -> &mut Order
+    {
+        &mut self._nodes[handle.0 as usize].order
+    }
+
+    /// len returns the number of orders stored
+    pub fn len(&self) -> usize
+    {
+        self._len
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self._len == 0
+    }
+}
+
+/// OrderQueue is a FIFO of orders linked through the slab of the book, it owns
+/// no memory so that the price levels holding it are not allocated
+/// * _id identifies the queue in the slab, it is given when the first order is stored
+#[derive(Copy, Clone, Debug)]
+pub struct OrderQueue
+{
+    _head : u32,
+    _tail : u32,
+    _len : u32,
+    _id : u32,
+}
+
+impl Default for OrderQueue
+{
+    fn default() -> Self
+    {
+        Self { _head : NIL, _tail : NIL, _len : 0, _id : NIL }
+    }
+}
+
+impl OrderQueue
+{
+    pub fn len(&self) -> usize
+    {
+        self._len as usize
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self._len == 0
+    }
+
+    /// push_back stores the order in the slab and links it at the back of the queue
+    pub fn push_back(&mut self, slab : &mut OrderSlab, order : Order) -> OrderHandle
+    {
+        if self._id == NIL
+        {
+            self._id = slab._next_queue;
+            slab._next_queue += 1;
+        }
+        let handle = slab.insert(order, self._id);
+        slab._nodes[handle.0 as usize].prev = self._tail;
+        if self._tail == NIL
+        {
+            self._head = handle.0;
+        }
+        else
+        {
+            slab._nodes[self._tail as usize].next = handle.0;
+        }
+        self._tail = handle.0;
+        self._len += 1;
+        handle
+    }
+
+    /// front returns the order with the highest time priority
+    pub fn front(&self) -> Option<OrderHandle>
+    {
+        (self._head != NIL).then_some(OrderHandle(self._head))
+    }
+
+    /// contains returns true if the order of the handle is linked in this queue
+    pub fn contains(&self, slab : &OrderSlab, handle : OrderHandle) -> bool
+    {
+        self._id != NIL && slab._nodes.get(handle.0 as usize).is_some_and(|node| node.queue == self._id)
+    }
+
+    /// remove unlinks an order of the queue and frees its slot, None when the order
+    /// is not linked in this queue
+    pub fn remove(&mut self, slab : &mut OrderSlab, handle : OrderHandle) -> Option<Order>
+    {
+        if !self.contains(slab, handle)
+        {
+            return None;
+        }
+        let (prev, next) = { let node = &slab._nodes[handle.0 as usize]; (node.prev, node.next) };
+        if prev == NIL
+        {
+            self._head = next;
+        }
+        else
+        {
+            slab._nodes[prev as usize].next = next;
+        }
+        if next == NIL
+        {
+            self._tail = prev;
+        }
+        else
+        {
+            slab._nodes[next as usize].prev = prev;
+        }
+        self._len -= 1;
+        Some(slab.release(handle))
+    }
+
+    /// handles iterates the orders of the queue by time priority
+    pub fn handles<'a>(&self, slab : &'a OrderSlab) -> impl Iterator<Item = OrderHandle> + 'a
+    {
+        std::iter::successors(self.front(), |handle|
+        {
+            let next = slab._nodes[handle.0 as usize].next;
+            (next != NIL).then_some(OrderHandle(next))
+        })
+    }
+
+    /// remove_if removes the orders selected by the predicate, keeping the others in order
+    ///
+    /// # Arguments
+    ///
+    /// * `slab` - The slab of the book
+    /// * `predicate` - The function returning true for the orders to be removed
+    /// * `removed` - The vector collecting the removed orders
+    pub fn remove_if(&mut self, slab : &mut OrderSlab, predicate : impl Fn(&Order) -> bool, removed : &mut Vec<Order>)
+    {
+        let mut current = self._head;
+        while current != NIL
+        {
+            let next = slab._nodes[current as usize].next;
+            if predicate(&slab._nodes[current as usize].order)
+            {
+                removed.extend(self.remove(slab, OrderHandle(current)));
+            }
+            current = next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::data_types::{Order, Side};
+    use super::*;
+
+    fn ids(queue : &OrderQueue, slab : &OrderSlab) -> Vec<u32>
+    {
+        queue.handles(slab).map(|handle| slab.get(handle).id).collect()
+    }
+
+    #[test]
+    fn queues_keep_the_time_priority()
+    {
+        let mut slab = OrderSlab::with_capacity(4);
+        let mut bids = OrderQueue::default();
+        let mut asks = OrderQueue::default();
+        for id in 1..=3
+        {
+            bids.push_back(&mut slab, Order::new(id, Side::Buy, 12.2, 10));
+            asks.push_back(&mut slab, Order::new(10 + id, Side::Sell, 12.5, 10));
+        }
+
+        assert_eq!(ids(&bids, &slab), vec![1, 2, 3]);
+        assert_eq!(ids(&asks, &slab), vec![11, 12, 13]);
+        assert_eq!(slab.len(), 6);
+
+        let middle = slab.handle(Side::Buy, 2).unwrap();
+        assert_eq!(bids.remove(&mut slab, middle).unwrap().id, 2);
+        let front = bids.front().unwrap();
+        assert_eq!(bids.remove(&mut slab, front).unwrap().id, 1);
+        assert_eq!(ids(&bids, &slab), vec![3]);
+        assert_eq!(bids.len(), 1);
+        assert!(slab.handle(Side::Buy, 1).is_none());
+
+        // An order of another queue, or a freed slot, is left untouched
+        let ask = asks.front().unwrap();
+        assert!(!bids.contains(&slab, ask) && asks.contains(&slab, ask));
+        assert_eq!(bids.remove(&mut slab, ask), None);
+        assert_eq!(bids.remove(&mut slab, front), None);
+        assert_eq!((bids.len(), asks.len(), slab.len()), (1, 3, 4));
+    }
+
+    #[test]
+    fn freed_slots_are_reused()
+    {
+        let mut slab = OrderSlab::with_capacity(2);
+        let mut queue = OrderQueue::default();
+        let first = queue.push_back(&mut slab, Order::new(1, Side::Buy, 12.2, 10));
+        queue.push_back(&mut slab, Order::new(2, Side::Buy, 12.2, 10));
+        queue.remove(&mut slab, first);
+
+        let third = queue.push_back(&mut slab, Order::new(3, Side::Buy, 12.2, 10));
+        assert_eq!(third, first);
+        assert_eq!(slab._nodes.len(), 2);
+        assert_eq!(ids(&queue, &slab), vec![2, 3]);
+    }
+
+    #[test]
+    fn orders_are_indexed_by_id()
+    {
+        let mut slab = OrderSlab::with_capacity(4);
+        let mut queue = OrderQueue::default();
+        let first = queue.push_back(&mut slab, Order::new(1, Side::Buy, 12.2, 10));
+        let second = queue.push_back(&mut slab, Order::new(2, Side::Buy, 12.2, 10));
+        assert_eq!(slab.handle(Side::Buy, 1), Some(first));
+        assert_eq!(slab.handle(Side::Buy, 2), Some(second));
+        assert_eq!(slab.handle(Side::Buy, 3), None);
+        assert_eq!(slab.handle(Side::Sell, 1), None);
+
+        queue.remove(&mut slab, first);
+        assert_eq!(slab.handle(Side::Buy, 1), None);
+
+        // A reused id points to the last order, which links the previous ones
+        let reused = queue.push_back(&mut slab, Order::new(2, Side::Buy, 12.2, 20));
+        let again = queue.push_back(&mut slab, Order::new(2, Side::Buy, 12.1, 30));
+        assert_eq!(slab.handles(Side::Buy, 2).collect::<Vec<_>>(), vec![again, reused, second]);
+        queue.remove(&mut slab, reused);
+        assert_eq!(slab.handles(Side::Buy, 2).collect::<Vec<_>>(), vec![again, second]);
+        queue.remove(&mut slab, again);
+        assert_eq!(slab.handles(Side::Buy, 2).collect::<Vec<_>>(), vec![second]);
+        let reused = queue.push_back(&mut slab, Order::new(2, Side::Buy, 12.2, 20));
+        queue.remove(&mut slab, second);
+        assert_eq!(slab.handles(Side::Buy, 2).collect::<Vec<_>>(), vec![reused]);
+        assert_eq!(slab.get(reused).qty, 20);
+
+        // The same id resting on the other side has its own entry
+        let mut asks = OrderQueue::default();
+        let ask = asks.push_back(&mut slab, Order::new(2, Side::Sell, 12.5, 30));
+        assert_eq!((slab.handle(Side::Buy, 2), slab.handle(Side::Sell, 2)), (Some(reused), Some(ask)));
+    }
+
+    #[test]
+    fn remove_if_keeps_the_other_orders_in_order()
+    {
+        let mut slab = OrderSlab::with_capacity(4);
+        let mut queue = OrderQueue::default();
+        for id in 1..=4
+        {
+            queue.push_back(&mut slab, Order::new(id, Side::Buy, 12.2, 10));
+        }
+
+        let mut removed = vec![];
+        queue.remove_if(&mut slab, |order| order.id % 2 == 1, &mut removed);
+        assert_eq!(removed.iter().map(|order| order.id).collect::<Vec<u32>>(), vec![1, 3]);
+        assert_eq!(ids(&queue, &slab), vec![2, 4]);
+        assert!(slab.len() == 2 && !slab.is_empty());
+    }
+}
@@ -0,0 +1,570 @@
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+/// Number of busy spins of a parking side before it parks its thread
+const SPINS_BEFORE_PARK : u32 = 1000;
+
+/// WaitStrategy tells how a side waits for the other one when the ring is full or empty
+/// * BusySpin keeps the core busy checking the ring, for the lowest latency on a dedicated core
+/// * Park spins for a short while then parks the thread until the other side wakes it up,
+///   a publish then costs a fence to check whether the consumer is parked
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum WaitStrategy
+{
+    BusySpin,
+    #[default]
+    Park,
+}
+
+/// CachePadded keeps a counter alone on its cache line, so that the producer and the
+/// consumer do not invalidate each other's line on every operation
+#[repr(align(64))]
+#[derive(Debug)]
+struct CachePadded<T>(T);
+
+/// Waiter is a side of the ring parked until the other side makes progress
+/// * _parked is set by the side before it parks, the other side wakes it up when set
+/// * _thread is the thread of the parked side, only locked when parking and waking up
+#[derive(Debug, Default)]
+struct Waiter
+{
+    _parked : AtomicBool,
+    _thread : Mutex<Option<Thread>>,
+}
+
+impl Waiter
+{
+    /// wait returns once ready is true, or false when the deadline is reached
+    fn wait(&self, strategy : WaitStrategy, deadline : Option<Instant>, ready : impl Fn() -> bool) -> bool
+    {
+        let mut spins = 0;
+        loop
+        {
+            if ready()
+            {
+                return true;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                return false;
+            }
+            if strategy == WaitStrategy::BusySpin || spins < SPINS_BEFORE_PARK
+            {
+                spins += 1;
+                std::hint::spin_loop();
+                continue;
+            }
+
+            *self._thread.lock().unwrap() = Some(thread::current());
+            self._parked.store(true, Ordering::SeqCst);
+            fence(Ordering::SeqCst);
+            // The other side may have made progress before seeing the flag
+            if !ready()
+            {
+                match deadline
+                {
+                    Some(deadline) => thread::park_timeout(deadline.saturating_duration_since(Instant::now())),
+                    None => thread::park(),
+                }
+            }
+            self._parked.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// wake unparks the side if it is parked, the progress must be stored before
+    fn wake(&self)
+    {
+        fence(Ordering::SeqCst);
+        if self._parked.swap(false, Ordering::SeqCst)
+        {
+            if let Some(thread) = self._thread.lock().unwrap().as_ref()
+            {
+                thread.unpark();
+            }
+        }
+    }
+}
+
+/// Shared is the ring of slots, the positions grow forever and are wrapped by the mask.
+/// The slots are allocated once, the values written in place are reused by the next laps
+/// * _head is the position of the next slot to be consumed, written by the consumer only
+/// * _tail is the position of the next slot to be published, written by the producer only
+/// * _closed is set when either side is dropped
+/// * _readable wakes up the consumer waiting for a slot to be published
+/// * _writable wakes up the producer waiting for a slot to be consumed
+struct Shared<T>
+{
+    _slots : Box<[UnsafeCell<T>]>,
+    _mask : usize,
+    _strategy : WaitStrategy,
+    _head : CachePadded<AtomicUsize>,
+    _tail : CachePadded<AtomicUsize>,
+    _closed : AtomicBool,
+    _readable : Waiter,
+    _writable : Waiter,
+}
+
+// The slots between head and tail are only accessed by the consumer and the others by
+// the producer, the positions hand the slots over from one side to the other
+unsafe impl<T : Send> Sync for Shared<T> {}
+
+impl<T> Shared<T>
+{
+    fn is_closed(&self) -> bool
+    {
+        self._closed.load(Ordering::Acquire)
+    }
+}
+
+/// Producer is the publishing half of a ring, it can be moved to another thread
+pub struct Producer<T>
+{
+    _shared : Arc<Shared<T>>,
+    _tail : usize,
+    _cached_head : usize,
+}
+
+/// Consumer is the consuming half of a ring, it can be moved to another thread
+pub struct Consumer<T>
+{
+    _shared : Arc<Shared<T>>,
+    _head : usize,
+    _cached_tail : usize,
+}
+
+unsafe impl<T : Send> Send for Producer<T> {}
+unsafe impl<T : Send> Send for Consumer<T> {}
+
+impl<T> fmt::Debug for Producer<T>
+{
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        f.debug_struct("Producer").field("capacity", &self._shared._slots.len()).field("tail", &self._tail).finish()
+    }
+}
+
+impl<T> fmt::Debug for Consumer<T>
+{
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        f.debug_struct("Consumer").field("capacity", &self._shared._slots.len()).field("head", &self._head).finish()
+    }
+}
+
+/// channel creates a bounded lock-free ring between a single producer and a single consumer,
+/// in the style of a disruptor: the slots are preallocated with the default value and the
+/// producer writes each message in place, so that the buffers of a slot are reused. Each
+/// side keeps a copy of the position of the other one and only reads the shared position
+/// when the copy says that the ring is full or empty
+///
+/// # Arguments
+///
+/// * `capacity` - The number of slots, rounded up to a power of two
+/// * `strategy` - How both sides wait when the ring is full or empty
+pub fn channel<T : Default>(capacity : usize, strategy : WaitStrategy) -> (Producer<T>, Consumer<T>)
+{
+    let capacity = capacity.max(1).next_power_of_two();
+    let shared = Arc::new(Shared {
+        _slots : (0..capacity).map(|_| UnsafeCell::new(T::default())).collect(),
+        _mask : capacity - 1,
+        _strategy : strategy,
+        _head : CachePadded(AtomicUsize::new(0)),
+        _tail : CachePadded(AtomicUsize::new(0)),
+        _closed : AtomicBool::new(false),
+        _readable : Waiter::default(),
+        _writable : Waiter::default(),
+    });
+    (Producer { _shared : shared.clone(), _tail : 0, _cached_head : 0 },
+     Consumer { _shared : shared, _head : 0, _cached_tail : 0 })
+}
+
+impl<T> Producer<T>
+{
+    fn is_full(&mut self) -> bool
+    {
+        if self._tail - self._cached_head < self._shared._slots.len()
+        {
+            return false;
+        }
+        self._cached_head = self._shared._head.0.load(Ordering::Acquire);
+        self._tail - self._cached_head == self._shared._slots.len()
+    }
+
+    /// try_publish writes the next message in its slot, nothing is written when the ring is full
+    ///
+    /// # Arguments
+    ///
+    /// * `write` - The function updating the slot, it holds the message of the previous lap
+    ///
+    /// # Return
+    ///
+    /// true when the message has been published
+    pub fn try_publish(&mut self, write : impl FnOnce(&mut T)) -> bool
+    {
+        if self.is_full()
+        {
+            return false;
+        }
+
+        let shared = &*self._shared;
+        write(unsafe { &mut *shared._slots[self._tail & shared._mask].get() });
+        self._tail += 1;
+        shared._tail.0.store(self._tail, Ordering::Release);
+        if shared._strategy == WaitStrategy::Park
+        {
+            shared._readable.wake();
+        }
+        true
+    }
+
+    /// publish writes the next message in its slot, waiting for a free slot when the ring is full
+    ///
+    /// # Return
+    ///
+    /// false when the consumer has been dropped, the message is not written
+    pub fn publish(&mut self, write : impl FnOnce(&mut T)) -> bool
+    {
+        self.publish_until(write, None)
+    }
+
+    /// publish_timeout writes the next message in its slot, waiting at most the timeout
+    /// for a free slot when the ring is full
+    ///
+    /// # Return
+    ///
+    /// false when the message is not written, the ring being still full or closed
+    pub fn publish_timeout(&mut self, write : impl FnOnce(&mut T), timeout : Duration) -> bool
+    {
+        self.publish_until(write, Some(Instant::now() + timeout))
+    }
+
+    fn publish_until(&mut self, write : impl FnOnce(&mut T), deadline : Option<Instant>) -> bool
+    {
+        if self.is_full()
+        {
+            let shared = &*self._shared;
+            let (tail, capacity) = (self._tail, shared._slots.len());
+            shared._writable.wait(shared._strategy, deadline, || tail - shared._head.0.load(Ordering::Acquire) < capacity || shared.is_closed());
+        }
+        !self.is_closed() && self.try_publish(write)
+    }
+
+    /// try_push publishes the value, it is given back when the ring is full
+    pub fn try_push(&mut self, value : T) -> Result<(), T>
+    {
+        let mut value = Some(value);
+        match self.try_publish(|slot| *slot = value.take().unwrap())
+        {
+            true => Ok(()),
+            false => Err(value.unwrap()),
+        }
+    }
+
+    /// push publishes the value, waiting for a free slot, it is given back when the
+    /// consumer has been dropped
+    pub fn push(&mut self, value : T) -> Result<(), T>
+    {
+        let mut value = Some(value);
+        match self.publish(|slot| *slot = value.take().unwrap())
+        {
+            true => Ok(()),
+            false => Err(value.unwrap()),
+        }
+    }
+
+    /// is_closed tells whether the consumer has been dropped, nothing is read anymore
+    pub fn is_closed(&self) -> bool
+    {
+        self._shared.is_closed()
+    }
+
+    pub fn capacity(&self) -> usize
+    {
+        self._shared._slots.len()
+    }
+}
+
+impl<T> Consumer<T>
+{
+    /// available returns the number of published slots, without reading the shared
+    /// position while the cached one says there are some
+    fn available(&mut self) -> usize
+    {
+        if self._head == self._cached_tail
+        {
+            self._cached_tail = self._shared._tail.0.load(Ordering::Acquire);
+        }
+        self._cached_tail - self._head
+    }
+
+    /// try_consume reads all the published messages in a batch, their slots are handed
+    /// back to the producer at the end of the batch
+    ///
+    /// # Arguments
+    ///
+    /// * `read` - The function called with each slot in order, it can take the message
+    ///   or leave it in place to be overwritten by the producer
+    ///
+    /// # Return
+    ///
+    /// The number of messages read
+    pub fn try_consume(&mut self, mut read : impl FnMut(&mut T)) -> usize
+    {
+        let count = self.available();
+        if count == 0
+        {
+            return 0;
+        }
+
+        let shared = &*self._shared;
+        for position in self._head..self._head + count
+        {
+            read(unsafe { &mut *shared._slots[position & shared._mask].get() });
+        }
+        self._head += count;
+        shared._head.0.store(self._head, Ordering::Release);
+        if shared._strategy == WaitStrategy::Park
+        {
+            shared._writable.wake();
+        }
+        count
+    }
+
+    /// consume waits for published messages and reads them in a batch
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The longest wait, None waits until a message is published or the producer is dropped
+    /// * `read` - The function called with each slot in order
+    ///
+    /// # Return
+    ///
+    /// The number of messages read, 0 when the wait timed out or the producer has been dropped
+    pub fn consume(&mut self, timeout : Option<Duration>, read : impl FnMut(&mut T)) -> usize
+    {
+        self.wait_readable(timeout);
+        self.try_consume(read)
+    }
+
+    /// try_pop takes the oldest message, its slot is left with the default value
+    pub fn try_pop(&mut self) -> Option<T> where T : Default
+    {
+        if self.available() == 0
+        {
+            return None;
+        }
+
+        let shared = &*self._shared;
+        let value = std::mem::take(unsafe { &mut *shared._slots[self._head & shared._mask].get() });
+        self._head += 1;
+        shared._head.0.store(self._head, Ordering::Release);
+        if shared._strategy == WaitStrategy::Park
+        {
+            shared._writable.wake();
+        }
+        Some(value)
+    }
+
+    /// pop takes the oldest message, waiting for one to be published
+    ///
+    /// # Return
+    ///
+    /// None when the producer has been dropped and all its messages have been taken
+    pub fn pop(&mut self) -> Option<T> where T : Default
+    {
+        self.wait_readable(None);
+        self.try_pop()
+    }
+
+    /// wait_readable returns when a message is published, the producer is dropped or the timeout elapsed
+    fn wait_readable(&mut self, timeout : Option<Duration>)
+    {
+        if self.available() > 0
+        {
+            return;
+        }
+        let shared = &*self._shared;
+        let head = self._head;
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        shared._readable.wait(shared._strategy, deadline, || shared._tail.0.load(Ordering::Acquire) != head || shared.is_closed());
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self._head == self._shared._tail.0.load(Ordering::Acquire)
+    }
+
+    /// is_closed tells whether the producer has been dropped, the messages already
+    /// published can still be read
+    pub fn is_closed(&self) -> bool
+    {
+        self._shared.is_closed()
+    }
+}
+
+impl<T> Drop for Producer<T>
+{
+    /// Wakes up the consumer so that it sees the ring closed
+    fn drop(&mut self)
+    {
+        self._shared._closed.store(true, Ordering::Release);
+        self._shared._readable.wake();
+    }
+}
+
+impl<T> Drop for Consumer<T>
+{
+    /// Wakes up the producer so that it sees the ring closed
+    fn drop(&mut self)
+    {
+        self._shared._closed.store(true, Ordering::Release);
+        self._shared._writable.wake();
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use super::*;
+
+    #[test]
+    fn values_are_popped_in_order_until_empty()
+    {
+        let (mut producer, mut consumer) = channel(3, WaitStrategy::BusySpin);
+        assert_eq!(producer.capacity(), 4);
+        for value in 1..=4
+        {
+            assert_eq!(producer.try_push(value), Ok(()));
+        }
+        assert_eq!(producer.try_push(5), Err(5));
+        assert_eq!(consumer.try_pop(), Some(1));
+        assert_eq!(producer.try_push(5), Ok(()));
+
+        let values : Vec<u32> = std::iter::from_fn(|| consumer.try_pop()).collect();
+        assert_eq!(values, vec![2, 3, 4, 5]);
+        assert!(consumer.is_empty());
+        assert!(!consumer.is_closed());
+        drop(producer);
+        assert!(consumer.is_closed());
+        assert_eq!(consumer.pop(), None);
+    }
+
+    #[test]
+    fn slots_are_written_in_place_and_consumed_in_batches()
+    {
+        let (mut producer, mut consumer) = channel::<Vec<u32>>(2, WaitStrategy::Park);
+        for lap in 0..3
+        {
+            for value in 0..2
+            {
+                assert!(producer.try_publish(|slot|
+                {
+                    // The buffer of the previous lap is still there to be reused
+                    assert!(lap == 0 || slot.capacity() >= 8);
+                    slot.clear();
+                    slot.extend((0..8).map(|index| lap * 100 + value * 10 + index));
+                }));
+            }
+            assert!(!producer.try_publish(|_| panic!("the ring is full")));
+
+            let mut firsts = vec![];
+            assert_eq!(consumer.try_consume(|slot| firsts.push(slot[0])), 2);
+            assert_eq!(firsts, vec![lap * 100, lap * 100 + 10]);
+        }
+        assert_eq!(consumer.try_consume(|_| panic!("the ring is empty")), 0);
+    }
+
+    #[test]
+    fn publish_waits_until_the_timeout()
+    {
+        let (mut producer, consumer) = channel::<u32>(1, WaitStrategy::Park);
+        assert!(producer.try_publish(|slot| *slot = 1));
+        let start = Instant::now();
+        assert!(!producer.publish_timeout(|_| panic!("the ring is full"), Duration::from_millis(20)));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        drop(consumer);
+        assert!(!producer.publish_timeout(|_| panic!("the ring is closed"), Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn consume_waits_until_the_timeout()
+    {
+        for strategy in [WaitStrategy::BusySpin, WaitStrategy::Park]
+        {
+            let (_producer, mut consumer) = channel::<u32>(4, strategy);
+            let start = Instant::now();
+            assert_eq!(consumer.consume(Some(Duration::from_millis(20)), |_| {}), 0);
+            assert!(start.elapsed() >= Duration::from_millis(20));
+        }
+    }
+
+    #[test]
+    fn values_cross_threads_in_order()
+    {
+        const COUNT : u64 = 100_000;
+        for strategy in [WaitStrategy::BusySpin, WaitStrategy::Park]
+        {
+            // A small ring makes both sides wait for each other, a spinning side only
+            // gives the core up at the end of its time slice when they share one
+            let capacity = if strategy == WaitStrategy::Park { 16 } else { 4096 };
+            let (mut producer, mut consumer) = channel(capacity, strategy);
+            let sender = thread::spawn(move ||
+            {
+                for value in 0..COUNT
+                {
+                    producer.push(value).unwrap();
+                }
+            });
+
+            let mut expected = 0;
+            while let Some(value) = consumer.pop()
+            {
+                assert_eq!(value, expected, "{:?}", strategy);
+                expected += 1;
+            }
+            assert_eq!(expected, COUNT);
+            sender.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn waiting_sides_see_the_ring_closed()
+    {
+        let (producer, mut consumer) = channel::<u32>(1, WaitStrategy::Park);
+        let receiver = thread::spawn(move || consumer.pop());
+        thread::sleep(Duration::from_millis(20));
+        drop(producer);
+        assert_eq!(receiver.join().unwrap(), None);
+
+        let (mut producer, consumer) = channel::<u32>(1, WaitStrategy::Park);
+        producer.push(1).unwrap();
+        let sender = thread::spawn(move || producer.push(2));
+        thread::sleep(Duration::from_millis(20));
+        drop(consumer);
+        assert_eq!(sender.join().unwrap(), Err(2));
+    }
+
+    #[test]
+    fn values_left_in_the_ring_are_dropped()
+    {
+        let value = Arc::new(0);
+        let (mut producer, mut consumer) = channel(4, WaitStrategy::BusySpin);
+        for _ in 0..3
+        {
+            producer.try_push(Some(value.clone())).unwrap();
+        }
+        consumer.try_pop();
+        assert_eq!(Arc::strong_count(&value), 3);
+        drop(consumer);
+        assert!(producer.is_closed());
+        drop(producer);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}